@@ -0,0 +1,36 @@
+import { db, userKeypair } from "@krypt-vault/db";
+import { eq } from "@krypt-vault/db";
+
+// A key row as stored in file_key or folder_key
+interface SharedKeyRecord {
+	sharedBy: string;
+	grant: string | null;
+	grantSignature: string | null;
+}
+
+/**
+ * The signed grant stored with a key, who signed it and the Ed25519 key they
+ * publish. `shareGrant` is null for keys stored before grants existed.
+ * None of this is trusted by the client: it checks the signer against its own
+ * key or a key it pinned earlier, and only uses unsigned keys the user allowed.
+ */
+export async function shareGrantFor(record: SharedKeyRecord) {
+	if (!record.grant || !record.grantSignature) {
+		return { shareGrant: null };
+	}
+
+	const [sharer] = await db
+		.select({ ed25519PublicKey: userKeypair.ed25519PublicKey })
+		.from(userKeypair)
+		.where(eq(userKeypair.userId, record.sharedBy))
+		.limit(1);
+
+	return {
+		shareGrant: {
+			grant: record.grant,
+			signature: record.grantSignature,
+			sharerId: record.sharedBy,
+			sharerPublicKey: sharer?.ed25519PublicKey ?? null,
+		},
+	};
+}
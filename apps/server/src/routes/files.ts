@@ -8,6 +8,7 @@ import { z } from "zod";
 import sodium from "libsodium-wrappers";
 import { auth } from "@krypt-vault/auth";
 import { s3Client, s3Presigner, BUCKET_NAME } from "../lib/s3";
+import { shareGrantFor } from "../lib/share-grants";
 
 // Server keypair for sealing/unsealing DEKs (should be stored securely in production)
 let SERVER_PUBLIC_KEY: string;
@@ -61,6 +62,20 @@ const uploadCompleteSchema = z.object({
 	description: z.string().optional(),
 	tags: z.array(z.string()).optional(),
 	folderId: z.string().optional(), // Optional folder assignment
+	escrowWrappedDek: z.string().nullish(), // DEK sealed to the uploader's escrow key, if they have one
+	grant: z.string().optional(), // Grant the uploader signed over their own wrappedDek
+	grantSignature: z.string().optional(),
+});
+
+const migratedKeysSchema = z.object({
+	keys: z.array(
+		z.object({
+			fileId: z.string(),
+			fileKey: z.string(), // DEK re-wrapped with the owner's X25519 public key
+			grant: z.string().optional(), // Grant the owner signed over fileKey
+			grantSignature: z.string().optional(),
+		})
+	),
 });
 
 // Middleware to extract user from session
@@ -328,7 +343,10 @@ app.post("/upload/complete", async (c) => {
 			fileId: data.fileId,
 			recipientUserId: userId,
 			wrappedDek: data.wrappedDek,
+			escrowWrappedDek: data.escrowWrappedDek ?? null,
 			sharedBy: userId, // Owner shares with themselves
+			grant: data.grant ?? null,
+			grantSignature: data.grantSignature ?? null,
 			createdAt: new Date(),
 		});
 		
@@ -347,6 +365,83 @@ app.post("/upload/complete", async (c) => {
 	}
 });
 
+// GET /api/files/legacy-keys - Owned files whose DEK is only sealed to the server keypair
+// Must come before the /:fileId route
+app.get("/legacy-keys", async (c) => {
+	try {
+		const userId = (c as any).get("userId") as string;
+		
+		const files = await db
+			.select({
+				fileId: file.id,
+				wrappedDek: file.wrappedDek,
+			})
+			.from(file)
+			.leftJoin(
+				fileKey,
+				and(eq(fileKey.fileId, file.id), eq(fileKey.recipientUserId, userId))
+			)
+			.where(
+				and(
+					eq(file.userId, userId),
+					sql`${file.wrappedDek} IS NOT NULL`,
+					sql`${fileKey.id} IS NULL`
+				)
+			);
+		
+		return c.json({ files });
+	} catch (error) {
+		console.error("❌ List legacy keys error:", error);
+		return c.json({ error: "Failed to list legacy keys" }, 500);
+	}
+});
+
+// POST /api/files/legacy-keys - Store DEKs the owner re-wrapped for themselves
+app.post("/legacy-keys", async (c) => {
+	try {
+		const data = migratedKeysSchema.parse(await c.req.json());
+		const userId = (c as any).get("userId") as string;
+		
+		let stored = 0;
+		for (const key of data.keys) {
+			const [fileRecord] = await db
+				.select()
+				.from(file)
+				.where(and(eq(file.id, key.fileId), eq(file.userId, userId)))
+				.limit(1);
+			if (!fileRecord) {
+				continue;
+			}
+			
+			const [existing] = await db
+				.select()
+				.from(fileKey)
+				.where(and(eq(fileKey.fileId, key.fileId), eq(fileKey.recipientUserId, userId)))
+				.limit(1);
+			if (existing) {
+				continue;
+			}
+			
+			await db.insert(fileKey).values({
+				id: crypto.randomUUID(),
+				fileId: key.fileId,
+				recipientUserId: userId,
+				wrappedDek: key.fileKey,
+				sharedBy: userId,
+				grant: key.grant ?? null,
+				grantSignature: key.grantSignature ?? null,
+				createdAt: new Date(),
+			});
+			stored++;
+		}
+		
+		return c.json({ success: true, stored });
+	} catch (error) {
+		console.error("❌ Store legacy keys error:", error);
+		return c.json({ error: "Failed to store migrated keys" }, 500);
+	}
+});
+
 // GET /api/files or /api/files/ - List user's files (owned + shared)
 // IMPORTANT: This must come BEFORE the /:fileId route to avoid conflicts
 app.get("/", async (c) => {
@@ -729,10 +824,12 @@ app.post("/:fileId/download", async (c) => {
 				return c.json({
 					downloadUrl: presignedUrl,
 					wrappedFolderKey: folderKeyRecord.wrappedFolderKey, // Folder key wrapped with owner's key
+					...(await shareGrantFor(folderKeyRecord)),
 					wrappedDek: fileFolderKeyRecord.wrappedDek, // DEK wrapped with folder key
 					wrappingNonce: fileFolderKeyRecord.wrappingNonce,
 					nonce: fileRecord.nonce,
 					originalFilename: fileRecord.originalFilename,
+					fileSize: fileRecord.fileSize,
 					mimeType: fileRecord.mimeType,
 				});
 		}
@@ -763,8 +860,10 @@ app.post("/:fileId/download", async (c) => {
 			return c.json({
 				downloadUrl: presignedUrl,
 				wrappedDek: ownerFileKey.wrappedDek,
+				...(await shareGrantFor(ownerFileKey)),
 				nonce: fileRecord.nonce,
 				originalFilename: fileRecord.originalFilename,
+				fileSize: fileRecord.fileSize,
 				mimeType: fileRecord.mimeType,
 			});
 		}
@@ -789,6 +888,7 @@ app.post("/:fileId/download", async (c) => {
 			wrappedDek: fileRecord.wrappedDek,
 			nonce: fileRecord.nonce,
 			originalFilename: fileRecord.originalFilename,
+			fileSize: fileRecord.fileSize,
 			mimeType: fileRecord.mimeType,
 		});
 	}		// Check if file was directly shared with user (via fileKey)
@@ -817,8 +917,10 @@ app.post("/:fileId/download", async (c) => {
 			return c.json({
 				downloadUrl: presignedUrl,
 				wrappedDek: fileKeyRecord.wrappedDek, // User-specific wrapped DEK
+				...(await shareGrantFor(fileKeyRecord)),
 				nonce: fileRecord.nonce,
 				originalFilename: fileRecord.originalFilename,
+				fileSize: fileRecord.fileSize,
 				mimeType: fileRecord.mimeType,
 			});
 		}
@@ -870,6 +972,7 @@ app.post("/:fileId/download", async (c) => {
 					wrappingNonce: fileFolderKeyRecord.wrappingNonce,
 					nonce: fileRecord.nonce,
 					originalFilename: fileRecord.originalFilename,
+					fileSize: fileRecord.fileSize,
 					mimeType: fileRecord.mimeType,
 				});
 			}
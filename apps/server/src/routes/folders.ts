@@ -3,6 +3,7 @@ import { db, folder, folderKey, file, fileFolderKey, user, userKeypair, userSett
 import { eq, and, sql } from "@krypt-vault/db";
 import { z } from "zod";
 import { auth } from "@krypt-vault/auth";
+import { shareGrantFor } from "../lib/share-grants";
 
 const app = new Hono();
 
@@ -18,6 +19,14 @@ const shareFolderSchema = z.object({
 	folderId: z.string(),
 	recipientUserId: z.string(),
 	wrappedFolderKey: z.string(), // Folder key wrapped with recipient's public key
+	grant: z.string(), // Share grant signed by the sharer, with the folder ID as its file ID
+	grantSignature: z.string(), // Ed25519 signature over the grant
+	escrowWrappedKey: z.string().nullish(), // Folder key sealed to the sharer's escrow key, if they have one
+});
+
+const ownerGrantSchema = z.object({
+	grant: z.string(), // Grant the owner signed over their own wrappedFolderKey
+	grantSignature: z.string(),
 });
 
 const addFileToFolderSchema = z.object({
@@ -417,6 +426,9 @@ app.get("/:folderId", async (c) => {
 				ownerName: user.name,
 				ownerEmail: user.email,
 				wrappedFolderKey: folderKey.wrappedFolderKey,
+				sharedBy: folderKey.sharedBy,
+				grant: folderKey.grant,
+				grantSignature: folderKey.grantSignature,
 			})
 			.from(folderKey)
 			.innerJoin(folder, eq(folderKey.folderId, folder.id))
@@ -433,6 +445,9 @@ app.get("/:folderId", async (c) => {
 			return c.json({ error: "Folder not found or access denied" }, 404);
 		}
 		
+		const { sharedBy, grant, grantSignature, ...folderDetails } = folderAccess;
+		const keyGrant = await shareGrantFor({ sharedBy, grant, grantSignature });
+		
 		// Get files in folder (excluding deleted files)
 		const files = await db
 			.select({
@@ -457,7 +472,7 @@ app.get("/:folderId", async (c) => {
 			);
 		
 		return c.json({
-			folder: folderAccess,
+			folder: { ...folderDetails, ...keyGrant },
 			files,
 		});
 	} catch (error) {
@@ -525,6 +540,9 @@ app.post("/:folderId/share", async (c) => {
 			folderId,
 			recipientUserId: validated.recipientUserId,
 			wrappedFolderKey: validated.wrappedFolderKey,
+			grant: validated.grant,
+			grantSignature: validated.grantSignature,
+			escrowWrappedKey: validated.escrowWrappedKey ?? null,
 			sharedBy: sharingUserId,
 			createdAt: new Date(),
 		});
@@ -542,6 +560,44 @@ app.post("/:folderId/share", async (c) => {
 	}
 });
 
+// PUT /api/folders/:folderId/owner-grant - Store the grant the creator signed over their own folder key
+// The folder ID is only known once the folder exists, so this follows POST /api/folders
+app.put("/:folderId/owner-grant", async (c) => {
+	try {
+		const folderId = c.req.param("folderId");
+		const validated = ownerGrantSchema.parse(await c.req.json());
+		const userId = (c as any).get("userId") as string;
+
+		const updated = await db
+			.update(folderKey)
+			.set({
+				grant: validated.grant,
+				grantSignature: validated.grantSignature,
+			})
+			.where(
+				and(
+					eq(folderKey.folderId, folderId),
+					eq(folderKey.recipientUserId, userId),
+					eq(folderKey.sharedBy, userId),
+					sql`${folderKey.grant} IS NULL`
+				)
+			)
+			.returning({ id: folderKey.id });
+
+		if (updated.length === 0) {
+			return c.json({ error: "No unsigned folder key of your own to sign" }, 404);
+		}
+
+		return c.json({ success: true });
+	} catch (error) {
+		console.error("Store owner grant error:", error);
+		return c.json({
+			error: "Failed to store owner grant",
+			details: error instanceof Error ? error.message : String(error)
+		}, 500);
+	}
+});
+
 // DELETE /api/folders/:folderId/revoke - Revoke folder access
 app.delete("/:folderId/revoke", async (c) => {
 	try {
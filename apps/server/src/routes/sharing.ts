@@ -11,6 +11,9 @@ const shareFileSchema = z.object({
 	fileId: z.string(),
 	recipientUserId: z.string(),
 	wrappedDek: z.string(), // DEK wrapped with recipient's public key
+	grant: z.string(), // Share grant signed by the sharer (base64 JSON)
+	grantSignature: z.string(), // Ed25519 signature over the grant
+	escrowWrappedDek: z.string().nullish(), // DEK sealed to the sharer's escrow key, if they have one
 });
 
 const shareBulkSchema = z.object({
@@ -18,6 +21,9 @@ const shareBulkSchema = z.object({
 	recipients: z.array(z.object({
 		userId: z.string(),
 		wrappedDek: z.string(),
+		grant: z.string(),
+		grantSignature: z.string(),
+		escrowWrappedDek: z.string().nullish(),
 	})),
 });
 
@@ -107,6 +113,9 @@ app.post("/share", async (c) => {
 			fileId: validated.fileId,
 			recipientUserId: validated.recipientUserId,
 			wrappedDek: validated.wrappedDek,
+			grant: validated.grant,
+			grantSignature: validated.grantSignature,
+			escrowWrappedDek: validated.escrowWrappedDek ?? null,
 			sharedBy: sharingUserId,
 			createdAt: new Date(),
 		});
@@ -155,6 +164,9 @@ app.post("/share-bulk", async (c) => {
 			fileId: validated.fileId,
 			recipientUserId: recipient.userId,
 			wrappedDek: recipient.wrappedDek,
+			grant: recipient.grant,
+			grantSignature: recipient.grantSignature,
+			escrowWrappedDek: recipient.escrowWrappedDek ?? null,
 			sharedBy: sharingUserId,
 			createdAt: new Date(),
 		}));
@@ -0,0 +1,605 @@
+use crate::crypto::{decrypt_with_key, encrypt_with_key, UserKeypair};
+use crate::keyring::write_private_file;
+use anyhow::{Context, Result};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sodiumoxide::crypto::sign;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+const AUDIT_LOG_FILE: &str = "audit.jsonl";
+const DEVICE_KEY_FILE: &str = "device_key.json";
+
+// Domain separation for keys and signatures derived here
+const DEVICE_KEY_SEALING_INFO: &[u8] = b"kryptvault-audit-device-key-v1";
+const HEAD_CONTEXT: &[u8] = b"kryptvault-audit-head-v1";
+const KEY_ID_LABEL: &[u8] = b"kryptvault-key-id-v1";
+
+/// Crypto operations recorded in the audit log
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOperation {
+    Share,
+    Unwrap,
+    Decrypt,
+    KeyExport,
+    EscrowRecovery,
+}
+
+/// One line of the audit log
+/// `hash` covers every other field except `signature`, and `prev_hash`
+/// links each entry to the one before it (empty for the first entry)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub timestamp: u64, // Unix timestamp in seconds
+    pub operation: AuditOperation,
+    pub details: serde_json::Value,
+    pub prev_hash: String, // Base64 SHA-256
+    pub hash: String,      // Base64 SHA-256
+    pub signature: String, // Base64 Ed25519 signature over the hash
+}
+
+/// The part of an entry that is hashed
+#[derive(Serialize)]
+struct EntryBody<'a> {
+    seq: u64,
+    timestamp: u64,
+    operation: AuditOperation,
+    details: &'a serde_json::Value,
+    prev_hash: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditVerification {
+    pub valid: bool,
+    pub entries: usize,
+    pub first_invalid_seq: Option<u64>,
+    pub error: Option<String>,
+    pub head_verified: bool, // A signed head was found and the log reaches it
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditExport {
+    pub device_public_key: Option<String>, // Base64 Ed25519; none until the first entry
+    pub verification: AuditVerification,
+    pub entries: Vec<AuditEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DeviceKeyFile {
+    public_key: String, // Base64
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    private_key: Option<String>, // Base64, unprotected; only in files from older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sealed_private_key: Option<SealedDeviceKey>,
+}
+
+/// The device signing key encrypted under a key derived from the vault keypair
+#[derive(Serialize, Deserialize)]
+struct SealedDeviceKey {
+    ciphertext: String, // Base64
+    nonce: String,      // Base64
+}
+
+/// Entry count and last hash, signed with the device key and kept apart
+/// from the log so truncating the log can be detected
+#[derive(Serialize, Deserialize)]
+struct SignedHead {
+    entries: u64,
+    last_hash: String,
+    signature: String, // Base64 Ed25519 over `HEAD_CONTEXT`, `entries` and `last_hash`
+}
+
+struct ChainHead {
+    next_seq: u64,
+    last_hash: String,
+}
+
+/// Append-only, hash-chained log of crypto operations on this device
+/// Every entry is signed with a per-device Ed25519 key, which is stored
+/// sealed under the vault keypair, so entries can only be added while the
+/// vault is unlocked
+pub struct AuditLog {
+    path: PathBuf,
+    key_path: PathBuf,
+    head_path: PathBuf,
+    public_key: Mutex<Option<sign::PublicKey>>,
+    head: Mutex<ChainHead>,
+}
+
+impl AuditLog {
+    /// Open the log in `dir`, with its signed head at `head_path`
+    /// The device signing key is created on the first `record`
+    pub fn open(dir: &Path, head_path: PathBuf) -> Result<Self> {
+        sodiumoxide::init().map_err(|_| anyhow::anyhow!("Failed to initialize sodiumoxide"))?;
+        let key_path = dir.join(DEVICE_KEY_FILE);
+        let public_key = read_device_public_key(&key_path)?;
+
+        let path = dir.join(AUDIT_LOG_FILE);
+        let mut head = match last_entry(&path) {
+            Some(entry) => ChainHead {
+                next_seq: entry.seq + 1,
+                last_hash: entry.hash.clone(),
+            },
+            None => ChainHead {
+                next_seq: 0,
+                last_hash: String::new(),
+            },
+        };
+
+        // If the log was cut short, keep numbering from the signed head so the
+        // gap stays visible instead of being papered over by new entries
+        if let (Some(public_key), Some(signed)) = (&public_key, read_signed_head(&head_path)) {
+            if signed.entries > head.next_seq && verify_head(&signed, public_key).is_ok() {
+                head = ChainHead {
+                    next_seq: signed.entries,
+                    last_hash: signed.last_hash,
+                };
+            }
+        }
+
+        Ok(AuditLog {
+            path,
+            key_path,
+            head_path,
+            public_key: Mutex::new(public_key),
+            head: Mutex::new(head),
+        })
+    }
+
+    pub fn device_public_key(&self) -> Option<String> {
+        self.public_key.lock().unwrap().map(|key| base64::encode(key.as_ref()))
+    }
+
+    /// Where the signed head is kept, so it can be removed with the profile
+    pub fn head_path(&self) -> &Path {
+        &self.head_path
+    }
+
+    /// Append a signed entry to the log
+    /// `keypair` is the unlocked vault keypair, which opens the device key
+    pub fn record(&self, keypair: &UserKeypair, operation: AuditOperation, details: serde_json::Value) -> Result<()> {
+        let mut head = self.head.lock().unwrap();
+        let (public_key, secret_key) = load_or_create_device_key(&self.key_path, keypair)?;
+        *self.public_key.lock().unwrap() = Some(public_key);
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let body = EntryBody {
+            seq: head.next_seq,
+            timestamp,
+            operation,
+            details: &details,
+            prev_hash: &head.last_hash,
+        };
+        let hash = hash_body(&body)?;
+        let signature = sign::sign_detached(&hash, &secret_key);
+
+        let entry = AuditEntry {
+            seq: body.seq,
+            timestamp,
+            operation,
+            prev_hash: head.last_hash.clone(),
+            hash: base64::encode(hash),
+            signature: base64::encode(signature.to_bytes()),
+            details,
+        };
+
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context("Failed to open audit log")?;
+        file.write_all(line.as_bytes()).context("Failed to write audit log")?;
+
+        head.next_seq += 1;
+        head.last_hash = entry.hash;
+        write_signed_head(&self.head_path, &head, &secret_key)
+    }
+
+    pub fn entries(&self) -> Result<Vec<AuditEntry>> {
+        read_entries(&self.path)
+    }
+
+    /// Recompute every hash, check the chain links and the signatures, and
+    /// check the log still reaches its signed head
+    pub fn verify(&self) -> Result<AuditVerification> {
+        let entries = self.entries()?;
+        Ok(self.verify_entries(&entries))
+    }
+
+    /// All entries together with the device key and a verification report
+    pub fn export(&self) -> Result<AuditExport> {
+        let entries = self.entries()?;
+        Ok(AuditExport {
+            device_public_key: self.device_public_key(),
+            verification: self.verify_entries(&entries),
+            entries,
+        })
+    }
+
+    fn verify_entries(&self, entries: &[AuditEntry]) -> AuditVerification {
+        let Some(public_key) = *self.public_key.lock().unwrap() else {
+            return AuditVerification {
+                valid: entries.is_empty(),
+                entries: entries.len(),
+                first_invalid_seq: entries.first().map(|entry| entry.seq),
+                error: (!entries.is_empty()).then(|| "No device key to verify entries with".to_string()),
+                head_verified: false,
+            };
+        };
+
+        let mut verification = verify_entries(entries, &public_key);
+        if verification.valid {
+            if let Some(signed) = read_signed_head(&self.head_path) {
+                match check_head(entries, &signed, &public_key) {
+                    Ok(()) => verification.head_verified = true,
+                    Err(e) => {
+                        verification.valid = false;
+                        verification.first_invalid_seq = Some(entries.len() as u64);
+                        verification.error = Some(format!("{:#}", e));
+                    }
+                }
+            }
+        }
+        verification
+    }
+}
+
+/// Identifier for a key in audit entries: an HMAC of a fixed label keyed
+/// by the key, so every wrap of the same key gets the same ID and the key
+/// itself is never hashed
+pub fn key_id(key: &[u8]) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(KEY_ID_LABEL);
+    base64::encode(&mac.finalize().into_bytes()[..12])
+}
+
+/// Short identifier for public data such as a public key
+pub fn fingerprint(data: &str) -> String {
+    base64::encode(&Sha256::digest(data.as_bytes())[..12])
+}
+
+fn verify_entries(entries: &[AuditEntry], public_key: &sign::PublicKey) -> AuditVerification {
+    let mut expected_prev = String::new();
+
+    for (index, entry) in entries.iter().enumerate() {
+        if let Err(e) = verify_entry(entry, index as u64, &expected_prev, public_key) {
+            return AuditVerification {
+                valid: false,
+                entries: entries.len(),
+                first_invalid_seq: Some(entry.seq),
+                error: Some(format!("{:#}", e)),
+                head_verified: false,
+            };
+        }
+        expected_prev = entry.hash.clone();
+    }
+
+    AuditVerification {
+        valid: true,
+        entries: entries.len(),
+        first_invalid_seq: None,
+        error: None,
+        head_verified: false,
+    }
+}
+
+/// The log must hold at least as many entries as the signed head records,
+/// with the head's last hash where it says. A crash between appending and
+/// updating the head can leave the log one entry ahead, which is fine
+fn check_head(entries: &[AuditEntry], signed: &SignedHead, public_key: &sign::PublicKey) -> Result<()> {
+    verify_head(signed, public_key)?;
+
+    if (entries.len() as u64) < signed.entries {
+        return Err(anyhow::anyhow!(
+            "Log has {} entries but its signed head records {}",
+            entries.len(),
+            signed.entries
+        ));
+    }
+    if let Some(index) = signed.entries.checked_sub(1) {
+        if entries[index as usize].hash != signed.last_hash {
+            return Err(anyhow::anyhow!("Entry {} does not match the signed head", index));
+        }
+    }
+    Ok(())
+}
+
+fn head_message(entries: u64, last_hash: &str) -> Vec<u8> {
+    [HEAD_CONTEXT, &entries.to_be_bytes(), last_hash.as_bytes()].concat()
+}
+
+fn verify_head(signed: &SignedHead, public_key: &sign::PublicKey) -> Result<()> {
+    let signature_bytes = base64::decode(&signed.signature).context("Failed to decode head signature")?;
+    let signature = sign::Signature::from_bytes(&signature_bytes)
+        .map_err(|_| anyhow::anyhow!("Invalid head signature size"))?;
+    if !sign::verify_detached(&signature, &head_message(signed.entries, &signed.last_hash), public_key) {
+        return Err(anyhow::anyhow!("Signed head verification failed"));
+    }
+    Ok(())
+}
+
+fn read_signed_head(path: &Path) -> Option<SignedHead> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Replace the signed head atomically, so a crash never leaves it unreadable
+fn write_signed_head(path: &Path, head: &ChainHead, secret_key: &sign::SecretKey) -> Result<()> {
+    let signature = sign::sign_detached(&head_message(head.next_seq, &head.last_hash), secret_key);
+    let signed = SignedHead {
+        entries: head.next_seq,
+        last_hash: head.last_hash.clone(),
+        signature: base64::encode(signature.to_bytes()),
+    };
+
+    let tmp_path = path.with_extension("json.tmp");
+    write_private_file(&tmp_path, serde_json::to_string_pretty(&signed)?.as_bytes())?;
+    std::fs::rename(&tmp_path, path).context("Failed to write signed audit head")
+}
+
+fn verify_entry(
+    entry: &AuditEntry,
+    expected_seq: u64,
+    expected_prev: &str,
+    public_key: &sign::PublicKey,
+) -> Result<()> {
+    if entry.seq != expected_seq {
+        return Err(anyhow::anyhow!("Expected sequence {}, found {}", expected_seq, entry.seq));
+    }
+    if entry.prev_hash != expected_prev {
+        return Err(anyhow::anyhow!("Chain broken: previous hash does not match"));
+    }
+
+    let hash = hash_body(&EntryBody {
+        seq: entry.seq,
+        timestamp: entry.timestamp,
+        operation: entry.operation,
+        details: &entry.details,
+        prev_hash: &entry.prev_hash,
+    })?;
+    if base64::encode(hash) != entry.hash {
+        return Err(anyhow::anyhow!("Entry hash does not match its contents"));
+    }
+
+    let signature_bytes = base64::decode(&entry.signature).context("Failed to decode signature")?;
+    let signature = sign::Signature::from_bytes(&signature_bytes)
+        .map_err(|_| anyhow::anyhow!("Invalid signature size"))?;
+    if !sign::verify_detached(&signature, &hash, public_key) {
+        return Err(anyhow::anyhow!("Signature verification failed"));
+    }
+
+    Ok(())
+}
+
+fn hash_body(body: &EntryBody) -> Result<[u8; 32]> {
+    let encoded = serde_json::to_vec(body)?;
+    Ok(Sha256::digest(&encoded).into())
+}
+
+fn read_entries(path: &Path) -> Result<Vec<AuditEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let contents = std::fs::read_to_string(path).context("Failed to read audit log")?;
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Failed to parse audit log line {}", index + 1))
+        })
+        .collect()
+}
+
+/// The last well-formed entry, so a damaged log never stops the app from
+/// starting; `verify` still reports the damage
+fn last_entry(path: &Path) -> Option<AuditEntry> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    contents
+        .lines()
+        .rev()
+        .find_map(|line| serde_json::from_str(line).ok())
+}
+
+fn read_device_key_file(path: &Path) -> Result<Option<DeviceKeyFile>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = std::fs::read_to_string(path).context("Failed to read device key")?;
+    serde_json::from_str(&contents).context("Failed to parse device key").map(Some)
+}
+
+fn read_device_public_key(path: &Path) -> Result<Option<sign::PublicKey>> {
+    let Some(key_file) = read_device_key_file(path)? else {
+        return Ok(None);
+    };
+    let pk_bytes = base64::decode(&key_file.public_key).context("Failed to decode device public key")?;
+    sign::PublicKey::from_slice(&pk_bytes).context("Invalid device public key").map(Some)
+}
+
+/// Key that seals the device signing key, derived from the vault's Ed25519
+/// private key so the signing key is only usable while the vault is unlocked
+fn device_sealing_key(keypair: &UserKeypair) -> Result<Zeroizing<[u8; 32]>> {
+    let ikm = Zeroizing::new(base64::decode(&keypair.ed25519_private_key).context("Failed to decode Ed25519 private key")?);
+    let mut key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(None, &ikm)
+        .expand(DEVICE_KEY_SEALING_INFO, key.as_mut())
+        .map_err(|_| anyhow::anyhow!("Failed to derive device key sealing key"))?;
+    Ok(key)
+}
+
+fn write_sealed_device_key(
+    path: &Path,
+    public_key: &sign::PublicKey,
+    secret_key: &sign::SecretKey,
+    sealing_key: &[u8; 32],
+) -> Result<()> {
+    let (ciphertext, nonce) = encrypt_with_key(secret_key.as_ref(), sealing_key)?;
+    let key_file = DeviceKeyFile {
+        public_key: base64::encode(public_key.as_ref()),
+        private_key: None,
+        sealed_private_key: Some(SealedDeviceKey { ciphertext, nonce }),
+    };
+    write_private_file(path, serde_json::to_string_pretty(&key_file)?.as_bytes())
+}
+
+/// Open the device signing key with the vault keypair, creating it on first
+/// use; an unprotected key from an older version is sealed in place
+fn load_or_create_device_key(path: &Path, keypair: &UserKeypair) -> Result<(sign::PublicKey, sign::SecretKey)> {
+    let sealing_key = device_sealing_key(keypair)?;
+
+    let Some(key_file) = read_device_key_file(path)? else {
+        let (public_key, secret_key) = sign::gen_keypair();
+        write_sealed_device_key(path, &public_key, &secret_key, &sealing_key)?;
+        return Ok((public_key, secret_key));
+    };
+
+    let pk_bytes = base64::decode(&key_file.public_key).context("Failed to decode device public key")?;
+    let public_key = sign::PublicKey::from_slice(&pk_bytes).context("Invalid device public key")?;
+
+    let sk_bytes = Zeroizing::new(match (&key_file.sealed_private_key, &key_file.private_key) {
+        (Some(sealed), _) => decrypt_with_key(&sealed.ciphertext, &sealed.nonce, &sealing_key)
+            .context("Failed to open device key with this vault keypair")?,
+        (None, Some(private_key)) => base64::decode(private_key).context("Failed to decode device private key")?,
+        (None, None) => return Err(anyhow::anyhow!("Device key file has no private key")),
+    });
+    let secret_key = sign::SecretKey::from_slice(&sk_bytes).context("Invalid device private key")?;
+    if secret_key.public_key() != public_key {
+        return Err(anyhow::anyhow!("Device private key does not match its public key"));
+    }
+
+    if key_file.sealed_private_key.is_none() {
+        write_sealed_device_key(path, &public_key, &secret_key, &sealing_key)?;
+    }
+    Ok((public_key, secret_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_user_keypair;
+    use serde_json::json;
+
+    fn open_log(dir: &Path) -> AuditLog {
+        AuditLog::open(dir, dir.join("audit_head.json")).unwrap()
+    }
+
+    fn record_three(log: &AuditLog, keypair: &UserKeypair) {
+        for file_id in ["a", "b", "c"] {
+            log.record(keypair, AuditOperation::Decrypt, json!({ "file_id": file_id })).unwrap();
+        }
+    }
+
+    fn rewrite_log(dir: &Path, edit: impl FnOnce(&mut Vec<String>)) {
+        let path = dir.join(AUDIT_LOG_FILE);
+        let mut lines: Vec<String> = std::fs::read_to_string(&path).unwrap().lines().map(str::to_string).collect();
+        edit(&mut lines);
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+    }
+
+    #[test]
+    fn test_clean_chain_verifies() {
+        let dir = tempfile::tempdir().unwrap();
+        let keypair = generate_user_keypair().unwrap();
+        let log = open_log(dir.path());
+        record_three(&log, &keypair);
+
+        let verification = log.verify().unwrap();
+        assert!(verification.valid, "{:?}", verification.error);
+        assert!(verification.head_verified);
+        assert_eq!(verification.entries, 3);
+
+        // Numbering carries on after a restart
+        let log = open_log(dir.path());
+        log.record(&keypair, AuditOperation::Share, json!({})).unwrap();
+        assert_eq!(log.entries().unwrap().last().unwrap().seq, 3);
+        assert!(log.verify().unwrap().valid);
+    }
+
+    #[test]
+    fn test_edited_details_fail_at_that_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let keypair = generate_user_keypair().unwrap();
+        let log = open_log(dir.path());
+        record_three(&log, &keypair);
+
+        rewrite_log(dir.path(), |lines| {
+            let mut entry: AuditEntry = serde_json::from_str(&lines[1]).unwrap();
+            entry.details = json!({ "file_id": "z" });
+            lines[1] = serde_json::to_string(&entry).unwrap();
+        });
+
+        let verification = log.verify().unwrap();
+        assert!(!verification.valid);
+        assert_eq!(verification.first_invalid_seq, Some(1));
+    }
+
+    #[test]
+    fn test_truncated_log_fails_against_signed_head() {
+        let dir = tempfile::tempdir().unwrap();
+        let keypair = generate_user_keypair().unwrap();
+        let log = open_log(dir.path());
+        record_three(&log, &keypair);
+
+        rewrite_log(dir.path(), |lines| {
+            lines.pop();
+        });
+
+        let verification = log.verify().unwrap();
+        assert!(!verification.valid);
+        assert!(!verification.head_verified);
+        assert_eq!(verification.first_invalid_seq, Some(2));
+
+        // Reopening keeps the gap instead of reusing the missing sequence number
+        let log = open_log(dir.path());
+        log.record(&keypair, AuditOperation::Share, json!({})).unwrap();
+        assert_eq!(log.entries().unwrap().last().unwrap().seq, 3);
+        assert!(!log.verify().unwrap().valid);
+    }
+
+    #[test]
+    fn test_legacy_device_key_is_sealed_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let keypair = generate_user_keypair().unwrap();
+        sodiumoxide::init().unwrap();
+        let (public_key, secret_key) = sign::gen_keypair();
+
+        let key_path = dir.path().join(DEVICE_KEY_FILE);
+        let legacy = DeviceKeyFile {
+            public_key: base64::encode(public_key.as_ref()),
+            private_key: Some(base64::encode(secret_key.as_ref())),
+            sealed_private_key: None,
+        };
+        std::fs::write(&key_path, serde_json::to_string(&legacy).unwrap()).unwrap();
+
+        let log = open_log(dir.path());
+        log.record(&keypair, AuditOperation::Unwrap, json!({})).unwrap();
+
+        let key_file = read_device_key_file(&key_path).unwrap().unwrap();
+        assert!(key_file.private_key.is_none());
+        assert!(key_file.sealed_private_key.is_some());
+        assert_eq!(key_file.public_key, legacy.public_key);
+        assert_eq!(log.device_public_key(), Some(legacy.public_key));
+        assert!(log.verify().unwrap().valid);
+
+        // The sealed key only opens with the vault keypair that sealed it
+        let other = generate_user_keypair().unwrap();
+        assert!(log.record(&other, AuditOperation::Unwrap, json!({})).is_err());
+    }
+}
@@ -0,0 +1,397 @@
+//! Headless KryptVault client for scripting and CI machines
+//! Shares the crypto and S3 code with the desktop app
+
+use anyhow::{Context, Result};
+use app_lib::crypto::{
+    decrypt_file_with_dek, decrypt_with_key, encrypt_file, encrypt_with_key, generate_user_keypair,
+    unwrap_dek_for_user, wrap_dek_for_recipient, UserKeypair,
+};
+use app_lib::device_link::{run_relay, DEFAULT_RELAY_ADDR};
+use app_lib::keyring::{keyring_path, passphrase_from_env, resolve_keyring, save_protected_keyring};
+use app_lib::s3::{download_from_s3, upload_to_s3};
+use clap::{Parser, Subcommand};
+use rand::RngCore;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+#[derive(Parser)]
+#[command(name = "kryptvault", version, about = "Headless KryptVault client")]
+struct Cli {
+    /// Print machine-readable JSON instead of text
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Keyring file (defaults to $KRYPTVAULT_KEYRING or the config directory)
+    #[arg(long, global = true)]
+    keyring: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a new user keypair and write it to the keyring file,
+    /// protected by a passphrase ($KRYPTVAULT_PASSPHRASE or prompted)
+    Keygen {
+        /// Overwrite an existing keyring file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Encrypt a file locally
+    Encrypt {
+        input: PathBuf,
+        output: PathBuf,
+        /// X25519 public key to wrap the DEK for (defaults to your own)
+        #[arg(long)]
+        recipient_key: Option<String>,
+    },
+    /// Decrypt a file locally
+    Decrypt {
+        input: PathBuf,
+        output: PathBuf,
+        #[command(flatten)]
+        key: DekArgs,
+    },
+    /// Encrypt a file and upload it with a presigned PUT URL
+    Upload {
+        input: PathBuf,
+        #[arg(long)]
+        presigned_url: String,
+        #[arg(long)]
+        file_key: String,
+        /// X25519 public key to wrap the DEK for (defaults to your own)
+        #[arg(long)]
+        recipient_key: Option<String>,
+        /// The URL was presigned with the ciphertext's x-amz-checksum-sha256, so send it
+        #[arg(long)]
+        checksum_signed: bool,
+    },
+    /// Download a file with a presigned GET URL and decrypt it
+    Download {
+        output: PathBuf,
+        #[arg(long)]
+        url: String,
+        #[command(flatten)]
+        key: DekArgs,
+    },
+    /// Re-wrap one of your DEKs for another user
+    Share {
+        #[arg(long)]
+        wrapped_dek: String,
+        #[arg(long)]
+        recipient_key: String,
+    },
+    /// List files visible to your account on the server
+    Ls {
+        #[arg(long, env = "KRYPTVAULT_SERVER_URL", default_value = "http://localhost:3000")]
+        server: String,
+        /// Session token, sent as a bearer token
+        #[arg(long, env = "KRYPTVAULT_SESSION_TOKEN", hide_env_values = true)]
+        token: String,
+    },
+    /// Generate folder keys and wrap or unwrap DEKs with them
+    FolderKey {
+        #[command(subcommand)]
+        command: FolderKeyCommand,
+    },
+    /// Run a relay that lets two devices link (it only forwards ciphertext)
+    Relay {
+        #[arg(long, default_value = DEFAULT_RELAY_ADDR)]
+        listen: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum FolderKeyCommand {
+    /// Generate a random 256-bit folder key
+    Generate,
+    /// Wrap a DEK with a folder key
+    Wrap {
+        #[arg(long, env = "KRYPTVAULT_DEK", hide_env_values = true)]
+        dek: String,
+        #[arg(long, env = "KRYPTVAULT_FOLDER_KEY", hide_env_values = true)]
+        folder_key: String,
+    },
+    /// Unwrap a DEK with a folder key
+    Unwrap {
+        #[arg(long)]
+        wrapped_dek: String,
+        #[arg(long)]
+        wrapping_nonce: String,
+        #[arg(long, env = "KRYPTVAULT_FOLDER_KEY", hide_env_values = true)]
+        folder_key: String,
+    },
+}
+
+/// How to obtain a file's DEK: directly, or by unwrapping it with the keyring
+#[derive(clap::Args)]
+struct DekArgs {
+    /// Base64 nonce of the encrypted file
+    #[arg(long)]
+    nonce: String,
+    /// DEK wrapped for your X25519 key
+    #[arg(long, required_unless_present = "dek", conflicts_with = "dek")]
+    wrapped_dek: Option<String>,
+    /// Already unwrapped DEK in base64
+    #[arg(long, env = "KRYPTVAULT_DEK", hide_env_values = true)]
+    dek: Option<String>,
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let json = cli.json;
+
+    match run(cli).await {
+        Ok(output) => print_output(&output, json),
+        Err(e) => {
+            if json {
+                eprintln!("{}", json!({ "error": format!("{:#}", e) }));
+            } else {
+                eprintln!("Error: {:#}", e);
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<Value> {
+    let keyring = cli.keyring.as_deref();
+
+    match cli.command {
+        Command::Keygen { force } => {
+            let path = keyring_path(keyring)?;
+            if path.exists() && !force {
+                return Err(anyhow::anyhow!(
+                    "Keyring already exists at {} (use --force to overwrite)",
+                    path.display()
+                ));
+            }
+
+            let passphrase = new_passphrase()?;
+            let keypair = generate_user_keypair()?;
+            save_protected_keyring(&path, &keypair, &passphrase)?;
+
+            Ok(json!({
+                "keyring_path": path,
+                "x25519_public_key": keypair.x25519_public_key,
+                "ed25519_public_key": keypair.ed25519_public_key,
+            }))
+        }
+        Command::Encrypt { input, output, recipient_key } => {
+            let recipient_key = recipient_or_self(recipient_key, keyring)?;
+            let result = encrypt_file(path_str(&input)?, path_str(&output)?, &recipient_key)?;
+            Ok(serde_json::to_value(result)?)
+        }
+        Command::Decrypt { input, output, key } => {
+            let dek = resolve_dek(&key, keyring)?;
+            let output_path = decrypt_file_with_dek(path_str(&input)?, &dek, &key.nonce, path_str(&output)?)?;
+            Ok(json!({ "output_path": output_path }))
+        }
+        Command::Upload { input, presigned_url, file_key, recipient_key, checksum_signed } => {
+            let recipient_key = recipient_or_self(recipient_key, keyring)?;
+            let encrypted_path = temp_path();
+            let encrypted_str = path_str(&encrypted_path)?;
+
+            // Always remove the temp ciphertext, whether or not the upload worked
+            let result = async {
+                let encryption_result = encrypt_file(path_str(&input)?, encrypted_str, &recipient_key)?;
+                let upload_result = upload_to_s3(
+                    encrypted_str,
+                    &presigned_url,
+                    &file_key,
+                    checksum_signed.then_some(encryption_result.ciphertext_sha256.as_str()),
+                )
+                .await?;
+                Ok::<_, anyhow::Error>((encryption_result, upload_result))
+            }
+            .await;
+            let _ = std::fs::remove_file(&encrypted_path);
+            let (encryption_result, upload_result) = result?;
+
+            Ok(json!({
+                "success": upload_result.success,
+                "file_key": upload_result.file_key,
+                "wrapped_dek": encryption_result.wrapped_dek,
+                "nonce": encryption_result.nonce,
+                "file_size": encryption_result.file_size,
+                "original_filename": encryption_result.original_filename,
+                "ciphertext_sha256": encryption_result.ciphertext_sha256,
+                "bucket": upload_result.bucket,
+                "etag": upload_result.etag,
+                "checksum_verified": upload_result.checksum_verified,
+            }))
+        }
+        Command::Download { output, url, key } => {
+            let dek = resolve_dek(&key, keyring)?;
+            let encrypted_path = temp_path();
+            let encrypted_str = path_str(&encrypted_path)?;
+
+            let result = async {
+                download_from_s3(&url, encrypted_str).await?;
+                decrypt_file_with_dek(encrypted_str, &dek, &key.nonce, path_str(&output)?)
+            }
+            .await;
+            let _ = std::fs::remove_file(&encrypted_path);
+
+            Ok(json!({ "output_path": result? }))
+        }
+        Command::Share { wrapped_dek, recipient_key } => {
+            let keypair = resolve_keyring(keyring, passphrase)?;
+            let dek = unwrap_dek_for_user(&wrapped_dek, &keypair.x25519_public_key, &keypair.x25519_private_key)?;
+            let wrapped_for_recipient = wrap_dek_for_recipient(&dek, &recipient_key)?;
+            Ok(json!({ "wrapped_dek": wrapped_for_recipient }))
+        }
+        Command::Ls { server, token } => {
+            let response = reqwest::Client::new()
+                .get(format!("{}/api/files", server.trim_end_matches('/')))
+                .bearer_auth(token)
+                .send()
+                .await
+                .context("Failed to reach server")?;
+
+            if !response.status().is_success() {
+                return Err(anyhow::anyhow!("Listing files failed with status: {}", response.status()));
+            }
+
+            let body: Value = response.json().await.context("Failed to parse file listing")?;
+            Ok(body)
+        }
+        Command::FolderKey { command } => run_folder_key(command),
+        Command::Relay { listen } => {
+            let listener = tokio::net::TcpListener::bind(&listen)
+                .await
+                .with_context(|| format!("Failed to listen on {}", listen))?;
+            eprintln!("Relay listening on {}", listener.local_addr()?);
+            run_relay(listener).await?;
+            Ok(Value::Null)
+        }
+    }
+}
+
+fn run_folder_key(command: FolderKeyCommand) -> Result<Value> {
+    match command {
+        FolderKeyCommand::Generate => {
+            let mut folder_key = [0u8; 32];
+            rand::rngs::OsRng.fill_bytes(&mut folder_key);
+            Ok(json!({ "folder_key": base64::encode(folder_key) }))
+        }
+        FolderKeyCommand::Wrap { dek, folder_key } => {
+            let dek = base64::decode(&dek).context("Failed to decode DEK")?;
+            let folder_key = decode_key(&folder_key)?;
+            let (wrapped_dek, wrapping_nonce) = encrypt_with_key(&dek, &folder_key)?;
+            Ok(json!({ "wrapped_dek": wrapped_dek, "wrapping_nonce": wrapping_nonce }))
+        }
+        FolderKeyCommand::Unwrap { wrapped_dek, wrapping_nonce, folder_key } => {
+            let folder_key = decode_key(&folder_key)?;
+            let dek = decrypt_with_key(&wrapped_dek, &wrapping_nonce, &folder_key)?;
+            Ok(json!({ "dek": base64::encode(dek) }))
+        }
+    }
+}
+
+/// Use the given recipient key, or fall back to the keyring's own public key
+fn recipient_or_self(recipient_key: Option<String>, keyring: Option<&Path>) -> Result<String> {
+    match recipient_key {
+        Some(key) => Ok(key),
+        None => Ok(resolve_keyring(keyring, passphrase)?.x25519_public_key),
+    }
+}
+
+/// Return the DEK in base64, unwrapping it with the keyring if needed
+fn resolve_dek(args: &DekArgs, keyring: Option<&Path>) -> Result<String> {
+    if let Some(dek) = &args.dek {
+        return Ok(dek.clone());
+    }
+
+    let wrapped_dek = args.wrapped_dek.as_deref().context("Either --dek or --wrapped-dek is required")?;
+    let UserKeypair { x25519_public_key, x25519_private_key, .. } = resolve_keyring(keyring, passphrase)?;
+    let dek = unwrap_dek_for_user(wrapped_dek, &x25519_public_key, &x25519_private_key)?;
+    Ok(base64::encode(dek))
+}
+
+/// Keyring passphrase from `KRYPTVAULT_PASSPHRASE`, or prompted for
+fn passphrase() -> Result<Zeroizing<String>> {
+    if let Some(passphrase) = passphrase_from_env() {
+        return Ok(passphrase);
+    }
+    rpassword::prompt_password("Keyring passphrase: ")
+        .map(Zeroizing::new)
+        .context("Failed to read passphrase")
+}
+
+/// Passphrase for a new keyring; a prompted one has to be entered twice
+fn new_passphrase() -> Result<Zeroizing<String>> {
+    let passphrase = match passphrase_from_env() {
+        Some(passphrase) => passphrase,
+        None => {
+            let passphrase = passphrase()?;
+            let repeated = rpassword::prompt_password("Repeat passphrase: ")
+                .map(Zeroizing::new)
+                .context("Failed to read passphrase")?;
+            if passphrase != repeated {
+                return Err(anyhow::anyhow!("Passphrases do not match"));
+            }
+            passphrase
+        }
+    };
+
+    if passphrase.is_empty() {
+        return Err(anyhow::anyhow!("Keyring passphrase must not be empty"));
+    }
+    Ok(passphrase)
+}
+
+fn decode_key(key_b64: &str) -> Result<[u8; 32]> {
+    let key_vec = base64::decode(key_b64).context("Failed to decode folder key")?;
+    key_vec
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Invalid folder key size"))
+}
+
+fn temp_path() -> PathBuf {
+    std::env::temp_dir().join(format!("kryptvault-cli-{}.enc", uuid::Uuid::new_v4()))
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .with_context(|| format!("Path is not valid UTF-8: {}", path.display()))
+}
+
+/// Print a result as JSON, or as `key: value` lines for humans
+fn print_output(output: &Value, json: bool) {
+    if json {
+        println!("{}", output);
+        return;
+    }
+
+    match output {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                match value {
+                    Value::String(s) => println!("{}: {}", key, s),
+                    Value::Array(items) => {
+                        println!("{}:", key);
+                        for item in items {
+                            println!("  {}", summarize(item));
+                        }
+                    }
+                    other => println!("{}: {}", key, other),
+                }
+            }
+        }
+        other => println!("{}", other),
+    }
+}
+
+/// One-line summary of a listed file, falling back to raw JSON
+fn summarize(item: &Value) -> String {
+    match (item.get("fileId"), item.get("fileSize"), item.get("originalFilename")) {
+        (Some(Value::String(id)), Some(size), Some(Value::String(name))) => {
+            format!("{}  {:>12}  {}", id, size, name)
+        }
+        _ => item.to_string(),
+    }
+}
@@ -0,0 +1,384 @@
+//! Content-defined chunking for large files
+//!
+//! Files are cut with FastCDC so an edit only changes the chunks around it.
+//! Chunk IDs are HMACs of the plaintext under a key the server never sees,
+//! so it can deduplicate chunks without being able to confirm guessed
+//! content. A manifest, encrypted under the same chunk key and bound to the
+//! file ID and version, lists the chunks in order so the file can be rebuilt.
+
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use fastcdc::v2020::StreamCDC;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fs::File;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+// Chunk size bounds; small edits only touch one or two ~1 MiB chunks
+const MIN_CHUNK_SIZE: u32 = 256 * 1024;
+const AVG_CHUNK_SIZE: u32 = 1024 * 1024;
+const MAX_CHUNK_SIZE: u32 = 4 * 1024 * 1024;
+const NONCE_SIZE: usize = 24;
+const TAG_SIZE: u64 = 16;
+
+/// One chunk of a file, in order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkRef {
+    pub id: String, // Base64url HMAC-SHA256 of the plaintext
+    pub length: u64,
+}
+
+/// Everything needed to rebuild a chunked file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkManifest {
+    pub original_filename: String,
+    pub file_size: u64,
+    pub chunks: Vec<ChunkRef>,
+}
+
+/// Part of one chunk that falls inside a requested byte range
+#[derive(Debug, Clone)]
+pub struct ChunkSpan {
+    pub id: String,
+    pub length: u64,        // Plaintext length of the whole chunk
+    pub packed_offset: u64, // Where the sealed chunk starts when chunks are stored back to back
+    pub start: u64,         // First wanted byte within the chunk's plaintext
+    pub end: u64,           // One past the last wanted byte
+}
+
+impl ChunkSpan {
+    /// Size of the sealed chunk (`nonce || ciphertext || tag`)
+    pub fn sealed_length(&self) -> u64 {
+        sealed_length(self.length)
+    }
+}
+
+fn sealed_length(length: u64) -> u64 {
+    NONCE_SIZE as u64 + length + TAG_SIZE
+}
+
+impl ChunkManifest {
+    /// The chunks covering plaintext bytes `offset..offset + len`, in order
+    /// Only these need fetching; every other chunk can stay on the server
+    pub fn locate_range(&self, offset: u64, len: u64) -> Result<Vec<ChunkSpan>> {
+        let end = offset
+            .checked_add(len)
+            .filter(|end| *end <= self.file_size)
+            .with_context(|| format!("Range {}+{} is past the end of the file ({} bytes)", offset, len, self.file_size))?;
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut spans = Vec::new();
+        let mut chunk_start = 0;
+        let mut packed_offset = 0;
+        for chunk in &self.chunks {
+            let chunk_end = chunk_start + chunk.length;
+            if chunk_end > offset && chunk_start < end {
+                spans.push(ChunkSpan {
+                    id: chunk.id.clone(),
+                    length: chunk.length,
+                    packed_offset,
+                    start: offset.saturating_sub(chunk_start),
+                    end: end.min(chunk_end) - chunk_start,
+                });
+            }
+            if chunk_end >= end {
+                break;
+            }
+            chunk_start = chunk_end;
+            packed_offset += sealed_length(chunk.length);
+        }
+        Ok(spans)
+    }
+}
+
+/// What the server stores in place of the file's ciphertext
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedChunkManifest {
+    pub ciphertext: String, // Base64
+    pub nonce: String,      // Base64
+}
+
+/// A plaintext chunk cut from a file
+pub struct Chunk {
+    pub id: String,
+    pub data: Zeroizing<Vec<u8>>,
+}
+
+/// Keys derived from a chunk key (per file, or per user to deduplicate
+/// across files)
+pub struct ChunkKeys {
+    id_key: Zeroizing<[u8; 32]>,
+    encryption_key: Zeroizing<[u8; 32]>,
+}
+
+impl ChunkKeys {
+    pub fn derive(chunk_key: &[u8; 32]) -> Result<Self> {
+        let hkdf = Hkdf::<Sha256>::new(None, chunk_key);
+        let mut id_key = Zeroizing::new([0u8; 32]);
+        let mut encryption_key = Zeroizing::new([0u8; 32]);
+        hkdf.expand(b"kryptvault chunk id", id_key.as_mut())
+            .map_err(|_| anyhow::anyhow!("Failed to derive chunk ID key"))?;
+        hkdf.expand(b"kryptvault chunk encryption", encryption_key.as_mut())
+            .map_err(|_| anyhow::anyhow!("Failed to derive chunk encryption key"))?;
+
+        Ok(ChunkKeys { id_key, encryption_key })
+    }
+
+    /// Keyed hash of a chunk's plaintext
+    pub fn chunk_id(&self, data: &[u8]) -> String {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(self.id_key.as_ref())
+            .expect("HMAC accepts any key size");
+        mac.update(data);
+        URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    }
+
+    /// Encrypt a chunk as `nonce || ciphertext`, bound to its ID
+    pub fn encrypt_chunk(&self, chunk: &Chunk) -> Result<Vec<u8>> {
+        let cipher = XChaCha20Poly1305::new(self.encryption_key.as_ref().into());
+        let mut nonce_bytes = [0u8; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce_bytes);
+
+        let ciphertext = cipher
+            .encrypt(
+                XNonce::from_slice(&nonce_bytes),
+                Payload { msg: &chunk.data, aad: chunk.id.as_bytes() },
+            )
+            .map_err(|e| anyhow::anyhow!("Chunk encryption failed: {}", e))?;
+
+        let mut sealed = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        sealed.extend_from_slice(&nonce_bytes);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypt a chunk and check its plaintext still hashes to `id`
+    pub fn decrypt_chunk(&self, id: &str, sealed: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        if sealed.len() < NONCE_SIZE {
+            return Err(anyhow::anyhow!("Chunk {} is truncated", id));
+        }
+        let (nonce_bytes, ciphertext) = sealed.split_at(NONCE_SIZE);
+
+        let cipher = XChaCha20Poly1305::new(self.encryption_key.as_ref().into());
+        let data = Zeroizing::new(
+            cipher
+                .decrypt(XNonce::from_slice(nonce_bytes), Payload { msg: ciphertext, aad: id.as_bytes() })
+                .map_err(|_| anyhow::anyhow!("Chunk {} failed to decrypt", id))?,
+        );
+
+        if self.chunk_id(&data) != id {
+            return Err(anyhow::anyhow!("Chunk {} does not match its ID", id));
+        }
+        Ok(data)
+    }
+
+    /// Decrypt and authenticate a whole chunk, then return just the wanted part of it
+    pub fn decrypt_span(&self, span: &ChunkSpan, sealed: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        let data = self.decrypt_chunk(&span.id, sealed)?;
+        if data.len() as u64 != span.length {
+            return Err(anyhow::anyhow!("Chunk {} has the wrong length", span.id));
+        }
+        Ok(Zeroizing::new(data[span.start as usize..span.end as usize].to_vec()))
+    }
+
+    /// Encrypt a manifest for one version of one file
+    /// Both are bound as associated data, so the server can't hand back
+    /// another file's manifest, or an older one, in its place
+    pub fn seal_manifest(&self, manifest: &ChunkManifest, file_id: &str, version: u64) -> Result<EncryptedChunkManifest> {
+        let cipher = XChaCha20Poly1305::new(self.encryption_key.as_ref().into());
+        let mut nonce_bytes = [0u8; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce_bytes);
+
+        let plaintext = Zeroizing::new(serde_json::to_vec(manifest)?);
+        let ciphertext = cipher
+            .encrypt(
+                XNonce::from_slice(&nonce_bytes),
+                Payload { msg: &plaintext, aad: &manifest_aad(file_id, version) },
+            )
+            .map_err(|e| anyhow::anyhow!("Manifest encryption failed: {}", e))?;
+
+        Ok(EncryptedChunkManifest {
+            ciphertext: base64::encode(ciphertext),
+            nonce: base64::encode(nonce_bytes),
+        })
+    }
+
+    /// Decrypt the manifest sealed for this file and version, and check its
+    /// declared size matches its chunks
+    pub fn open_manifest(&self, encrypted: &EncryptedChunkManifest, file_id: &str, version: u64) -> Result<ChunkManifest> {
+        let ciphertext = base64::decode(&encrypted.ciphertext).context("Failed to decode chunk manifest")?;
+        let nonce_bytes = base64::decode(&encrypted.nonce).context("Failed to decode chunk manifest nonce")?;
+        if nonce_bytes.len() != NONCE_SIZE {
+            return Err(anyhow::anyhow!("Invalid chunk manifest nonce size"));
+        }
+
+        let cipher = XChaCha20Poly1305::new(self.encryption_key.as_ref().into());
+        let bytes = Zeroizing::new(
+            cipher
+                .decrypt(
+                    XNonce::from_slice(&nonce_bytes),
+                    Payload { msg: &ciphertext, aad: &manifest_aad(file_id, version) },
+                )
+                .map_err(|_| anyhow::anyhow!("Failed to decrypt chunk manifest"))?,
+        );
+        let manifest: ChunkManifest = serde_json::from_slice(&bytes).context("Failed to parse chunk manifest")?;
+
+        let chunk_total = manifest
+            .chunks
+            .iter()
+            .try_fold(0u64, |total, chunk| total.checked_add(chunk.length))
+            .context("Chunk manifest lengths overflow")?;
+        if chunk_total != manifest.file_size {
+            return Err(anyhow::anyhow!(
+                "Chunk manifest declares {} bytes but its chunks hold {}",
+                manifest.file_size,
+                chunk_total
+            ));
+        }
+        Ok(manifest)
+    }
+}
+
+fn manifest_aad(file_id: &str, version: u64) -> Vec<u8> {
+    format!("kryptvault chunk manifest\0{}\0{}", file_id, version).into_bytes()
+}
+
+/// Cut a file into content-defined chunks, reading it as a stream
+pub fn chunk_file<'a>(path: &Path, keys: &'a ChunkKeys) -> Result<impl Iterator<Item = Result<Chunk>> + 'a> {
+    let file = File::open(path).context("Failed to open file for chunking")?;
+
+    Ok(StreamCDC::new(file, MIN_CHUNK_SIZE, AVG_CHUNK_SIZE, MAX_CHUNK_SIZE).map(move |chunk| {
+        let chunk = chunk.context("Failed to read file for chunking")?;
+        let data = Zeroizing::new(chunk.data);
+        Ok(Chunk { id: keys.chunk_id(&data), data })
+    }))
+}
+
+/// Chunk a file and describe it without keeping any chunk data around
+pub fn build_manifest(path: &Path, keys: &ChunkKeys) -> Result<ChunkManifest> {
+    let mut chunks = Vec::new();
+    let mut file_size = 0;
+
+    for chunk in chunk_file(path, keys)? {
+        let chunk = chunk?;
+        file_size += chunk.data.len() as u64;
+        chunks.push(ChunkRef { id: chunk.id, length: chunk.data.len() as u64 });
+    }
+
+    Ok(ChunkManifest {
+        original_filename: path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown")
+            .to_string(),
+        file_size,
+        chunks,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Chunks of 100, 50 and 200 bytes
+    fn manifest() -> ChunkManifest {
+        ChunkManifest {
+            original_filename: "file.bin".to_string(),
+            file_size: 350,
+            chunks: [("a", 100), ("b", 50), ("c", 200)]
+                .into_iter()
+                .map(|(id, length)| ChunkRef { id: id.to_string(), length })
+                .collect(),
+        }
+    }
+
+    fn spans(offset: u64, len: u64) -> Vec<(String, u64, u64, u64)> {
+        manifest()
+            .locate_range(offset, len)
+            .unwrap()
+            .into_iter()
+            .map(|span| (span.id, span.packed_offset, span.start, span.end))
+            .collect()
+    }
+
+    fn span(id: &str, packed_offset: u64, start: u64, end: u64) -> (String, u64, u64, u64) {
+        (id.to_string(), packed_offset, start, end)
+    }
+
+    #[test]
+    fn test_range_inside_one_chunk() {
+        assert_eq!(spans(10, 20), vec![span("a", 0, 10, 30)]);
+        assert_eq!(spans(100, 50), vec![span("b", sealed_length(100), 0, 50)]);
+    }
+
+    #[test]
+    fn test_range_ending_on_chunk_boundary() {
+        // Ends exactly where "b" starts, so "b" is not fetched
+        assert_eq!(spans(90, 10), vec![span("a", 0, 90, 100)]);
+    }
+
+    #[test]
+    fn test_range_starting_on_chunk_boundary() {
+        assert_eq!(spans(150, 1), vec![span("c", sealed_length(100) + sealed_length(50), 0, 1)]);
+    }
+
+    #[test]
+    fn test_range_spanning_chunks() {
+        assert_eq!(
+            spans(99, 52),
+            vec![
+                span("a", 0, 99, 100),
+                span("b", sealed_length(100), 0, 50),
+                span("c", sealed_length(100) + sealed_length(50), 0, 1),
+            ]
+        );
+        assert_eq!(spans(0, 350).len(), 3);
+    }
+
+    #[test]
+    fn test_empty_and_out_of_bounds_ranges() {
+        assert!(spans(350, 0).is_empty());
+        assert!(spans(10, 0).is_empty());
+        assert!(manifest().locate_range(300, 51).is_err());
+        assert!(manifest().locate_range(351, 0).is_err());
+        assert!(manifest().locate_range(u64::MAX, 2).is_err());
+    }
+
+    fn keys() -> ChunkKeys {
+        ChunkKeys::derive(&[3u8; 32]).unwrap()
+    }
+
+    #[test]
+    fn test_manifest_is_bound_to_file_and_version() {
+        let keys = keys();
+        let sealed = keys.seal_manifest(&manifest(), "file-1", 2).unwrap();
+
+        let opened = keys.open_manifest(&sealed, "file-1", 2).unwrap();
+        assert_eq!(opened.file_size, 350);
+        assert_eq!(opened.chunks.len(), 3);
+
+        assert!(keys.open_manifest(&sealed, "file-2", 2).is_err());
+        assert!(keys.open_manifest(&sealed, "file-1", 1).is_err());
+        assert!(ChunkKeys::derive(&[4u8; 32]).unwrap().open_manifest(&sealed, "file-1", 2).is_err());
+    }
+
+    #[test]
+    fn test_manifest_size_must_match_its_chunks() {
+        let keys = keys();
+        let mut bad = manifest();
+        bad.file_size = 351;
+        let sealed = keys.seal_manifest(&bad, "file-1", 1).unwrap();
+
+        assert!(keys.open_manifest(&sealed, "file-1", 1).is_err());
+    }
+}
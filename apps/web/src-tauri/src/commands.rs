@@ -1,13 +1,49 @@
 use crate::crypto::{
-    encrypt_file, decrypt_file, decrypt_file_with_dek, generate_server_keypair, generate_user_keypair,
-    wrap_dek_for_recipient, unwrap_dek_for_user, encrypt_with_key, decrypt_with_key,
-    EncryptionResult, DecryptionParams, UserKeypair,
+    encrypt_file, decrypt_file_with_dek, generate_server_keypair, generate_user_keypair,
+    wrap_dek_for_recipient, unwrap_dek_for_user, encrypt_with_key, decrypt_with_key, verify_user_keypair,
+    encrypt_with_password, decrypt_with_password, encrypt_file_with_dek, generate_dek,
+    decrypt_bytes_with_dek, unwrap_dek, TAG_SIZE,
+    EncryptionResult, DecryptionParams, PasswordWrappedKey, UserKeypair,
 };
-use crate::s3::{upload_to_s3, S3UploadResult};
+use crate::error::{CommandError, CommandResult, ErrorCode, ResultExt};
+use crate::s3::{
+    upload_to_s3, download_from_s3, upload_bytes_to_s3, download_bytes_from_s3, download_range_from_s3,
+    check_uploaded_object, head_s3_object, SizeLimitExceeded,
+};
+use crate::search::{index_key, EncryptedSearchIndex, IndexEntry, SearchHit, SearchIndex};
+use crate::share_link::{create_download_file, create_link, open_link, NewLink, ShareLink};
+use crate::share_grant::{claimed_share_grant, sign_share_grant, verify_share_grant, ShareGrant, SignedShareGrant};
+#[cfg(target_os = "linux")]
+use crate::vault_fs::{self, VaultFs, VaultFileEntry, VaultFolderEntry};
+use crate::audit::{fingerprint, key_id, AuditOperation, AuditVerification};
+use crate::csp::server_origin;
+use crate::chunks::{build_manifest, chunk_file, ChunkKeys, ChunkSpan, EncryptedChunkManifest};
+use crate::escrow::EscrowKey;
+use crate::device_link::{generate_link_code, LinkChannel, LinkRole, DEFAULT_RELAY_ADDR};
+use crate::integrity::{check_file, FileCheck, FileHealth, ScanDepth, ScanReport, ScanState, ScanTarget};
+use crate::keyring::{load_protected_keyring, save_protected_keyring};
+use crate::manifest::{
+    compare_listing, load_known_versions, open_manifest, remember_version, seal_manifest,
+    EncryptedManifest, FolderManifest, ListedFile, ManifestEntry, ManifestReport,
+};
+use crate::preview::{mime_type_for, Preview, PreviewInfo, DEFAULT_PREVIEW_LIMIT};
+use crate::shred::{secure_delete, DeletionReport, PendingDeletion};
+use crate::stream::{verify_file, StreamInfo, StreamSource};
+use crate::profile::{Profile, ProfileConfig, ProfileManager, ProfilesFile};
+use crate::thumbnails::{generate_thumbnail, open_thumbnail, seal_thumbnail};
+use crate::transfers::{TransferInfo, TransferKind};
+use crate::versions::{
+    open_chain, seal_chain, EncryptedVersionChain, FileVersion, NewVersion, VersionChain, VersionDiff,
+};
+use crate::vault::{AutoLockSettings, LockReason, VaultLockedPayload, VaultStatus, VAULT_LOCKED_EVENT};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use tauri::State;
-use std::sync::Mutex;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+use tauri::{AppHandle, Emitter, State};
+use zeroize::Zeroizing;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FileUploadParams {
@@ -15,6 +51,12 @@ pub struct FileUploadParams {
     pub server_public_key: String,
     pub presigned_url: String,
     pub file_key: String,
+    #[serde(default)]
+    pub thumbnail_presigned_url: Option<String>, // Companion object for the encrypted thumbnail
+    #[serde(default)]
+    pub verify_head_url: Option<String>, // Presigned HEAD URL, for when S3 doesn't echo the checksum
+    #[serde(default)]
+    pub checksum_signed: bool, // `presigned_url` was signed with the ciphertext's x-amz-checksum-sha256
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,6 +67,13 @@ pub struct FileUploadResponse {
     pub nonce: String,
     pub file_size: u64,
     pub original_filename: String,
+    pub ciphertext_sha256: String,
+    pub has_thumbnail: bool,
+    pub bucket: String,
+    pub etag: Option<String>,
+    pub checksum_verified: bool, // S3 confirmed it stored exactly this ciphertext
+    pub user_file_key: String,   // DEK sealed to the vault's own key; the uploader's per-user file key
+    pub escrow_wrapped_dek: Option<String>, // DEK sealed to the profile's escrow key, if one is set
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -33,12 +82,83 @@ pub struct FileDownloadParams {
     pub wrapped_dek: String,
     pub nonce: String,
     pub server_public_key: String,
-    pub server_private_key: String,
+    pub server_private_key: String, // Legacy; refused once the profile disables server keys
+    pub output_path: String,
+    #[serde(default)]
+    pub ciphertext_sha256: Option<String>, // Checked before decrypting when given
+    #[serde(default)]
+    pub file_size: Option<u64>, // Ciphertext size; temp space is checked before downloading
+}
+
+/// Download a file whose DEK is sealed to the vault's own key
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileKeyDownloadParams {
+    pub download_url: String,
+    pub user_file_key: String, // The current user's per-user file key
+    pub file_id: String,
+    #[serde(default)]
+    pub grant: Option<KeyGrant>, // Checked before the key is unwrapped
+    pub nonce: String,
     pub output_path: String,
+    #[serde(default)]
+    pub ciphertext_sha256: Option<String>, // Checked before decrypting when given
+    #[serde(default)]
+    pub file_size: Option<u64>, // Ciphertext size; temp space is checked before downloading
 }
 
 pub struct AppState {
-    pub temp_dir: Mutex<PathBuf>,
+    pub profiles: ProfileManager,
+}
+
+/// Record a crypto operation in the profile's audit log
+/// The operation has already happened, so a failed write is only logged
+fn audit(profile: &Profile, operation: AuditOperation, details: serde_json::Value) {
+    let result = match profile.vault.keypair() {
+        Ok(keypair) => profile.audit.record(&keypair, operation, details),
+        Err(_) => Err(anyhow::anyhow!("Vault locked before the entry was signed")),
+    };
+    if let Err(e) = result {
+        log::error!("Failed to write audit log entry: {:#}", e);
+    }
+}
+
+/// Refuse to use a client-supplied server private key once the profile has
+/// migrated its files to per-user wraps
+fn ensure_legacy_server_keys(profile: &Profile) -> CommandResult<()> {
+    if profile.legacy_server_keys_disabled() {
+        return Err(CommandError::new(
+            ErrorCode::Unsupported,
+            "Server-key decryption is disabled for this profile; use the per-user file key",
+        ));
+    }
+    Ok(())
+}
+
+/// Seal a file key to the profile's escrow key when one is configured
+fn seal_for_escrow(profile: &Profile, key: &[u8; 32]) -> CommandResult<Option<String>> {
+    profile.escrow_key()
+        .map(|escrow| escrow.seal(key))
+        .transpose()
+        .or_command_error(ErrorCode::InvalidKey, "Failed to seal key for escrow")
+}
+
+/// Run CPU-heavy work (Argon2, image decoding) off the async runtime
+async fn run_blocking<T, F>(task: F) -> CommandResult<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    tauri::async_runtime::spawn_blocking(task)
+        .await
+        .map_err(|e| CommandError::new(ErrorCode::Internal, format!("Background task failed: {}", e)))
+}
+
+/// Display name for a transfer: the file name of a local path
+fn transfer_name(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
 }
 
 /// Tauri command to encrypt and upload a file
@@ -46,44 +166,149 @@ pub struct AppState {
 pub async fn encrypt_and_upload_file(
     params: FileUploadParams,
     state: State<'_, AppState>,
-) -> Result<FileUploadResponse, String> {
-    // Get temp directory
-    let temp_dir = state.temp_dir.lock().unwrap().clone();
-    
-    // Generate unique filename for encrypted file
-    let file_id = uuid::Uuid::new_v4().to_string();
-    let encrypted_path = temp_dir.join(format!("{}.enc", file_id));
+) -> CommandResult<FileUploadResponse> {
+    upload_encrypted(&state.profiles.active(), &params).await
+}
+
+/// Encrypt a file under a fresh DEK and upload it as a tracked transfer
+/// Besides `wrapped_dek` (sealed to `server_public_key`), the DEK is always
+/// sealed to the vault's own key as the uploader's per-user file key
+async fn upload_encrypted(profile: &Profile, params: &FileUploadParams) -> CommandResult<FileUploadResponse> {
+    let (upload, ()) = upload_encrypted_with(profile, params, |_, _| Ok(())).await?;
+    Ok(upload)
+}
+
+/// Like `upload_encrypted`, but `before_upload` is given the encrypted file
+/// and the per-user file key before anything is sent; if it fails, nothing
+/// is uploaded
+async fn upload_encrypted_with<T>(
+    profile: &Profile,
+    params: &FileUploadParams,
+    before_upload: impl FnOnce(&EncryptionResult, &str) -> CommandResult<T>,
+) -> CommandResult<(FileUploadResponse, T)> {
+    // The ciphertext is the plaintext plus a tag; make sure it will fit first
+    let size_hint = std::fs::metadata(&params.file_path).ok().map(|m| m.len() + TAG_SIZE as u64);
+    let encrypted_file = profile.temp.stage("enc", size_hint)
+        .or_command_error(ErrorCode::Io, "Failed to create temp file")?;
     
-    // Encrypt the file
-    let encryption_result = encrypt_file(
-        &params.file_path,
-        encrypted_path.to_str().unwrap(),
-        &params.server_public_key,
-    )
-    .map_err(|e| format!("Encryption failed: {}", e))?;
+    let dek = Zeroizing::new(generate_dek());
+    let keypair = profile.vault.keypair()?;
+    let user_file_key = wrap_dek_for_recipient(&dek, &keypair.x25519_public_key)
+        .or_command_error(ErrorCode::InvalidKey, "Failed to wrap DEK for the current user")?;
+    profile.remember_own_wrap(&user_file_key);
+    let escrow_wrapped_dek = seal_for_escrow(profile, &dek)?;
     
-    // Upload to S3
-    let upload_result = upload_to_s3(
-        &encryption_result.encrypted_file_path,
-        &params.presigned_url,
-        &params.file_key,
-    )
-    .await
-    .map_err(|e| format!("S3 upload failed: {}", e))?;
+    let transfer = profile.transfers.run(TransferKind::Upload, transfer_name(&params.file_path), async {
+        // Encrypt the file
+        let encryption_result = encrypt_file_with_dek(
+            &params.file_path,
+            encrypted_file.path(),
+            &dek,
+            &params.server_public_key,
+        )
+        .or_command_error(ErrorCode::InvalidKey, "Encryption failed")?;
+        let prepared = before_upload(&encryption_result, &user_file_key)?;
+        
+        // S3 rejects a signed request carrying headers the signature doesn't cover, so the
+        // checksum is only sent when the server presigned the URL with it
+        let checksum = params.checksum_signed.then_some(encryption_result.ciphertext_sha256.as_str());
+        let mut upload_result = upload_to_s3(
+            &encryption_result.encrypted_file_path,
+            &params.presigned_url,
+            &params.file_key,
+            checksum,
+        )
+        .await
+        .or_command_error(ErrorCode::NetworkError, "S3 upload failed")?;
+        
+        if let (false, Some(url)) = (upload_result.checksum_verified, &params.verify_head_url) {
+            let object = head_s3_object(url)
+                .await
+                .or_command_error(ErrorCode::NetworkError, "Upload check failed")?;
+            upload_result.checksum_verified = check_uploaded_object(
+                &object,
+                encryption_result.file_size,
+                &encryption_result.ciphertext_sha256,
+            )
+            .map_err(|reason| CommandError::new(ErrorCode::NetworkError, reason))?;
+            upload_result.etag = upload_result.etag.or(object.etag);
+        }
+        
+        // A missing thumbnail only means a generic icon, so never fail the upload over it
+        let has_thumbnail = match &params.thumbnail_presigned_url {
+            Some(url) => upload_thumbnail(&params.file_path, &dek, url).await,
+            None => false,
+        };
+        
+        Ok((encryption_result, upload_result, has_thumbnail, prepared))
+    })
+    .await;
     
-    // Clean up encrypted temp file
-    if let Err(e) = std::fs::remove_file(&encryption_result.encrypted_file_path) {
-        log::warn!("Failed to remove temp encrypted file: {}", e);
-    }
+    let (encryption_result, upload_result, has_thumbnail, prepared) = transfer?;
     
-    Ok(FileUploadResponse {
+    let upload = FileUploadResponse {
         success: upload_result.success,
         file_key: upload_result.file_key,
         wrapped_dek: encryption_result.wrapped_dek,
         nonce: encryption_result.nonce,
         file_size: encryption_result.file_size,
         original_filename: encryption_result.original_filename,
-    })
+        ciphertext_sha256: encryption_result.ciphertext_sha256,
+        has_thumbnail,
+        bucket: upload_result.bucket,
+        etag: upload_result.etag,
+        checksum_verified: upload_result.checksum_verified,
+        user_file_key,
+        escrow_wrapped_dek,
+    };
+    Ok((upload, prepared))
+}
+
+/// Check a downloaded ciphertext against its SHA-256 before anything decrypts it
+fn check_ciphertext_sha256(path: &str, expected: &str) -> CommandResult<()> {
+    let mut file = std::fs::File::open(path)
+        .or_command_error(ErrorCode::Io, "Failed to read downloaded file")?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .or_command_error(ErrorCode::Io, "Failed to read downloaded file")?;
+    
+    if base64::encode(hasher.finalize()) != expected {
+        return Err(CommandError::new(
+            ErrorCode::DecryptionFailed,
+            "Downloaded file does not match its recorded hash",
+        ));
+    }
+    Ok(())
+}
+
+/// Generate, encrypt and upload a thumbnail; returns whether one was stored
+async fn upload_thumbnail(file_path: &str, dek: &[u8; 32], presigned_url: &str) -> bool {
+    // Decoding and resizing the image is CPU-heavy
+    let path = file_path.to_string();
+    let thumbnail = match run_blocking(move || generate_thumbnail(Path::new(&path))).await {
+        Ok(Some(thumbnail)) => thumbnail,
+        Ok(None) => return false,
+        Err(e) => {
+            log::warn!("Failed to generate thumbnail: {}", e.message);
+            return false;
+        }
+    };
+    
+    let sealed = match seal_thumbnail(&thumbnail, dek) {
+        Ok(sealed) => sealed,
+        Err(e) => {
+            log::warn!("Failed to encrypt thumbnail: {:#}", e);
+            return false;
+        }
+    };
+    
+    match upload_bytes_to_s3(sealed, presigned_url).await {
+        Ok(()) => true,
+        Err(e) => {
+            log::warn!("Failed to upload thumbnail: {:#}", e);
+            false
+        }
+    }
 }
 
 /// Tauri command to download and decrypt a file
@@ -91,63 +316,104 @@ pub async fn encrypt_and_upload_file(
 pub async fn download_and_decrypt_file(
     params: FileDownloadParams,
     state: State<'_, AppState>,
-) -> Result<String, String> {
-    // Get temp directory
-    let temp_dir = state.temp_dir.lock().unwrap().clone();
+) -> CommandResult<String> {
+    let profile = state.profiles.active();
+    profile.vault.ensure_unlocked()?;
     
-    // Generate unique filename for downloaded encrypted file
-    let file_id = uuid::Uuid::new_v4().to_string();
-    let encrypted_path = temp_dir.join(format!("{}.enc", file_id));
-    
-    // Download the encrypted file from S3
-    let client = reqwest::Client::new();
-    let response = client
-        .get(&params.download_url)
-        .send()
-        .await
-        .map_err(|e| format!("Download failed: {}", e))?;
-    
-    if !response.status().is_success() {
-        return Err(format!("Download failed with status: {}", response.status()));
-    }
+    download_decrypted(&profile, params, "download_and_decrypt_file", None).await
+}
+
+/// Download and decrypt a file with the current user's per-user file key
+/// Replaces `download_and_decrypt_file` for files uploaded since per-user file
+/// keys, so no server private key is needed; the key's grant is checked first
+#[tauri::command]
+pub async fn download_and_decrypt_file_with_key(
+    params: FileKeyDownloadParams,
+    state: State<'_, AppState>,
+) -> CommandResult<String> {
+    let profile = state.profiles.active();
+    let (dek, _) = unwrap_granted_key(&state.profiles, &profile, &params.user_file_key, &params.file_id, params.grant.as_ref())?;
+    let dek_base64 = Zeroizing::new(base64::encode(&dek[..]));
     
-    let encrypted_data = response
-        .bytes()
-        .await
-        .map_err(|e| format!("Failed to read downloaded data: {}", e))?;
-    
-    // Save encrypted file temporarily
-    std::fs::write(&encrypted_path, encrypted_data)
-        .map_err(|e| format!("Failed to save encrypted file: {}", e))?;
-    
-    // Decrypt the file
-    let decryption_params = DecryptionParams {
-        encrypted_file_path: encrypted_path.to_str().unwrap().to_string(),
-        wrapped_dek: params.wrapped_dek,
-        nonce: params.nonce,
-        server_private_key: params.server_private_key,
+    let download = DekDownload {
+        download_url: &params.download_url,
+        nonce: &params.nonce,
+        output_path: &params.output_path,
+        expected_sha256: params.ciphertext_sha256.as_deref(),
+        file_size: params.file_size,
     };
+    download_with_dek(&profile, download, &dek_base64, "download_and_decrypt_file_with_key").await
+}
+
+/// Download a file and decrypt it as a tracked transfer
+/// If `expected_sha256` (or the params' checksum) is given, the ciphertext
+/// must match it before decrypting
+async fn download_decrypted(
+    profile: &Profile,
+    params: FileDownloadParams,
+    command: &str,
+    expected_sha256: Option<&str>,
+) -> CommandResult<String> {
+    ensure_legacy_server_keys(profile)?;
+    let expected_sha256 = expected_sha256.map(str::to_string).or_else(|| params.ciphertext_sha256.clone());
+    let encrypted_file = profile.temp.stage("enc", params.file_size)
+        .or_command_error(ErrorCode::Io, "Failed to create temp file")?;
     
-    let decrypted_path = decrypt_file(
-        decryption_params,
-        &params.output_path,
-        &params.server_public_key,
-    )
-    .map_err(|e| format!("Decryption failed: {}", e))?;
-    
-    // Clean up encrypted temp file
-    if let Err(e) = std::fs::remove_file(&encrypted_path) {
-        log::warn!("Failed to remove temp encrypted file: {}", e);
-    }
+    let transfer = profile.transfers.run(TransferKind::Download, transfer_name(&params.output_path), async {
+        // Download the encrypted file from S3 and save it temporarily
+        download_from_s3(&params.download_url, encrypted_file.path())
+            .await
+            .or_command_error(ErrorCode::NetworkError, "Download failed")?;
+        
+        if let Some(expected) = &expected_sha256 {
+            check_ciphertext_sha256(encrypted_file.path(), expected)?;
+        }
+        
+        // Decrypt the file
+        let decryption_params = DecryptionParams {
+            encrypted_file_path: encrypted_file.path().to_string(),
+            wrapped_dek: params.wrapped_dek,
+            nonce: params.nonce,
+            server_private_key: params.server_private_key,
+        };
+        
+        let (decrypted_path, key_id) = decrypt_with_server_key(
+            decryption_params,
+            &params.output_path,
+            &params.server_public_key,
+        )
+        .or_command_error(ErrorCode::DecryptionFailed, "Decryption failed")?;
+        
+        audit(profile, AuditOperation::Decrypt, json!({
+            "command": command,
+            "key_id": key_id,
+            "output_path": decrypted_path,
+        }));
+        Ok(decrypted_path)
+    })
+    .await;
     
-    Ok(decrypted_path)
+    transfer
+}
+
+/// Decrypt a file whose DEK is sealed to the server keypair
+/// Returns the output path and the DEK's audit key ID
+fn decrypt_with_server_key(
+    params: DecryptionParams,
+    output_path: &str,
+    server_public_key: &str,
+) -> anyhow::Result<(String, String)> {
+    let dek = Zeroizing::new(unwrap_dek(&params.wrapped_dek, server_public_key, &params.server_private_key)?);
+    let dek_base64 = Zeroizing::new(base64::encode(dek.as_ref()));
+    let decrypted_path = decrypt_file_with_dek(&params.encrypted_file_path, &dek_base64, &params.nonce, output_path)?;
+    Ok((decrypted_path, key_id(dek.as_ref())))
 }
 
 /// Tauri command to generate server keypair (for initial setup)
 #[tauri::command]
-pub fn generate_keypair() -> Result<(String, String), String> {
+pub fn generate_keypair() -> CommandResult<(String, String)> {
     generate_server_keypair()
-        .map_err(|e| format!("Failed to generate keypair: {}", e))
+        .or_command_error(ErrorCode::Internal, "Failed to generate keypair")
 }
 
 /// Tauri command to encrypt a file locally (without upload)
@@ -156,9 +422,9 @@ pub fn encrypt_file_only(
     input_path: String,
     output_path: String,
     server_public_key: String,
-) -> Result<EncryptionResult, String> {
+) -> CommandResult<EncryptionResult> {
     encrypt_file(&input_path, &output_path, &server_public_key)
-        .map_err(|e| format!("Encryption failed: {}", e))
+        .or_command_error(ErrorCode::InvalidKey, "Encryption failed")
 }
 
 /// Tauri command to decrypt a file locally (without download)
@@ -167,53 +433,341 @@ pub fn decrypt_file_only(
     params: DecryptionParams,
     output_path: String,
     server_public_key: String,
-) -> Result<String, String> {
-    decrypt_file(params, &output_path, &server_public_key)
-        .map_err(|e| format!("Decryption failed: {}", e))
+    state: State<'_, AppState>,
+) -> CommandResult<String> {
+    let profile = state.profiles.active();
+    profile.vault.ensure_unlocked()?;
+    ensure_legacy_server_keys(&profile)?;
+    
+    let (decrypted_path, key_id) = decrypt_with_server_key(params, &output_path, &server_public_key)
+        .or_command_error(ErrorCode::DecryptionFailed, "Decryption failed")?;
+    
+    audit(&profile, AuditOperation::Decrypt, json!({
+        "command": "decrypt_file_only",
+        "key_id": key_id,
+        "output_path": decrypted_path,
+    }));
+    Ok(decrypted_path)
 }
 
 // ============================================================================
 // USER KEYPAIR MANAGEMENT
 // ============================================================================
 
-/// Generate new user keypairs (X25519 + Ed25519)
-/// Private keys should be stored in OS keychain, public keys sent to server
+/// The unlocked vault's public keys, to register with the server
+#[derive(Debug, Serialize)]
+pub struct VaultPublicKeys {
+    pub x25519_public_key: String,
+    pub ed25519_public_key: String,
+}
+
+impl VaultPublicKeys {
+    fn of(keypair: &UserKeypair) -> Self {
+        VaultPublicKeys {
+            x25519_public_key: keypair.x25519_public_key.clone(),
+            ed25519_public_key: keypair.ed25519_public_key.clone(),
+        }
+    }
+}
+
+/// Encrypt a keypair under the passphrase into the profile's keyring file
+/// and unlock the vault with it
+async fn store_vault_keyring(profile: &Profile, keypair: UserKeypair, passphrase: String) -> CommandResult<VaultPublicKeys> {
+    verify_user_keypair(&keypair)
+        .or_command_error(ErrorCode::InvalidKey, "Keypair is invalid")?;
+    
+    let path = profile.keyring_path.clone();
+    let keypair = run_blocking(move || {
+        save_protected_keyring(&path, &keypair, &passphrase).map(|()| keypair)
+    })
+    .await?
+    .or_command_error(ErrorCode::Io, "Failed to save keyring")?;
+    
+    let public_keys = VaultPublicKeys::of(&keypair);
+    profile.vault.unlock(keypair);
+    Ok(public_keys)
+}
+
+/// Generate new user keypairs (X25519 + Ed25519) for the active profile
+/// The private keys never leave Rust: they are saved encrypted under the
+/// passphrase and the vault is unlocked with them
+#[tauri::command]
+pub async fn create_vault_keyring(
+    passphrase: String,
+    state: State<'_, AppState>,
+) -> CommandResult<VaultPublicKeys> {
+    let profile = state.profiles.active();
+    if profile.keyring_path.exists() {
+        return Err(CommandError::new(ErrorCode::InvalidInput, "This profile already has a keyring"));
+    }
+    
+    let keypair = generate_user_keypair()
+        .or_command_error(ErrorCode::Internal, "Failed to generate user keypair")?;
+    store_vault_keyring(&profile, keypair, passphrase).await
+}
+
+/// Protect existing keys (e.g. from an older install) under a passphrase
+/// in the active profile's keyring file
+/// Replacing a keyring already there needs the vault unlocked and its
+/// current passphrase; the old file is kept next to it as a backup
+#[tauri::command]
+pub async fn import_vault_keyring(
+    keypair: UserKeypair,
+    passphrase: String,
+    current_passphrase: Option<String>,
+    state: State<'_, AppState>,
+) -> CommandResult<VaultPublicKeys> {
+    let profile = state.profiles.active();
+    if profile.keyring_path.exists() {
+        let unlocked = profile.vault.keypair()?;
+        let current_passphrase = current_passphrase.ok_or_else(|| {
+            CommandError::new(
+                ErrorCode::AuthenticationFailed,
+                "Enter the current passphrase to replace this profile's keyring",
+            )
+        })?;
+        
+        let path = profile.keyring_path.clone();
+        let current = run_blocking(move || load_protected_keyring(&path, &current_passphrase).map(Zeroizing::new))
+            .await?
+            .or_command_error(ErrorCode::AuthenticationFailed, "Incorrect passphrase")?;
+        if current.x25519_public_key != unlocked.x25519_public_key {
+            return Err(CommandError::new(
+                ErrorCode::AuthenticationFailed,
+                "The saved keyring does not match the unlocked vault",
+            ));
+        }
+        
+        let stamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let backup_path = profile.keyring_path.with_extension(format!("{}.bak", stamp));
+        std::fs::copy(&profile.keyring_path, &backup_path)
+            .or_command_error(ErrorCode::Io, "Failed to back up the current keyring")?;
+        log::info!("Backed up replaced keyring to {}", backup_path.display());
+    }
+    
+    store_vault_keyring(&profile, keypair, passphrase).await
+}
+
+/// Whether the active profile has a keyring file to unlock
+#[tauri::command]
+pub fn has_vault_keyring(state: State<'_, AppState>) -> CommandResult<bool> {
+    Ok(state.profiles.active().keyring_path.exists())
+}
+
+#[tauri::command]
+pub fn get_vault_public_keys(state: State<'_, AppState>) -> CommandResult<VaultPublicKeys> {
+    let keypair = state.profiles.active().vault.keypair()?;
+    Ok(VaultPublicKeys::of(&keypair))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SharedFileKey {
+    pub wrapped_dek: String,               // DEK sealed to the recipient
+    pub grant: SignedShareGrant,           // Stored by the server and checked by the recipient
+    pub escrow_wrapped_dek: Option<String>, // DEK sealed to the profile's escrow key, if one is set
+}
+
+/// Signed grant stored with a wrapped key, and who the server says signed it
+/// The server's word only ever chooses a key to pin on first use; whether
+/// the key is the user's own or shared is decided from the grant itself
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyGrant {
+    pub grant: SignedShareGrant,
+    #[serde(default)]
+    pub sharer_id: Option<String>, // Server user ID of the sharer; pins are kept per ID
+    #[serde(default)]
+    pub sharer_public_key: Option<String>, // Ed25519 key the server publishes for the sharer
+}
+
+/// Decide whether a key sealed to the current user can be trusted, and
+/// where it came from
+/// A grant signed by the vault's own Ed25519 key is the user's own wrap.
+/// Any other signer has to match the key pinned for that sharer, which is
+/// pinned the first time one of their grants verifies. A key without a
+/// grant only opens if the user allowed it for this file or folder
+fn check_key_grant(
+    profiles: &ProfileManager,
+    profile: &Profile,
+    keypair: &UserKeypair,
+    wrapped_dek: &str,
+    file_id: &str,
+    grant: Option<&KeyGrant>,
+) -> CommandResult<&'static str> {
+    let Some(grant) = grant else {
+        if profile.unsigned_key_allowed(file_id) {
+            return Ok("legacy_unsigned");
+        }
+        return Err(CommandError::new(ErrorCode::UnsignedKey, "This key has no signed grant"));
+    };
+
+    let claimed = claimed_share_grant(&grant.grant)
+        .or_command_error(ErrorCode::InvalidKey, "Share grant verification failed")?;
+    if claimed.sharer_ed25519_public_key == keypair.ed25519_public_key {
+        verify_share_grant(&grant.grant, &keypair.ed25519_public_key, &keypair.x25519_public_key, file_id, wrapped_dek)
+            .or_command_error(ErrorCode::InvalidKey, "Share grant verification failed")?;
+        return Ok("own");
+    }
+
+    let sharer_id = grant.sharer_id.as_deref()
+        .ok_or_else(|| CommandError::new(ErrorCode::InvalidKey, "Shared key does not say who shared it"))?;
+    let pinned = profile.sharer_key(sharer_id);
+    let sharer_key = match (&pinned, &grant.sharer_public_key) {
+        (Some(pinned), Some(published)) if pinned != published => {
+            return Err(CommandError::new(
+                ErrorCode::InvalidKey,
+                "The sharer's signing key has changed since it was first seen",
+            ));
+        }
+        (Some(pinned), _) => pinned.clone(),
+        (None, Some(published)) => published.clone(),
+        (None, None) => {
+            return Err(CommandError::new(ErrorCode::InvalidKey, "The sharer's signing key is unknown"));
+        }
+    };
+
+    verify_share_grant(&grant.grant, &sharer_key, &keypair.x25519_public_key, file_id, wrapped_dek)
+        .or_command_error(ErrorCode::InvalidKey, "Share grant verification failed")?;
+    if pinned.is_none() {
+        profiles.pin_sharer_key(&profile.config.id, sharer_id, &sharer_key)
+            .or_command_error(ErrorCode::Io, "Failed to pin the sharer's key")?;
+    }
+    Ok("shared")
+}
+
+/// Check a wrapped key's grant, then unwrap it with the vault's keypair
+fn unwrap_granted_key(
+    profiles: &ProfileManager,
+    profile: &Profile,
+    wrapped_dek: &str,
+    file_id: &str,
+    grant: Option<&KeyGrant>,
+) -> CommandResult<(Zeroizing<[u8; 32]>, &'static str)> {
+    let keypair = profile.vault.keypair()?;
+    let origin = check_key_grant(profiles, profile, &keypair, wrapped_dek, file_id, grant)?;
+    let dek = Zeroizing::new(
+        unwrap_dek_for_user(wrapped_dek, &keypair.x25519_public_key, &keypair.x25519_private_key)
+            .or_command_error(ErrorCode::InvalidKey, "Failed to unwrap DEK")?,
+    );
+    Ok((dek, origin))
+}
+
+/// Sign a grant over a key this app just wrapped for the user's own key,
+/// once the server has given the file or folder its ID
+/// Keys that came from anywhere else are refused, so a key planted by the
+/// server can never be passed off as the user's own
+#[tauri::command]
+pub fn sign_own_key_grant(
+    wrapped_key: String,
+    file_id: String,
+    state: State<'_, AppState>,
+) -> CommandResult<SignedShareGrant> {
+    let profile = state.profiles.active();
+    let keypair = profile.vault.keypair()?;
+    if !profile.take_own_wrap(&wrapped_key) {
+        return Err(CommandError::new(ErrorCode::InvalidKey, "Only keys wrapped on this device can be self-signed"));
+    }
+
+    sign_share_grant(
+        &file_id,
+        &wrapped_key,
+        &keypair.x25519_public_key,
+        &keypair.x25519_public_key,
+        &keypair.ed25519_private_key,
+    )
+    .or_command_error(ErrorCode::InvalidKey, "Failed to sign key grant")
+}
+
+/// Let a key without a grant (shared before grants existed) open for one
+/// file or folder; the UI asks the user before calling this
 #[tauri::command]
-pub fn generate_user_keypair_command() -> Result<UserKeypair, String> {
-    generate_user_keypair()
-        .map_err(|e| format!("Failed to generate user keypair: {}", e))
+pub fn allow_unsigned_key(file_id: String, state: State<'_, AppState>) -> CommandResult<()> {
+    let profile = state.profiles.active();
+    state.profiles.allow_unsigned_key(&profile.config.id, &file_id)
+        .or_command_error(ErrorCode::Io, "Failed to save profile settings")
 }
 
 /// Wrap a DEK for sharing with another user
-/// Takes the DEK (in base64), unwraps it with current user's private key,
-/// then re-wraps it with recipient's public key
+/// Unwraps the DEK (sealed to the current user) with the vault's keypair,
+/// then re-wraps it with recipient's public key and signs a grant for it
+/// with the sharer's Ed25519 key; folder keys use the folder ID as file ID
 #[tauri::command]
 pub fn share_file_key(
     wrapped_dek: String,
-    user_public_key: String,
-    user_private_key: String,
+    grant: Option<KeyGrant>,
     recipient_public_key: String,
-) -> Result<String, String> {
-    // First, unwrap the DEK using the current user's keypair
-    let dek = unwrap_dek_for_user(&wrapped_dek, &user_public_key, &user_private_key)
-        .map_err(|e| format!("Failed to unwrap DEK: {}", e))?;
+    file_id: String,
+    state: State<'_, AppState>,
+) -> CommandResult<SharedFileKey> {
+    let profile = state.profiles.active();
+    let keypair = profile.vault.keypair()?;
+    
+    // First, unwrap the DEK using the current user's keypair; only a key
+    // whose grant checks out is passed on
+    let (dek, _) = unwrap_granted_key(&state.profiles, &profile, &wrapped_dek, &file_id, grant.as_ref())?;
     
     // Then, wrap it for the recipient
-    wrap_dek_for_recipient(&dek, &recipient_public_key)
-        .map_err(|e| format!("Failed to wrap DEK for recipient: {}", e))
+    let wrapped_for_recipient = wrap_dek_for_recipient(&dek, &recipient_public_key)
+        .or_command_error(ErrorCode::InvalidKey, "Failed to wrap DEK for recipient")?;
+    let escrow_wrapped_dek = seal_for_escrow(&profile, &dek)?;
+    
+    let grant = sign_share_grant(
+        &file_id,
+        &wrapped_for_recipient,
+        &recipient_public_key,
+        &keypair.x25519_public_key,
+        &keypair.ed25519_private_key,
+    )
+    .or_command_error(ErrorCode::InvalidKey, "Failed to sign share grant")?;
+    
+    audit(&profile, AuditOperation::Share, json!({
+        "key_id": key_id(&dek[..]),
+        "file_id": file_id,
+        "recipient_public_key": recipient_public_key,
+    }));
+    Ok(SharedFileKey {
+        wrapped_dek: wrapped_for_recipient,
+        grant,
+        escrow_wrapped_dek,
+    })
+}
+
+/// Check that a key's grant was signed by the user or by the sharer's
+/// pinned key, and covers this wrapped DEK, the current user and the file
+#[tauri::command]
+pub fn verify_share_grant_command(
+    wrapped_dek: String,
+    file_id: String,
+    grant: KeyGrant,
+    state: State<'_, AppState>,
+) -> CommandResult<ShareGrant> {
+    let profile = state.profiles.active();
+    let keypair = profile.vault.keypair()?;
+    check_key_grant(&state.profiles, &profile, &keypair, &wrapped_dek, &file_id, Some(&grant))?;
+    claimed_share_grant(&grant.grant).or_command_error(ErrorCode::InvalidKey, "Failed to parse share grant")
 }
 
-/// Unwrap a DEK that was shared with the current user
+/// Unwrap a DEK sealed to the current user
+/// Only unwrapped if its grant verifies, or the user allowed this file's
+/// key to open without one; `file_id` is the folder ID for a folder key
 #[tauri::command]
 pub fn unwrap_shared_dek(
     wrapped_dek: String,
-    user_public_key: String,
-    user_private_key: String,
-) -> Result<String, String> {
-    let dek = unwrap_dek_for_user(&wrapped_dek, &user_public_key, &user_private_key)
-        .map_err(|e| format!("Failed to unwrap DEK: {}", e))?;
+    file_id: String,
+    grant: Option<KeyGrant>,
+    state: State<'_, AppState>,
+) -> CommandResult<String> {
+    let profile = state.profiles.active();
+    let (dek, origin) = unwrap_granted_key(&state.profiles, &profile, &wrapped_dek, &file_id, grant.as_ref())?;
     
-    Ok(base64::encode(&dek))
+    audit(&profile, AuditOperation::Unwrap, json!({
+        "command": "unwrap_shared_dek",
+        "key_id": key_id(&dek[..]),
+        "origin": origin,
+    }));
+    Ok(base64::encode(&dek[..]))
 }
 
 /// Download and decrypt a shared file using an already-unwrapped DEK
@@ -224,51 +778,83 @@ pub async fn download_and_decrypt_shared_file(
     dek_base64: String,
     nonce: String,
     output_path: String,
+    ciphertext_sha256: Option<String>,
+    file_size: Option<u64>,
     state: State<'_, AppState>,
-) -> Result<String, String> {
-    // Get temp directory
-    let temp_dir = state.temp_dir.lock().unwrap().clone();
-    
-    // Generate unique filename for downloaded encrypted file
-    let file_id = uuid::Uuid::new_v4().to_string();
-    let encrypted_path = temp_dir.join(format!("{}.enc", file_id));
-    
-    // Download the encrypted file from S3
-    let client = reqwest::Client::new();
-    let response = client
-        .get(&download_url)
-        .send()
-        .await
-        .map_err(|e| format!("Download failed: {}", e))?;
+) -> CommandResult<String> {
+    let profile = state.profiles.active();
+    profile.vault.ensure_unlocked()?;
     
-    if !response.status().is_success() {
-        return Err(format!("Download failed with status: {}", response.status()));
-    }
-    
-    let encrypted_data = response
-        .bytes()
-        .await
-        .map_err(|e| format!("Failed to read downloaded data: {}", e))?;
-    
-    // Save encrypted file temporarily
-    std::fs::write(&encrypted_path, encrypted_data)
-        .map_err(|e| format!("Failed to save encrypted file: {}", e))?;
-    
-    // Decrypt the file using the unwrapped DEK
-    let decrypted_path = decrypt_file_with_dek(
-        encrypted_path.to_str().unwrap(),
-        &dek_base64,
-        &nonce,
-        &output_path,
-    )
-    .map_err(|e| format!("Decryption failed: {}", e))?;
+    let download = DekDownload {
+        download_url: &download_url,
+        nonce: &nonce,
+        output_path: &output_path,
+        expected_sha256: ciphertext_sha256.as_deref(),
+        file_size,
+    };
+    download_with_dek(&profile, download, &dek_base64, "download_and_decrypt_shared_file").await
+}
+
+/// Where to fetch a file from and write it to, for a DEK the caller holds
+struct DekDownload<'a> {
+    download_url: &'a str,
+    nonce: &'a str,
+    output_path: &'a str,
+    expected_sha256: Option<&'a str>, // Checked before decrypting when given
+    file_size: Option<u64>,           // Ciphertext size, when known
+}
+
+/// Download a file and decrypt it with an unwrapped DEK as a tracked transfer
+async fn download_with_dek(
+    profile: &Profile,
+    download: DekDownload<'_>,
+    dek_base64: &str,
+    command: &str,
+) -> CommandResult<String> {
+    let encrypted_file = profile.temp.stage("enc", download.file_size)
+        .or_command_error(ErrorCode::Io, "Failed to create temp file")?;
     
-    // Clean up encrypted temp file
-    if let Err(e) = std::fs::remove_file(&encrypted_path) {
-        log::warn!("Failed to remove temp encrypted file: {}", e);
-    }
+    let transfer = profile.transfers.run(TransferKind::Download, transfer_name(download.output_path), async {
+        // Download the encrypted file from S3 and save it temporarily
+        download_from_s3(download.download_url, encrypted_file.path())
+            .await
+            .or_command_error(ErrorCode::NetworkError, "Download failed")?;
+        
+        if let Some(expected) = download.expected_sha256 {
+            check_ciphertext_sha256(encrypted_file.path(), expected)?;
+        }
+        
+        // Decrypt the file using the unwrapped DEK
+        let decrypted_path = decrypt_file_with_dek(
+            encrypted_file.path(),
+            dek_base64,
+            download.nonce,
+            download.output_path,
+        )
+        .or_command_error(ErrorCode::DecryptionFailed, "Decryption failed")?;
+        
+        audit(profile, AuditOperation::Decrypt, json!({
+            "command": command,
+            "key_id": base64::decode(dek_base64).ok().map(|dek| key_id(&Zeroizing::new(dek))),
+            "output_path": decrypted_path,
+        }));
+        Ok(decrypted_path)
+    })
+    .await;
     
-    Ok(decrypted_path)
+    transfer
+}
+
+/// Unwrap a per-user file key with the vault's keypair
+/// Only for keys already covered by a signature, like a signed version
+/// history; keys the server hands out go through `unwrap_granted_key`
+fn unwrap_user_file_key(profile: &Profile, user_file_key: &str) -> CommandResult<Zeroizing<String>> {
+    let keypair = profile.vault.keypair()?;
+    let dek = Zeroizing::new(
+        unwrap_dek_for_user(user_file_key, &keypair.x25519_public_key, &keypair.x25519_private_key)
+            .or_command_error(ErrorCode::InvalidKey, "Failed to unwrap file key")?,
+    );
+    Ok(Zeroizing::new(base64::encode(&dek[..])))
 }
 
 // ============================================================================
@@ -291,24 +877,19 @@ pub struct WrapDekWithFolderKeyResult {
 #[tauri::command]
 pub fn wrap_dek_with_folder_key(
     params: WrapDekWithFolderKeyParams,
-) -> Result<WrapDekWithFolderKeyResult, String> {
+    state: State<'_, AppState>,
+) -> CommandResult<WrapDekWithFolderKeyResult> {
+    state.profiles.active().vault.ensure_unlocked()?;
+    
     // Decode the DEK and folder key
     let dek = base64::decode(&params.dek_b64)
-        .map_err(|e| format!("Failed to decode DEK: {}", e))?;
-    
-    let folder_key_vec = base64::decode(&params.folder_key_b64)
-        .map_err(|e| format!("Failed to decode folder key: {}", e))?;
-    
-    if folder_key_vec.len() != 32 {
-        return Err("Invalid folder key size".to_string());
-    }
+        .or_command_error(ErrorCode::InvalidKey, "Failed to decode DEK")?;
     
-    let mut folder_key = [0u8; 32];
-    folder_key.copy_from_slice(&folder_key_vec);
+    let folder_key = decode_folder_key(&params.folder_key_b64)?;
     
     // Encrypt DEK with folder key
     let (wrapped_dek, wrapping_nonce) = encrypt_with_key(&dek, &folder_key)
-        .map_err(|e| format!("Failed to wrap DEK: {}", e))?;
+        .or_command_error(ErrorCode::InvalidKey, "Failed to wrap DEK")?;
     
     Ok(WrapDekWithFolderKeyResult {
         wrapped_dek,
@@ -327,28 +908,71 @@ pub struct UnwrapDekWithFolderKeyParams {
 #[tauri::command]
 pub fn unwrap_dek_with_folder_key(
     params: UnwrapDekWithFolderKeyParams,
-) -> Result<String, String> {
+    state: State<'_, AppState>,
+) -> CommandResult<String> {
+    let profile = state.profiles.active();
+    profile.vault.ensure_unlocked()?;
+    
     // Decode folder key
-    let folder_key_vec = base64::decode(&params.folder_key_b64)
-        .map_err(|e| format!("Failed to decode folder key: {}", e))?;
+    let folder_key = decode_folder_key(&params.folder_key_b64)?;
+    
+    // Decrypt DEK with folder key
+    let dek = decrypt_with_key(&params.wrapped_dek, &params.wrapping_nonce, &folder_key)
+        .or_command_error(ErrorCode::InvalidKey, "Failed to unwrap DEK")?;
+    
+    audit(&profile, AuditOperation::Unwrap, json!({
+        "command": "unwrap_dek_with_folder_key",
+        "key_id": key_id(&dek),
+    }));
+    Ok(base64::encode(&dek))
+}
+
+fn decode_folder_key(folder_key_b64: &str) -> CommandResult<[u8; 32]> {
+    let folder_key_vec = base64::decode(folder_key_b64)
+        .or_command_error(ErrorCode::InvalidKey, "Failed to decode folder key")?;
     
     if folder_key_vec.len() != 32 {
-        return Err("Invalid folder key size".to_string());
+        return Err(CommandError::new(ErrorCode::InvalidKey, "Invalid folder key size"));
     }
     
     let mut folder_key = [0u8; 32];
     folder_key.copy_from_slice(&folder_key_vec);
+    Ok(folder_key)
+}
+
+/// Wrap a key with a password (Argon2id), for password-protected sharing
+#[tauri::command]
+pub async fn wrap_key_with_password(
+    key_b64: String,
+    password: String,
+) -> CommandResult<PasswordWrappedKey> {
+    let key = Zeroizing::new(base64::decode(&key_b64)
+        .or_command_error(ErrorCode::InvalidKey, "Failed to decode key")?);
     
-    // Decrypt DEK with folder key
-    let dek = decrypt_with_key(&params.wrapped_dek, &params.wrapping_nonce, &folder_key)
-        .map_err(|e| format!("Failed to unwrap DEK: {}", e))?;
+    run_blocking(move || encrypt_with_password(&key, &password))
+        .await?
+        .or_command_error(ErrorCode::InvalidInput, "Failed to wrap key with password")
+}
+
+/// Unwrap a password-wrapped key
+/// Wrong passwords and corrupted data fail the same way
+#[tauri::command]
+pub async fn unwrap_key_with_password(
+    wrapped: PasswordWrappedKey,
+    password: String,
+) -> CommandResult<String> {
+    let key = Zeroizing::new(
+        run_blocking(move || decrypt_with_password(&wrapped, &password))
+            .await?
+            .or_command_error(ErrorCode::InvalidKey, "Incorrect password")?,
+    );
     
-    Ok(base64::encode(&dek))
+    Ok(base64::encode(&*key))
 }
 
 /// Generate a random folder key (256-bit)
 #[tauri::command]
-pub fn generate_folder_key() -> Result<String, String> {
+pub fn generate_folder_key() -> CommandResult<String> {
     use rand::RngCore;
     let mut folder_key = [0u8; 32];
     rand::rngs::OsRng.fill_bytes(&mut folder_key);
@@ -360,24 +984,2290 @@ pub fn generate_folder_key() -> Result<String, String> {
 pub fn seal_data(
     data: String,
     recipient_public_key: String,
-) -> Result<String, String> {
+    state: State<'_, AppState>,
+) -> CommandResult<String> {
     use sodiumoxide::crypto::sealedbox;
     use sodiumoxide::crypto::box_::PublicKey;
     
     // Decode the data from base64
     let data_bytes = base64::decode(&data)
-        .map_err(|e| format!("Failed to decode data: {}", e))?;
+        .or_command_error(ErrorCode::InvalidInput, "Failed to decode data")?;
     
     // Decode recipient's public key
     let pk_bytes = base64::decode(&recipient_public_key)
-        .map_err(|e| format!("Failed to decode public key: {}", e))?;
+        .or_command_error(ErrorCode::InvalidKey, "Failed to decode public key")?;
     
     let public_key = PublicKey::from_slice(&pk_bytes)
-        .ok_or_else(|| "Invalid public key".to_string())?;
+        .ok_or_else(|| CommandError::new(ErrorCode::InvalidKey, "Invalid public key"))?;
     
     // Seal the data
-    let sealed = sealedbox::seal(&data_bytes, &public_key);
+    let sealed = base64::encode(sealedbox::seal(&data_bytes, &public_key));
+    
+    // A key sealed to the user's own vault (e.g. a new folder key) may get a self-signed grant
+    let profile = state.profiles.active();
+    if profile.vault.keypair().is_ok_and(|keypair| keypair.x25519_public_key == recipient_public_key) {
+        profile.remember_own_wrap(&sealed);
+    }
+
+    Ok(sealed)
+}
+
+// ============================================================================
+// VAULT MOUNT (FUSE)
+// ============================================================================
+
+#[cfg(target_os = "linux")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MountVaultParams {
+    pub mount_point: String,
+    pub folders: Vec<VaultFolderEntry>,
+    pub files: Vec<VaultFileEntry>,
+}
+
+/// Mount the vault as a read-only filesystem presenting decrypted names
+/// Content is downloaded and decrypted on demand when a file is opened
+#[cfg(target_os = "linux")]
+#[tauri::command]
+pub fn mount_vault(
+    params: MountVaultParams,
+    state: State<'_, AppState>,
+) -> CommandResult<()> {
+    let profile = state.profiles.active();
+    profile.vault.ensure_unlocked()?;
+    
+    let mut vault_mount = profile.vault_mount.lock().unwrap();
+    if vault_mount.is_some() {
+        return Err(CommandError::new(ErrorCode::InvalidInput, "Vault is already mounted"));
+    }
+    
+    let cache_dir = profile.cache_dir.join("ciphertext");
+    let mount_point = std::path::PathBuf::from(&params.mount_point);
+    std::fs::create_dir_all(&mount_point)
+        .or_command_error(ErrorCode::Io, "Failed to create mount point")?;
+    
+    let fs = VaultFs::new(params.folders, params.files, cache_dir)
+        .or_command_error(ErrorCode::Io, "Failed to build vault filesystem")?;
+    
+    let session = vault_fs::mount(fs, &mount_point)
+        .or_command_error(ErrorCode::Io, "Mount failed")?;
+    
+    *vault_mount = Some(session);
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+#[tauri::command]
+pub fn mount_vault() -> CommandResult<()> {
+    Err(CommandError::new(ErrorCode::Unsupported, "Vault mounts are only supported on Linux"))
+}
+
+/// Unmount the vault filesystem if it is mounted
+#[cfg(target_os = "linux")]
+#[tauri::command]
+pub fn unmount_vault(state: State<'_, AppState>) -> CommandResult<()> {
+    // Dropping the session unmounts the filesystem
+    state.profiles.active().vault_mount.lock().unwrap().take();
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+#[tauri::command]
+pub fn unmount_vault() -> CommandResult<()> {
+    Ok(())
+}
+
+// ============================================================================
+// VAULT LOCK
+// ============================================================================
+
+/// Lock a profile's vault: wipe in-memory keys, unmount the vault
+/// filesystem, cancel transfers, remove temp files and notify the frontend
+pub fn lock_profile(app: &AppHandle, profile: &Profile, reason: LockReason) {
+    profile.vault.lock();
+    
+    #[cfg(target_os = "linux")]
+    profile.vault_mount.lock().unwrap().take();
+    
+    profile.transfers.cancel_all();
+    profile.device_link.lock().unwrap().take();
+    profile.previews.clear();
+    profile.streams.clear();
+    profile.temp.wipe();
+    
+    let payload = VaultLockedPayload {
+        profile_id: profile.config.id.clone(),
+        reason,
+    };
+    if let Err(e) = app.emit(VAULT_LOCKED_EVENT, payload) {
+        log::warn!("Failed to emit vault-locked event: {}", e);
+    }
+}
+
+/// Unlock the active profile's vault by opening its keyring file with the
+/// passphrase
+#[tauri::command]
+pub async fn unlock_vault(
+    passphrase: String,
+    state: State<'_, AppState>,
+) -> CommandResult<VaultPublicKeys> {
+    let profile = state.profiles.active();
+    if !profile.keyring_path.exists() {
+        return Err(CommandError::new(ErrorCode::NotFound, "No keyring saved for this profile"));
+    }
+    
+    let path = profile.keyring_path.clone();
+    let keypair = run_blocking(move || load_protected_keyring(&path, &passphrase))
+        .await?
+        .or_command_error(ErrorCode::AuthenticationFailed, "Incorrect passphrase")?;
+    verify_user_keypair(&keypair)
+        .or_command_error(ErrorCode::InvalidKey, "Keypair is invalid")?;
+    
+    let public_keys = VaultPublicKeys::of(&keypair);
+    profile.vault.unlock(keypair);
+    Ok(public_keys)
+}
+
+/// Lock the active profile's vault immediately
+#[tauri::command]
+pub fn lock_vault(app: AppHandle, state: State<'_, AppState>) -> CommandResult<()> {
+    lock_profile(&app, &state.profiles.active(), LockReason::Manual);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_vault_status(state: State<'_, AppState>) -> CommandResult<VaultStatus> {
+    Ok(state.profiles.active().vault.status())
+}
+
+#[tauri::command]
+pub fn set_auto_lock_settings(
+    settings: AutoLockSettings,
+    state: State<'_, AppState>,
+) -> CommandResult<()> {
+    state.profiles.active().vault.set_settings(settings);
+    Ok(())
+}
+
+/// Reset the idle timer; the frontend calls this on user input
+#[tauri::command]
+pub fn record_activity(state: State<'_, AppState>) -> CommandResult<()> {
+    state.profiles.active().vault.touch();
+    Ok(())
+}
+
+/// Stage temp files in memory instead of on disk where supported (Linux)
+/// Returns whether memory staging is now in effect
+#[tauri::command]
+pub fn set_ram_staging(enabled: bool, state: State<'_, AppState>) -> CommandResult<bool> {
+    Ok(state.profiles.active().temp.set_ram_staging(enabled))
+}
+
+// ============================================================================
+// PROFILES
+// ============================================================================
+
+#[tauri::command]
+pub fn list_profiles(state: State<'_, AppState>) -> CommandResult<ProfilesFile> {
+    Ok(state.profiles.list())
+}
+
+#[tauri::command]
+pub fn get_active_profile(state: State<'_, AppState>) -> CommandResult<ProfileConfig> {
+    Ok(state.profiles.active().current_config())
+}
+
+/// Add a profile for an account on the given server
+/// The window CSP allows its origin from the next page load on, which
+/// `switch_profile` triggers on the frontend
+#[tauri::command]
+pub fn create_profile(
+    name: String,
+    server_url: String,
+    state: State<'_, AppState>,
+) -> CommandResult<ProfileConfig> {
+    if name.trim().is_empty() {
+        return Err(CommandError::new(ErrorCode::InvalidInput, "Profile name is required"));
+    }
+    if server_origin(&server_url).is_none() {
+        return Err(CommandError::new(ErrorCode::InvalidInput, "Server URL must be an http or https URL"));
+    }
+    
+    state.profiles.create(name.trim(), &server_url)
+        .or_command_error(ErrorCode::Io, "Failed to create profile")
+}
+
+/// Make another profile active
+/// The previous profile stays unlocked until it locks on its own
+#[tauri::command]
+pub fn switch_profile(
+    profile_id: String,
+    state: State<'_, AppState>,
+) -> CommandResult<ProfileConfig> {
+    let profile = state.profiles.switch(&profile_id)
+        .or_command_error(ErrorCode::NotFound, "Profile not found")?;
+    Ok(profile.current_config())
+}
+
+/// Lock and remove a profile together with its keyring and local files
+#[tauri::command]
+pub fn delete_profile(
+    profile_id: String,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> CommandResult<()> {
+    if state.profiles.active().config.id == profile_id {
+        return Err(CommandError::new(ErrorCode::InvalidInput, "Cannot delete the active profile"));
+    }
+    
+    let profile = state.profiles.get(&profile_id)
+        .ok_or_else(|| CommandError::new(ErrorCode::NotFound, "Profile not found"))?;
+    lock_profile(&app, &profile, LockReason::Manual);
+    
+    state.profiles.delete(&profile_id)
+        .or_command_error(ErrorCode::Io, "Failed to delete profile")?;
+    Ok(())
+}
+
+// ============================================================================
+// TRANSFERS
+// ============================================================================
+
+/// Uploads and downloads in flight for the active profile
+#[tauri::command]
+pub fn list_transfers(state: State<'_, AppState>) -> CommandResult<Vec<TransferInfo>> {
+    Ok(state.profiles.active().transfers.list())
+}
+
+#[tauri::command]
+pub fn cancel_transfer(
+    transfer_id: String,
+    state: State<'_, AppState>,
+) -> CommandResult<()> {
+    if state.profiles.active().transfers.cancel(&transfer_id) {
+        Ok(())
+    } else {
+        Err(CommandError::new(ErrorCode::NotFound, "Transfer not found"))
+    }
+}
+
+// ============================================================================
+// DEVICE LINKING
+// ============================================================================
+
+#[derive(Debug, Serialize)]
+pub struct LinkHandshake {
+    pub role: LinkRole,
+    pub safety_code: String,
+}
+
+/// Generate the short code the user types on the new device
+#[tauri::command]
+pub fn create_link_code() -> CommandResult<String> {
+    Ok(generate_link_code())
+}
+
+/// Join the other device through the relay and agree on a channel key
+/// Resolves once both devices have connected with the same code; the
+/// returned safety code must match on both screens before continuing
+#[tauri::command]
+pub async fn connect_device_link(
+    code: String,
+    role: LinkRole,
+    relay_addr: Option<String>,
+    state: State<'_, AppState>,
+) -> CommandResult<LinkHandshake> {
+    let profile = state.profiles.active();
+    if role == LinkRole::Sender {
+        profile.vault.ensure_unlocked()?;
+    }
+    
+    let relay_addr = relay_addr.unwrap_or_else(|| DEFAULT_RELAY_ADDR.to_string());
+    let link = LinkChannel::connect(&relay_addr, &code, role)
+        .await
+        .or_command_error(ErrorCode::NetworkError, "Device link failed")?;
+    
+    let handshake = LinkHandshake {
+        role,
+        safety_code: link.safety_code().to_string(),
+    };
+    *profile.device_link.lock().unwrap() = Some(link);
+    Ok(handshake)
+}
+
+fn take_device_link(profile: &Profile, role: LinkRole) -> CommandResult<LinkChannel> {
+    let mut device_link = profile.device_link.lock().unwrap();
+    match device_link.take() {
+        Some(link) if link.role() == role => Ok(link),
+        Some(link) => {
+            *device_link = Some(link);
+            Err(CommandError::new(ErrorCode::InvalidInput, "Wrong side of the device link"))
+        }
+        None => Err(CommandError::new(ErrorCode::NotFound, "No device link in progress")),
+    }
+}
+
+/// Send this profile's keyring to the linked device
+/// Call only after the user confirmed the safety codes match
+#[tauri::command]
+pub async fn send_linked_keyring(state: State<'_, AppState>) -> CommandResult<()> {
+    let profile = state.profiles.active();
+    let keypair = profile.vault.keypair()?;
+    let link = take_device_link(&profile, LinkRole::Sender)?;
+    
+    link.send_keyring(&keypair)
+        .await
+        .or_command_error(ErrorCode::NetworkError, "Failed to send keyring")?;
+    
+    audit(&profile, AuditOperation::KeyExport, json!({
+        "method": "device_link",
+        "x25519_public_key": keypair.x25519_public_key,
+    }));
+    Ok(())
+}
+
+/// Receive the keyring from the existing device, save it to this
+/// profile's keyring file under the passphrase and unlock the vault with it
+#[tauri::command]
+pub async fn receive_linked_keyring(
+    passphrase: String,
+    state: State<'_, AppState>,
+) -> CommandResult<VaultPublicKeys> {
+    let profile = state.profiles.active();
+    let link = take_device_link(&profile, LinkRole::Receiver)?;
+    
+    let keypair = link.receive_keyring()
+        .await
+        .or_command_error(ErrorCode::NetworkError, "Failed to receive keyring")?;
+    
+    store_vault_keyring(&profile, keypair, passphrase).await
+}
+
+/// Abandon a device link, closing the connection to the relay
+#[tauri::command]
+pub fn cancel_device_link(state: State<'_, AppState>) -> CommandResult<()> {
+    state.profiles.active().device_link.lock().unwrap().take();
+    Ok(())
+}
+
+// ============================================================================
+// AUDIT LOG
+// ============================================================================
+
+/// Check the active profile's audit log: hashes, chain links and signatures
+#[tauri::command]
+pub fn verify_audit_log(state: State<'_, AppState>) -> CommandResult<AuditVerification> {
+    state.profiles.active().audit.verify()
+        .or_command_error(ErrorCode::Io, "Failed to read audit log")
+}
+
+/// Verify the audit log and write it, with the device key, as JSON
+#[tauri::command]
+pub fn export_audit_log(
+    output_path: String,
+    state: State<'_, AppState>,
+) -> CommandResult<AuditVerification> {
+    let export = state.profiles.active().audit.export()
+        .or_command_error(ErrorCode::Io, "Failed to read audit log")?;
+    
+    let contents = serde_json::to_string_pretty(&export)
+        .or_command_error(ErrorCode::Internal, "Failed to serialize audit log")?;
+    std::fs::write(&output_path, contents)
+        .or_command_error(ErrorCode::Io, "Failed to write audit log export")?;
+    
+    Ok(export.verification)
+}
+
+// ============================================================================
+// FOLDER MANIFESTS
+// ============================================================================
+
+const KNOWN_MANIFEST_VERSIONS_FILE: &str = "manifest_versions.json";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateFolderManifestParams {
+    pub folder_id: String,
+    pub manifest_version: u64,
+    pub entries: Vec<ManifestEntry>,
+    pub folder_key_b64: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyFolderManifestParams {
+    pub folder_id: String,
+    pub encrypted_manifest: EncryptedManifest,
+    pub folder_key_b64: String,
+    pub owner_public_key: String, // Folder owner's Ed25519 public key
+    pub files: Vec<ListedFile>,   // Server's listing of the folder
+}
+
+/// Sign the folder's file list with the owner's key and encrypt it under
+/// the folder key; the result is stored on the server next to the folder
+#[tauri::command]
+pub fn create_folder_manifest(
+    params: CreateFolderManifestParams,
+    state: State<'_, AppState>,
+) -> CommandResult<EncryptedManifest> {
+    let profile = state.profiles.active();
+    let keypair = profile.vault.keypair()?;
+    let folder_key = decode_folder_key(&params.folder_key_b64)?;
+    
+    let versions_path = profile.data_dir.join(KNOWN_MANIFEST_VERSIONS_FILE);
+    let known_versions = load_known_versions(&versions_path)
+        .or_command_error(ErrorCode::Io, "Failed to read known manifest versions")?;
+    if let Some(known) = known_versions.get(&params.folder_id) {
+        if params.manifest_version <= *known {
+            return Err(CommandError::new(
+                ErrorCode::InvalidInput,
+                format!("Manifest version must be greater than {}", known),
+            ));
+        }
+    }
+    
+    let manifest = FolderManifest {
+        folder_id: params.folder_id,
+        manifest_version: params.manifest_version,
+        created_at: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        entries: params.entries,
+    };
+    
+    let encrypted = seal_manifest(&manifest, &keypair.ed25519_private_key, &folder_key)
+        .or_command_error(ErrorCode::InvalidKey, "Failed to seal manifest")?;
+    
+    remember_version(&versions_path, &manifest.folder_id, manifest.manifest_version)
+        .or_command_error(ErrorCode::Io, "Failed to record manifest version")?;
+    Ok(encrypted)
+}
+
+/// Check the owner's signed manifest and compare it with the server's
+/// listing, reporting dropped, added, altered or rolled back files
+#[tauri::command]
+pub fn verify_folder_manifest(
+    params: VerifyFolderManifestParams,
+    state: State<'_, AppState>,
+) -> CommandResult<ManifestReport> {
+    let profile = state.profiles.active();
+    profile.vault.ensure_unlocked()?;
+    let folder_key = decode_folder_key(&params.folder_key_b64)?;
+    
+    let manifest = open_manifest(
+        &params.encrypted_manifest,
+        &folder_key,
+        &params.owner_public_key,
+        &params.folder_id,
+    )
+    .or_command_error(ErrorCode::InvalidKey, "Manifest verification failed")?;
+    
+    let versions_path = profile.data_dir.join(KNOWN_MANIFEST_VERSIONS_FILE);
+    let known_versions = load_known_versions(&versions_path)
+        .or_command_error(ErrorCode::Io, "Failed to read known manifest versions")?;
+    let report = compare_listing(&manifest, &params.files, known_versions.get(&params.folder_id).copied());
+    
+    remember_version(&versions_path, &manifest.folder_id, manifest.manifest_version)
+        .or_command_error(ErrorCode::Io, "Failed to record manifest version")?;
+    Ok(report)
+}
+
+// ============================================================================
+// SHARE LINKS
+// ============================================================================
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateShareLinkParams {
+    pub file_id: String,
+    pub file_name: String,
+    pub dek_b64: String,  // File's unwrapped DEK
+    pub nonce: String,    // File's nonce
+    pub expires_in_secs: Option<u64>,
+    pub max_downloads: Option<u32>,
+    pub base_url: Option<String>, // Defaults to the profile's server URL
+    pub password: Option<String>, // Also require this password to open the link
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DownloadShareLinkParams {
+    pub link_url: String,
+    pub wrapped_payload: String, // As stored by the server for this link
+    pub payload_nonce: String,
+    pub download_count: u32,     // Downloads the server has counted so far; advisory only
+    pub download_url: String,
+    pub output_dir: String,
+    pub password: Option<String>, // For password-protected links
+    #[serde(default)]
+    pub file_size: Option<u64>, // Ciphertext size, when the server reports it
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShareLinkDownload {
+    pub output_path: String,
+    pub file_name: String,
+}
+
+/// Create an anonymous share link for a file
+/// The link key only ever lives in the URL fragment, never on the server
+#[tauri::command]
+pub async fn create_share_link(
+    params: CreateShareLinkParams,
+    state: State<'_, AppState>,
+) -> CommandResult<ShareLink> {
+    let profile = state.profiles.active();
+    profile.vault.ensure_unlocked()?;
+    
+    let base_url = params.base_url.clone().unwrap_or_else(|| profile.config.server_url.clone());
+    let file_id = params.file_id.clone();
+    // Password-protected links wrap the DEK with Argon2
+    let link = run_blocking(move || {
+        create_link(NewLink {
+            base_url: &base_url,
+            file_id: &params.file_id,
+            file_name: &params.file_name,
+            dek: &params.dek_b64,
+            nonce: &params.nonce,
+            expires_in_secs: params.expires_in_secs,
+            max_downloads: params.max_downloads,
+            password: params.password.as_deref(),
+        })
+    })
+    .await?
+    .or_command_error(ErrorCode::InvalidKey, "Failed to create share link")?;
+    
+    audit(&profile, AuditOperation::Share, json!({
+        "file_id": file_id,
+        "link_id": link.link_id,
+        "expires_at": link.expires_at,
+        "max_downloads": link.max_downloads,
+        "password_protected": link.password_protected,
+    }));
+    Ok(link)
+}
+
+/// Download and decrypt the file behind a share link
+/// Works without an unlocked vault, since link recipients have no account
+/// The file is saved under a new name in `output_dir` rather than replacing
+/// anything; the download limit relies on the server's count
+#[tauri::command]
+pub async fn download_share_link(
+    params: DownloadShareLinkParams,
+    state: State<'_, AppState>,
+) -> CommandResult<ShareLinkDownload> {
+    let profile = state.profiles.active();
+    
+    let payload = open_link(
+        &params.link_url,
+        &params.wrapped_payload,
+        &params.payload_nonce,
+        params.download_count,
+    )
+    .or_command_error(ErrorCode::InvalidInput, "Share link cannot be opened")?;
+    // Password-protected links unwrap the DEK with Argon2
+    let password = params.password.clone();
+    let (payload, dek) = run_blocking(move || {
+        let dek = payload.dek(password.as_deref());
+        (payload, dek)
+    })
+    .await?;
+    let dek = dek.or_command_error(ErrorCode::InvalidKey, "Incorrect password")?;
+    
+    // The file name comes from the sharer; never let it escape the output directory
+    let file_name = Path::new(&payload.file_name)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("download")
+        .to_string();
+    
+    let encrypted_file = profile.temp.stage("enc", params.file_size)
+        .or_command_error(ErrorCode::Io, "Failed to create temp file")?;
+    
+    let transfer = profile.transfers.run(TransferKind::Download, &file_name, async {
+        download_from_s3(&params.download_url, encrypted_file.path())
+            .await
+            .or_command_error(ErrorCode::NetworkError, "Download failed")?;
+        
+        let ciphertext = std::fs::read(encrypted_file.path())
+            .or_command_error(ErrorCode::Io, "Failed to read downloaded file")?;
+        let plaintext = Zeroizing::new(
+            decrypt_bytes_with_dek(&ciphertext, &dek, &payload.nonce)
+                .or_command_error(ErrorCode::DecryptionFailed, "Decryption failed")?,
+        );
+        
+        let (output_path, mut output_file) = create_download_file(Path::new(&params.output_dir), &file_name)
+            .or_command_error(ErrorCode::Io, "Failed to create output file")?;
+        if let Err(e) = output_file.write_all(&plaintext) {
+            let _ = std::fs::remove_file(&output_path);
+            return Err(CommandError::from_anyhow(ErrorCode::Io, "Failed to write decrypted file", e.into()));
+        }
+        
+        output_path.to_str()
+            .map(str::to_string)
+            .ok_or_else(|| CommandError::new(ErrorCode::InvalidInput, "Output path is not valid UTF-8"))
+    })
+    .await;
+    
+    let output_path = transfer?;
+    audit(&profile, AuditOperation::Decrypt, json!({
+        "command": "download_share_link",
+        "file_id": payload.file_id,
+        "link_id": payload.link_id,
+        "output_path": output_path,
+    }));
+    
+    // The saved name differs from the shared one when that was already taken
+    let file_name = Path::new(&output_path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(&file_name)
+        .to_string();
+    Ok(ShareLinkDownload {
+        output_path,
+        file_name,
+    })
+}
+
+// ============================================================================
+// VERSION HISTORY
+// ============================================================================
+
+const KNOWN_FILE_VERSIONS_FILE: &str = "file_versions.json";
+
+/// A file's stored version history and what is needed to open it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VersionHistoryRef {
+    pub file_id: String,
+    pub history: EncryptedVersionChain,
+    pub folder_key_b64: String,
+    pub owner_public_key: String, // File owner's Ed25519 public key
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadFileVersionParams {
+    pub upload: FileUploadParams,
+    pub file_id: String,
+    pub history: Option<EncryptedVersionChain>, // None for the first version
+    pub folder_key_b64: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileVersionUpload {
+    pub upload: FileUploadResponse,
+    pub version: u64,
+    pub history: EncryptedVersionChain, // Store this in place of the old history
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DownloadFileVersionParams {
+    pub history: VersionHistoryRef,
+    pub version: u64,
+    pub download_url: String, // Presigned URL for the version's file_key
+    pub output_path: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoredFileVersion {
+    pub version: u64,
+    pub history: EncryptedVersionChain,
+}
+
+/// Open a version history and make sure the server didn't hand back an
+/// older one than this device has already seen
+fn open_version_history(
+    profile: &Profile,
+    file_id: &str,
+    history: &EncryptedVersionChain,
+    folder_key: &[u8; 32],
+    owner_public_key: &str,
+) -> CommandResult<VersionChain> {
+    let chain = open_chain(history, folder_key, owner_public_key, file_id)
+        .or_command_error(ErrorCode::InvalidKey, "Version history verification failed")?;
+    let latest = chain.latest().map(|v| v.version).unwrap_or(0);
+    
+    let versions_path = profile.data_dir.join(KNOWN_FILE_VERSIONS_FILE);
+    let known_versions = load_known_versions(&versions_path)
+        .or_command_error(ErrorCode::Io, "Failed to read known file versions")?;
+    if let Some(known) = known_versions.get(file_id) {
+        if latest < *known {
+            return Err(CommandError::new(
+                ErrorCode::InvalidKey,
+                format!("Version history was rolled back from version {} to {}", known, latest),
+            ));
+        }
+    }
+    
+    remember_version(&versions_path, file_id, latest)
+        .or_command_error(ErrorCode::Io, "Failed to record file version")?;
+    Ok(chain)
+}
+
+/// Sign and encrypt a changed history with this user's key
+fn seal_version_history(
+    profile: &Profile,
+    chain: &VersionChain,
+    keypair: &UserKeypair,
+    folder_key: &[u8; 32],
+) -> CommandResult<EncryptedVersionChain> {
+    let sealed = seal_chain(chain, &keypair.ed25519_private_key, folder_key)
+        .or_command_error(ErrorCode::InvalidKey, "Failed to seal version history")?;
+    record_latest_version(profile, chain)?;
+    Ok(sealed)
+}
+
+/// Remember the newest version seen, so an older history is refused later
+fn record_latest_version(profile: &Profile, chain: &VersionChain) -> CommandResult<()> {
+    let latest = chain.latest().map(|v| v.version).unwrap_or(0);
+    remember_version(&profile.data_dir.join(KNOWN_FILE_VERSIONS_FILE), &chain.file_id, latest)
+        .or_command_error(ErrorCode::Io, "Failed to record file version")
+}
+
+/// Upload a new version of a file under a fresh DEK and append it to the
+/// file's signed version history
+/// The new entry is sealed and signed before the ciphertext is sent, so a
+/// failure there never leaves an object on the server that no history covers
+#[tauri::command]
+pub async fn upload_file_version(
+    params: UploadFileVersionParams,
+    state: State<'_, AppState>,
+) -> CommandResult<FileVersionUpload> {
+    let profile = state.profiles.active();
+    let keypair = profile.vault.keypair()?;
+    let folder_key = decode_folder_key(&params.folder_key_b64)?;
+    
+    let mut chain = match &params.history {
+        Some(history) => open_version_history(
+            &profile,
+            &params.file_id,
+            history,
+            &folder_key,
+            &keypair.ed25519_public_key,
+        )?,
+        None => VersionChain::new(&params.file_id),
+    };
+    
+    let (upload, (version, history)) = upload_encrypted_with(&profile, &params.upload, |encrypted, user_file_key| {
+        let version = chain.append(NewVersion {
+            file_key: params.upload.file_key.clone(),
+            wrapped_dek: encrypted.wrapped_dek.clone(),
+            nonce: encrypted.nonce.clone(),
+            file_size: encrypted.file_size,
+            original_filename: encrypted.original_filename.clone(),
+            ciphertext_sha256: encrypted.ciphertext_sha256.clone(),
+            user_file_key: user_file_key.to_string(),
+        })
+        .or_command_error(ErrorCode::Internal, "Failed to add version")?;
+        let history = seal_chain(&chain, &keypair.ed25519_private_key, &folder_key)
+            .or_command_error(ErrorCode::InvalidKey, "Failed to seal version history")?;
+        Ok((version, history))
+    })
+    .await?;
+    
+    // Only once the upload went through, so a failed one doesn't make the server's history look stale
+    record_latest_version(&profile, &chain)?;
+    Ok(FileVersionUpload { upload, version, history })
+}
+
+/// List every version of a file, oldest first
+#[tauri::command]
+pub fn list_file_versions(
+    history: VersionHistoryRef,
+    state: State<'_, AppState>,
+) -> CommandResult<Vec<FileVersion>> {
+    let profile = state.profiles.active();
+    let folder_key = decode_folder_key(&history.folder_key_b64)?;
+    
+    let chain = open_version_history(
+        &profile,
+        &history.file_id,
+        &history.history,
+        &folder_key,
+        &history.owner_public_key,
+    )?;
+    Ok(chain.versions)
+}
+
+/// Download and decrypt one version of a file
+/// The ciphertext must match the hash recorded in the signed history, and
+/// the DEK comes from the version's per-user file key, never a server key
+#[tauri::command]
+pub async fn download_file_version(
+    params: DownloadFileVersionParams,
+    state: State<'_, AppState>,
+) -> CommandResult<String> {
+    let profile = state.profiles.active();
+    profile.vault.ensure_unlocked()?;
+    let folder_key = decode_folder_key(&params.history.folder_key_b64)?;
+    
+    let chain = open_version_history(
+        &profile,
+        &params.history.file_id,
+        &params.history.history,
+        &folder_key,
+        &params.history.owner_public_key,
+    )?;
+    let version = chain.get(params.version)
+        .or_command_error(ErrorCode::NotFound, "Version not found")?;
+    let user_file_key = version.user_file_key.as_deref().ok_or_else(|| {
+        CommandError::new(ErrorCode::Unsupported, "This version was stored without a per-user file key")
+    })?;
+    let dek_base64 = unwrap_user_file_key(&profile, user_file_key)?;
+    
+    let download = DekDownload {
+        download_url: &params.download_url,
+        nonce: &version.nonce,
+        output_path: &params.output_path,
+        expected_sha256: Some(&version.ciphertext_sha256),
+        file_size: Some(version.file_size),
+    };
+    download_with_dek(&profile, download, &dek_base64, "download_file_version").await
+}
+
+/// Make an older version current by appending it to the history again
+/// Only the file owner can change the history
+#[tauri::command]
+pub fn restore_file_version(
+    history: VersionHistoryRef,
+    version: u64,
+    state: State<'_, AppState>,
+) -> CommandResult<RestoredFileVersion> {
+    let profile = state.profiles.active();
+    let keypair = profile.vault.keypair()?;
+    let folder_key = decode_folder_key(&history.folder_key_b64)?;
+    
+    if history.owner_public_key != keypair.ed25519_public_key {
+        return Err(CommandError::new(
+            ErrorCode::InvalidKey,
+            "Only the file owner can restore versions",
+        ));
+    }
+    
+    let mut chain = open_version_history(
+        &profile,
+        &history.file_id,
+        &history.history,
+        &folder_key,
+        &history.owner_public_key,
+    )?;
+    let restored = chain.restore(version)
+        .or_command_error(ErrorCode::NotFound, "Version not found")?;
+    
+    let history = seal_version_history(&profile, &chain, &keypair, &folder_key)?;
+    Ok(RestoredFileVersion { version: restored, history })
+}
+
+/// Compare the metadata of two versions of a file
+#[tauri::command]
+pub fn diff_file_versions(
+    history: VersionHistoryRef,
+    from_version: u64,
+    to_version: u64,
+    state: State<'_, AppState>,
+) -> CommandResult<VersionDiff> {
+    let profile = state.profiles.active();
+    let folder_key = decode_folder_key(&history.folder_key_b64)?;
+    
+    let chain = open_version_history(
+        &profile,
+        &history.file_id,
+        &history.history,
+        &folder_key,
+        &history.owner_public_key,
+    )?;
+    chain.diff(from_version, to_version)
+        .or_command_error(ErrorCode::NotFound, "Version not found")
+}
+
+// ============================================================================
+// CHUNKED STORAGE
+// ============================================================================
+
+#[derive(Debug, Serialize)]
+pub struct ChunkedUploadPlan {
+    pub manifest: EncryptedChunkManifest,
+    pub chunk_ids: Vec<String>, // Unique IDs; ask the server which are missing
+    pub chunk_count: usize,
+    pub file_size: u64,
+    pub escrow_wrapped_key: Option<String>, // Chunk key sealed to the profile's escrow key, if one is set
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadChunksParams {
+    pub file_path: String,
+    pub chunk_key_b64: String,
+    pub uploads: HashMap<String, String>, // Chunk ID -> presigned PUT URL, missing chunks only
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChunkUploadReport {
+    pub chunks_uploaded: usize,
+    pub bytes_uploaded: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DownloadChunkedFileParams {
+    pub manifest: EncryptedChunkManifest,
+    pub file_id: String,
+    pub version: u64, // Version the manifest was sealed for
+    pub chunk_key_b64: String,
+    pub chunk_urls: HashMap<String, String>, // Chunk ID -> presigned GET URL
+    pub output_path: String,
+}
+
+/// Where `decrypt_range` gets sealed chunks it hasn't cached yet
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ChunkSource {
+    /// Chunks stored as separate objects
+    Objects { chunk_urls: HashMap<String, String> }, // Chunk ID -> presigned GET URL
+    /// Sealed chunks stored back to back in manifest order, read with ranged GETs
+    Packed { packed_url: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DecryptRangeParams {
+    pub manifest: EncryptedChunkManifest,
+    pub file_id: String,
+    pub version: u64, // Version the manifest was sealed for
+    pub chunk_key_b64: String,
+    pub offset: u64,
+    pub length: u64,
+    pub source: ChunkSource,
+}
+
+// Sealed chunks are safe to keep on disk; they're cached here by ID
+const CHUNK_CACHE_DIR: &str = "chunks";
+// Largest range `decrypt_range` returns in one call
+const MAX_RANGE_LENGTH: u64 = 64 * 1024 * 1024;
+
+fn decode_chunk_key(chunk_key_b64: &str) -> CommandResult<Zeroizing<[u8; 32]>> {
+    let chunk_key = Zeroizing::new(base64::decode(chunk_key_b64)
+        .or_command_error(ErrorCode::InvalidKey, "Failed to decode chunk key")?);
+    if chunk_key.len() != 32 {
+        return Err(CommandError::new(ErrorCode::InvalidKey, "Invalid chunk key size"));
+    }
+    
+    let mut key = Zeroizing::new([0u8; 32]);
+    key.copy_from_slice(&chunk_key);
+    Ok(key)
+}
+
+fn decode_chunk_keys(chunk_key_b64: &str) -> CommandResult<ChunkKeys> {
+    let chunk_key = decode_chunk_key(chunk_key_b64)?;
+    ChunkKeys::derive(&chunk_key)
+        .or_command_error(ErrorCode::InvalidKey, "Failed to derive chunk keys")
+}
+
+/// Generate a random chunk key (256-bit)
+/// Use one per user to deduplicate across files, or one per file
+#[tauri::command]
+pub fn generate_chunk_key() -> CommandResult<String> {
+    use rand::RngCore;
+    let mut chunk_key = Zeroizing::new([0u8; 32]);
+    rand::rngs::OsRng.fill_bytes(chunk_key.as_mut());
+    Ok(base64::encode(chunk_key.as_ref()))
+}
+
+/// Chunk a file and build its encrypted manifest for `file_id` at `version`
+/// Nothing is uploaded; the caller asks the server which chunk IDs it lacks
+#[tauri::command]
+pub async fn plan_chunked_upload(
+    file_path: String,
+    chunk_key_b64: String,
+    file_id: String,
+    version: u64,
+    state: State<'_, AppState>,
+) -> CommandResult<ChunkedUploadPlan> {
+    let chunk_key = decode_chunk_key(&chunk_key_b64)?;
+    let keys = ChunkKeys::derive(&chunk_key)
+        .or_command_error(ErrorCode::InvalidKey, "Failed to derive chunk keys")?;
+    let escrow_wrapped_key = seal_for_escrow(&state.profiles.active(), &chunk_key)?;
+    
+    // Reading and hashing the whole file is slow; keep it off the async runtime
+    let (manifest, sealed) = run_blocking(move || -> CommandResult<_> {
+        let manifest = build_manifest(Path::new(&file_path), &keys)
+            .or_command_error(ErrorCode::Io, "Failed to chunk file")?;
+        let sealed = keys.seal_manifest(&manifest, &file_id, version)
+            .or_command_error(ErrorCode::InvalidKey, "Failed to seal chunk manifest")?;
+        Ok((manifest, sealed))
+    })
+    .await??;
+    
+    let mut seen = HashSet::new();
+    let chunk_ids = manifest.chunks
+        .iter()
+        .filter(|chunk| seen.insert(chunk.id.as_str()))
+        .map(|chunk| chunk.id.clone())
+        .collect();
+    
+    Ok(ChunkedUploadPlan {
+        manifest: sealed,
+        chunk_ids,
+        chunk_count: manifest.chunks.len(),
+        file_size: manifest.file_size,
+        escrow_wrapped_key,
+    })
+}
+
+/// Encrypt and upload the chunks the server is missing
+#[tauri::command]
+pub async fn upload_chunks(
+    params: UploadChunksParams,
+    state: State<'_, AppState>,
+) -> CommandResult<ChunkUploadReport> {
+    let profile = state.profiles.active();
+    let keys = decode_chunk_keys(&params.chunk_key_b64)?;
+    
+    profile.transfers.run(TransferKind::Upload, transfer_name(&params.file_path), async {
+        let mut pending = params.uploads.clone();
+        let mut report = ChunkUploadReport { chunks_uploaded: 0, bytes_uploaded: 0 };
+        
+        for chunk in chunk_file(Path::new(&params.file_path), &keys)
+            .or_command_error(ErrorCode::Io, "Failed to chunk file")?
+        {
+            let chunk = chunk.or_command_error(ErrorCode::Io, "Failed to chunk file")?;
+            let Some(url) = pending.remove(&chunk.id) else {
+                continue; // Already on the server, or a repeat within this file
+            };
+            
+            let sealed = keys.encrypt_chunk(&chunk)
+                .or_command_error(ErrorCode::InvalidKey, "Chunk encryption failed")?;
+            report.bytes_uploaded += sealed.len() as u64;
+            upload_bytes_to_s3(sealed, &url)
+                .await
+                .or_command_error(ErrorCode::NetworkError, "Chunk upload failed")?;
+            report.chunks_uploaded += 1;
+        }
+        
+        if !pending.is_empty() {
+            return Err(CommandError::new(
+                ErrorCode::InvalidInput,
+                "File changed since the upload was planned",
+            ));
+        }
+        Ok(report)
+    })
+    .await
+}
+
+/// Rebuild a chunked file from its manifest
+/// Every chunk is checked against its keyed ID before it is written
+#[tauri::command]
+pub async fn download_chunked_file(
+    params: DownloadChunkedFileParams,
+    state: State<'_, AppState>,
+) -> CommandResult<String> {
+    let profile = state.profiles.active();
+    profile.vault.ensure_unlocked()?;
+    let keys = decode_chunk_keys(&params.chunk_key_b64)?;
+    
+    let manifest = keys.open_manifest(&params.manifest, &params.file_id, params.version)
+        .or_command_error(ErrorCode::DecryptionFailed, "Failed to open chunk manifest")?;
+    
+    let transfer = profile.transfers.run(TransferKind::Download, transfer_name(&params.output_path), async {
+        let mut output = std::fs::File::create(&params.output_path)
+            .or_command_error(ErrorCode::Io, "Failed to create output file")?;
+        
+        for chunk in &manifest.chunks {
+            let url = params.chunk_urls.get(&chunk.id).ok_or_else(|| {
+                CommandError::new(ErrorCode::NotFound, format!("No download URL for chunk {}", chunk.id))
+            })?;
+            let sealed = download_bytes_from_s3(url, None)
+                .await
+                .or_command_error(ErrorCode::NetworkError, "Chunk download failed")?;
+            let data = keys.decrypt_chunk(&chunk.id, &sealed)
+                .or_command_error(ErrorCode::DecryptionFailed, "Chunk decryption failed")?;
+            
+            if data.len() as u64 != chunk.length {
+                return Err(CommandError::new(ErrorCode::DecryptionFailed, "Chunk has the wrong length"));
+            }
+            output.write_all(&data)
+                .or_command_error(ErrorCode::Io, "Failed to write output file")?;
+        }
+        
+        Ok(params.output_path.clone())
+    })
+    .await;
+    
+    // Never leave a partially rebuilt file behind
+    if transfer.is_err() {
+        let _ = std::fs::remove_file(&params.output_path);
+    }
+    
+    let output_path = transfer?;
+    audit(&profile, AuditOperation::Decrypt, json!({
+        "command": "download_chunked_file",
+        "key_id": key_id(&decode_chunk_key(&params.chunk_key_b64)?[..]),
+        "chunks": manifest.chunks.len(),
+        "output_path": output_path,
+    }));
+    Ok(output_path)
+}
+
+/// Sealed bytes of one chunk, from the local cache or the server
+async fn fetch_sealed_chunk(cache_dir: &Path, span: &ChunkSpan, source: &ChunkSource) -> CommandResult<Vec<u8>> {
+    let cached_path = cache_dir.join(&span.id);
+    if let Ok(sealed) = std::fs::read(&cached_path) {
+        return Ok(sealed);
+    }
+    
+    let sealed = match source {
+        ChunkSource::Objects { chunk_urls } => {
+            let url = chunk_urls.get(&span.id).ok_or_else(|| {
+                CommandError::new(ErrorCode::NotFound, format!("No download URL for chunk {}", span.id))
+            })?;
+            download_bytes_from_s3(url, Some(span.sealed_length())).await
+        }
+        ChunkSource::Packed { packed_url } => {
+            download_range_from_s3(packed_url, span.packed_offset, span.sealed_length()).await
+        }
+    }
+    .or_command_error(ErrorCode::NetworkError, "Chunk download failed")?;
+    
+    // Best effort; a failed cache write just means fetching it again next time
+    let partial_path = cache_dir.join(format!("{}.part", span.id));
+    if std::fs::write(&partial_path, &sealed).is_ok() {
+        let _ = std::fs::rename(&partial_path, &cached_path);
+    }
+    Ok(sealed)
+}
+
+/// Decrypt plaintext bytes `offset..offset + length` of a chunked file
+/// Only the chunks covering the range are fetched, and each is authenticated
+/// in full before any of its bytes are returned
+#[tauri::command]
+pub async fn decrypt_range(
+    params: DecryptRangeParams,
+    state: State<'_, AppState>,
+) -> CommandResult<tauri::ipc::Response> {
+    let profile = state.profiles.active();
+    profile.vault.ensure_unlocked()?;
+    
+    if params.length > MAX_RANGE_LENGTH {
+        return Err(CommandError::new(
+            ErrorCode::InvalidInput,
+            format!("Ranges are limited to {} bytes", MAX_RANGE_LENGTH),
+        ));
+    }
+    let keys = decode_chunk_keys(&params.chunk_key_b64)?;
+    let manifest = keys.open_manifest(&params.manifest, &params.file_id, params.version)
+        .or_command_error(ErrorCode::DecryptionFailed, "Failed to open chunk manifest")?;
+    let spans = manifest.locate_range(params.offset, params.length)
+        .or_command_error(ErrorCode::InvalidInput, "Invalid range")?;
+    
+    let cache_dir = profile.cache_dir.join(CHUNK_CACHE_DIR);
+    std::fs::create_dir_all(&cache_dir)
+        .or_command_error(ErrorCode::Io, "Failed to create chunk cache directory")?;
+    
+    let mut plaintext = Zeroizing::new(Vec::with_capacity(params.length as usize));
+    for span in &spans {
+        let sealed = fetch_sealed_chunk(&cache_dir, span, &params.source).await?;
+        let data = match keys.decrypt_span(span, &sealed) {
+            Ok(data) => data,
+            Err(e) => {
+                // Don't keep serving a corrupt cached chunk
+                let _ = std::fs::remove_file(cache_dir.join(&span.id));
+                return Err(CommandError::from_anyhow(ErrorCode::DecryptionFailed, "Chunk decryption failed", e));
+            }
+        };
+        plaintext.extend_from_slice(&data);
+    }
+    
+    audit(&profile, AuditOperation::Decrypt, json!({
+        "command": "decrypt_range",
+        "key_id": key_id(&decode_chunk_key(&params.chunk_key_b64)?[..]),
+        "offset": params.offset,
+        "length": params.length,
+        "chunks": spans.len(),
+    }));
+    Ok(tauri::ipc::Response::new(plaintext.to_vec()))
+}
+
+// ============================================================================
+// SEARCH
+// ============================================================================
+
+const SEARCH_INDEX_FILE: &str = "search_index.json";
+const DEFAULT_SEARCH_LIMIT: usize = 50;
+
+/// Load the profile's search index; needs the unlocked keyring for its key
+fn load_search_index(profile: &Profile) -> CommandResult<(SearchIndex, Zeroizing<[u8; 32]>)> {
+    let keypair = profile.vault.keypair()?;
+    let key = index_key(&keypair)
+        .or_command_error(ErrorCode::InvalidKey, "Failed to derive search index key")?;
+    let index = SearchIndex::load(&profile.data_dir.join(SEARCH_INDEX_FILE), &key)
+        .or_command_error(ErrorCode::DecryptionFailed, "Failed to open search index")?;
+    Ok((index, key))
+}
+
+fn save_search_index(profile: &Profile, index: &SearchIndex, key: &[u8; 32]) -> CommandResult<()> {
+    index.save(&profile.data_dir.join(SEARCH_INDEX_FILE), key)
+        .or_command_error(ErrorCode::Io, "Failed to save search index")
+}
+
+/// Add or update decrypted file metadata in the search index
+/// Returns the number of files in the index
+#[tauri::command]
+pub fn update_search_index(
+    entries: Vec<IndexEntry>,
+    state: State<'_, AppState>,
+) -> CommandResult<usize> {
+    let profile = state.profiles.active();
+    let (mut index, key) = load_search_index(&profile)?;
+    
+    for entry in entries {
+        index.upsert(entry);
+    }
+    
+    save_search_index(&profile, &index, &key)?;
+    Ok(index.live_count())
+}
+
+/// Remove files from the search index
+#[tauri::command]
+pub fn remove_from_search_index(
+    file_ids: Vec<String>,
+    state: State<'_, AppState>,
+) -> CommandResult<usize> {
+    let profile = state.profiles.active();
+    let (mut index, key) = load_search_index(&profile)?;
+    
+    for file_id in &file_ids {
+        index.remove(file_id);
+    }
+    
+    save_search_index(&profile, &index, &key)?;
+    Ok(index.live_count())
+}
+
+/// Search file names, tags and folder paths with prefix and fuzzy matching
+#[tauri::command]
+pub fn search_files(
+    query: String,
+    limit: Option<usize>,
+    state: State<'_, AppState>,
+) -> CommandResult<Vec<SearchHit>> {
+    let profile = state.profiles.active();
+    let (index, _) = load_search_index(&profile)?;
+    
+    Ok(index.query(&query, limit.unwrap_or(DEFAULT_SEARCH_LIMIT)))
+}
+
+/// The encrypted index, for syncing to the server as an opaque blob
+#[tauri::command]
+pub fn export_search_index(state: State<'_, AppState>) -> CommandResult<EncryptedSearchIndex> {
+    let profile = state.profiles.active();
+    let (index, key) = load_search_index(&profile)?;
+    
+    index.encrypt(&key)
+        .or_command_error(ErrorCode::InvalidKey, "Failed to encrypt search index")
+}
+
+/// Merge an index synced from another device into the local one
+#[tauri::command]
+pub fn import_search_index(
+    blob: EncryptedSearchIndex,
+    state: State<'_, AppState>,
+) -> CommandResult<usize> {
+    let profile = state.profiles.active();
+    let (mut index, key) = load_search_index(&profile)?;
+    
+    let synced = SearchIndex::decrypt(&blob, &key)
+        .or_command_error(ErrorCode::DecryptionFailed, "Failed to decrypt synced search index")?;
+    index.merge(synced);
+    
+    save_search_index(&profile, &index, &key)?;
+    Ok(index.live_count())
+}
+
+// ============================================================================
+// THUMBNAILS
+// ============================================================================
+
+fn decode_dek(dek_b64: &str) -> CommandResult<Zeroizing<[u8; 32]>> {
+    let dek_vec = Zeroizing::new(base64::decode(dek_b64)
+        .or_command_error(ErrorCode::InvalidKey, "Failed to decode DEK")?);
+    
+    if dek_vec.len() != 32 {
+        return Err(CommandError::new(ErrorCode::InvalidKey, "Invalid DEK size"));
+    }
+    
+    let mut dek = Zeroizing::new([0u8; 32]);
+    dek.copy_from_slice(&dek_vec);
+    Ok(dek)
+}
+
+/// Download and decrypt a file's thumbnail
+/// Returns raw JPEG bytes (an ArrayBuffer on the JS side)
+#[tauri::command]
+pub async fn get_file_thumbnail(
+    download_url: String,
+    dek_b64: String,
+    state: State<'_, AppState>,
+) -> CommandResult<tauri::ipc::Response> {
+    let profile = state.profiles.active();
+    profile.vault.ensure_unlocked()?;
+    let dek = decode_dek(&dek_b64)?;
+    
+    let sealed = download_bytes_from_s3(&download_url, None)
+        .await
+        .or_command_error(ErrorCode::NetworkError, "Thumbnail download failed")?;
+    let thumbnail = open_thumbnail(&sealed, &dek)
+        .or_command_error(ErrorCode::DecryptionFailed, "Thumbnail decryption failed")?;
+    
+    Ok(tauri::ipc::Response::new(thumbnail))
+}
+
+// ============================================================================
+// PREVIEWS
+// ============================================================================
+
+/// How to get a file's DEK for a preview
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PreviewKey {
+    /// Already unwrapped (shared or folder files)
+    Dek { dek_b64: String },
+    /// The current user's per-user file key, unwrapped with the vault once its grant checks out
+    FileKey {
+        user_file_key: String,
+        file_id: String,
+        #[serde(default)]
+        grant: Option<KeyGrant>,
+    },
+}
+
+impl PreviewKey {
+    fn resolve(&self, profiles: &ProfileManager, profile: &Profile) -> CommandResult<Zeroizing<[u8; 32]>> {
+        match self {
+            PreviewKey::Dek { dek_b64 } => decode_dek(dek_b64),
+            PreviewKey::FileKey { user_file_key, file_id, grant } => {
+                Ok(unwrap_granted_key(profiles, profile, user_file_key, file_id, grant.as_ref())?.0)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PreviewParams {
+    pub download_url: String,
+    pub nonce: String,
+    pub key: PreviewKey,
+    pub file_name: String,      // Used to pick the MIME type
+    pub max_bytes: Option<u64>, // Plaintext size cap; defaults to 50 MiB
+}
+
+/// Download and decrypt a file entirely in memory
+async fn fetch_preview(
+    profiles: &ProfileManager,
+    profile: &Profile,
+    params: &PreviewParams,
+    command: &str,
+) -> CommandResult<Preview> {
+    profile.vault.ensure_unlocked()?;
+    
+    let dek = params.key.resolve(profiles, profile)?;
+    let max_bytes = params.max_bytes.unwrap_or(DEFAULT_PREVIEW_LIMIT);
+    
+    let ciphertext = profile.transfers.run(TransferKind::Download, &params.file_name, async {
+        download_bytes_from_s3(&params.download_url, Some(max_bytes + TAG_SIZE as u64))
+            .await
+            .map_err(|e| match e.downcast_ref::<SizeLimitExceeded>() {
+                Some(_) => CommandError::new(
+                    ErrorCode::InvalidInput,
+                    format!("File is larger than the {} byte preview limit", max_bytes),
+                ),
+                None => CommandError::from_anyhow(ErrorCode::NetworkError, "Download failed", e),
+            })
+    })
+    .await?;
+    
+    let dek_b64 = Zeroizing::new(base64::encode(dek.as_ref()));
+    let data = Zeroizing::new(
+        decrypt_bytes_with_dek(&ciphertext, &dek_b64, &params.nonce)
+            .or_command_error(ErrorCode::DecryptionFailed, "Decryption failed")?,
+    );
+    
+    audit(profile, AuditOperation::Decrypt, json!({
+        "command": command,
+        "file_name": params.file_name,
+        "size": data.len(),
+    }));
+    Ok(Preview {
+        data,
+        mime_type: mime_type_for(&params.file_name).to_string(),
+    })
+}
+
+/// Decrypt a file into memory and return its bytes directly
+/// (an ArrayBuffer on the JS side); nothing is written to disk
+#[tauri::command]
+pub async fn preview_file(
+    params: PreviewParams,
+    state: State<'_, AppState>,
+) -> CommandResult<tauri::ipc::Response> {
+    let profile = state.profiles.active();
+    let preview = fetch_preview(&state.profiles, &profile, &params, "preview_file").await?;
+    
+    // The IPC response takes ownership; copy out so the original is zeroed
+    Ok(tauri::ipc::Response::new(preview.data.to_vec()))
+}
+
+/// Decrypt a file into memory and keep it behind a short-lived handle
+/// Read it with `read_preview`; it is dropped on close, expiry or lock
+#[tauri::command]
+pub async fn open_preview(
+    params: PreviewParams,
+    state: State<'_, AppState>,
+) -> CommandResult<PreviewInfo> {
+    let profile = state.profiles.active();
+    let preview = fetch_preview(&state.profiles, &profile, &params, "open_preview").await?;
+    
+    Ok(profile.previews.insert(preview))
+}
+
+/// Read part of an open preview; omit `length` to read to the end
+#[tauri::command]
+pub fn read_preview(
+    handle: String,
+    offset: Option<u64>,
+    length: Option<u64>,
+    state: State<'_, AppState>,
+) -> CommandResult<tauri::ipc::Response> {
+    let profile = state.profiles.active();
+    profile.vault.ensure_unlocked()?;
+    let preview = profile.previews.get(&handle)
+        .ok_or_else(|| CommandError::new(ErrorCode::NotFound, "Preview has expired or was closed"))?;
+    
+    let size = preview.data.len() as u64;
+    let start = offset.unwrap_or(0).min(size);
+    let end = length.map_or(size, |length| start.saturating_add(length).min(size));
+    
+    Ok(tauri::ipc::Response::new(preview.data[start as usize..end as usize].to_vec()))
+}
+
+/// Drop an open preview and zero its contents
+#[tauri::command]
+pub fn close_preview(handle: String, state: State<'_, AppState>) -> CommandResult<()> {
+    state.profiles.active().previews.remove(&handle);
+    Ok(())
+}
+
+// ============================================================================
+// STREAMING
+// ============================================================================
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StreamParams {
+    pub file_id: String, // Names the ciphertext cache entry
+    pub download_url: String,
+    pub nonce: String,
+    pub key: PreviewKey,
+    pub file_name: String, // Used to pick the MIME type
+}
+
+/// Make a file playable at a `kryptvault://` URL
+/// The ciphertext is cached on disk and authenticated once here; the
+/// protocol handler then decrypts only the ranges the webview asks for.
+/// The whole file is downloaded and verified before the URL is returned,
+/// since a file has a single tag; slower to start, but nothing unverified
+/// is ever played
+#[tauri::command]
+pub async fn open_stream(
+    params: StreamParams,
+    state: State<'_, AppState>,
+) -> CommandResult<StreamInfo> {
+    let profile = state.profiles.active();
+    profile.vault.ensure_unlocked()?;
+    
+    if params.file_id.is_empty() || params.file_id.contains(['/', '\\', '.']) {
+        return Err(CommandError::new(ErrorCode::InvalidInput, "Invalid file ID"));
+    }
+    let dek = params.key.resolve(&state.profiles, &profile)?;
+    
+    let cache_dir = profile.cache_dir.join("ciphertext");
+    std::fs::create_dir_all(&cache_dir)
+        .or_command_error(ErrorCode::Io, "Failed to create ciphertext cache directory")?;
+    let cached_path = cache_dir.join(format!("{}.enc", params.file_id));
+    
+    if !cached_path.exists() {
+        // Download to a .part file of its own, then rename, so a partial download
+        // is never mistaken for a cached file and two opens of the same file
+        // never write into the same .part
+        let partial_path = cache_dir.join(format!("{}.{}.part", params.file_id, uuid::Uuid::new_v4()));
+        let partial_str = partial_path.to_str()
+            .ok_or_else(|| CommandError::new(ErrorCode::Io, "Invalid cache path"))?;
+        
+        let downloaded = profile.transfers.run(TransferKind::Download, &params.file_name, async {
+            download_from_s3(&params.download_url, partial_str)
+                .await
+                .or_command_error(ErrorCode::NetworkError, "Download failed")
+        })
+        .await
+        .and_then(|_| {
+            std::fs::rename(&partial_path, &cached_path)
+                .or_command_error(ErrorCode::Io, "Failed to move downloaded file into cache")
+        });
+        if let Err(e) = downloaded {
+            let _ = std::fs::remove_file(&partial_path);
+            return Err(e);
+        }
+    }
+    
+    let mime_type = mime_type_for(&params.file_name);
+    let nonce = params.nonce.clone();
+    let path = cached_path.clone();
+    let source = tauri::async_runtime::spawn_blocking(move || StreamSource::open(&path, dek, &nonce, mime_type))
+        .await
+        .map_err(|e| CommandError::new(ErrorCode::Internal, format!("Verification task failed: {}", e)))?;
+    
+    let source = match source {
+        Ok(source) => source,
+        Err(e) => {
+            // A bad cache entry would fail every time; drop it so the next open re-downloads
+            let _ = std::fs::remove_file(&cached_path);
+            return Err(CommandError::from_anyhow(ErrorCode::DecryptionFailed, "Decryption failed", e));
+        }
+    };
+    
+    audit(&profile, AuditOperation::Decrypt, json!({
+        "command": "open_stream",
+        "file_id": params.file_id,
+        "file_name": params.file_name,
+        "size": source.size,
+    }));
+    Ok(profile.streams.insert(source))
+}
+
+/// Stop serving a stream handle and drop its key
+#[tauri::command]
+pub fn close_stream(handle: String, state: State<'_, AppState>) -> CommandResult<()> {
+    state.profiles.active().streams.remove(&handle);
+    Ok(())
+}
+
+// ============================================================================
+// MOVE INTO VAULT
+// ============================================================================
+
+/// How to confirm the server has the whole ciphertext before the original goes
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum UploadVerification {
+    /// HEAD the object and compare its size, and its checksum when S3 has one
+    Head { url: String }, // Presigned HEAD URL
+    /// Download the object and compare its SHA-256
+    RoundTrip { url: String }, // Presigned GET URL
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MoveIntoVaultParams {
+    #[serde(flatten)]
+    pub upload: FileUploadParams,
+    pub verification: UploadVerification,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MoveIntoVaultResponse {
+    #[serde(flatten)]
+    pub upload: FileUploadResponse,
+    pub deletion_id: Option<String>, // Pass to `delete_moved_original` once the upload is saved; None when the original is kept
+    pub kept_reason: Option<String>,
+}
+
+/// Check the uploaded object matches what was encrypted
+async fn verify_upload(verification: &UploadVerification, upload: &FileUploadResponse) -> Result<(), String> {
+    match verification {
+        UploadVerification::Head { url } => {
+            let object = head_s3_object(url).await.map_err(|e| format!("Upload check failed: {:#}", e))?;
+            check_uploaded_object(&object, upload.file_size, &upload.ciphertext_sha256)?;
+        }
+        UploadVerification::RoundTrip { url } => {
+            let ciphertext = download_bytes_from_s3(url, Some(upload.file_size))
+                .await
+                .map_err(|e| format!("Upload check failed: {:#}", e))?;
+            if base64::encode(Sha256::digest(&ciphertext)) != upload.ciphertext_sha256 {
+                return Err("Uploaded file does not match what was encrypted".to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Encrypt and upload a file and verify the upload
+/// Nothing is deleted here: once the server has saved the file's record,
+/// `delete_moved_original` removes the original. It is kept for good (with
+/// `kept_reason` set) if the upload can't be confirmed or the file changed
+/// while it was uploading
+#[tauri::command]
+pub async fn move_into_vault(
+    params: MoveIntoVaultParams,
+    state: State<'_, AppState>,
+) -> CommandResult<MoveIntoVaultResponse> {
+    let profile = state.profiles.active();
+    let source = Path::new(&params.upload.file_path);
+    let before = std::fs::metadata(source)
+        .or_command_error(ErrorCode::Io, "Failed to read file")?;
+    
+    let upload = upload_encrypted(&profile, &params.upload).await?;
+    
+    if let Err(reason) = verify_upload(&params.verification, &upload).await {
+        log::warn!("Keeping {}: {}", params.upload.file_path, reason);
+        return Ok(MoveIntoVaultResponse { upload, deletion_id: None, kept_reason: Some(reason) });
+    }
+    
+    let pending = PendingDeletion::new(source, &before);
+    if !pending.unchanged() {
+        let reason = "File changed while it was uploading".to_string();
+        return Ok(MoveIntoVaultResponse { upload, deletion_id: None, kept_reason: Some(reason) });
+    }
+    
+    let deletion_id = profile.pending_deletions.insert(pending);
+    Ok(MoveIntoVaultResponse { upload, deletion_id: Some(deletion_id), kept_reason: None })
+}
+
+/// Securely delete the original of a file moved into the vault
+/// Call only after the server has saved the upload's record, so a failed
+/// save never leaves the file with no copy at all
+#[tauri::command]
+pub fn delete_moved_original(deletion_id: String, state: State<'_, AppState>) -> CommandResult<DeletionReport> {
+    let profile = state.profiles.active();
+    let pending = profile.pending_deletions.take(&deletion_id)
+        .ok_or_else(|| CommandError::new(ErrorCode::NotFound, "No original is waiting to be deleted under this ID"))?;
+    if !pending.unchanged() {
+        return Err(CommandError::new(
+            ErrorCode::InvalidInput,
+            "File changed since it was uploaded; the original was kept",
+        ));
+    }
+    
+    let deletion = secure_delete(pending.path())
+        .or_command_error(ErrorCode::Io, "Deleting the original failed")?;
+    if let Some(caveat) = &deletion.caveat {
+        log::warn!("Deleted {} without a reliable overwrite: {}", pending.path().display(), caveat);
+    }
+    Ok(deletion)
+}
+
+// ============================================================================
+// INTEGRITY SCAN
+// ============================================================================
+
+const DEFAULT_SCAN_CONCURRENCY: usize = 4;
+const MAX_SCAN_CONCURRENCY: usize = 16;
+
+/// A stored file to check; which fields are needed depends on the scan depth
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScanRecord {
+    pub file_id: String,
+    #[serde(default)]
+    pub download_url: Option<String>, // Presigned GET URL, for checksum and decrypt scans
+    #[serde(default)]
+    pub head_url: Option<String>, // Presigned HEAD URL, for head scans
+    #[serde(default)]
+    pub file_size: Option<u64>, // Ciphertext size
+    #[serde(default)]
+    pub ciphertext_sha256: Option<String>,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub key: Option<PreviewKey>, // For decrypt scans
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScanParams {
+    pub scan_id: Option<String>, // A previous scan's ID to resume it
+    pub depth: ScanDepth,
+    pub records: Vec<ScanRecord>,
+    pub concurrency: Option<usize>,
+}
+
+fn scan_target(
+    profiles: &ProfileManager,
+    profile: &Profile,
+    record: ScanRecord,
+    depth: ScanDepth,
+) -> Result<ScanTarget, FileCheck> {
+    let dek = match (depth, &record.key) {
+        (ScanDepth::Decrypt, Some(key)) => match key.resolve(profiles, profile) {
+            Ok(dek) => Some(dek),
+            Err(e) => return Err(FileCheck::new(record.file_id, FileHealth::Undecryptable, Some(e.to_string()))),
+        },
+        _ => None,
+    };
+    
+    Ok(ScanTarget {
+        file_id: record.file_id,
+        download_url: record.download_url,
+        head_url: record.head_url,
+        file_size: record.file_size,
+        ciphertext_sha256: record.ciphertext_sha256,
+        dek,
+        nonce: record.nonce,
+    })
+}
+
+/// Check stored files for missing, corrupted or undecryptable ciphertexts
+/// Results are saved as they arrive; run again with the returned `scan_id`
+/// (and fresh URLs) to resume, skipping files that already have a result
+#[tauri::command]
+pub async fn scan_vault(
+    params: ScanParams,
+    state: State<'_, AppState>,
+) -> CommandResult<ScanReport> {
+    let profile = state.profiles.active();
+    if params.depth == ScanDepth::Decrypt {
+        profile.vault.ensure_unlocked()?;
+    }
+    
+    let scan_id = params.scan_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let depth = params.depth;
+    let mut scan = ScanState::load_or_new(&profile.data_dir, &scan_id, depth)
+        .or_command_error(ErrorCode::InvalidInput, "Failed to open scan")?;
+    let concurrency = params.concurrency.unwrap_or(DEFAULT_SCAN_CONCURRENCY).clamp(1, MAX_SCAN_CONCURRENCY);
+    
+    let file_ids: Vec<String> = params.records.iter().map(|record| record.file_id.clone()).collect();
+    let pending: Vec<ScanRecord> = params.records
+        .into_iter()
+        .filter(|record| !scan.is_done(&record.file_id))
+        .collect();
+    let skipped = file_ids.len() - pending.len();
+    let checked = pending.len();
+    
+    let transfer = profile.transfers.run(TransferKind::Download, "Integrity scan", async {
+        let mut pending = pending.into_iter();
+        let mut tasks = tokio::task::JoinSet::new();
+        
+        loop {
+            while tasks.len() < concurrency {
+                let Some(record) = pending.next() else { break };
+                match scan_target(&state.profiles, &profile, record, depth) {
+                    Ok(target) => {
+                        let profile = profile.clone();
+                        tasks.spawn(async move { check_file(&profile.temp, target, depth).await });
+                    }
+                    Err(check) => scan.record(check)
+                        .or_command_error(ErrorCode::Io, "Failed to save scan progress")?,
+                }
+            }
+            
+            let Some(result) = tasks.join_next().await else { break };
+            let check = result
+                .map_err(|e| CommandError::new(ErrorCode::Internal, format!("Scan task failed: {}", e)))?;
+            // Each result is on disk as soon as it is known, so a cancelled scan keeps its progress
+            scan.record(check)
+                .or_command_error(ErrorCode::Io, "Failed to save scan progress")?;
+        }
+        Ok(())
+    })
+    .await;
+    
+    transfer?;
+    
+    if depth == ScanDepth::Decrypt {
+        audit(&profile, AuditOperation::Decrypt, json!({
+            "command": "scan_vault",
+            "scan_id": scan_id,
+            "files": checked,
+        }));
+    }
+    Ok(scan.report(&file_ids, skipped))
+}
+
+// ============================================================================
+// KEY CONSISTENCY
+// ============================================================================
+
+/// A file's DEK wrapped under a folder key (`file_folder_key`)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FolderWrap {
+    pub wrapped_dek: String,    // Base64
+    pub wrapping_nonce: String, // Base64
+    pub folder_key_b64: String,
+}
+
+/// Every wrap of one file's DEK that the caller has
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyCheckRecord {
+    pub file_id: String,
+    #[serde(default)]
+    pub file_key: Option<String>, // Sealed to the current user's X25519 key
+    #[serde(default)]
+    pub folder_wraps: Vec<FolderWrap>,
+    #[serde(default)]
+    pub download_url: Option<String>, // With `nonce`, decides which DEK is right when they disagree
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub file_size: Option<u64>, // Ciphertext size, when known
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyCheckParams {
+    pub records: Vec<KeyCheckRecord>,
+    #[serde(default)]
+    pub repair: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyConsistency {
+    /// Every readable wrap gives the same DEK
+    Consistent,
+    /// Some wraps couldn't be unwrapped; the rest agree
+    Unreadable,
+    /// Wraps give different DEKs
+    Mismatch,
+    /// No wrap could be unwrapped, or none of the DEKs opens the ciphertext
+    Broken,
+}
+
+/// Where a DEK came from: `personal`, or `folder:<index into folder_wraps>`
+#[derive(Debug, Serialize)]
+pub struct KeyPathResult {
+    pub path: String,
+    pub unwrapped: bool,
+    pub matches: bool, // Gives the good DEK
+    pub detail: Option<String>,
+}
+
+/// Replacement wraps for the paths that were wrong, made from the good DEK
+/// Nothing is changed on the server; the caller stores these
+#[derive(Debug, Default, Serialize)]
+pub struct KeyRepair {
+    pub file_key: Option<String>,
+    pub folder_wraps: Vec<RepairedFolderWrap>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RepairedFolderWrap {
+    pub index: usize, // Into the record's `folder_wraps`
+    pub wrapped_dek: String,
+    pub wrapping_nonce: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeyCheckResult {
+    pub file_id: String,
+    pub status: KeyConsistency,
+    pub verified_against_ciphertext: bool,
+    pub paths: Vec<KeyPathResult>,
+    pub repair: Option<KeyRepair>,
+    pub error: Option<String>, // Why the ciphertext couldn't be checked, if it wasn't
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeyCheckReport {
+    pub checked: usize,
+    pub consistent: usize,
+    pub problems: usize,
+    pub repaired: usize,
+    pub results: Vec<KeyCheckResult>,
+}
+
+// A wrap's path name and its DEK, or why it couldn't be unwrapped
+type UnwrappedPath = (String, Result<Zeroizing<[u8; 32]>, String>);
+
+/// Unwrap every path of a record, in order: personal first, then folders
+fn unwrap_key_paths(record: &KeyCheckRecord, keypair: &UserKeypair) -> Vec<UnwrappedPath> {
+    let mut paths = Vec::new();
+    
+    if let Some(file_key) = &record.file_key {
+        let dek = unwrap_dek_for_user(file_key, &keypair.x25519_public_key, &keypair.x25519_private_key)
+            .map(Zeroizing::new)
+            .map_err(|e| format!("{:#}", e));
+        paths.push(("personal".to_string(), dek));
+    }
+    
+    for (index, wrap) in record.folder_wraps.iter().enumerate() {
+        let dek = decode_folder_key(&wrap.folder_key_b64)
+            .map_err(|e| e.to_string())
+            .and_then(|folder_key| {
+                decrypt_with_key(&wrap.wrapped_dek, &wrap.wrapping_nonce, &folder_key)
+                    .map(Zeroizing::new)
+                    .map_err(|e| format!("{:#}", e))
+            })
+            .and_then(|dek| {
+                <[u8; 32]>::try_from(dek.as_slice())
+                    .map(Zeroizing::new)
+                    .map_err(|_| "Invalid DEK size".to_string())
+            });
+        paths.push((format!("folder:{}", index), dek));
+    }
+    
+    paths
+}
+
+/// Download the ciphertext once and return which of the candidate DEKs opens it
+async fn dek_opening_ciphertext(
+    profile: &Profile,
+    download_url: &str,
+    nonce: &str,
+    file_size: Option<u64>,
+    candidates: &[Zeroizing<[u8; 32]>],
+) -> CommandResult<Option<usize>> {
+    let encrypted_file = profile.temp.stage("enc", file_size)
+        .or_command_error(ErrorCode::Io, "Failed to create temp file")?;
+    download_from_s3(download_url, encrypted_file.path())
+        .await
+        .or_command_error(ErrorCode::NetworkError, "Download failed")?;
+    
+    Ok(candidates
+        .iter()
+        .position(|dek| verify_file(Path::new(encrypted_file.path()), dek, nonce).is_ok()))
+}
+
+async fn check_key_record(
+    profile: &Profile,
+    keypair: &UserKeypair,
+    record: &KeyCheckRecord,
+    repair: bool,
+) -> CommandResult<KeyCheckResult> {
+    let unwrapped = unwrap_key_paths(record, keypair);
+    
+    // Distinct DEKs, compared in constant time
+    let mut candidates: Vec<Zeroizing<[u8; 32]>> = Vec::new();
+    for (_, dek) in &unwrapped {
+        if let Ok(dek) = dek {
+            if !candidates.iter().any(|known| sodiumoxide::utils::memcmp(&known[..], &dek[..])) {
+                candidates.push(dek.clone());
+            }
+        }
+    }
+    
+    // Only the ciphertext can say which DEK is right, and it's worth
+    // checking even when they agree before writing new wraps from it
+    let unverified = if candidates.len() == 1 { Some(0) } else { None };
+    let mut verified = false;
+    let mut error = None;
+    let good = match (&record.download_url, &record.nonce) {
+        (Some(url), Some(nonce)) if !candidates.is_empty() && (candidates.len() > 1 || repair) => {
+            // One file failing to download mustn't stop the rest of the batch
+            match dek_opening_ciphertext(profile, url, nonce, record.file_size, &candidates).await {
+                Ok(good) => {
+                    verified = true;
+                    good
+                }
+                Err(e) => {
+                    error = Some(e.message);
+                    unverified
+                }
+            }
+        }
+        _ if repair && !candidates.is_empty() => {
+            error = Some("No download URL and nonce given to check the DEK against".to_string());
+            unverified
+        }
+        _ => unverified,
+    };
+    let good_dek = good.map(|index| &candidates[index]);
+    
+    let paths: Vec<KeyPathResult> = unwrapped
+        .iter()
+        .map(|(path, dek)| KeyPathResult {
+            path: path.clone(),
+            unwrapped: dek.is_ok(),
+            matches: match (dek, good_dek) {
+                (Ok(dek), Some(good)) => sodiumoxide::utils::memcmp(&dek[..], &good[..]),
+                _ => false,
+            },
+            detail: dek.as_ref().err().cloned(),
+        })
+        .collect();
+    
+    let status = match good_dek {
+        None if candidates.len() > 1 => KeyConsistency::Mismatch,
+        None => KeyConsistency::Broken,
+        Some(_) if candidates.len() > 1 => KeyConsistency::Mismatch,
+        Some(_) if paths.iter().all(|path| path.matches) => KeyConsistency::Consistent,
+        Some(_) => KeyConsistency::Unreadable,
+    };
+    
+    // New wraps are only ever made from a DEK that opened the ciphertext
+    let repair = match good_dek {
+        Some(dek) if repair && verified && status != KeyConsistency::Consistent => {
+            let mut fix = KeyRepair::default();
+            if paths.iter().any(|path| path.path == "personal" && !path.matches) {
+                fix.file_key = Some(
+                    wrap_dek_for_recipient(dek, &keypair.x25519_public_key)
+                        .or_command_error(ErrorCode::InvalidKey, "Failed to re-wrap DEK")?,
+                );
+            }
+            for (index, wrap) in record.folder_wraps.iter().enumerate() {
+                let path = format!("folder:{}", index);
+                if paths.iter().any(|result| result.path == path && result.matches) {
+                    continue;
+                }
+                // A folder key that doesn't decode can't be repaired from here
+                let Ok(folder_key) = decode_folder_key(&wrap.folder_key_b64) else { continue };
+                let (wrapped_dek, wrapping_nonce) = encrypt_with_key(&dek[..], &folder_key)
+                    .or_command_error(ErrorCode::InvalidKey, "Failed to re-wrap DEK")?;
+                fix.folder_wraps.push(RepairedFolderWrap { index, wrapped_dek, wrapping_nonce });
+            }
+            Some(fix)
+        }
+        _ => None,
+    };
+    
+    Ok(KeyCheckResult {
+        file_id: record.file_id.clone(),
+        status,
+        verified_against_ciphertext: verified,
+        paths,
+        repair,
+        error,
+    })
+}
+
+/// Unwrap every available wrap of each file's DEK and check they agree
+/// With `repair`, returns new wraps made from the good DEK for the paths
+/// that disagree or can't be read; the good DEK is the one that opens the
+/// ciphertext, so repairs need a download URL and nonce
+#[tauri::command]
+pub async fn check_key_consistency(
+    params: KeyCheckParams,
+    state: State<'_, AppState>,
+) -> CommandResult<KeyCheckReport> {
+    let profile = state.profiles.active();
+    let keypair = profile.vault.keypair()?;
+    
+    let checked = params.records.len();
+    let repair = params.repair;
+    let results = profile.transfers.run(TransferKind::Download, "Key consistency check", async {
+        let mut results = Vec::with_capacity(checked);
+        for record in &params.records {
+            results.push(check_key_record(&profile, &keypair, record, repair).await?);
+        }
+        Ok(results)
+    })
+    .await?;
+    
+    audit(&profile, AuditOperation::Unwrap, json!({
+        "command": "check_key_consistency",
+        "files": checked,
+        "repair": repair,
+    }));
+    
+    let consistent = results.iter().filter(|result| result.status == KeyConsistency::Consistent).count();
+    Ok(KeyCheckReport {
+        checked,
+        consistent,
+        problems: checked - consistent,
+        repaired: results.iter().filter(|result| result.repair.is_some()).count(),
+        results,
+    })
+}
+
+// ============================================================================
+// LEGACY KEY MIGRATION
+// ============================================================================
+
+/// A file whose DEK is only sealed to the server keypair (`file.wrapped_dek`)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LegacyWrapRecord {
+    pub file_id: String,
+    pub wrapped_dek: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MigrateLegacyWrapsParams {
+    pub server_public_key: String,
+    pub server_private_key: String,
+    pub records: Vec<LegacyWrapRecord>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MigratedWrap {
+    pub file_id: String,
+    pub file_key: Option<String>, // DEK sealed to the current user; upload as the file's `file_key`
+    pub grant: Option<SignedShareGrant>, // Self-signed grant over `file_key`, stored with it
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LegacyMigrationReport {
+    pub migrated: usize,
+    pub failed: usize,
+    pub results: Vec<MigratedWrap>,
+}
+
+fn migrate_legacy_wrap(
+    record: &LegacyWrapRecord,
+    params: &MigrateLegacyWrapsParams,
+    keypair: &UserKeypair,
+) -> anyhow::Result<(String, SignedShareGrant)> {
+    let dek = Zeroizing::new(unwrap_dek(&record.wrapped_dek, &params.server_public_key, &params.server_private_key)?);
+    let file_key = wrap_dek_for_recipient(&dek, &keypair.x25519_public_key)?;
+    
+    // Make sure the new wrap opens before it replaces anything
+    let check = Zeroizing::new(unwrap_dek_for_user(&file_key, &keypair.x25519_public_key, &keypair.x25519_private_key)?);
+    if !sodiumoxide::utils::memcmp(&check[..], &dek[..]) {
+        return Err(anyhow::anyhow!("Re-wrapped DEK does not match"));
+    }
+
+    let grant = sign_share_grant(
+        &record.file_id,
+        &file_key,
+        &keypair.x25519_public_key,
+        &keypair.x25519_public_key,
+        &keypair.ed25519_private_key,
+    )?;
+    Ok((file_key, grant))
+}
+
+/// Unwrap DEKs sealed to the server keypair and re-wrap them for the
+/// current user, so the server private key no longer has to reach clients
+/// Nothing is uploaded; the caller stores each returned `file_key`
+#[tauri::command]
+pub fn migrate_legacy_wraps(
+    params: MigrateLegacyWrapsParams,
+    state: State<'_, AppState>,
+) -> CommandResult<LegacyMigrationReport> {
+    let profile = state.profiles.active();
+    let keypair = profile.vault.keypair()?;
+    ensure_legacy_server_keys(&profile)?;
+    
+    let results: Vec<MigratedWrap> = params.records
+        .iter()
+        .map(|record| match migrate_legacy_wrap(record, &params, &keypair) {
+            Ok((file_key, grant)) => MigratedWrap {
+                file_id: record.file_id.clone(),
+                file_key: Some(file_key),
+                grant: Some(grant),
+                error: None,
+            },
+            Err(e) => MigratedWrap {
+                file_id: record.file_id.clone(),
+                file_key: None,
+                grant: None,
+                error: Some(format!("{:#}", e)),
+            },
+        })
+        .collect();
+    
+    let migrated = results.iter().filter(|result| result.file_key.is_some()).count();
+    audit(&profile, AuditOperation::Unwrap, json!({
+        "command": "migrate_legacy_wraps",
+        "files": migrated,
+    }));
+    Ok(LegacyMigrationReport {
+        migrated,
+        failed: results.len() - migrated,
+        results,
+    })
+}
+
+/// Turn off (or back on) every command that takes a server private key
+/// for the active profile; saved with the profile
+/// This is the user's call and is not checked against the server: files
+/// still keyed only to the server keypair stay unreadable until they are
+/// migrated with `migrate_legacy_wraps` or the switch is turned back on
+#[tauri::command]
+pub fn set_legacy_server_keys_disabled(disabled: bool, state: State<'_, AppState>) -> CommandResult<()> {
+    let profile = state.profiles.active();
+    state.profiles.set_legacy_server_keys_disabled(&profile.config.id, disabled)
+        .or_command_error(ErrorCode::Io, "Failed to save profile settings")
+}
+
+// ============================================================================
+// ESCROW
+// ============================================================================
+
+/// Fingerprint of an organization signing key, for the user to check with
+/// the organization before pinning it
+#[tauri::command]
+pub fn org_key_fingerprint(org_signing_key: String) -> CommandResult<String> {
+    crate::escrow::org_key_fingerprint(&org_signing_key)
+        .or_command_error(ErrorCode::InvalidKey, "Invalid organization key")
+}
+
+/// Pin the organization signing key escrow keys must be signed by
+/// `fingerprint` is the one the user was shown and confirmed; the key is
+/// only pinned if it still matches
+#[tauri::command]
+pub fn pin_org_signing_key(
+    org_signing_key: String,
+    fingerprint: String,
+    state: State<'_, AppState>,
+) -> CommandResult<String> {
+    let actual = crate::escrow::org_key_fingerprint(&org_signing_key)
+        .or_command_error(ErrorCode::InvalidKey, "Invalid organization key")?;
+    let normalize = |fingerprint: &str| -> String {
+        fingerprint.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_lowercase()
+    };
+    if normalize(&fingerprint) != normalize(&actual) {
+        return Err(CommandError::new(ErrorCode::InvalidKey, "Fingerprint does not match the organization key"));
+    }
+
+    let profile = state.profiles.active();
+    state.profiles.pin_org_signing_key(&profile.config.id, &org_signing_key)
+        .or_command_error(ErrorCode::InvalidKey, "A different organization key is already pinned")?;
+    Ok(actual)
+}
+
+/// Set (or with `None`, clear) the active profile's organization escrow key
+/// The key must be signed by the organization key pinned with
+/// `pin_org_signing_key` (or built in), and clearing it needs that key's
+/// `clear_signature`
+#[tauri::command]
+pub fn set_escrow_key(
+    escrow_key: Option<EscrowKey>,
+    clear_signature: Option<String>,
+    state: State<'_, AppState>,
+) -> CommandResult<()> {
+    let profile = state.profiles.active();
+    profile.vault.ensure_unlocked()?;
+    
+    match (&escrow_key, profile.escrow_key()) {
+        (Some(escrow_key), _) => {
+            let pinned = profile.org_signing_key().ok_or_else(|| {
+                CommandError::new(ErrorCode::InvalidKey, "Pin the organization's signing key before setting an escrow key")
+            })?;
+            escrow_key.verify(&pinned)
+                .or_command_error(ErrorCode::InvalidKey, "Escrow key is not signed by the organization key")?;
+        }
+        (None, Some(current)) => {
+            let signature = clear_signature.as_deref().ok_or_else(|| {
+                CommandError::new(ErrorCode::InvalidKey, "Removing the escrow key needs the organization's signature")
+            })?;
+            current.verify_clearance(signature)
+                .or_command_error(ErrorCode::InvalidKey, "Removing the escrow key was not signed by the organization key")?;
+        }
+        (None, None) => {}
+    }
+    
+    state.profiles.set_escrow_key(&profile.config.id, escrow_key)
+        .or_command_error(ErrorCode::Io, "Failed to save profile settings")
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EscrowRecoveryParams {
+    pub file_id: String,
+    pub escrow_wrapped_dek: String,
+    pub escrow_public_key: String,
+    pub escrow_private_key: String,
+    pub reason: Option<String>, // Recorded in the audit log
+}
+
+/// Recover a file's DEK with the organization's escrow private key
+/// Every attempt is audited, whether or not it succeeds
+#[tauri::command]
+pub fn recover_escrowed_dek(
+    params: EscrowRecoveryParams,
+    state: State<'_, AppState>,
+) -> CommandResult<String> {
+    let profile = state.profiles.active();
+    profile.vault.ensure_unlocked()?;
+    
+    let dek = unwrap_dek_for_user(&params.escrow_wrapped_dek, &params.escrow_public_key, &params.escrow_private_key)
+        .map(Zeroizing::new);
+    
+    audit(&profile, AuditOperation::EscrowRecovery, json!({
+        "command": "recover_escrowed_dek",
+        "file_id": params.file_id,
+        "key_id": dek.as_ref().ok().map(|dek| key_id(&dek[..])),
+        "escrow_key_id": fingerprint(&params.escrow_public_key),
+        "reason": params.reason,
+        "success": dek.is_ok(),
+    }));
     
-    // Return base64 encoded sealed box
-    Ok(base64::encode(&sealed))
+    let dek = dek.or_command_error(ErrorCode::InvalidKey, "Failed to unwrap escrowed DEK")?;
+    Ok(base64::encode(dek.as_ref()))
 }
@@ -5,13 +5,27 @@ use chacha20poly1305::{
 };
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use sodiumoxide::crypto::sealedbox;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::Path;
+use zeroize::{Zeroize, Zeroizing};
 
 const NONCE_SIZE: usize = 24; // XChaCha20 uses 192-bit nonces
 const KEY_SIZE: usize = 32; // 256-bit key
+pub const TAG_SIZE: usize = 16; // Poly1305 authentication tag
+const SALT_SIZE: usize = 16;
+
+// Argon2id defaults for password-wrapped keys: 64 MiB, 3 passes, 1 lane
+const ARGON2_MEMORY_KIB: u32 = 64 * 1024;
+const ARGON2_ITERATIONS: u32 = 3;
+const ARGON2_PARALLELISM: u32 = 1;
+// Upper bounds accepted when unwrapping, so a stored blob can't make us
+// allocate gigabytes or spin for minutes
+const ARGON2_MAX_MEMORY_KIB: u32 = 1024 * 1024;
+const ARGON2_MAX_ITERATIONS: u32 = 10;
+const ARGON2_MAX_PARALLELISM: u32 = 8;
 
 /// User keypairs for E2EE
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -22,6 +36,13 @@ pub struct UserKeypair {
     pub ed25519_private_key: String, // Base64 - NEVER send to server
 }
 
+impl Zeroize for UserKeypair {
+    fn zeroize(&mut self) {
+        self.x25519_private_key.zeroize();
+        self.ed25519_private_key.zeroize();
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EncryptionResult {
     pub encrypted_file_path: String,
@@ -29,6 +50,7 @@ pub struct EncryptionResult {
     pub nonce: String,        // Base64 encoded nonce
     pub file_size: u64,
     pub original_filename: String,
+    pub ciphertext_sha256: String, // Base64 SHA-256 of the encrypted file
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -40,7 +62,7 @@ pub struct DecryptionParams {
 }
 
 /// Generate a random 256-bit DEK (Data Encryption Key)
-fn generate_dek() -> [u8; KEY_SIZE] {
+pub fn generate_dek() -> [u8; KEY_SIZE] {
     let mut dek = [0u8; KEY_SIZE];
     OsRng.fill_bytes(&mut dek);
     dek
@@ -108,16 +130,27 @@ pub fn encrypt_file(
     input_path: &str,
     output_path: &str,
     server_public_key: &str,
+) -> Result<EncryptionResult> {
+    let dek = Zeroizing::new(generate_dek());
+    encrypt_file_with_dek(input_path, output_path, &dek, server_public_key)
+}
+
+/// Encrypt a file under a DEK the caller generated, e.g. to derive
+/// companion keys (thumbnails) from the same DEK
+pub fn encrypt_file_with_dek(
+    input_path: &str,
+    output_path: &str,
+    dek: &[u8; KEY_SIZE],
+    server_public_key: &str,
 ) -> Result<EncryptionResult> {
     // Initialize sodiumoxide
     sodiumoxide::init().map_err(|_| anyhow::anyhow!("Failed to initialize sodiumoxide"))?;
     
-    // Generate random DEK and nonce
-    let dek = generate_dek();
+    // Generate random nonce
     let nonce_bytes = generate_nonce();
     
     // Create cipher
-    let cipher = XChaCha20Poly1305::new(&dek.into());
+    let cipher = XChaCha20Poly1305::new(dek.into());
     let nonce = XNonce::from_slice(&nonce_bytes);
     
     // Read input file
@@ -139,7 +172,7 @@ pub fn encrypt_file(
         .context("Failed to write encrypted file")?;
     
     // Wrap the DEK with server's public key
-    let wrapped_dek = wrap_dek(&dek, server_public_key)?;
+    let wrapped_dek = wrap_dek(dek, server_public_key)?;
     
     // Get original filename
     let original_filename = Path::new(input_path)
@@ -154,6 +187,7 @@ pub fn encrypt_file(
         nonce: base64::encode(&nonce_bytes),
         file_size: ciphertext.len() as u64,
         original_filename,
+        ciphertext_sha256: base64::encode(Sha256::digest(&ciphertext)),
     })
 }
 
@@ -210,6 +244,32 @@ pub fn decrypt_file_with_dek(
     nonce_base64: &str,
     output_path: &str,
 ) -> Result<String> {
+    // Read encrypted file
+    let mut input_file = File::open(encrypted_file_path)
+        .context("Failed to open encrypted file")?;
+    let mut ciphertext = Vec::new();
+    input_file.read_to_end(&mut ciphertext)
+        .context("Failed to read encrypted file")?;
+    
+    // Decrypt the data
+    let plaintext = decrypt_bytes_with_dek(&ciphertext, dek_base64, nonce_base64)?;
+    
+    // Write decrypted data to output file
+    let mut output_file = File::create(output_path)
+        .context("Failed to create output file")?;
+    output_file.write_all(&plaintext)
+        .context("Failed to write decrypted file")?;
+    
+    Ok(output_path.to_string())
+}
+
+/// Decrypt an in-memory ciphertext with an already unwrapped DEK
+/// The plaintext never touches disk - used for mounts and previews
+pub fn decrypt_bytes_with_dek(
+    ciphertext: &[u8],
+    dek_base64: &str,
+    nonce_base64: &str,
+) -> Result<Vec<u8>> {
     // Decode the DEK
     let dek_bytes = base64::decode(dek_base64)
         .context("Failed to decode DEK")?;
@@ -233,25 +293,10 @@ pub fn decrypt_file_with_dek(
     let cipher = XChaCha20Poly1305::new(&dek.into());
     let nonce = XNonce::from_slice(&nonce_bytes);
     
-    // Read encrypted file
-    let mut input_file = File::open(encrypted_file_path)
-        .context("Failed to open encrypted file")?;
-    let mut ciphertext = Vec::new();
-    input_file.read_to_end(&mut ciphertext)
-        .context("Failed to read encrypted file")?;
-    
     // Decrypt the data
-    let plaintext = cipher
-        .decrypt(nonce, ciphertext.as_ref())
-        .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))?;
-    
-    // Write decrypted data to output file
-    let mut output_file = File::create(output_path)
-        .context("Failed to create output file")?;
-    output_file.write_all(&plaintext)
-        .context("Failed to write decrypted file")?;
-    
-    Ok(output_path.to_string())
+    cipher
+        .decrypt(nonce, ciphertext)
+        .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))
 }
 
 /// Generate a new libsodium keypair for the server
@@ -285,6 +330,35 @@ pub fn generate_user_keypair() -> Result<UserKeypair> {
     })
 }
 
+/// Check that the private keys in a user keypair match its public keys
+pub fn verify_user_keypair(keypair: &UserKeypair) -> Result<()> {
+    sodiumoxide::init().map_err(|_| anyhow::anyhow!("Failed to initialize sodiumoxide"))?;
+    
+    let x25519_sk_bytes = base64::decode(&keypair.x25519_private_key)
+        .context("Failed to decode X25519 private key")?;
+    let x25519_sk = sodiumoxide::crypto::scalarmult::curve25519::Scalar::from_slice(&x25519_sk_bytes)
+        .context("Invalid X25519 private key")?;
+    let derived_pk = sodiumoxide::crypto::scalarmult::curve25519::scalarmult_base(&x25519_sk);
+    
+    if base64::encode(derived_pk.as_ref()) != keypair.x25519_public_key {
+        return Err(anyhow::anyhow!("X25519 private key does not match public key"));
+    }
+    
+    // An Ed25519 secret key embeds its public key in the last 32 bytes
+    if !keypair.ed25519_private_key.is_empty() {
+        let ed25519_sk_bytes = base64::decode(&keypair.ed25519_private_key)
+            .context("Failed to decode Ed25519 private key")?;
+        let ed25519_sk = sodiumoxide::crypto::sign::SecretKey::from_slice(&ed25519_sk_bytes)
+            .context("Invalid Ed25519 private key")?;
+        
+        if base64::encode(ed25519_sk.public_key().as_ref()) != keypair.ed25519_public_key {
+            return Err(anyhow::anyhow!("Ed25519 private key does not match public key"));
+        }
+    }
+    
+    Ok(())
+}
+
 /// Wrap DEK with recipient's X25519 public key (sealed box)
 /// This is used for sharing - recipient can unwrap with their private key
 pub fn wrap_dek_for_recipient(dek: &[u8; KEY_SIZE], recipient_public_key: &str) -> Result<String> {
@@ -380,6 +454,81 @@ pub fn decrypt_with_key(
     Ok(plaintext)
 }
 
+/// Argon2id parameters stored next to a password-wrapped key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordKdfParams {
+    pub algorithm: String, // Always "argon2id"
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    pub salt: String, // Base64
+}
+
+/// Data encrypted under a key derived from a password
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordWrappedKey {
+    pub ciphertext: String, // Base64
+    pub nonce: String,      // Base64
+    pub kdf: PasswordKdfParams,
+}
+
+fn derive_password_key(password: &str, salt: &[u8], kdf: &PasswordKdfParams) -> Result<Zeroizing<[u8; KEY_SIZE]>> {
+    let params = argon2::Params::new(kdf.memory_kib, kdf.iterations, kdf.parallelism, Some(KEY_SIZE))
+        .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {}", e))?;
+    let argon2 = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+    
+    let mut key = Zeroizing::new([0u8; KEY_SIZE]);
+    argon2
+        .hash_password_into(password.as_bytes(), salt, key.as_mut())
+        .map_err(|e| anyhow::anyhow!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+/// Encrypt data (usually a DEK) under a key derived from a password with
+/// Argon2id and a random salt
+pub fn encrypt_with_password(data: &[u8], password: &str) -> Result<PasswordWrappedKey> {
+    if password.is_empty() {
+        return Err(anyhow::anyhow!("Password must not be empty"));
+    }
+    
+    let mut salt = [0u8; SALT_SIZE];
+    OsRng.fill_bytes(&mut salt);
+    let kdf = PasswordKdfParams {
+        algorithm: "argon2id".to_string(),
+        memory_kib: ARGON2_MEMORY_KIB,
+        iterations: ARGON2_ITERATIONS,
+        parallelism: ARGON2_PARALLELISM,
+        salt: base64::encode(salt),
+    };
+    
+    let key = derive_password_key(password, &salt, &kdf)?;
+    let (ciphertext, nonce) = encrypt_with_key(data, &key)?;
+    
+    Ok(PasswordWrappedKey { ciphertext, nonce, kdf })
+}
+
+/// Decrypt data wrapped by `encrypt_with_password`
+/// A wrong password and tampered data fail identically, after the full
+/// key derivation, so the failure reveals nothing about which it was
+pub fn decrypt_with_password(wrapped: &PasswordWrappedKey, password: &str) -> Result<Vec<u8>> {
+    let kdf = &wrapped.kdf;
+    if kdf.algorithm != "argon2id" {
+        return Err(anyhow::anyhow!("Unsupported key derivation: {}", kdf.algorithm));
+    }
+    if kdf.memory_kib > ARGON2_MAX_MEMORY_KIB
+        || kdf.iterations > ARGON2_MAX_ITERATIONS
+        || kdf.parallelism > ARGON2_MAX_PARALLELISM
+    {
+        return Err(anyhow::anyhow!("Key derivation parameters exceed the allowed limits"));
+    }
+    
+    let salt = base64::decode(&kdf.salt).context("Failed to decode salt")?;
+    let key = derive_password_key(password, &salt, kdf)?;
+    
+    decrypt_with_key(&wrapped.ciphertext, &wrapped.nonce, &key)
+        .map_err(|_| anyhow::anyhow!("Incorrect password or corrupted data"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -423,4 +572,15 @@ mod tests {
         let decrypted_content = fs::read(&decrypted_path).unwrap();
         assert_eq!(test_content, decrypted_content.as_slice());
     }
+    
+    #[test]
+    fn test_password_wrapping() {
+        let dek = generate_dek();
+        
+        let wrapped = encrypt_with_password(&dek, "correct horse battery staple").unwrap();
+        let unwrapped = decrypt_with_password(&wrapped, "correct horse battery staple").unwrap();
+        assert_eq!(dek.as_slice(), unwrapped.as_slice());
+        
+        assert!(decrypt_with_password(&wrapped, "wrong password").is_err());
+    }
 }
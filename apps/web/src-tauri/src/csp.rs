@@ -0,0 +1,69 @@
+//! Content Security Policy for the app window
+//! Each profile picks its own server, so their origins are added to
+//! `connect-src` whenever a page loads instead of being fixed in tauri.conf.json
+
+/// `scheme://host[:port]` of an http(s) server URL, usable as a CSP source
+pub fn server_origin(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return None;
+    }
+    Some(url.origin().ascii_serialization())
+}
+
+/// Add `sources` to the policy's `connect-src` directive, creating it if missing
+pub fn with_connect_sources(policy: &str, sources: &[String]) -> String {
+    let mut directives: Vec<String> = policy
+        .split(';')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    let index = match directives
+        .iter()
+        .position(|directive| directive.split_whitespace().next() == Some("connect-src"))
+    {
+        Some(index) => index,
+        None => {
+            directives.push("connect-src 'self'".to_string());
+            directives.len() - 1
+        }
+    };
+
+    for source in sources {
+        if !directives[index].split_whitespace().any(|existing| existing == source) {
+            directives[index].push(' ');
+            directives[index].push_str(source);
+        }
+    }
+    directives.join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_origin() {
+        assert_eq!(server_origin("https://api.example.com/v1/"), Some("https://api.example.com".to_string()));
+        assert_eq!(server_origin("http://localhost:3000"), Some("http://localhost:3000".to_string()));
+        assert_eq!(server_origin("javascript:alert(1)"), None);
+        assert_eq!(server_origin("not a url"), None);
+    }
+
+    #[test]
+    fn test_connect_sources_are_added_once() {
+        let policy = "default-src 'self'; connect-src 'self' http://localhost:3000; img-src 'self'";
+        let sources = vec!["http://localhost:3000".to_string(), "https://api.example.com".to_string()];
+
+        assert_eq!(
+            with_connect_sources(policy, &sources),
+            "default-src 'self'; connect-src 'self' http://localhost:3000 https://api.example.com; img-src 'self'"
+        );
+        assert_eq!(
+            with_connect_sources("default-src 'self';", &sources[1..]),
+            "default-src 'self'; connect-src 'self' https://api.example.com"
+        );
+    }
+}
@@ -0,0 +1,313 @@
+//! Device linking: move a keyring to a new device over a relay
+//!
+//! Both devices run SPAKE2 on a short code the user types, so the relay
+//! never learns the channel key. The keyring is then sent encrypted with a
+//! key derived from the PAKE output, and both devices show a safety code
+//! derived from the same output for the user to compare.
+
+use crate::crypto::{decrypt_with_key, encrypt_with_key, verify_user_keypair, UserKeypair};
+use anyhow::{Context, Result};
+use hkdf::Hkdf;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use spake2::{Ed25519Group, Identity, Password, Spake2};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use zeroize::Zeroizing;
+
+pub const DEFAULT_RELAY_ADDR: &str = "127.0.0.1:7420";
+
+// How long a device waits for the other one to join
+const LINK_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+const SENDER_IDENTITY: &[u8] = b"kryptvault-link-sender";
+const RECEIVER_IDENTITY: &[u8] = b"kryptvault-link-receiver";
+const CONFIRM_MESSAGE: &[u8] = b"kryptvault-link-confirm";
+const ACK_MESSAGE: &[u8] = b"kryptvault-link-ack";
+
+/// Which side of the link this device is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkRole {
+    Sender,   // Existing device that already has the keyring
+    Receiver, // New device
+}
+
+/// The two devices typed different codes (or someone guessed wrong)
+#[derive(Debug)]
+pub struct CodeMismatch;
+
+impl fmt::Display for CodeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Link code does not match the other device")
+    }
+}
+
+impl std::error::Error for CodeMismatch {}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedFrame {
+    ciphertext: String, // Base64
+    nonce: String,      // Base64
+}
+
+/// Generate a link code such as `4821-093117`
+/// The first group picks the relay channel; the whole code is the PAKE password
+pub fn generate_link_code() -> String {
+    let mut rng = rand::rngs::OsRng;
+    format!("{:04}-{:06}", rng.gen_range(1000..10000), rng.gen_range(0..1_000_000))
+}
+
+fn channel_of(code: &str) -> Result<&str> {
+    match code.split_once('-') {
+        Some((channel, secret)) if !channel.is_empty() && !secret.is_empty() => Ok(channel),
+        _ => Err(anyhow::anyhow!("Link code must look like 1234-567890")),
+    }
+}
+
+/// An established, encrypted channel to the other device
+pub struct LinkChannel {
+    stream: TcpStream,
+    role: LinkRole,
+    send_key: Zeroizing<[u8; 32]>,
+    recv_key: Zeroizing<[u8; 32]>,
+    safety_code: String,
+}
+
+impl LinkChannel {
+    /// Join the relay channel for `code` and run SPAKE2 with the other device
+    /// Fails with `CodeMismatch` if the other device used a different code
+    pub async fn connect(relay_addr: &str, code: &str, role: LinkRole) -> Result<Self> {
+        let code = code.trim();
+        let channel = channel_of(code)?;
+
+        let mut stream = TcpStream::connect(relay_addr)
+            .await
+            .with_context(|| format!("Failed to connect to relay {}", relay_addr))?;
+        write_frame(&mut stream, channel.as_bytes()).await?;
+
+        let password = Password::new(code.as_bytes());
+        let sender = Identity::new(SENDER_IDENTITY);
+        let receiver = Identity::new(RECEIVER_IDENTITY);
+        let (spake, outbound) = match role {
+            LinkRole::Sender => Spake2::<Ed25519Group>::start_a(&password, &sender, &receiver),
+            LinkRole::Receiver => Spake2::<Ed25519Group>::start_b(&password, &sender, &receiver),
+        };
+        write_frame(&mut stream, &outbound).await?;
+
+        let inbound = tokio::time::timeout(LINK_TIMEOUT, read_frame(&mut stream))
+            .await
+            .context("Timed out waiting for the other device")??;
+        let shared = Zeroizing::new(
+            spake
+                .finish(&inbound)
+                .map_err(|e| anyhow::anyhow!("Key agreement failed: {:?}", e))?,
+        );
+
+        let hkdf = Hkdf::<Sha256>::new(None, &shared);
+        let sender_key = derive_key(&hkdf, b"kryptvault-link sender key")?;
+        let receiver_key = derive_key(&hkdf, b"kryptvault-link receiver key")?;
+        let (send_key, recv_key) = match role {
+            LinkRole::Sender => (sender_key, receiver_key),
+            LinkRole::Receiver => (receiver_key, sender_key),
+        };
+
+        let mut safety = [0u8; 4];
+        hkdf.expand(b"kryptvault-link safety code", &mut safety)
+            .map_err(|_| anyhow::anyhow!("Failed to derive safety code"))?;
+        let safety = u32::from_be_bytes(safety) % 1_000_000;
+
+        let mut link = LinkChannel {
+            stream,
+            role,
+            send_key,
+            recv_key,
+            safety_code: format!("{:03} {:03}", safety / 1000, safety % 1000),
+        };
+
+        // Key confirmation: a wrong code shows up here rather than as a
+        // garbled keyring later
+        link.send(CONFIRM_MESSAGE).await?;
+        let confirm = link.receive().await?;
+        if confirm.as_slice() != CONFIRM_MESSAGE {
+            return Err(CodeMismatch.into());
+        }
+
+        Ok(link)
+    }
+
+    pub fn role(&self) -> LinkRole {
+        self.role
+    }
+
+    /// Code both devices display; the user checks that they match
+    pub fn safety_code(&self) -> &str {
+        &self.safety_code
+    }
+
+    /// Send the keyring and wait for the new device to acknowledge it
+    pub async fn send_keyring(mut self, keypair: &UserKeypair) -> Result<()> {
+        let payload = Zeroizing::new(serde_json::to_vec(keypair)?);
+        self.send(&payload).await?;
+
+        let ack = self.receive().await?;
+        if ack.as_slice() != ACK_MESSAGE {
+            return Err(anyhow::anyhow!("Unexpected reply from the other device"));
+        }
+        Ok(())
+    }
+
+    /// Receive and check the keyring sent by the existing device
+    pub async fn receive_keyring(mut self) -> Result<UserKeypair> {
+        let payload = self.receive().await?;
+        let keypair: UserKeypair = serde_json::from_slice(&payload)
+            .context("Failed to parse received keyring")?;
+        verify_user_keypair(&keypair).context("Received keyring is invalid")?;
+
+        self.send(ACK_MESSAGE).await?;
+        Ok(keypair)
+    }
+
+    async fn send(&mut self, data: &[u8]) -> Result<()> {
+        let (ciphertext, nonce) = encrypt_with_key(data, &self.send_key)?;
+        let frame = serde_json::to_vec(&EncryptedFrame { ciphertext, nonce })?;
+        write_frame(&mut self.stream, &frame).await
+    }
+
+    async fn receive(&mut self) -> Result<Zeroizing<Vec<u8>>> {
+        let frame = tokio::time::timeout(LINK_TIMEOUT, read_frame(&mut self.stream))
+            .await
+            .context("Timed out waiting for the other device")??;
+        let frame: EncryptedFrame = serde_json::from_slice(&frame)
+            .context("Malformed message from the other device")?;
+
+        // Only a peer holding the same code can produce frames that decrypt
+        decrypt_with_key(&frame.ciphertext, &frame.nonce, &self.recv_key)
+            .map(Zeroizing::new)
+            .map_err(|_| CodeMismatch.into())
+    }
+}
+
+fn derive_key(hkdf: &Hkdf<Sha256>, info: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    hkdf.expand(info, key.as_mut())
+        .map_err(|_| anyhow::anyhow!("Failed to derive link key"))?;
+    Ok(key)
+}
+
+async fn write_frame(stream: &mut TcpStream, data: &[u8]) -> Result<()> {
+    stream.write_u32(data.len() as u32).await?;
+    stream.write_all(data).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn read_frame(stream: &mut TcpStream) -> Result<Vec<u8>> {
+    let len = stream.read_u32().await.context("Connection closed")? as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(anyhow::anyhow!("Frame too large: {} bytes", len));
+    }
+
+    let mut data = vec![0u8; len];
+    stream.read_exact(&mut data).await.context("Connection closed")?;
+    Ok(data)
+}
+
+// Connections waiting for their peer, with when they arrived
+type WaitingPeers = Mutex<HashMap<String, (Instant, TcpStream)>>;
+
+/// Minimal relay: pairs the two connections that name the same channel
+/// and copies bytes between them. It only ever sees ciphertext and the
+/// SPAKE2 messages, neither of which reveal the code or the keyring
+pub async fn run_relay(listener: TcpListener) -> Result<()> {
+    let waiting: Arc<WaitingPeers> = Arc::default();
+
+    loop {
+        let (stream, peer) = listener.accept().await.context("Relay accept failed")?;
+        let waiting = waiting.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = pair_connection(stream, &waiting).await {
+                log::warn!("Relay connection from {} failed: {:#}", peer, e);
+            }
+        });
+    }
+}
+
+async fn pair_connection(
+    mut stream: TcpStream,
+    waiting: &WaitingPeers,
+) -> Result<()> {
+    let channel = tokio::time::timeout(LINK_TIMEOUT, read_frame(&mut stream))
+        .await
+        .context("Timed out waiting for channel")??;
+    let channel = String::from_utf8(channel).context("Invalid channel name")?;
+
+    // Look up and insert under one guard so two arrivals on the same channel
+    // can't both miss each other; peers that waited past the code's
+    // lifetime are dropped so the map can't grow without bound
+    let mut peer = {
+        let mut waiting = waiting.lock().unwrap();
+        waiting.retain(|_, (arrived, _)| arrived.elapsed() < LINK_TIMEOUT);
+        match waiting.entry(channel) {
+            Entry::Occupied(entry) => entry.remove().1,
+            Entry::Vacant(entry) => {
+                entry.insert((Instant::now(), stream));
+                return Ok(());
+            }
+        }
+    };
+
+    tokio::io::copy_bidirectional(&mut stream, &mut peer).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_user_keypair;
+
+    async fn start_relay() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(run_relay(listener));
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_link_transfers_keyring() {
+        let relay = start_relay().await;
+        let code = generate_link_code();
+        let keypair = generate_user_keypair().unwrap();
+
+        let (sender, receiver) = tokio::join!(
+            LinkChannel::connect(&relay, &code, LinkRole::Sender),
+            LinkChannel::connect(&relay, &code, LinkRole::Receiver),
+        );
+        let (sender, receiver) = (sender.unwrap(), receiver.unwrap());
+        assert_eq!(sender.safety_code(), receiver.safety_code());
+
+        let (sent, received) = tokio::join!(sender.send_keyring(&keypair), receiver.receive_keyring());
+        sent.unwrap();
+        assert_eq!(received.unwrap().x25519_private_key, keypair.x25519_private_key);
+    }
+
+    #[tokio::test]
+    async fn test_link_rejects_wrong_code() {
+        let relay = start_relay().await;
+
+        let (sender, receiver) = tokio::join!(
+            LinkChannel::connect(&relay, "1234-111111", LinkRole::Sender),
+            LinkChannel::connect(&relay, "1234-222222", LinkRole::Receiver),
+        );
+        assert!(sender.err().unwrap().downcast_ref::<CodeMismatch>().is_some());
+        assert!(receiver.err().unwrap().downcast_ref::<CodeMismatch>().is_some());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::device_link::CodeMismatch;
+use crate::s3::HttpStatusError;
+use crate::temp::InsufficientSpace;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Stable error codes returned to the frontend
+/// Renaming a variant is a breaking change for the UI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorCode {
+    AuthenticationFailed,
+    InvalidKey,
+    InvalidInput,
+    DecryptionFailed,
+    NetworkError,
+    PresignedUrlExpired,
+    Cancelled,
+    NotFound,
+    Io,
+    StorageFull,
+    Unsupported,
+    Locked,
+    UnsignedKey,
+    Internal,
+}
+
+impl ErrorCode {
+    /// Default user-safe message for this code
+    pub fn default_message(self) -> &'static str {
+        match self {
+            ErrorCode::AuthenticationFailed => "Authentication failed",
+            ErrorCode::InvalidKey => "The key is invalid or does not match",
+            ErrorCode::InvalidInput => "Invalid input",
+            ErrorCode::DecryptionFailed => "The file could not be decrypted",
+            ErrorCode::NetworkError => "A network error occurred",
+            ErrorCode::PresignedUrlExpired => "The transfer link has expired",
+            ErrorCode::Cancelled => "The operation was cancelled",
+            ErrorCode::NotFound => "The file was not found",
+            ErrorCode::Io => "A file system error occurred",
+            ErrorCode::StorageFull => "There is not enough storage space",
+            ErrorCode::Unsupported => "This operation is not supported on this platform",
+            ErrorCode::Locked => "The vault is locked",
+            ErrorCode::UnsignedKey => "This key has no signed grant; confirm you trust it to open the file",
+            ErrorCode::Internal => "An unexpected error occurred",
+        }
+    }
+}
+
+/// Error returned by every Tauri command
+/// Serialized as `{ code, message, details? }`; `message` is safe to show
+/// to users, `details` carries the underlying error chain for diagnostics
+#[derive(Debug, Serialize)]
+pub struct CommandError {
+    pub code: ErrorCode,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+pub type CommandResult<T> = Result<T, CommandError>;
+
+impl CommandError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        CommandError {
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: impl fmt::Display) -> Self {
+        self.details = Some(details.to_string());
+        self
+    }
+
+    /// Classify an error chain, falling back to `fallback` when nothing in
+    /// the chain identifies a more specific cause
+    pub fn from_anyhow(fallback: ErrorCode, message: &str, error: anyhow::Error) -> Self {
+        let code = classify(&error).unwrap_or(fallback);
+        let message = if code == fallback {
+            message.to_string()
+        } else {
+            code.default_message().to_string()
+        };
+
+        CommandError::new(code, message).with_details(format!("{:#}", error))
+    }
+}
+
+impl From<ErrorCode> for CommandError {
+    fn from(code: ErrorCode) -> Self {
+        CommandError::new(code, code.default_message())
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.details {
+            Some(details) => write!(f, "{:?}: {} ({})", self.code, self.message, details),
+            None => write!(f, "{:?}: {}", self.code, self.message),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Convert any error into a `CommandError` at the command boundary
+pub trait ResultExt<T> {
+    fn or_command_error(self, fallback: ErrorCode, message: &str) -> CommandResult<T>;
+}
+
+impl<T, E: Into<anyhow::Error>> ResultExt<T> for Result<T, E> {
+    fn or_command_error(self, fallback: ErrorCode, message: &str) -> CommandResult<T> {
+        self.map_err(|e| CommandError::from_anyhow(fallback, message, e.into()))
+    }
+}
+
+/// Look for a well-known cause anywhere in the error chain
+fn classify(error: &anyhow::Error) -> Option<ErrorCode> {
+    for cause in error.chain() {
+        if let Some(status_error) = cause.downcast_ref::<HttpStatusError>() {
+            return Some(classify_status(status_error));
+        }
+
+        if cause.downcast_ref::<CodeMismatch>().is_some() {
+            return Some(ErrorCode::InvalidKey);
+        }
+
+        if cause.downcast_ref::<InsufficientSpace>().is_some() {
+            return Some(ErrorCode::StorageFull);
+        }
+
+        if let Some(reqwest_error) = cause.downcast_ref::<reqwest::Error>() {
+            return Some(match reqwest_error.status() {
+                Some(status) if status == reqwest::StatusCode::NOT_FOUND => ErrorCode::NotFound,
+                Some(status) if status == reqwest::StatusCode::UNAUTHORIZED => ErrorCode::AuthenticationFailed,
+                _ => ErrorCode::NetworkError,
+            });
+        }
+
+        if let Some(io_error) = cause.downcast_ref::<std::io::Error>() {
+            return Some(match io_error.kind() {
+                std::io::ErrorKind::NotFound => ErrorCode::NotFound,
+                _ if is_storage_full(io_error) => ErrorCode::StorageFull,
+                _ => ErrorCode::Io,
+            });
+        }
+    }
+
+    None
+}
+
+fn classify_status(error: &HttpStatusError) -> ErrorCode {
+    match error.status.as_u16() {
+        // S3 rejects expired presigned URLs with 403 AccessDenied
+        403 if error.body.contains("Request has expired") || error.body.contains("ExpiredToken") => {
+            ErrorCode::PresignedUrlExpired
+        }
+        401 | 403 => ErrorCode::AuthenticationFailed,
+        404 => ErrorCode::NotFound,
+        413 | 507 => ErrorCode::StorageFull,
+        400 if error.body.contains("EntityTooLarge") => ErrorCode::StorageFull,
+        _ => ErrorCode::NetworkError,
+    }
+}
+
+fn is_storage_full(error: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    const DISK_FULL_CODES: &[i32] = &[28]; // ENOSPC
+    #[cfg(windows)]
+    const DISK_FULL_CODES: &[i32] = &[39, 112]; // ERROR_HANDLE_DISK_FULL, ERROR_DISK_FULL
+    #[cfg(not(any(unix, windows)))]
+    const DISK_FULL_CODES: &[i32] = &[];
+
+    error
+        .raw_os_error()
+        .is_some_and(|code| DISK_FULL_CODES.contains(&code))
+}
@@ -0,0 +1,194 @@
+//! Organization escrow key for admin recovery
+//!
+//! A profile can carry an organization's escrow X25519 key, signed by the
+//! organization's Ed25519 key. Every DEK uploaded or shared from the profile
+//! is also sealed to it, so IT can recover files with the escrow private key,
+//! which never reaches this app except during a recovery.
+//!
+//! No escrow key is accepted until the profile has an organization key
+//! pinned, either built in with `KRYPTVAULT_ORG_SIGNING_KEY` or pinned by
+//! the user after checking its fingerprint with the organization. Escrow
+//! keys must be signed by that key, and escrow can only be turned off with
+//! its signature.
+
+
+use crate::crypto::wrap_dek_for_recipient;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sodiumoxide::crypto::{box_, sign};
+
+/// Organization signing key built into this app, pinned for every profile
+pub const BUILT_IN_ORG_SIGNING_KEY: Option<&str> = option_env!("KRYPTVAULT_ORG_SIGNING_KEY");
+
+// Domain separation for the org signature over an escrow key
+const ESCROW_KEY_CONTEXT: &[u8] = b"kryptvault-escrow-key-v1";
+// Domain separation for the org signature allowing an escrow key to be removed
+const ESCROW_CLEAR_CONTEXT: &[u8] = b"kryptvault-escrow-clear-v1";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EscrowKey {
+    pub public_key: String,      // Escrow X25519 public key, base64
+    pub org_signing_key: String, // Organization's Ed25519 public key, base64
+    pub signature: String,       // Base64 Ed25519 over `ESCROW_KEY_CONTEXT` then `public_key`'s raw bytes
+}
+
+fn signed_message(context: &[u8], public_key: &[u8]) -> Vec<u8> {
+    [context, public_key].concat()
+}
+
+/// Check a base64 Ed25519 `signature` over `message` by the base64 `org_signing_key`
+fn verify_org_signature(org_signing_key: &str, message: &[u8], signature: &str) -> Result<()> {
+    sodiumoxide::init().map_err(|_| anyhow::anyhow!("Failed to initialize sodiumoxide"))?;
+
+    let org_bytes = base64::decode(org_signing_key).context("Failed to decode org signing key")?;
+    let org_key = sign::PublicKey::from_slice(&org_bytes).context("Invalid org signing key")?;
+    let signature_bytes = base64::decode(signature).context("Failed to decode signature")?;
+    let signature = sign::Signature::from_bytes(&signature_bytes)
+        .map_err(|_| anyhow::anyhow!("Invalid signature size"))?;
+
+    if !sign::verify_detached(&signature, message, &org_key) {
+        return Err(anyhow::anyhow!("Not signed by the organization key"));
+    }
+    Ok(())
+}
+
+/// Fingerprint of an organization signing key for the user to compare with
+/// the one the organization publishes: the first 16 bytes of the key's
+/// SHA-256, as hex in groups of four
+pub fn org_key_fingerprint(org_signing_key: &str) -> Result<String> {
+    let key_bytes = base64::decode(org_signing_key).context("Failed to decode org signing key")?;
+    if sign::PublicKey::from_slice(&key_bytes).is_none() {
+        return Err(anyhow::anyhow!("Invalid org signing key"));
+    }
+
+    let digest = Sha256::digest(&key_bytes);
+    let groups: Vec<String> = digest[..16]
+        .chunks(2)
+        .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+        .collect();
+    Ok(groups.join(" "))
+}
+
+impl EscrowKey {
+    /// Check the escrow key is a valid X25519 key signed by `org_signing_key`,
+    /// and that `org_signing_key` is the profile's pinned organization key
+    pub fn verify(&self, pinned_org_key: &str) -> Result<()> {
+        let pinned = base64::decode(pinned_org_key).context("Failed to decode pinned org signing key")?;
+        let claimed = base64::decode(&self.org_signing_key).context("Failed to decode org signing key")?;
+        if pinned != claimed {
+            return Err(anyhow::anyhow!("Escrow key is signed by a different organization key"));
+        }
+
+        let pk_bytes = base64::decode(&self.public_key).context("Failed to decode escrow public key")?;
+        if box_::PublicKey::from_slice(&pk_bytes).is_none() {
+            return Err(anyhow::anyhow!("Invalid escrow public key"));
+        }
+
+        verify_org_signature(
+            &self.org_signing_key,
+            &signed_message(ESCROW_KEY_CONTEXT, &pk_bytes),
+            &self.signature,
+        )
+        .context("Escrow key was not signed by the organization key")
+    }
+
+    /// Check `signature` is the organization's permission to stop escrowing to this key
+    /// Signed over `ESCROW_CLEAR_CONTEXT` then the escrow public key's raw bytes
+    pub fn verify_clearance(&self, signature: &str) -> Result<()> {
+        let pk_bytes = base64::decode(&self.public_key).context("Failed to decode escrow public key")?;
+        verify_org_signature(
+            &self.org_signing_key,
+            &signed_message(ESCROW_CLEAR_CONTEXT, &pk_bytes),
+            signature,
+        )
+        .context("Removing the escrow key was not signed by the organization key")
+    }
+
+    /// Seal a DEK (or any 32-byte file key) to the escrow key
+    pub fn seal(&self, dek: &[u8; 32]) -> Result<String> {
+        wrap_dek_for_recipient(dek, &self.public_key).context("Failed to seal key for escrow")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signed_escrow_key(org_sk: &sign::SecretKey, org_pk: &sign::PublicKey) -> EscrowKey {
+        let (escrow_pk, _) = box_::gen_keypair();
+        let signature = sign::sign_detached(&signed_message(ESCROW_KEY_CONTEXT, escrow_pk.as_ref()), org_sk);
+        EscrowKey {
+            public_key: base64::encode(escrow_pk.as_ref()),
+            org_signing_key: base64::encode(org_pk.as_ref()),
+            signature: base64::encode(signature.to_bytes()),
+        }
+    }
+
+    #[test]
+    fn test_verify_valid_key() {
+        sodiumoxide::init().unwrap();
+        let (org_pk, org_sk) = sign::gen_keypair();
+        let key = signed_escrow_key(&org_sk, &org_pk);
+
+        assert!(key.verify(&key.org_signing_key).is_ok());
+    }
+
+    #[test]
+    fn test_verify_rejects_other_pinned_org() {
+        sodiumoxide::init().unwrap();
+        let (org_pk, org_sk) = sign::gen_keypair();
+        let (pinned_pk, _) = sign::gen_keypair();
+        let key = signed_escrow_key(&org_sk, &org_pk);
+
+        // Validly self-signed, but not by the organization the profile trusts
+        assert!(key.verify(&base64::encode(pinned_pk.as_ref())).is_err());
+    }
+
+    #[test]
+    fn test_verify_rejects_bad_signature() {
+        sodiumoxide::init().unwrap();
+        let (org_pk, org_sk) = sign::gen_keypair();
+        let (other_pk, _) = sign::gen_keypair();
+
+        let mut swapped = signed_escrow_key(&org_sk, &org_pk);
+        swapped.org_signing_key = base64::encode(other_pk.as_ref());
+        assert!(swapped.verify(&swapped.org_signing_key).is_err());
+
+        let mut replaced = signed_escrow_key(&org_sk, &org_pk);
+        let (attacker_pk, _) = box_::gen_keypair();
+        replaced.public_key = base64::encode(attacker_pk.as_ref());
+        assert!(replaced.verify(&replaced.org_signing_key).is_err());
+    }
+
+    #[test]
+    fn test_org_key_fingerprint() {
+        sodiumoxide::init().unwrap();
+        let (org_pk, _) = sign::gen_keypair();
+        let (other_pk, _) = sign::gen_keypair();
+        let fingerprint = org_key_fingerprint(&base64::encode(org_pk.as_ref())).unwrap();
+
+        assert_eq!(fingerprint.len(), 8 * 4 + 7);
+        assert_eq!(fingerprint, org_key_fingerprint(&base64::encode(org_pk.as_ref())).unwrap());
+        assert_ne!(fingerprint, org_key_fingerprint(&base64::encode(other_pk.as_ref())).unwrap());
+        assert!(org_key_fingerprint(&base64::encode([1u8; 16])).is_err());
+    }
+
+    #[test]
+    fn test_clearance_needs_org_signature() {
+        sodiumoxide::init().unwrap();
+        let (org_pk, org_sk) = sign::gen_keypair();
+        let (_, other_sk) = sign::gen_keypair();
+        let key = signed_escrow_key(&org_sk, &org_pk);
+        let pk_bytes = base64::decode(&key.public_key).unwrap();
+
+        let clearance = sign::sign_detached(&signed_message(ESCROW_CLEAR_CONTEXT, &pk_bytes), &org_sk);
+        assert!(key.verify_clearance(&base64::encode(clearance.to_bytes())).is_ok());
+
+        // The escrow key's own signature is not a clearance
+        assert!(key.verify_clearance(&key.signature).is_err());
+
+        let forged = sign::sign_detached(&signed_message(ESCROW_CLEAR_CONTEXT, &pk_bytes), &other_sk);
+        assert!(key.verify_clearance(&base64::encode(forged.to_bytes())).is_err());
+    }
+}
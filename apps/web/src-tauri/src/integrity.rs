@@ -0,0 +1,334 @@
+//! Vault-wide integrity scans
+//!
+//! Each stored ciphertext is checked (by HEAD, by downloading and hashing
+//! it, or by also authenticating it with its DEK) and the result appended to
+//! the scan's log as soon as it is known, so an interrupted scan picks up
+//! where it stopped.
+
+use crate::keyring::write_private_file;
+use crate::s3::{download_from_s3, head_s3_object, HttpStatusError};
+use crate::stream::verify_file;
+use crate::temp::TempManager;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+const SCANS_DIR: &str = "scans";
+
+/// How thoroughly to check each file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanDepth {
+    /// HEAD only: the object exists, with the right size (and checksum, if S3 stored one)
+    Head,
+    /// Download and compare size and SHA-256
+    Checksum,
+    /// Also authenticate the whole ciphertext with its DEK; no plaintext is written
+    Decrypt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileHealth {
+    Healthy,
+    Corrupted,
+    Missing,
+    Undecryptable,
+    /// Couldn't be checked (network error, expired URL); retried when the scan resumes
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileCheck {
+    pub file_id: String,
+    pub health: FileHealth,
+    pub detail: Option<String>,
+    pub checked_at: u64, // Unix timestamp in seconds
+}
+
+impl FileCheck {
+    pub fn new(file_id: String, health: FileHealth, detail: Option<String>) -> Self {
+        FileCheck {
+            file_id,
+            health,
+            detail,
+            checked_at: now(),
+        }
+    }
+}
+
+/// One file to check
+pub struct ScanTarget {
+    pub file_id: String,
+    pub download_url: Option<String>,
+    pub head_url: Option<String>,
+    pub file_size: Option<u64>,            // Ciphertext size
+    pub ciphertext_sha256: Option<String>, // Base64
+    pub dek: Option<Zeroizing<[u8; 32]>>,  // Needed for `ScanDepth::Decrypt`
+    pub nonce: Option<String>,             // Base64
+}
+
+/// First line of a scan log; every later line is a `FileCheck`
+#[derive(Debug, Serialize, Deserialize)]
+struct ScanHeader {
+    scan_id: String,
+    depth: ScanDepth,
+}
+
+/// Progress of a scan, backed by an append-only log
+#[derive(Debug)]
+pub struct ScanState {
+    pub scan_id: String,
+    pub depth: ScanDepth,
+    pub results: BTreeMap<String, FileCheck>, // Latest result per file
+    path: PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScanReport {
+    pub scan_id: String,
+    pub total: usize,
+    pub healthy: usize,
+    pub corrupted: usize,
+    pub missing: usize,
+    pub undecryptable: usize,
+    pub failed: usize,
+    pub skipped: usize,           // Already checked by an earlier run of this scan
+    pub problems: Vec<FileCheck>, // Everything that isn't healthy
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn scan_path(data_dir: &Path, scan_id: &str) -> Result<PathBuf> {
+    // Scan IDs name files, so keep them to something path-safe
+    if scan_id.is_empty() || !scan_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(anyhow::anyhow!("Invalid scan ID"));
+    }
+    Ok(data_dir.join(SCANS_DIR).join(format!("{}.jsonl", scan_id)))
+}
+
+impl ScanState {
+    /// Load a scan to resume, or start a new one
+    pub fn load_or_new(data_dir: &Path, scan_id: &str, depth: ScanDepth) -> Result<Self> {
+        let path = scan_path(data_dir, scan_id)?;
+        let mut state = ScanState {
+            scan_id: scan_id.to_string(),
+            depth,
+            results: BTreeMap::new(),
+            path,
+        };
+
+        if !state.path.exists() {
+            let header = ScanHeader { scan_id: state.scan_id.clone(), depth };
+            let mut line = serde_json::to_string(&header)?;
+            line.push('\n');
+            write_private_file(&state.path, line.as_bytes())?;
+            return Ok(state);
+        }
+
+        let contents = std::fs::read_to_string(&state.path).context("Failed to read scan progress")?;
+        let mut lines = contents.lines();
+        let header: ScanHeader = lines
+            .next()
+            .map(serde_json::from_str)
+            .transpose()
+            .context("Failed to parse scan progress")?
+            .context("Scan progress is empty")?;
+        if header.depth != depth {
+            return Err(anyhow::anyhow!("Scan {} was started with a different depth", scan_id));
+        }
+
+        // Start the next result on its own line after a write cut short by a crash
+        if !contents.ends_with('\n') {
+            let mut file = OpenOptions::new()
+                .append(true)
+                .open(&state.path)
+                .context("Failed to open scan progress")?;
+            file.write_all(b"\n").context("Failed to write scan progress")?;
+        }
+
+        for line in lines {
+            // A cut-short line is simply checked again
+            match serde_json::from_str::<FileCheck>(line) {
+                Ok(check) => {
+                    state.results.insert(check.file_id.clone(), check);
+                }
+                Err(e) => log::warn!("Skipping unreadable scan result: {}", e),
+            }
+        }
+        Ok(state)
+    }
+
+    /// Whether a file already has a final result; failed checks are retried
+    pub fn is_done(&self, file_id: &str) -> bool {
+        self.results
+            .get(file_id)
+            .is_some_and(|check| check.health != FileHealth::Failed)
+    }
+
+    /// Append a result to the log; a later result for the same file replaces an earlier one
+    pub fn record(&mut self, check: FileCheck) -> Result<()> {
+        let mut line = serde_json::to_string(&check)?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .context("Failed to open scan progress")?;
+        file.write_all(line.as_bytes()).context("Failed to write scan progress")?;
+
+        self.results.insert(check.file_id.clone(), check);
+        Ok(())
+    }
+
+    /// Summarize the results for `file_ids`
+    pub fn report(&self, file_ids: &[String], skipped: usize) -> ScanReport {
+        let mut report = ScanReport {
+            scan_id: self.scan_id.clone(),
+            total: file_ids.len(),
+            healthy: 0,
+            corrupted: 0,
+            missing: 0,
+            undecryptable: 0,
+            failed: 0,
+            skipped,
+            problems: Vec::new(),
+        };
+
+        for check in file_ids.iter().filter_map(|id| self.results.get(id)) {
+            match check.health {
+                FileHealth::Healthy => report.healthy += 1,
+                FileHealth::Corrupted => report.corrupted += 1,
+                FileHealth::Missing => report.missing += 1,
+                FileHealth::Undecryptable => report.undecryptable += 1,
+                FileHealth::Failed => report.failed += 1,
+            }
+            if check.health != FileHealth::Healthy {
+                report.problems.push(check.clone());
+            }
+        }
+        report
+    }
+}
+
+/// A 404 means the object is gone; anything else is worth retrying
+fn fetch_failure(error: anyhow::Error) -> (FileHealth, String) {
+    let missing = error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<HttpStatusError>())
+        .any(|status| status.status == reqwest::StatusCode::NOT_FOUND);
+    let health = if missing { FileHealth::Missing } else { FileHealth::Failed };
+    (health, format!("{:#}", error))
+}
+
+/// Check one file at the given depth
+pub async fn check_file(temp: &TempManager, target: ScanTarget, depth: ScanDepth) -> FileCheck {
+    let (health, detail) = match run_check(temp, &target, depth).await {
+        Ok(()) => (FileHealth::Healthy, None),
+        Err((health, detail)) => (health, Some(detail)),
+    };
+
+    FileCheck::new(target.file_id, health, detail)
+}
+
+async fn run_check(temp: &TempManager, target: &ScanTarget, depth: ScanDepth) -> Result<(), (FileHealth, String)> {
+    let failed = |detail: &str| (FileHealth::Failed, detail.to_string());
+
+    if depth == ScanDepth::Head {
+        // The object existing says nothing about its contents without a size to compare
+        if target.file_size.is_none() {
+            return Err(failed("Nothing to verify: no expected size given"));
+        }
+        let url = target.head_url.as_deref().ok_or_else(|| failed("No HEAD URL given"))?;
+        let object = head_s3_object(url).await.map_err(fetch_failure)?;
+
+        if let Some(expected) = target.file_size.filter(|size| *size != object.size) {
+            return Err((FileHealth::Corrupted, format!("Size is {} bytes, expected {}", object.size, expected)));
+        }
+        if let (Some(expected), Some(actual)) = (&target.ciphertext_sha256, &object.checksum_sha256) {
+            if expected != actual {
+                return Err((FileHealth::Corrupted, "Stored checksum does not match".to_string()));
+            }
+        }
+        return Ok(());
+    }
+
+    if depth == ScanDepth::Checksum && target.file_size.is_none() && target.ciphertext_sha256.is_none() {
+        return Err(failed("Nothing to verify: no expected size or SHA-256 given"));
+    }
+    let url = target.download_url.as_deref().ok_or_else(|| failed("No download URL given"))?;
+    let encrypted_file = temp
+        .stage("enc", target.file_size)
+        .map_err(|e| failed(&format!("{:#}", e)))?;
+    let size = download_from_s3(url, encrypted_file.path()).await.map_err(fetch_failure)?;
+
+    if let Some(expected) = target.file_size.filter(|expected| *expected != size) {
+        return Err((FileHealth::Corrupted, format!("Size is {} bytes, expected {}", size, expected)));
+    }
+    if let Some(expected) = &target.ciphertext_sha256 {
+        let mut file = std::fs::File::open(encrypted_file.path()).map_err(|e| failed(&e.to_string()))?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher).map_err(|e| failed(&e.to_string()))?;
+        if base64::encode(hasher.finalize()) != *expected {
+            return Err((FileHealth::Corrupted, "SHA-256 does not match".to_string()));
+        }
+    }
+
+    if depth == ScanDepth::Decrypt {
+        let (Some(dek), Some(nonce)) = (&target.dek, &target.nonce) else {
+            return Err(failed("No key given for a decrypt check"));
+        };
+        verify_file(Path::new(encrypted_file.path()), dek, nonce)
+            .map_err(|e| (FileHealth::Undecryptable, format!("{:#}", e)))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_resumes_from_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut scan = ScanState::load_or_new(dir.path(), "scan-1", ScanDepth::Checksum).unwrap();
+        scan.record(FileCheck::new("a".into(), FileHealth::Healthy, None)).unwrap();
+        scan.record(FileCheck::new("b".into(), FileHealth::Failed, Some("timeout".into()))).unwrap();
+        scan.record(FileCheck::new("b".into(), FileHealth::Corrupted, None)).unwrap();
+
+        let resumed = ScanState::load_or_new(dir.path(), "scan-1", ScanDepth::Checksum).unwrap();
+        assert!(resumed.is_done("a"));
+        assert_eq!(resumed.results["b"].health, FileHealth::Corrupted);
+        assert!(!resumed.is_done("c"));
+        assert!(ScanState::load_or_new(dir.path(), "scan-1", ScanDepth::Head).is_err());
+    }
+
+    #[test]
+    fn test_truncated_result_is_rechecked() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut scan = ScanState::load_or_new(dir.path(), "scan-2", ScanDepth::Head).unwrap();
+        scan.record(FileCheck::new("a".into(), FileHealth::Healthy, None)).unwrap();
+
+        let path = scan_path(dir.path(), "scan-2").unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"file_id":"b","health":"hea"#).unwrap();
+
+        let mut resumed = ScanState::load_or_new(dir.path(), "scan-2", ScanDepth::Head).unwrap();
+        assert!(resumed.is_done("a"));
+        assert!(!resumed.is_done("b"));
+
+        resumed.record(FileCheck::new("b".into(), FileHealth::Healthy, None)).unwrap();
+        let resumed = ScanState::load_or_new(dir.path(), "scan-2", ScanDepth::Head).unwrap();
+        assert!(resumed.is_done("b"));
+    }
+}
@@ -0,0 +1,150 @@
+use crate::crypto::{decrypt_with_password, encrypt_with_password, PasswordWrappedKey, UserKeypair};
+use anyhow::{Context, Result};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+/// Environment variables that can supply keys instead of a keyring file
+pub const ENV_KEYRING_PATH: &str = "KRYPTVAULT_KEYRING";
+pub const ENV_X25519_PUBLIC_KEY: &str = "KRYPTVAULT_X25519_PUBLIC_KEY";
+pub const ENV_X25519_PRIVATE_KEY: &str = "KRYPTVAULT_X25519_PRIVATE_KEY";
+pub const ENV_ED25519_PUBLIC_KEY: &str = "KRYPTVAULT_ED25519_PUBLIC_KEY";
+pub const ENV_ED25519_PRIVATE_KEY: &str = "KRYPTVAULT_ED25519_PRIVATE_KEY";
+pub const ENV_KEYRING_PASSPHRASE: &str = "KRYPTVAULT_PASSPHRASE";
+
+/// Default keyring location: `<config dir>/kryptvault/keyring.json`
+pub fn default_keyring_path() -> Result<PathBuf> {
+    let config_dir = dirs::config_dir().context("Could not determine config directory")?;
+    Ok(config_dir.join("kryptvault").join("keyring.json"))
+}
+
+/// Write a keyring file readable only by the current user
+pub fn save_keyring(path: &Path, keypair: &UserKeypair) -> Result<()> {
+    let contents = serde_json::to_string_pretty(keypair)?;
+    write_private_file(path, contents.as_bytes())
+}
+
+/// Write a keyring file encrypted under a passphrase (Argon2id), as the
+/// desktop app keeps each profile's keys
+pub fn save_protected_keyring(path: &Path, keypair: &UserKeypair, passphrase: &str) -> Result<()> {
+    let plaintext = Zeroizing::new(serde_json::to_vec(keypair)?);
+    let wrapped = encrypt_with_password(&plaintext, passphrase)?;
+    write_private_file(path, serde_json::to_string_pretty(&wrapped)?.as_bytes())
+}
+
+/// Open a passphrase-protected keyring file
+/// A plaintext keyring from an older version is sealed under the passphrase
+/// on first open. A wrong passphrase and a damaged file fail the same way
+pub fn load_protected_keyring(path: &Path, passphrase: &str) -> Result<UserKeypair> {
+    let contents = Zeroizing::new(std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read keyring file {}", path.display()))?);
+    if let Ok(keypair) = serde_json::from_str::<UserKeypair>(&contents) {
+        save_protected_keyring(path, &keypair, passphrase)?;
+        return Ok(keypair);
+    }
+    let wrapped: PasswordWrappedKey = serde_json::from_str(&contents)
+        .context("Failed to parse keyring file")?;
+
+    let plaintext = Zeroizing::new(decrypt_with_password(&wrapped, passphrase)?);
+    serde_json::from_slice(&plaintext).context("Failed to parse keyring file")
+}
+
+/// Open a keyring file, asking for the passphrase only if it is protected
+/// Plaintext keyrings from older versions are still read as they are
+pub fn open_keyring(
+    path: &Path,
+    passphrase: impl FnOnce() -> Result<Zeroizing<String>>,
+) -> Result<UserKeypair> {
+    let contents = Zeroizing::new(std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read keyring file {}", path.display()))?);
+    if let Ok(keypair) = serde_json::from_str::<UserKeypair>(&contents) {
+        return Ok(keypair);
+    }
+    let wrapped: PasswordWrappedKey = serde_json::from_str(&contents)
+        .context("Failed to parse keyring file")?;
+
+    let plaintext = Zeroizing::new(decrypt_with_password(&wrapped, &passphrase()?)?);
+    serde_json::from_slice(&plaintext).context("Failed to parse keyring file")
+}
+
+/// Keyring passphrase from `KRYPTVAULT_PASSPHRASE`, if set
+pub fn passphrase_from_env() -> Option<Zeroizing<String>> {
+    std::env::var(ENV_KEYRING_PASSPHRASE).ok().map(Zeroizing::new)
+}
+
+/// Write a file readable only by the current user, creating its directory
+pub fn write_private_file(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    // `mode` only applies to new files; tighten one that already existed
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("Failed to restrict {}", path.display()))?;
+    }
+    file.write_all(contents)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Read keys from the environment, if the X25519 pair is set
+pub fn keyring_from_env() -> Option<UserKeypair> {
+    let x25519_public_key = std::env::var(ENV_X25519_PUBLIC_KEY).ok()?;
+    let x25519_private_key = std::env::var(ENV_X25519_PRIVATE_KEY).ok()?;
+
+    Some(UserKeypair {
+        x25519_public_key,
+        x25519_private_key,
+        ed25519_public_key: std::env::var(ENV_ED25519_PUBLIC_KEY).unwrap_or_default(),
+        ed25519_private_key: std::env::var(ENV_ED25519_PRIVATE_KEY).unwrap_or_default(),
+    })
+}
+
+/// Keyring file to use: an explicit `path`, then `KRYPTVAULT_KEYRING`,
+/// then the default location
+pub fn keyring_path(path: Option<&Path>) -> Result<PathBuf> {
+    if let Some(path) = path {
+        return Ok(path.to_path_buf());
+    }
+
+    match std::env::var_os(ENV_KEYRING_PATH) {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => default_keyring_path(),
+    }
+}
+
+/// Resolve the user's keys
+/// An explicit `path` wins, then keys from the environment, then
+/// `KRYPTVAULT_KEYRING`, then the default keyring file.
+/// `passphrase` is only called when the file is protected
+pub fn resolve_keyring(
+    path: Option<&Path>,
+    passphrase: impl FnOnce() -> Result<Zeroizing<String>>,
+) -> Result<UserKeypair> {
+    if let Some(path) = path {
+        return open_keyring(path, passphrase);
+    }
+
+    if let Some(keypair) = keyring_from_env() {
+        return Ok(keypair);
+    }
+
+    open_keyring(&keyring_path(None)?, passphrase)
+}
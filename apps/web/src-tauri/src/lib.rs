@@ -1,14 +1,55 @@
-mod crypto;
-mod s3;
+pub mod crypto;
+pub mod s3;
+pub mod keyring;
+pub mod device_link;
+mod audit;
+mod chunks;
 mod commands;
+mod csp;
+mod error;
+mod escrow;
+mod integrity;
+mod manifest;
+mod search;
+mod share_grant;
+mod share_link;
+mod shred;
+mod stream;
+mod temp;
+mod thumbnails;
+mod preview;
+mod profile;
+mod transfers;
+mod vault;
+mod versions;
+#[cfg(target_os = "linux")]
+mod vault_fs;
 
 use commands::{
-    AppState, encrypt_and_upload_file, download_and_decrypt_file, download_and_decrypt_shared_file,
-    generate_keypair, encrypt_file_only, decrypt_file_only, generate_user_keypair_command, 
+    AppState, encrypt_and_upload_file, download_and_decrypt_file, download_and_decrypt_file_with_key,
+    download_and_decrypt_shared_file,
+    generate_keypair, encrypt_file_only, decrypt_file_only, create_vault_keyring, import_vault_keyring,
+    has_vault_keyring, get_vault_public_keys,
     share_file_key, unwrap_shared_dek, wrap_dek_with_folder_key, unwrap_dek_with_folder_key,
-    generate_folder_key, seal_data,
+    wrap_key_with_password, unwrap_key_with_password,
+    generate_folder_key, seal_data, mount_vault, unmount_vault,
+    unlock_vault, lock_vault, get_vault_status, set_auto_lock_settings, record_activity,
+    set_ram_staging,
+    list_profiles, get_active_profile, create_profile, switch_profile, delete_profile,
+    list_transfers, cancel_transfer,
+    create_link_code, connect_device_link, send_linked_keyring, receive_linked_keyring,
+    cancel_device_link, verify_audit_log, export_audit_log, create_folder_manifest,
+    verify_folder_manifest, verify_share_grant_command, create_share_link, download_share_link,
+    upload_file_version, list_file_versions, download_file_version, restore_file_version,
+    diff_file_versions, generate_chunk_key, plan_chunked_upload, upload_chunks, download_chunked_file,
+    decrypt_range, update_search_index, remove_from_search_index, search_files, export_search_index,
+    import_search_index, get_file_thumbnail, preview_file, open_preview, read_preview, close_preview,
+    open_stream, close_stream, move_into_vault, delete_moved_original, scan_vault, check_key_consistency,
+    migrate_legacy_wraps, set_legacy_server_keys_disabled, set_escrow_key, recover_escrowed_dek,
+    sign_own_key_grant, allow_unsigned_key, org_key_fingerprint, pin_org_signing_key,
 };
-use std::sync::Mutex;
+use profile::{ProfileManager, ProfileRoots};
+use tauri::http::header::{HeaderValue, CONTENT_SECURITY_POLICY};
 use tauri::Manager;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -25,31 +66,134 @@ pub fn run() {
         )?;
       }
       
-      // Setup temp directory for encrypted files
-      let temp_dir = std::env::temp_dir().join("krypt-vault");
-      std::fs::create_dir_all(&temp_dir)?;
+      // Every profile gets its own subdirectory under these roots
+      let roots = ProfileRoots {
+        config_dir: app.path().app_config_dir()?,
+        data_dir: app.path().app_data_dir()?,
+        temp_dir: temp::user_temp_root(),
+        cache_dir: app.path().app_cache_dir()?,
+      };
       
       // Initialize app state
       app.manage(AppState {
-        temp_dir: Mutex::new(temp_dir),
+        profiles: ProfileManager::load(roots)?,
       });
       
+      // The CSP has to allow every profile's server, and profiles can be
+      // added at runtime, so the policy is extended whenever a page loads
+      let window_config = app.config().app.windows.first().cloned()
+        .ok_or("No window configured")?;
+      let handle = app.handle().clone();
+      tauri::WebviewWindowBuilder::from_config(app.handle(), &window_config)?
+        .on_web_resource_request(move |_request, response| {
+          let Some(policy) = response.headers_mut().get_mut(CONTENT_SECURITY_POLICY) else {
+            return;
+          };
+          let Ok(current) = policy.to_str() else {
+            return;
+          };
+          let origins = handle.state::<AppState>().profiles.server_origins();
+          let extended = csp::with_connect_sources(current, &origins);
+          if let Ok(value) = HeaderValue::from_str(&extended) {
+            *policy = value;
+          }
+        })
+        .build()?;
+      
+      // Lock vaults after inactivity or a suspend
+      vault::spawn_auto_lock_watcher(app.handle().clone());
+      
       Ok(())
     })
+    // Decrypted vault content for <video>/<img>, served from open stream handles
+    .register_asynchronous_uri_scheme_protocol(stream::SCHEME, |ctx, request, responder| {
+      let app = ctx.app_handle().clone();
+      tauri::async_runtime::spawn_blocking(move || {
+        let profile = app.state::<AppState>().profiles.active();
+        responder.respond(stream::respond(&profile, &request));
+      });
+    })
     .invoke_handler(tauri::generate_handler![
       encrypt_and_upload_file,
       download_and_decrypt_file,
+      download_and_decrypt_file_with_key,
       download_and_decrypt_shared_file,
       generate_keypair,
       encrypt_file_only,
       decrypt_file_only,
-      generate_user_keypair_command,
+      create_vault_keyring,
+      import_vault_keyring,
+      has_vault_keyring,
+      get_vault_public_keys,
       share_file_key,
       unwrap_shared_dek,
       wrap_dek_with_folder_key,
       unwrap_dek_with_folder_key,
+      wrap_key_with_password,
+      unwrap_key_with_password,
       generate_folder_key,
-      seal_data
+      seal_data,
+      mount_vault,
+      unmount_vault,
+      unlock_vault,
+      lock_vault,
+      get_vault_status,
+      set_auto_lock_settings,
+      record_activity,
+      set_ram_staging,
+      list_profiles,
+      get_active_profile,
+      create_profile,
+      switch_profile,
+      delete_profile,
+      list_transfers,
+      cancel_transfer,
+      create_link_code,
+      connect_device_link,
+      send_linked_keyring,
+      receive_linked_keyring,
+      cancel_device_link,
+      verify_audit_log,
+      export_audit_log,
+      create_folder_manifest,
+      verify_folder_manifest,
+      verify_share_grant_command,
+      create_share_link,
+      download_share_link,
+      upload_file_version,
+      list_file_versions,
+      download_file_version,
+      restore_file_version,
+      diff_file_versions,
+      generate_chunk_key,
+      plan_chunked_upload,
+      upload_chunks,
+      download_chunked_file,
+      decrypt_range,
+      update_search_index,
+      remove_from_search_index,
+      search_files,
+      export_search_index,
+      import_search_index,
+      get_file_thumbnail,
+      preview_file,
+      open_preview,
+      read_preview,
+      close_preview,
+      open_stream,
+      close_stream,
+      move_into_vault,
+      delete_moved_original,
+      scan_vault,
+      check_key_consistency,
+      migrate_legacy_wraps,
+      set_legacy_server_keys_disabled,
+      set_escrow_key,
+      recover_escrowed_dek,
+      sign_own_key_grant,
+      allow_unsigned_key,
+      org_key_fingerprint,
+      pin_org_signing_key
     ])
     .run(tauri::generate_context!())
     .expect("error while running tauri application");
@@ -0,0 +1,297 @@
+use crate::crypto::{decrypt_with_key, encrypt_with_key};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::sign;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// One file the folder owner says belongs in the folder
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub file_id: String,
+    pub ciphertext_sha256: String, // Base64
+    pub version: u64,
+}
+
+/// Owner-maintained list of a folder's contents
+/// `manifest_version` must grow with every change so old manifests can't be replayed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FolderManifest {
+    pub folder_id: String,
+    pub manifest_version: u64,
+    pub created_at: u64, // Unix timestamp in seconds
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// Manifest plus the owner's signature over its exact bytes
+#[derive(Debug, Serialize, Deserialize)]
+struct SignedManifest {
+    manifest: String,          // Base64 JSON of FolderManifest
+    signer_public_key: String, // Base64 Ed25519
+    signature: String,         // Base64 Ed25519
+}
+
+/// What the server stores: a signed manifest encrypted under the folder key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedManifest {
+    pub ciphertext: String, // Base64
+    pub nonce: String,      // Base64
+}
+
+/// A file as the server lists it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListedFile {
+    pub file_id: String,
+    pub ciphertext_sha256: Option<String>,
+    pub version: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Discrepancy {
+    /// In the manifest but not in the listing (dropped by the server)
+    Missing { file_id: String },
+    /// In the listing but not in the manifest (added by the server)
+    Unexpected { file_id: String },
+    /// A different hash than the manifest; `actual` is none when the server omitted it
+    HashMismatch { file_id: String, expected: String, actual: Option<String> },
+    /// A different version than the manifest; lower means a rollback, none
+    /// means the server omitted it
+    VersionMismatch { file_id: String, expected: u64, actual: Option<u64> },
+    /// The server returned an older manifest than this device has seen
+    ManifestRolledBack { known_version: u64, manifest_version: u64 },
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManifestReport {
+    pub folder_id: String,
+    pub manifest_version: u64,
+    pub consistent: bool,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+/// Sign a manifest with the owner's Ed25519 key and encrypt it under the folder key
+pub fn seal_manifest(
+    manifest: &FolderManifest,
+    ed25519_private_key: &str,
+    folder_key: &[u8; 32],
+) -> Result<EncryptedManifest> {
+    sodiumoxide::init().map_err(|_| anyhow::anyhow!("Failed to initialize sodiumoxide"))?;
+
+    let sk_bytes = base64::decode(ed25519_private_key).context("Failed to decode Ed25519 private key")?;
+    let secret_key = sign::SecretKey::from_slice(&sk_bytes).context("Invalid Ed25519 private key")?;
+
+    let manifest_bytes = serde_json::to_vec(manifest)?;
+    let signature = sign::sign_detached(&manifest_bytes, &secret_key);
+    let signed = SignedManifest {
+        manifest: base64::encode(&manifest_bytes),
+        signer_public_key: base64::encode(secret_key.public_key().as_ref()),
+        signature: base64::encode(signature.to_bytes()),
+    };
+
+    let (ciphertext, nonce) = encrypt_with_key(&serde_json::to_vec(&signed)?, folder_key)?;
+    Ok(EncryptedManifest { ciphertext, nonce })
+}
+
+/// Decrypt a manifest and check it was signed by `owner_public_key` for `folder_id`
+pub fn open_manifest(
+    encrypted: &EncryptedManifest,
+    folder_key: &[u8; 32],
+    owner_public_key: &str,
+    folder_id: &str,
+) -> Result<FolderManifest> {
+    sodiumoxide::init().map_err(|_| anyhow::anyhow!("Failed to initialize sodiumoxide"))?;
+
+    let signed_bytes = decrypt_with_key(&encrypted.ciphertext, &encrypted.nonce, folder_key)
+        .context("Failed to decrypt manifest")?;
+    let signed: SignedManifest = serde_json::from_slice(&signed_bytes).context("Failed to parse manifest")?;
+
+    // Trust the caller's idea of the owner, never the key embedded in the manifest
+    if signed.signer_public_key != owner_public_key {
+        return Err(anyhow::anyhow!("Manifest was not signed by the folder owner"));
+    }
+
+    let pk_bytes = base64::decode(owner_public_key).context("Failed to decode owner public key")?;
+    let public_key = sign::PublicKey::from_slice(&pk_bytes).context("Invalid owner public key")?;
+    let manifest_bytes = base64::decode(&signed.manifest).context("Failed to decode manifest")?;
+    let signature_bytes = base64::decode(&signed.signature).context("Failed to decode signature")?;
+    let signature = sign::Signature::from_bytes(&signature_bytes)
+        .map_err(|_| anyhow::anyhow!("Invalid signature size"))?;
+
+    if !sign::verify_detached(&signature, &manifest_bytes, &public_key) {
+        return Err(anyhow::anyhow!("Manifest signature is invalid"));
+    }
+
+    let manifest: FolderManifest = serde_json::from_slice(&manifest_bytes).context("Failed to parse manifest")?;
+    if manifest.folder_id != folder_id {
+        return Err(anyhow::anyhow!("Manifest belongs to a different folder"));
+    }
+
+    Ok(manifest)
+}
+
+/// Compare what the server lists against the manifest
+/// `known_version` is the newest manifest version this device has seen
+pub fn compare_listing(
+    manifest: &FolderManifest,
+    listing: &[ListedFile],
+    known_version: Option<u64>,
+) -> ManifestReport {
+    let mut discrepancies = Vec::new();
+
+    if let Some(known_version) = known_version {
+        if manifest.manifest_version < known_version {
+            discrepancies.push(Discrepancy::ManifestRolledBack {
+                known_version,
+                manifest_version: manifest.manifest_version,
+            });
+        }
+    }
+
+    let listed: HashMap<&str, &ListedFile> = listing
+        .iter()
+        .map(|file| (file.file_id.as_str(), file))
+        .collect();
+    let expected: HashSet<&str> = manifest
+        .entries
+        .iter()
+        .map(|entry| entry.file_id.as_str())
+        .collect();
+
+    for entry in &manifest.entries {
+        let Some(file) = listed.get(entry.file_id.as_str()) else {
+            discrepancies.push(Discrepancy::Missing { file_id: entry.file_id.clone() });
+            continue;
+        };
+
+        // A listing that leaves a field out can't be checked, so it counts
+        // as a mismatch rather than a pass
+        if file.ciphertext_sha256.as_deref() != Some(entry.ciphertext_sha256.as_str()) {
+            discrepancies.push(Discrepancy::HashMismatch {
+                file_id: entry.file_id.clone(),
+                expected: entry.ciphertext_sha256.clone(),
+                actual: file.ciphertext_sha256.clone(),
+            });
+        }
+
+        if file.version != Some(entry.version) {
+            discrepancies.push(Discrepancy::VersionMismatch {
+                file_id: entry.file_id.clone(),
+                expected: entry.version,
+                actual: file.version,
+            });
+        }
+    }
+
+    for file in listing {
+        if !expected.contains(file.file_id.as_str()) {
+            discrepancies.push(Discrepancy::Unexpected { file_id: file.file_id.clone() });
+        }
+    }
+
+    ManifestReport {
+        folder_id: manifest.folder_id.clone(),
+        manifest_version: manifest.manifest_version,
+        consistent: discrepancies.is_empty(),
+        discrepancies,
+    }
+}
+
+/// Newest manifest version seen per folder, kept so a server can't
+/// replay an older (validly signed) manifest
+pub fn load_known_versions(path: &Path) -> Result<HashMap<String, u64>> {
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let contents = std::fs::read_to_string(path).context("Failed to read known manifest versions")?;
+    serde_json::from_str(&contents).context("Failed to parse known manifest versions")
+}
+
+/// Record `version` for `folder_id` if it is newer than what is stored
+pub fn remember_version(path: &Path, folder_id: &str, version: u64) -> Result<()> {
+    let mut versions = load_known_versions(path)?;
+    let known = versions.entry(folder_id.to_string()).or_insert(version);
+    *known = (*known).max(version);
+
+    let contents = serde_json::to_string_pretty(&versions)?;
+    std::fs::write(path, contents).context("Failed to write known manifest versions")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest() -> FolderManifest {
+        FolderManifest {
+            folder_id: "folder".to_string(),
+            manifest_version: 3,
+            created_at: 0,
+            entries: vec![ManifestEntry {
+                file_id: "file".to_string(),
+                ciphertext_sha256: "hash".to_string(),
+                version: 2,
+            }],
+        }
+    }
+
+    fn listed(ciphertext_sha256: Option<&str>, version: Option<u64>) -> Vec<ListedFile> {
+        vec![ListedFile {
+            file_id: "file".to_string(),
+            ciphertext_sha256: ciphertext_sha256.map(str::to_string),
+            version,
+        }]
+    }
+
+    #[test]
+    fn test_matching_listing_is_consistent() {
+        let report = compare_listing(&manifest(), &listed(Some("hash"), Some(2)), Some(3));
+        assert!(report.consistent);
+    }
+
+    #[test]
+    fn test_missing_hash_is_reported() {
+        let report = compare_listing(&manifest(), &listed(None, Some(2)), None);
+        assert!(!report.consistent);
+        assert!(matches!(
+            report.discrepancies.as_slice(),
+            [Discrepancy::HashMismatch { actual: None, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_missing_version_is_reported() {
+        let report = compare_listing(&manifest(), &listed(Some("hash"), None), None);
+        assert!(!report.consistent);
+        assert!(matches!(
+            report.discrepancies.as_slice(),
+            [Discrepancy::VersionMismatch { actual: None, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_listing_with_nothing_to_check_is_not_consistent() {
+        let report = compare_listing(&manifest(), &listed(None, None), None);
+        assert_eq!(report.discrepancies.len(), 2);
+    }
+
+    #[test]
+    fn test_rollback_and_extra_files_are_reported() {
+        let mut listing = listed(Some("hash"), Some(1));
+        listing.push(ListedFile {
+            file_id: "extra".to_string(),
+            ciphertext_sha256: None,
+            version: None,
+        });
+
+        let report = compare_listing(&manifest(), &listing, Some(4));
+        assert!(matches!(
+            report.discrepancies.as_slice(),
+            [
+                Discrepancy::ManifestRolledBack { known_version: 4, manifest_version: 3 },
+                Discrepancy::VersionMismatch { actual: Some(1), .. },
+                Discrepancy::Unexpected { .. },
+            ]
+        ));
+    }
+}
@@ -0,0 +1,108 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
+
+/// Largest plaintext a preview may hold when the caller doesn't say
+pub const DEFAULT_PREVIEW_LIMIT: u64 = 50 * 1024 * 1024;
+// How long an unused preview handle stays readable
+const PREVIEW_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Decrypted file contents held in memory for display
+pub struct Preview {
+    pub data: Zeroizing<Vec<u8>>,
+    pub mime_type: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PreviewInfo {
+    pub handle: String,
+    pub size: u64,
+    pub mime_type: String,
+    pub expires_in_secs: u64,
+}
+
+struct Entry {
+    preview: Arc<Preview>,
+    last_used: Instant,
+}
+
+/// Short-lived, in-memory previews; plaintext never reaches disk and is
+/// zeroed when a handle is closed, expires or the vault locks
+#[derive(Default)]
+pub struct PreviewStore {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl PreviewStore {
+    pub fn insert(&self, preview: Preview) -> PreviewInfo {
+        let handle = uuid::Uuid::new_v4().to_string();
+        let info = PreviewInfo {
+            handle: handle.clone(),
+            size: preview.data.len() as u64,
+            mime_type: preview.mime_type.clone(),
+            expires_in_secs: PREVIEW_TTL.as_secs(),
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        retain_fresh(&mut entries);
+        entries.insert(handle, Entry {
+            preview: Arc::new(preview),
+            last_used: Instant::now(),
+        });
+        info
+    }
+
+    /// Look up a preview and keep it alive for another TTL
+    pub fn get(&self, handle: &str) -> Option<Arc<Preview>> {
+        let mut entries = self.entries.lock().unwrap();
+        retain_fresh(&mut entries);
+        let entry = entries.get_mut(handle)?;
+        entry.last_used = Instant::now();
+        Some(entry.preview.clone())
+    }
+
+    pub fn remove(&self, handle: &str) -> bool {
+        self.entries.lock().unwrap().remove(handle).is_some()
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Drop expired previews even when no handle is being used
+    pub fn purge_expired(&self) {
+        retain_fresh(&mut self.entries.lock().unwrap());
+    }
+}
+
+fn retain_fresh(entries: &mut HashMap<String, Entry>) {
+    entries.retain(|_, entry| entry.last_used.elapsed() < PREVIEW_TTL);
+}
+
+/// MIME type for display, from the file name
+/// Anything the webview might execute (HTML, SVG) is served as plain text
+pub fn mime_type_for(file_name: &str) -> &'static str {
+    let extension = Path::new(file_name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "json" => "application/json",
+        "txt" | "md" | "csv" | "log" | "html" | "htm" | "svg" | "xml" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
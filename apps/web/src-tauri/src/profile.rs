@@ -0,0 +1,416 @@
+use crate::audit::AuditLog;
+use crate::csp::server_origin;
+use crate::device_link::LinkChannel;
+use crate::escrow::{EscrowKey, BUILT_IN_ORG_SIGNING_KEY};
+use crate::preview::PreviewStore;
+use crate::shred::PendingDeletions;
+use crate::stream::StreamStore;
+use crate::temp::TempManager;
+use crate::transfers::TransferQueue;
+use crate::vault::{AutoLockSettings, Vault};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+pub const DEFAULT_PROFILE_ID: &str = "default";
+// Same variable the frontend build reads, so a release build's default
+// profile points at the server it was built for
+const DEFAULT_SERVER_URL: &str = match option_env!("VITE_SERVER_URL") {
+    Some(url) => url,
+    None => "http://localhost:3000",
+};
+const PROFILES_FILE: &str = "profiles.json";
+
+/// Persisted description of a profile (an account on one server)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProfileConfig {
+    pub id: String,
+    pub name: String,
+    pub server_url: String,
+    #[serde(default)]
+    pub disable_legacy_server_keys: bool, // Refuse DEKs sealed to the server keypair
+    #[serde(default)]
+    pub escrow_key: Option<EscrowKey>, // Every uploaded or shared DEK is also sealed to this
+    #[serde(default)]
+    pub org_signing_key: Option<String>, // Confirmed by the user or built in; escrow keys must be signed by it
+    #[serde(default)]
+    pub sharer_keys: HashMap<String, String>, // Sharer user ID -> Ed25519 key pinned by their first grant
+    #[serde(default)]
+    pub unsigned_keys_allowed: Vec<String>, // File or folder IDs the user chose to open without a grant
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProfilesFile {
+    pub active: String,
+    pub profiles: Vec<ProfileConfig>,
+}
+
+/// Base directories under which every profile gets its own subdirectory
+#[derive(Debug, Clone)]
+pub struct ProfileRoots {
+    pub config_dir: PathBuf,
+    pub data_dir: PathBuf,
+    pub temp_dir: PathBuf,
+    pub cache_dir: PathBuf,
+}
+
+/// Runtime state of one profile
+/// Each profile has its own vault, so keys unlocked in one profile are
+/// never reachable through another
+pub struct Profile {
+    pub config: ProfileConfig,
+    pub data_dir: PathBuf,
+    pub keyring_path: PathBuf,
+    pub temp: TempManager,
+    pub cache_dir: PathBuf,
+    pub vault: Vault,
+    pub transfers: TransferQueue,
+    pub audit: AuditLog,
+    pub device_link: std::sync::Mutex<Option<LinkChannel>>,
+    pub previews: PreviewStore,
+    pub streams: StreamStore,
+    pub pending_deletions: PendingDeletions, // Originals moved into the vault, deleted once the upload is saved
+    #[cfg(target_os = "linux")]
+    pub vault_mount: std::sync::Mutex<Option<fuser::BackgroundSession>>,
+    legacy_server_keys_disabled: AtomicBool,
+    escrow: RwLock<EscrowSettings>,
+    trust: RwLock<TrustSettings>,
+    own_wraps: Mutex<HashSet<String>>, // Keys wrapped here for the user's own key, awaiting their grant
+}
+
+/// A profile's escrow key and the organization key it is pinned to
+#[derive(Default)]
+struct EscrowSettings {
+    key: Option<EscrowKey>,
+    org_signing_key: Option<String>,
+}
+
+/// Which signers and unsigned keys the user has come to trust
+#[derive(Default)]
+struct TrustSettings {
+    sharer_keys: HashMap<String, String>,
+    unsigned_keys_allowed: HashSet<String>,
+}
+
+impl Profile {
+    fn open(config: ProfileConfig, roots: &ProfileRoots) -> Result<Self> {
+        let data_dir = roots.data_dir.join("profiles").join(&config.id);
+        let keyring_path = data_dir.join("keyring.json");
+        let cache_dir = roots.cache_dir.join("profiles").join(&config.id);
+
+        let temp = TempManager::open(&roots.temp_dir, &config.id)
+            .context("Failed to create profile temp directory")?;
+        std::fs::create_dir_all(&cache_dir).context("Failed to create profile cache directory")?;
+        // The signed audit head lives under the config directory, away from the log
+        let audit_head = roots.config_dir.join("audit").join(format!("{}.json", config.id));
+        let audit = AuditLog::open(&data_dir, audit_head).context("Failed to open audit log")?;
+
+        Ok(Profile {
+            legacy_server_keys_disabled: AtomicBool::new(config.disable_legacy_server_keys),
+            escrow: RwLock::new(EscrowSettings {
+                key: config.escrow_key.clone(),
+                org_signing_key: config
+                    .org_signing_key
+                    .clone()
+                    .or_else(|| BUILT_IN_ORG_SIGNING_KEY.map(str::to_string)),
+            }),
+            trust: RwLock::new(TrustSettings {
+                sharer_keys: config.sharer_keys.clone(),
+                unsigned_keys_allowed: config.unsigned_keys_allowed.iter().cloned().collect(),
+            }),
+            own_wraps: Mutex::new(HashSet::new()),
+            config,
+            data_dir,
+            keyring_path,
+            temp,
+            cache_dir,
+            vault: Vault::new(AutoLockSettings::default()),
+            transfers: TransferQueue::default(),
+            audit,
+            device_link: std::sync::Mutex::new(None),
+            previews: PreviewStore::default(),
+            streams: StreamStore::default(),
+            pending_deletions: PendingDeletions::default(),
+            #[cfg(target_os = "linux")]
+            vault_mount: std::sync::Mutex::new(None),
+        })
+    }
+
+    /// Whether files whose DEK is sealed to the server keypair may still be
+    /// opened with a server private key supplied by the client
+    pub fn legacy_server_keys_disabled(&self) -> bool {
+        self.legacy_server_keys_disabled.load(Ordering::Relaxed)
+    }
+
+    /// The organization escrow key, if one is configured
+    pub fn escrow_key(&self) -> Option<EscrowKey> {
+        self.escrow.read().unwrap().key.clone()
+    }
+
+    /// The organization signing key pinned for this profile, if any
+    pub fn org_signing_key(&self) -> Option<String> {
+        self.escrow.read().unwrap().org_signing_key.clone()
+    }
+
+    /// The Ed25519 key pinned for a sharer, if a grant from them verified before
+    pub fn sharer_key(&self, sharer_id: &str) -> Option<String> {
+        self.trust.read().unwrap().sharer_keys.get(sharer_id).cloned()
+    }
+
+    /// Whether the user chose to open this file's (or folder's) key without a grant
+    pub fn unsigned_key_allowed(&self, id: &str) -> bool {
+        self.trust.read().unwrap().unsigned_keys_allowed.contains(id)
+    }
+
+    /// Note a key just wrapped for the user's own key, so it may be self-signed
+    pub fn remember_own_wrap(&self, wrapped_key: &str) {
+        self.own_wraps.lock().unwrap().insert(wrapped_key.to_string());
+    }
+
+    /// Whether `wrapped_key` was wrapped here and not granted yet; forgets it
+    pub fn take_own_wrap(&self, wrapped_key: &str) -> bool {
+        self.own_wraps.lock().unwrap().remove(wrapped_key)
+    }
+
+    /// The persisted config, with settings changed since the profile opened
+    pub fn current_config(&self) -> ProfileConfig {
+        let trust = self.trust.read().unwrap();
+        let mut unsigned_keys_allowed: Vec<String> = trust.unsigned_keys_allowed.iter().cloned().collect();
+        unsigned_keys_allowed.sort();
+
+        ProfileConfig {
+            disable_legacy_server_keys: self.legacy_server_keys_disabled(),
+            escrow_key: self.escrow_key(),
+            org_signing_key: self.org_signing_key(),
+            sharer_keys: trust.sharer_keys.clone(),
+            unsigned_keys_allowed,
+            ..self.config.clone()
+        }
+    }
+}
+
+/// Profiles in display order plus which one is active, behind one lock so
+/// readers never see a profile listed that has already been removed
+struct ProfilesState {
+    profiles: HashMap<String, Arc<Profile>>,
+    order: Vec<String>,
+    active: String,
+}
+
+impl ProfilesState {
+    fn ordered(&self) -> impl Iterator<Item = &Arc<Profile>> {
+        self.order.iter().filter_map(|id| self.profiles.get(id))
+    }
+
+    fn to_file(&self) -> ProfilesFile {
+        ProfilesFile {
+            active: self.active.clone(),
+            profiles: self.ordered().map(|profile| profile.current_config()).collect(),
+        }
+    }
+}
+
+/// All configured profiles plus which one is active
+pub struct ProfileManager {
+    roots: ProfileRoots,
+    state: RwLock<ProfilesState>,
+}
+
+impl ProfileManager {
+    /// Load `profiles.json`, creating a default profile on first run
+    pub fn load(roots: ProfileRoots) -> Result<Self> {
+        let path = roots.config_dir.join(PROFILES_FILE);
+        let file = if path.exists() {
+            let contents = std::fs::read_to_string(&path).context("Failed to read profiles file")?;
+            serde_json::from_str(&contents).context("Failed to parse profiles file")?
+        } else {
+            ProfilesFile {
+                active: DEFAULT_PROFILE_ID.to_string(),
+                profiles: vec![ProfileConfig {
+                    id: DEFAULT_PROFILE_ID.to_string(),
+                    name: "Default".to_string(),
+                    server_url: DEFAULT_SERVER_URL.to_string(),
+                    disable_legacy_server_keys: false,
+                    escrow_key: None,
+                    org_signing_key: None,
+                    sharer_keys: HashMap::new(),
+                    unsigned_keys_allowed: Vec::new(),
+                }],
+            }
+        };
+
+        let mut profiles = HashMap::new();
+        let mut order = Vec::new();
+        for config in file.profiles {
+            order.push(config.id.clone());
+            profiles.insert(config.id.clone(), Arc::new(Profile::open(config, &roots)?));
+        }
+
+        let active = if profiles.contains_key(&file.active) {
+            file.active
+        } else {
+            order.first().cloned().context("No profiles configured")?
+        };
+
+        let state = ProfilesState { profiles, order, active };
+        write_profiles_file(&roots, &state)?;
+        Ok(ProfileManager {
+            roots,
+            state: RwLock::new(state),
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        write_profiles_file(&self.roots, &self.state.read().unwrap())
+    }
+
+    /// The active profile; commands resolve it once and use it throughout
+    pub fn active(&self) -> Arc<Profile> {
+        let state = self.state.read().unwrap();
+        // `delete` refuses the active profile, so it is always loaded
+        state
+            .profiles
+            .get(&state.active)
+            .cloned()
+            .expect("active profile is loaded")
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<Profile>> {
+        self.state.read().unwrap().profiles.get(id).cloned()
+    }
+
+    pub fn all(&self) -> Vec<Arc<Profile>> {
+        self.state.read().unwrap().ordered().cloned().collect()
+    }
+
+    pub fn list(&self) -> ProfilesFile {
+        self.state.read().unwrap().to_file()
+    }
+
+    /// Origins of every profile's server, for the window CSP
+    pub fn server_origins(&self) -> Vec<String> {
+        self.all()
+            .iter()
+            .filter_map(|profile| server_origin(&profile.config.server_url))
+            .collect()
+    }
+
+    pub fn create(&self, name: &str, server_url: &str) -> Result<ProfileConfig> {
+        let config = ProfileConfig {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            server_url: server_url.trim_end_matches('/').to_string(),
+            disable_legacy_server_keys: false,
+            escrow_key: None,
+            org_signing_key: None,
+            sharer_keys: HashMap::new(),
+            unsigned_keys_allowed: Vec::new(),
+        };
+        let profile = Profile::open(config.clone(), &self.roots)?;
+
+        let mut state = self.state.write().unwrap();
+        state.profiles.insert(config.id.clone(), Arc::new(profile));
+        state.order.push(config.id.clone());
+        write_profiles_file(&self.roots, &state)?;
+        Ok(config)
+    }
+
+    pub fn switch(&self, id: &str) -> Result<Arc<Profile>> {
+        let mut state = self.state.write().unwrap();
+        let profile = state.profiles.get(id).cloned().context("Profile not found")?;
+        state.active = id.to_string();
+        write_profiles_file(&self.roots, &state)?;
+        Ok(profile)
+    }
+
+    /// Turn the legacy server-key decrypt path off or back on for a profile
+    pub fn set_legacy_server_keys_disabled(&self, id: &str, disabled: bool) -> Result<()> {
+        let profile = self.get(id).context("Profile not found")?;
+        profile.legacy_server_keys_disabled.store(disabled, Ordering::Relaxed);
+        self.save()
+    }
+
+    /// Set or clear a profile's escrow key; the caller has verified the
+    /// change against the pinned organization signing key
+    pub fn set_escrow_key(&self, id: &str, escrow_key: Option<EscrowKey>) -> Result<()> {
+        let profile = self.get(id).context("Profile not found")?;
+        profile.escrow.write().unwrap().key = escrow_key;
+        self.save()
+    }
+
+    /// Pin the organization signing key the user confirmed; a profile keeps
+    /// its first one, so a different key is refused
+    pub fn pin_org_signing_key(&self, id: &str, org_signing_key: &str) -> Result<()> {
+        let profile = self.get(id).context("Profile not found")?;
+        {
+            let mut escrow = profile.escrow.write().unwrap();
+            let pinned = escrow.org_signing_key.get_or_insert_with(|| org_signing_key.to_string());
+            if pinned != org_signing_key {
+                return Err(anyhow::anyhow!("A different organization key is already pinned"));
+            }
+        }
+        self.save()
+    }
+
+    /// Pin a sharer's Ed25519 key the first time a grant from them verifies
+    /// A different key for a sharer who is already pinned is refused
+    pub fn pin_sharer_key(&self, id: &str, sharer_id: &str, public_key: &str) -> Result<()> {
+        let profile = self.get(id).context("Profile not found")?;
+        {
+            let mut trust = profile.trust.write().unwrap();
+            let pinned = trust.sharer_keys.entry(sharer_id.to_string()).or_insert_with(|| public_key.to_string());
+            if pinned != public_key {
+                return Err(anyhow::anyhow!("A different key is already pinned for this sharer"));
+            }
+        }
+        self.save()
+    }
+
+    /// Let a key without a grant open for one file or folder
+    pub fn allow_unsigned_key(&self, id: &str, file_id: &str) -> Result<()> {
+        let profile = self.get(id).context("Profile not found")?;
+        profile.trust.write().unwrap().unsigned_keys_allowed.insert(file_id.to_string());
+        self.save()
+    }
+
+    /// Remove a profile and its keyring, temp and cache directories
+    /// The active profile cannot be deleted
+    pub fn delete(&self, id: &str) -> Result<Arc<Profile>> {
+        let profile = {
+            let mut state = self.state.write().unwrap();
+            if state.active == id {
+                return Err(anyhow::anyhow!("Cannot delete the active profile"));
+            }
+
+            let profile = state.profiles.remove(id).context("Profile not found")?;
+            state.order.retain(|other| other != id);
+            write_profiles_file(&self.roots, &state)?;
+            profile
+        };
+
+        for dir in [profile.data_dir.as_path(), profile.temp.dir(), profile.cache_dir.as_path()] {
+            remove_dir_if_exists(dir);
+        }
+        let _ = std::fs::remove_file(profile.audit.head_path());
+
+        Ok(profile)
+    }
+}
+
+fn write_profiles_file(roots: &ProfileRoots, state: &ProfilesState) -> Result<()> {
+    std::fs::create_dir_all(&roots.config_dir).context("Failed to create config directory")?;
+    let contents = serde_json::to_string_pretty(&state.to_file())?;
+    std::fs::write(roots.config_dir.join(PROFILES_FILE), contents)
+        .context("Failed to write profiles file")
+}
+
+fn remove_dir_if_exists(dir: &Path) {
+    if dir.exists() {
+        if let Err(e) = std::fs::remove_dir_all(dir) {
+            log::warn!("Failed to remove profile directory {}: {}", dir.display(), e);
+        }
+    }
+}
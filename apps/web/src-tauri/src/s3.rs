@@ -2,7 +2,7 @@ use anyhow::{Context, Result};
 use reqwest::multipart;
 use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PresignedUrlResponse {
@@ -10,18 +10,103 @@ pub struct PresignedUrlResponse {
     pub fields: std::collections::HashMap<String, String>,
 }
 
+/// Non-success HTTP response from S3, kept typed so callers can classify it
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub operation: &'static str,
+    pub status: reqwest::StatusCode,
+    pub body: String,
+}
+
+impl std::fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} failed with status {}: {}", self.operation, self.status, self.body)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+/// S3 stored (or reported) an object whose checksum isn't the one we sent
+#[derive(Debug)]
+pub struct ChecksumMismatch {
+    pub expected: String,
+    pub actual: String,
+}
+
+impl std::fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Checksum mismatch: expected {}, got {}", self.expected, self.actual)
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct S3UploadResult {
     pub success: bool,
     pub file_key: String,
     pub bucket: String,
+    pub etag: Option<String>,
+    pub checksum_verified: bool, // S3 confirmed the SHA-256 we sent
+}
+
+/// What a HEAD request says about an object
+#[derive(Debug)]
+pub struct ObjectInfo {
+    pub size: u64,
+    pub etag: Option<String>,
+    pub checksum_sha256: Option<String>, // Base64; only for objects uploaded with one
+}
+
+const CHECKSUM_SHA256_HEADER: &str = "x-amz-checksum-sha256";
+
+fn header_value(headers: &reqwest::header::HeaderMap, name: impl reqwest::header::AsHeaderName) -> Option<String> {
+    headers.get(name).and_then(|value| value.to_str().ok()).map(str::to_string)
+}
+
+/// ETags come quoted
+fn etag(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    header_value(headers, reqwest::header::ETAG).map(|etag| etag.trim_matches('"').to_string())
+}
+
+/// Bucket a presigned URL points at, for virtual-hosted
+/// (`bucket.s3.region.amazonaws.com/key`) or path-style (`host/bucket/key`) URLs
+pub fn bucket_from_url(presigned_url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(presigned_url).ok()?;
+    let host = url.host_str()?;
+
+    if let Some(index) = host.find(".s3.").or_else(|| host.find(".s3-")) {
+        return Some(host[..index].to_string());
+    }
+    url.path_segments()?
+        .next()
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_string)
+}
+
+/// Compare a HEAD of an uploaded object with what was sent
+/// Returns whether S3 reported a checksum (and so whether it was compared)
+pub fn check_uploaded_object(object: &ObjectInfo, size: u64, ciphertext_sha256: &str) -> Result<bool, String> {
+    if object.size != size {
+        return Err(format!("Server has {} bytes but {} were uploaded", object.size, size));
+    }
+    match &object.checksum_sha256 {
+        Some(checksum) if checksum != ciphertext_sha256 => {
+            Err("Uploaded file does not match what was encrypted".to_string())
+        }
+        Some(_) => Ok(true),
+        None => Ok(false),
+    }
 }
 
 /// Upload encrypted file to S3 using presigned URL
+/// With `checksum_sha256` (base64), S3 rejects the upload unless the body
+/// matches it, and the checksum it echoes back is checked too
 pub async fn upload_to_s3(
     file_path: &str,
     presigned_url: &str,
     file_key: &str,
+    checksum_sha256: Option<&str>,
 ) -> Result<S3UploadResult> {
     // Read the encrypted file
     let mut file = File::open(file_path)
@@ -35,10 +120,14 @@ pub async fn upload_to_s3(
     let client = reqwest::Client::new();
     
     // Upload using PUT request (simple presigned URL)
-    let response = client
+    let mut request = client
         .put(presigned_url)
         .header("Content-Type", "application/octet-stream")
-        .body(file_contents)
+        .body(file_contents);
+    if let Some(checksum) = checksum_sha256 {
+        request = request.header(CHECKSUM_SHA256_HEADER, checksum);
+    }
+    let response = request
         .send()
         .await
         .context("Failed to upload file to S3")?;
@@ -46,20 +135,228 @@ pub async fn upload_to_s3(
     if !response.status().is_success() {
         let status = response.status();
         let error_body = response.text().await.unwrap_or_default();
-        return Err(anyhow::anyhow!(
-            "S3 upload failed with status {}: {}",
+        return Err(HttpStatusError {
+            operation: "S3 upload",
             status,
-            error_body
-        ));
+            body: error_body,
+        }
+        .into());
     }
     
+    let echoed = header_value(response.headers(), CHECKSUM_SHA256_HEADER);
+    let checksum_verified = match (checksum_sha256, echoed) {
+        (Some(expected), Some(actual)) if expected != actual => {
+            return Err(ChecksumMismatch { expected: expected.to_string(), actual }.into());
+        }
+        (Some(_), Some(_)) => true,
+        _ => false,
+    };
+    
     Ok(S3UploadResult {
         success: true,
         file_key: file_key.to_string(),
-        bucket: "krypt-vault-files".to_string(),
+        bucket: bucket_from_url(presigned_url).unwrap_or_default(),
+        etag: etag(response.headers()),
+        checksum_verified,
+    })
+}
+
+/// Download an encrypted object from S3 using a presigned GET URL
+/// Writes the ciphertext to `output_path` and returns its size in bytes
+pub async fn download_from_s3(download_url: &str, output_path: &str) -> Result<u64> {
+    // Create HTTP client
+    let client = reqwest::Client::new();
+    
+    let response = client
+        .get(download_url)
+        .send()
+        .await
+        .context("Failed to download file from S3")?;
+    
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_body = response.text().await.unwrap_or_default();
+        return Err(HttpStatusError {
+            operation: "S3 download",
+            status,
+            body: error_body,
+        }
+        .into());
+    }
+    
+    let encrypted_data = response
+        .bytes()
+        .await
+        .context("Failed to read downloaded data")?;
+    
+    // Save encrypted file
+    let mut file = File::create(output_path)
+        .context("Failed to create encrypted file")?;
+    file.write_all(&encrypted_data)
+        .context("Failed to save encrypted file")?;
+    
+    Ok(encrypted_data.len() as u64)
+}
+
+/// Look up an object with a presigned HEAD URL
+pub async fn head_s3_object(head_url: &str) -> Result<ObjectInfo> {
+    let client = reqwest::Client::new();
+    
+    let response = client
+        .head(head_url)
+        .header("x-amz-checksum-mode", "ENABLED")
+        .send()
+        .await
+        .context("Failed to check object in S3")?;
+    
+    if !response.status().is_success() {
+        return Err(HttpStatusError {
+            operation: "S3 head",
+            status: response.status(),
+            body: String::new(),
+        }
+        .into());
+    }
+    
+    // `content_length()` reports the (empty) body of a HEAD response, so read the header
+    let headers = response.headers();
+    let size = header_value(headers, reqwest::header::CONTENT_LENGTH)
+        .and_then(|value| value.parse().ok())
+        .context("S3 did not report the object size")?;
+    
+    Ok(ObjectInfo {
+        size,
+        etag: etag(headers),
+        checksum_sha256: header_value(headers, CHECKSUM_SHA256_HEADER),
     })
 }
 
+/// Upload an in-memory object (e.g. an encrypted chunk) with a presigned PUT URL
+pub async fn upload_bytes_to_s3(data: Vec<u8>, presigned_url: &str) -> Result<()> {
+    let client = reqwest::Client::new();
+    
+    let response = client
+        .put(presigned_url)
+        .header("Content-Type", "application/octet-stream")
+        .body(data)
+        .send()
+        .await
+        .context("Failed to upload object to S3")?;
+    
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_body = response.text().await.unwrap_or_default();
+        return Err(HttpStatusError {
+            operation: "S3 upload",
+            status,
+            body: error_body,
+        }
+        .into());
+    }
+    
+    Ok(())
+}
+
+/// The object is bigger than the caller allowed
+#[derive(Debug)]
+pub struct SizeLimitExceeded {
+    pub limit: u64,
+}
+
+impl std::fmt::Display for SizeLimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Object is larger than {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for SizeLimitExceeded {}
+
+/// Download an object into memory using a presigned GET URL
+/// With `max_bytes`, stops with `SizeLimitExceeded` instead of buffering more
+pub async fn download_bytes_from_s3(download_url: &str, max_bytes: Option<u64>) -> Result<Vec<u8>> {
+    let client = reqwest::Client::new();
+    
+    let mut response = client
+        .get(download_url)
+        .send()
+        .await
+        .context("Failed to download object from S3")?;
+    
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_body = response.text().await.unwrap_or_default();
+        return Err(HttpStatusError {
+            operation: "S3 download",
+            status,
+            body: error_body,
+        }
+        .into());
+    }
+    
+    let limit = max_bytes.unwrap_or(u64::MAX);
+    if response.content_length().is_some_and(|len| len > limit) {
+        return Err(SizeLimitExceeded { limit }.into());
+    }
+    
+    // Content-Length can be missing or wrong, so count as we go
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await.context("Failed to read downloaded data")? {
+        if data.len() as u64 + chunk.len() as u64 > limit {
+            return Err(SizeLimitExceeded { limit }.into());
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+/// Download `length` bytes starting at `start` with an HTTP Range request
+/// Servers that ignore the Range header still work; the rest of the body is discarded
+pub async fn download_range_from_s3(download_url: &str, start: u64, length: u64) -> Result<Vec<u8>> {
+    if length == 0 {
+        return Ok(Vec::new());
+    }
+    let client = reqwest::Client::new();
+    
+    let mut response = client
+        .get(download_url)
+        .header(reqwest::header::RANGE, format!("bytes={}-{}", start, start + length - 1))
+        .send()
+        .await
+        .context("Failed to download object range from S3")?;
+    
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_body = response.text().await.unwrap_or_default();
+        return Err(HttpStatusError {
+            operation: "S3 ranged download",
+            status,
+            body: error_body,
+        }
+        .into());
+    }
+    
+    // A 200 is the whole object, so skip ahead to `start`
+    let mut skip = if response.status() == reqwest::StatusCode::PARTIAL_CONTENT { 0 } else { start };
+    let mut data = Vec::with_capacity(length as usize);
+    while let Some(chunk) = response.chunk().await.context("Failed to read downloaded data")? {
+        let mut chunk = &chunk[..];
+        let skipped = skip.min(chunk.len() as u64);
+        chunk = &chunk[skipped as usize..];
+        skip -= skipped;
+        
+        let wanted = (length - data.len() as u64).min(chunk.len() as u64);
+        data.extend_from_slice(&chunk[..wanted as usize]);
+        if data.len() as u64 == length {
+            break;
+        }
+    }
+    
+    if (data.len() as u64) < length {
+        return Err(anyhow::anyhow!("Object ended before the requested range"));
+    }
+    Ok(data)
+}
+
 /// Upload using POST presigned URL with form fields (alternative method)
 pub async fn upload_to_s3_post(
     file_path: &str,
@@ -102,11 +399,12 @@ pub async fn upload_to_s3_post(
     if !response.status().is_success() {
         let status = response.status();
         let error_body = response.text().await.unwrap_or_default();
-        return Err(anyhow::anyhow!(
-            "S3 upload failed with status {}: {}",
+        return Err(HttpStatusError {
+            operation: "S3 upload",
             status,
-            error_body
-        ));
+            body: error_body,
+        }
+        .into());
     }
     
     Ok(S3UploadResult {
@@ -114,6 +412,57 @@ pub async fn upload_to_s3_post(
         file_key: presigned_data.fields.get("key")
             .cloned()
             .unwrap_or_default(),
-        bucket: "krypt-vault-files".to_string(),
+        bucket: presigned_data.fields.get("bucket")
+            .cloned()
+            .or_else(|| bucket_from_url(&presigned_data.url))
+            .unwrap_or_default(),
+        etag: etag(response.headers()),
+        checksum_verified: false,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(size: u64, checksum_sha256: Option<&str>) -> ObjectInfo {
+        ObjectInfo {
+            size,
+            etag: None,
+            checksum_sha256: checksum_sha256.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_bucket_from_virtual_hosted_url() {
+        let url = "https://vault-files.s3.us-east-1.amazonaws.com/user/file?X-Amz-Signature=abc";
+        assert_eq!(bucket_from_url(url).as_deref(), Some("vault-files"));
+
+        let legacy = "https://vault-files.s3-eu-west-1.amazonaws.com/user/file";
+        assert_eq!(bucket_from_url(legacy).as_deref(), Some("vault-files"));
+    }
+
+    #[test]
+    fn test_bucket_from_path_style_url() {
+        let url = "http://localhost:9000/vault-files/user/file?X-Amz-Signature=abc";
+        assert_eq!(bucket_from_url(url).as_deref(), Some("vault-files"));
+
+        let aws = "https://s3.us-east-1.amazonaws.com/vault-files/user/file";
+        assert_eq!(bucket_from_url(aws).as_deref(), Some("vault-files"));
+    }
+
+    #[test]
+    fn test_bucket_from_unusable_url() {
+        assert_eq!(bucket_from_url("not a url"), None);
+        assert_eq!(bucket_from_url("http://localhost:9000/"), None);
+    }
+
+    #[test]
+    fn test_check_uploaded_object() {
+        assert_eq!(check_uploaded_object(&object(10, Some("abc")), 10, "abc"), Ok(true));
+        assert_eq!(check_uploaded_object(&object(10, None), 10, "abc"), Ok(false));
+        assert!(check_uploaded_object(&object(10, Some("xyz")), 10, "abc").is_err());
+        assert!(check_uploaded_object(&object(9, Some("abc")), 10, "abc").is_err());
+        assert!(check_uploaded_object(&object(9, None), 10, "abc").is_err());
+    }
+}
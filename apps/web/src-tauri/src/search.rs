@@ -0,0 +1,268 @@
+use crate::crypto::{decrypt_with_key, encrypt_with_key, UserKeypair};
+use crate::keyring::write_private_file;
+use anyhow::{Context, Result};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+// Longest query term the fuzzy matcher will try; edit distance is quadratic
+const MAX_FUZZY_TERM: usize = 64;
+
+/// Decrypted metadata for one file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub file_id: String,
+    pub name: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub folder_path: String, // e.g. "Projects/2024/Taxes"
+    #[serde(default)]
+    pub updated_at: u64, // Unix timestamp in seconds; newer wins when merging
+    #[serde(default)]
+    pub deleted: bool, // Tombstone, so a removal survives a merge
+}
+
+/// Which part of an entry a query term matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchField {
+    Name,
+    Tag,
+    FolderPath,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchKind {
+    Fuzzy,
+    Substring,
+    Prefix,
+    Exact,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub file_id: String,
+    pub name: String,
+    pub tags: Vec<String>,
+    pub folder_path: String,
+    pub score: u32,
+    pub matched: Vec<(MatchField, MatchKind)>, // One per query term
+}
+
+/// The index as stored on disk and synced: encrypted under a key derived
+/// from the user's keyring, so only the user's devices can read it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedSearchIndex {
+    pub ciphertext: String, // Base64
+    pub nonce: String,      // Base64
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SearchIndex {
+    entries: BTreeMap<String, IndexEntry>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Key for the search index, derived from the user's X25519 private key
+pub fn index_key(keypair: &UserKeypair) -> Result<Zeroizing<[u8; 32]>> {
+    let secret = Zeroizing::new(
+        base64::decode(&keypair.x25519_private_key).context("Failed to decode private key")?,
+    );
+    let hkdf = Hkdf::<Sha256>::new(None, &secret);
+    let mut key = Zeroizing::new([0u8; 32]);
+    hkdf.expand(b"kryptvault search index", key.as_mut())
+        .map_err(|_| anyhow::anyhow!("Failed to derive search index key"))?;
+    Ok(key)
+}
+
+impl SearchIndex {
+    /// Load the local index, or start an empty one
+    pub fn load(path: &Path, key: &[u8; 32]) -> Result<Self> {
+        if !path.exists() {
+            return Ok(SearchIndex::default());
+        }
+
+        let contents = std::fs::read_to_string(path).context("Failed to read search index")?;
+        let encrypted: EncryptedSearchIndex =
+            serde_json::from_str(&contents).context("Failed to parse search index")?;
+        Self::decrypt(&encrypted, key)
+    }
+
+    pub fn save(&self, path: &Path, key: &[u8; 32]) -> Result<()> {
+        let encrypted = self.encrypt(key)?;
+        write_private_file(path, serde_json::to_string(&encrypted)?.as_bytes())
+    }
+
+    pub fn encrypt(&self, key: &[u8; 32]) -> Result<EncryptedSearchIndex> {
+        let plaintext = Zeroizing::new(serde_json::to_vec(self)?);
+        let (ciphertext, nonce) = encrypt_with_key(&plaintext, key)?;
+        Ok(EncryptedSearchIndex { ciphertext, nonce })
+    }
+
+    pub fn decrypt(encrypted: &EncryptedSearchIndex, key: &[u8; 32]) -> Result<Self> {
+        let plaintext = Zeroizing::new(
+            decrypt_with_key(&encrypted.ciphertext, &encrypted.nonce, key)
+                .context("Failed to decrypt search index")?,
+        );
+        serde_json::from_slice(&plaintext).context("Failed to parse search index")
+    }
+
+    /// Number of live (non-deleted) entries
+    pub fn live_count(&self) -> usize {
+        self.entries.values().filter(|e| !e.deleted).count()
+    }
+
+    pub fn upsert(&mut self, mut entry: IndexEntry) {
+        entry.updated_at = now();
+        entry.deleted = false;
+        self.entries.insert(entry.file_id.clone(), entry);
+    }
+
+    pub fn remove(&mut self, file_id: &str) {
+        if let Some(entry) = self.entries.get_mut(file_id) {
+            entry.deleted = true;
+            entry.updated_at = now();
+        }
+    }
+
+    /// Merge an index synced from another device; the newer entry wins
+    pub fn merge(&mut self, other: SearchIndex) {
+        for (file_id, theirs) in other.entries {
+            match self.entries.get(&file_id) {
+                Some(ours) if ours.updated_at >= theirs.updated_at => {}
+                _ => {
+                    self.entries.insert(file_id, theirs);
+                }
+            }
+        }
+    }
+
+    /// Find files whose name, tags or folder path match every query term
+    pub fn query(&self, query: &str, limit: usize) -> Vec<SearchHit> {
+        let terms: Vec<String> = query.split_whitespace().map(|t| t.to_lowercase()).collect();
+        if terms.is_empty() {
+            return Vec::new();
+        }
+
+        let mut hits: Vec<SearchHit> = self
+            .entries
+            .values()
+            .filter(|entry| !entry.deleted)
+            .filter_map(|entry| {
+                let mut score = 0;
+                let mut matched = Vec::with_capacity(terms.len());
+                for term in &terms {
+                    let (field, kind, term_score) = best_match(entry, term)?;
+                    score += term_score;
+                    matched.push((field, kind));
+                }
+
+                Some(SearchHit {
+                    file_id: entry.file_id.clone(),
+                    name: entry.name.clone(),
+                    tags: entry.tags.clone(),
+                    folder_path: entry.folder_path.clone(),
+                    score,
+                    matched,
+                })
+            })
+            .collect();
+
+        hits.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
+        hits.truncate(limit);
+        hits
+    }
+}
+
+/// Best match for one term across an entry's fields; names rank above tags
+/// and tags above folders when the match is equally good
+fn best_match(entry: &IndexEntry, term: &str) -> Option<(MatchField, MatchKind, u32)> {
+    let name = std::iter::once((MatchField::Name, entry.name.as_str(), 3));
+    let tags = entry.tags.iter().map(|tag| (MatchField::Tag, tag.as_str(), 2));
+    let folders = entry
+        .folder_path
+        .split(['/', '\\'])
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| (MatchField::FolderPath, segment, 1));
+
+    name.chain(tags)
+        .chain(folders)
+        .filter_map(|(field, text, weight)| {
+            let kind = match_text(&text.to_lowercase(), term)?;
+            let base = match kind {
+                MatchKind::Exact => 100,
+                MatchKind::Prefix => 75,
+                MatchKind::Substring => 50,
+                MatchKind::Fuzzy => 25,
+            };
+            Some((field, kind, base + weight))
+        })
+        .max_by_key(|(_, _, score)| *score)
+}
+
+/// How `term` matches `text` (both lowercase), trying whole text, then each word
+fn match_text(text: &str, term: &str) -> Option<MatchKind> {
+    if text == term {
+        return Some(MatchKind::Exact);
+    }
+
+    let words: Vec<&str> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+
+    if text.starts_with(term) || words.iter().any(|w| w.starts_with(term)) {
+        return Some(MatchKind::Prefix);
+    }
+    if text.contains(term) {
+        return Some(MatchKind::Substring);
+    }
+
+    // Allow roughly one typo per four characters, compared against the
+    // start of each word so partially typed words still match
+    let term_len = term.chars().count();
+    if !(3..=MAX_FUZZY_TERM).contains(&term_len) {
+        return None;
+    }
+    let max_distance = (term_len / 4).max(1);
+    words
+        .iter()
+        .any(|word| {
+            let prefix: String = word.chars().take(term_len + max_distance).collect();
+            let len = prefix.chars().count();
+            (term_len.saturating_sub(max_distance)..=len).any(|cut| {
+                let candidate: String = prefix.chars().take(cut).collect();
+                edit_distance(&candidate, term) <= max_distance
+            })
+        })
+        .then_some(MatchKind::Fuzzy)
+}
+
+/// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut curr = vec![0; b.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        curr[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+    prev[b.len()]
+}
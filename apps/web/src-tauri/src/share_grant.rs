@@ -0,0 +1,208 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::sign;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What the sharer vouches for when re-wrapping a DEK for someone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareGrant {
+    pub file_id: String,
+    pub wrapped_dek: String,                // DEK sealed to the recipient
+    pub recipient_public_key: String,       // Recipient's X25519 key
+    pub sharer_x25519_public_key: String,
+    pub sharer_ed25519_public_key: String,
+    pub issued_at: u64, // Unix timestamp in seconds
+}
+
+/// A grant plus the sharer's signature over its exact bytes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedShareGrant {
+    pub grant: String,     // Base64 JSON of ShareGrant
+    pub signature: String, // Base64 Ed25519
+}
+
+/// Sign a grant binding `wrapped_dek` to the file, recipient and sharer
+pub fn sign_share_grant(
+    file_id: &str,
+    wrapped_dek: &str,
+    recipient_public_key: &str,
+    sharer_x25519_public_key: &str,
+    sharer_ed25519_private_key: &str,
+) -> Result<SignedShareGrant> {
+    sodiumoxide::init().map_err(|_| anyhow::anyhow!("Failed to initialize sodiumoxide"))?;
+
+    let sk_bytes = base64::decode(sharer_ed25519_private_key).context("Failed to decode Ed25519 private key")?;
+    let secret_key = sign::SecretKey::from_slice(&sk_bytes).context("Invalid Ed25519 private key")?;
+
+    let grant = ShareGrant {
+        file_id: file_id.to_string(),
+        wrapped_dek: wrapped_dek.to_string(),
+        recipient_public_key: recipient_public_key.to_string(),
+        sharer_x25519_public_key: sharer_x25519_public_key.to_string(),
+        sharer_ed25519_public_key: base64::encode(secret_key.public_key().as_ref()),
+        issued_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    };
+
+    let grant_bytes = serde_json::to_vec(&grant)?;
+    let signature = sign::sign_detached(&grant_bytes, &secret_key);
+
+    Ok(SignedShareGrant {
+        grant: base64::encode(&grant_bytes),
+        signature: base64::encode(signature.to_bytes()),
+    })
+}
+
+/// What a grant claims, before its signature is checked
+/// Only used to pick the key to check it against
+pub fn claimed_share_grant(signed: &SignedShareGrant) -> Result<ShareGrant> {
+    let grant_bytes = base64::decode(&signed.grant).context("Failed to decode share grant")?;
+    serde_json::from_slice(&grant_bytes).context("Failed to parse share grant")
+}
+
+/// Check a grant's signature against the sharer's known Ed25519 key and
+/// that it covers this exact wrapped DEK, recipient and file
+pub fn verify_share_grant(
+    signed: &SignedShareGrant,
+    sharer_ed25519_public_key: &str,
+    recipient_public_key: &str,
+    file_id: &str,
+    wrapped_dek: &str,
+) -> Result<ShareGrant> {
+    sodiumoxide::init().map_err(|_| anyhow::anyhow!("Failed to initialize sodiumoxide"))?;
+
+    let pk_bytes = base64::decode(sharer_ed25519_public_key).context("Failed to decode sharer public key")?;
+    let public_key = sign::PublicKey::from_slice(&pk_bytes).context("Invalid sharer public key")?;
+    let grant_bytes = base64::decode(&signed.grant).context("Failed to decode share grant")?;
+    let signature_bytes = base64::decode(&signed.signature).context("Failed to decode signature")?;
+    let signature = sign::Signature::from_bytes(&signature_bytes)
+        .map_err(|_| anyhow::anyhow!("Invalid signature size"))?;
+
+    if !sign::verify_detached(&signature, &grant_bytes, &public_key) {
+        return Err(anyhow::anyhow!("Share grant was not signed by the claimed sharer"));
+    }
+
+    let grant: ShareGrant = serde_json::from_slice(&grant_bytes).context("Failed to parse share grant")?;
+    if grant.sharer_ed25519_public_key != sharer_ed25519_public_key {
+        return Err(anyhow::anyhow!("Share grant names a different sharer"));
+    }
+    if grant.recipient_public_key != recipient_public_key {
+        return Err(anyhow::anyhow!("Share grant is for a different recipient"));
+    }
+    if grant.file_id != file_id {
+        return Err(anyhow::anyhow!("Share grant is for a different file"));
+    }
+    if grant.wrapped_dek != wrapped_dek {
+        return Err(anyhow::anyhow!("Wrapped key does not match the share grant"));
+    }
+
+    Ok(grant)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_user_keypair;
+
+    const FILE_ID: &str = "file-1";
+    const WRAPPED_DEK: &str = "d3JhcHBlZA==";
+
+    fn signed_grant(sharer: &crate::crypto::UserKeypair, recipient_pk: &str) -> SignedShareGrant {
+        sign_share_grant(
+            FILE_ID,
+            WRAPPED_DEK,
+            recipient_pk,
+            &sharer.x25519_public_key,
+            &sharer.ed25519_private_key,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_valid_grant_verifies() {
+        let sharer = generate_user_keypair().unwrap();
+        let recipient = generate_user_keypair().unwrap();
+        let signed = signed_grant(&sharer, &recipient.x25519_public_key);
+
+        let grant = verify_share_grant(
+            &signed,
+            &sharer.ed25519_public_key,
+            &recipient.x25519_public_key,
+            FILE_ID,
+            WRAPPED_DEK,
+        )
+        .unwrap();
+        assert_eq!(grant.sharer_x25519_public_key, sharer.x25519_public_key);
+    }
+
+    #[test]
+    fn test_grant_from_other_signer_is_rejected() {
+        let sharer = generate_user_keypair().unwrap();
+        let impostor = generate_user_keypair().unwrap();
+        let recipient = generate_user_keypair().unwrap();
+        let signed = signed_grant(&impostor, &recipient.x25519_public_key);
+
+        assert!(verify_share_grant(
+            &signed,
+            &sharer.ed25519_public_key,
+            &recipient.x25519_public_key,
+            FILE_ID,
+            WRAPPED_DEK,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_tampered_grant_is_rejected() {
+        let sharer = generate_user_keypair().unwrap();
+        let recipient = generate_user_keypair().unwrap();
+        let mut signed = signed_grant(&sharer, &recipient.x25519_public_key);
+
+        let mut grant: ShareGrant = serde_json::from_slice(&base64::decode(&signed.grant).unwrap()).unwrap();
+        grant.file_id = "file-2".to_string();
+        signed.grant = base64::encode(serde_json::to_vec(&grant).unwrap());
+
+        assert!(verify_share_grant(
+            &signed,
+            &sharer.ed25519_public_key,
+            &recipient.x25519_public_key,
+            "file-2",
+            WRAPPED_DEK,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_grant_for_other_file_or_key_is_rejected() {
+        let sharer = generate_user_keypair().unwrap();
+        let recipient = generate_user_keypair().unwrap();
+        let other = generate_user_keypair().unwrap();
+        let signed = signed_grant(&sharer, &recipient.x25519_public_key);
+        let verify = |recipient_pk: &str, file_id: &str, wrapped_dek: &str| {
+            verify_share_grant(&signed, &sharer.ed25519_public_key, recipient_pk, file_id, wrapped_dek)
+        };
+
+        assert!(verify(&other.x25519_public_key, FILE_ID, WRAPPED_DEK).is_err());
+        assert!(verify(&recipient.x25519_public_key, "file-2", WRAPPED_DEK).is_err());
+        assert!(verify(&recipient.x25519_public_key, FILE_ID, "b3RoZXI=").is_err());
+    }
+
+    #[test]
+    fn test_self_signed_grant_names_its_signer() {
+        let owner = generate_user_keypair().unwrap();
+        let signed = signed_grant(&owner, &owner.x25519_public_key);
+
+        let claimed = claimed_share_grant(&signed).unwrap();
+        assert_eq!(claimed.sharer_ed25519_public_key, owner.ed25519_public_key);
+        assert!(verify_share_grant(
+            &signed,
+            &claimed.sharer_ed25519_public_key,
+            &owner.x25519_public_key,
+            FILE_ID,
+            WRAPPED_DEK,
+        )
+        .is_ok());
+    }
+}
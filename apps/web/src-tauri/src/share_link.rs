@@ -0,0 +1,233 @@
+use crate::crypto::{
+    decrypt_with_key, decrypt_with_password, encrypt_with_key, encrypt_with_password, PasswordWrappedKey,
+};
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+const LINK_KEY_PARAM: &str = "k";
+const MAX_NAME_ATTEMPTS: u32 = 1000;
+
+/// Everything an outside party needs to decrypt the file
+/// Encrypted under the link key, so expiry and download limit can't be
+/// changed by the server without the recipient noticing
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinkPayload {
+    pub link_id: String,
+    pub file_id: String,
+    pub file_name: String,
+    pub dek: Option<String>, // Base64; None for password-protected links
+    pub password_wrapped_dek: Option<PasswordWrappedKey>,
+    pub nonce: String, // Base64 file nonce
+    pub expires_at: Option<u64>, // Unix timestamp in seconds
+    pub max_downloads: Option<u32>,
+}
+
+/// A new share link; the server stores everything except `url`'s fragment
+#[derive(Debug, Serialize)]
+pub struct ShareLink {
+    pub url: String,
+    pub link_id: String,
+    pub wrapped_payload: String, // Base64
+    pub payload_nonce: String,   // Base64
+    pub expires_at: Option<u64>,
+    pub max_downloads: Option<u32>,
+    pub password_protected: bool,
+}
+
+pub struct NewLink<'a> {
+    pub base_url: &'a str,
+    pub file_id: &'a str,
+    pub file_name: &'a str,
+    pub dek: &'a str,
+    pub nonce: &'a str,
+    pub expires_in_secs: Option<u64>,
+    pub max_downloads: Option<u32>,
+    pub password: Option<&'a str>, // Recipients must also enter this
+}
+
+impl LinkPayload {
+    /// The file's DEK in base64, unwrapping it with the password if the
+    /// link is password-protected
+    pub fn dek(&self, password: Option<&str>) -> Result<Zeroizing<String>> {
+        match (&self.dek, &self.password_wrapped_dek) {
+            (Some(dek), _) => Ok(Zeroizing::new(dek.clone())),
+            (None, Some(wrapped)) => {
+                let password = password.context("This share link requires a password")?;
+                let dek = Zeroizing::new(decrypt_with_password(wrapped, password)?);
+                Ok(Zeroizing::new(base64::encode(&*dek)))
+            }
+            (None, None) => Err(anyhow::anyhow!("Link payload has no key")),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Make a random link key, wrap the DEK and link metadata with it, and
+/// put the key in the URL fragment (browsers never send fragments)
+pub fn create_link(link: NewLink) -> Result<ShareLink> {
+    let mut link_key = Zeroizing::new([0u8; 32]);
+    rand::rngs::OsRng.fill_bytes(link_key.as_mut());
+    let link_id = uuid::Uuid::new_v4().to_string();
+
+    let (dek, password_wrapped_dek) = match link.password {
+        Some(password) => {
+            let dek = Zeroizing::new(base64::decode(link.dek).context("Failed to decode DEK")?);
+            (None, Some(encrypt_with_password(&dek, password)?))
+        }
+        None => (Some(link.dek.to_string()), None),
+    };
+
+    let payload = LinkPayload {
+        link_id: link_id.clone(),
+        file_id: link.file_id.to_string(),
+        file_name: link.file_name.to_string(),
+        dek,
+        password_wrapped_dek,
+        nonce: link.nonce.to_string(),
+        expires_at: link.expires_in_secs.map(|secs| now() + secs),
+        max_downloads: link.max_downloads,
+    };
+    let payload_bytes = Zeroizing::new(serde_json::to_vec(&payload)?);
+    let (wrapped_payload, payload_nonce) = encrypt_with_key(&payload_bytes, &link_key)?;
+
+    let url = format!(
+        "{}/s/{}#{}={}",
+        link.base_url.trim_end_matches('/'),
+        link_id,
+        LINK_KEY_PARAM,
+        URL_SAFE_NO_PAD.encode(link_key.as_ref())
+    );
+
+    Ok(ShareLink {
+        url,
+        link_id,
+        wrapped_payload,
+        payload_nonce,
+        expires_at: payload.expires_at,
+        max_downloads: payload.max_downloads,
+        password_protected: payload.password_wrapped_dek.is_some(),
+    })
+}
+
+/// Split a share link into its link ID and link key
+pub fn parse_link(url: &str) -> Result<(String, Zeroizing<[u8; 32]>)> {
+    let url = reqwest::Url::parse(url).context("Invalid share link")?;
+    let link_id = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|segment| !segment.is_empty())
+        .context("Share link has no link ID")?
+        .to_string();
+
+    let key_b64 = url
+        .fragment()
+        .and_then(|fragment| {
+            fragment
+                .split('&')
+                .find_map(|param| param.strip_prefix(LINK_KEY_PARAM)?.strip_prefix('='))
+        })
+        .context("Share link has no key")?;
+
+    let key_vec = Zeroizing::new(URL_SAFE_NO_PAD.decode(key_b64).context("Failed to decode link key")?);
+    if key_vec.len() != 32 {
+        return Err(anyhow::anyhow!("Invalid link key size"));
+    }
+    let mut key = Zeroizing::new([0u8; 32]);
+    key.copy_from_slice(&key_vec);
+
+    Ok((link_id, key))
+}
+
+/// Decrypt a link's payload and check it still allows a download
+/// `download_count` is how many downloads the server says already happened;
+/// the limit is advisory, since a server that under-reports it (or a
+/// recipient who keeps the payload) is not stopped by this check
+pub fn open_link(
+    url: &str,
+    wrapped_payload: &str,
+    payload_nonce: &str,
+    download_count: u32,
+) -> Result<LinkPayload> {
+    let (link_id, link_key) = parse_link(url)?;
+    let payload_bytes = Zeroizing::new(
+        decrypt_with_key(wrapped_payload, payload_nonce, &link_key)
+            .context("Link key does not match this link")?,
+    );
+    let payload: LinkPayload = serde_json::from_slice(&payload_bytes).context("Failed to parse link payload")?;
+
+    // The server could hand out another link's payload; the ID inside is authenticated
+    if payload.link_id != link_id {
+        return Err(anyhow::anyhow!("Link payload belongs to a different link"));
+    }
+    if let Some(expires_at) = payload.expires_at {
+        if now() >= expires_at {
+            return Err(anyhow::anyhow!("This share link has expired"));
+        }
+    }
+    if let Some(max_downloads) = payload.max_downloads {
+        if download_count >= max_downloads {
+            return Err(anyhow::anyhow!("This share link has reached its download limit"));
+        }
+    }
+
+    Ok(payload)
+}
+
+/// Create a new file for a download in `dir`, never replacing an existing one
+/// Tries `name`, then `name (1).ext`, `name (2).ext`, ...
+pub fn create_download_file(dir: &Path, file_name: &str) -> Result<(PathBuf, File)> {
+    let name = Path::new(file_name);
+    let stem = name.file_stem().and_then(|stem| stem.to_str()).unwrap_or("download");
+    let extension = name.extension().and_then(|ext| ext.to_str());
+
+    for attempt in 0..MAX_NAME_ATTEMPTS {
+        let candidate = match (attempt, extension) {
+            (0, _) => file_name.to_string(),
+            (n, Some(ext)) => format!("{} ({}).{}", stem, n, ext),
+            (n, None) => format!("{} ({})", stem, n),
+        };
+        let path = dir.join(candidate);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to create {}", path.display())),
+        }
+    }
+
+    Err(anyhow::anyhow!("Too many files named {} in the output directory", file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_download_file_never_replaces_existing() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("report.pdf"), b"keep me").unwrap();
+        std::fs::write(dir.path().join("report (1).pdf"), b"keep me too").unwrap();
+
+        let (path, _) = create_download_file(dir.path(), "report.pdf").unwrap();
+        assert_eq!(path, dir.path().join("report (2).pdf"));
+        assert_eq!(std::fs::read(dir.path().join("report.pdf")).unwrap(), b"keep me");
+
+        let (path, _) = create_download_file(dir.path(), "notes").unwrap();
+        assert_eq!(path, dir.path().join("notes"));
+        let (path, _) = create_download_file(dir.path(), "notes").unwrap();
+        assert_eq!(path, dir.path().join("notes (1)"));
+    }
+}
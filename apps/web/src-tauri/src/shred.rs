@@ -0,0 +1,171 @@
+//! Best-effort secure deletion of local plaintext
+//!
+//! Overwriting a file in place only destroys its old contents when the
+//! filesystem writes to the same blocks. Copy-on-write and log-structured
+//! filesystems, and SSDs that remap writes, can keep old copies around, so
+//! every deletion reports whether the overwrite can be relied on.
+
+use anyhow::{Context, Result};
+use rand::RngCore;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::{Metadata, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+const OVERWRITE_BLOCK_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Serialize)]
+pub struct DeletionReport {
+    pub path: String,
+    pub overwrite_reliable: bool, // False when the storage may keep old copies
+    pub caveat: Option<String>,   // Why the overwrite can't be relied on
+}
+
+/// The original of a verified upload, kept until the server has recorded
+/// the upload so a failed save never loses the only copy
+pub struct PendingDeletion {
+    path: PathBuf,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl PendingDeletion {
+    /// `before` is the file's metadata from before it was uploaded
+    pub fn new(path: &Path, before: &Metadata) -> Self {
+        PendingDeletion {
+            path: path.to_path_buf(),
+            len: before.len(),
+            modified: before.modified().ok(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the file is still the one that was uploaded; deleting a file
+    /// edited since would lose the edits
+    pub fn unchanged(&self) -> bool {
+        std::fs::metadata(&self.path)
+            .is_ok_and(|now| now.len() == self.len && now.modified().ok() == self.modified)
+    }
+}
+
+/// Originals waiting to be deleted, keyed by an opaque ID
+#[derive(Default)]
+pub struct PendingDeletions {
+    entries: Mutex<HashMap<String, PendingDeletion>>,
+}
+
+impl PendingDeletions {
+    pub fn insert(&self, pending: PendingDeletion) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        self.entries.lock().unwrap().insert(id.clone(), pending);
+        id
+    }
+
+    pub fn take(&self, id: &str) -> Option<PendingDeletion> {
+        self.entries.lock().unwrap().remove(id)
+    }
+}
+
+/// Overwrite a file with random data, flush it to disk and remove it
+pub fn secure_delete(path: &Path) -> Result<DeletionReport> {
+    let metadata = std::fs::symlink_metadata(path)
+        .with_context(|| format!("Failed to inspect {}", path.display()))?;
+    if !metadata.is_file() {
+        return Err(anyhow::anyhow!("{} is not a regular file", path.display()));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // Other links would keep the (overwritten) data reachable under another name
+        if metadata.nlink() > 1 {
+            return Err(anyhow::anyhow!("{} has other hard links", path.display()));
+        }
+    }
+
+    // Check before writing; the answer depends on where the file lives
+    let caveat = overwrite_caveat(path);
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {} for overwriting", path.display()))?;
+    let mut block = vec![0u8; OVERWRITE_BLOCK_SIZE];
+    let mut remaining = metadata.len();
+    while remaining > 0 {
+        let len = remaining.min(OVERWRITE_BLOCK_SIZE as u64) as usize;
+        rand::rngs::OsRng.fill_bytes(&mut block[..len]);
+        file.write_all(&block[..len]).context("Failed to overwrite file")?;
+        remaining -= len as u64;
+    }
+    file.sync_all().context("Failed to flush overwritten file")?;
+    file.set_len(0).context("Failed to truncate file")?;
+    file.sync_all().context("Failed to flush truncated file")?;
+    drop(file);
+
+    // Don't leave the original name behind in the directory either
+    let renamed = path.with_file_name(uuid::Uuid::new_v4().to_string());
+    let target = match std::fs::rename(path, &renamed) {
+        Ok(()) => renamed.as_path(),
+        Err(e) => {
+            log::warn!("Failed to rename {} before deleting: {}", path.display(), e);
+            path
+        }
+    };
+    std::fs::remove_file(target).with_context(|| format!("Failed to delete {}", path.display()))?;
+
+    Ok(DeletionReport {
+        path: path.display().to_string(),
+        overwrite_reliable: caveat.is_none(),
+        caveat,
+    })
+}
+
+/// Why overwriting `path` in place might not destroy its old contents
+#[cfg(target_os = "linux")]
+fn overwrite_caveat(path: &Path) -> Option<String> {
+    use std::os::linux::fs::MetadataExt;
+    use std::os::unix::ffi::OsStrExt;
+
+    const COPY_ON_WRITE: &[(u32, &str)] = &[
+        (0x9123_683E, "Btrfs"),
+        (0x2FC1_2FC1, "ZFS"),
+        (0xCA45_1A4E, "bcachefs"),
+        (0xF2F5_2010, "F2FS"),
+        (0x794C_7630, "overlayfs"),
+        (0x6969, "NFS"),
+    ];
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Some("Could not identify the filesystem".to_string());
+    }
+    if let Some((_, name)) = COPY_ON_WRITE.iter().find(|(magic, _)| *magic == stat.f_type as u32) {
+        return Some(format!("{} may keep earlier copies of the file's blocks", name));
+    }
+
+    // Only a spinning disk overwrites in place; SSDs remap writes
+    let dev = std::fs::metadata(path).ok()?.st_dev();
+    let major = ((dev >> 32) & 0xffff_f000) | ((dev >> 8) & 0xfff);
+    let minor = ((dev >> 12) & 0xffff_ff00) | (dev & 0xff);
+    let device = format!("/sys/dev/block/{}:{}", major, minor);
+    let rotational = std::fs::read_to_string(format!("{}/queue/rotational", device))
+        .or_else(|_| std::fs::read_to_string(format!("{}/../queue/rotational", device)));
+
+    match rotational.as_deref().map(str::trim) {
+        Ok("1") => None,
+        Ok(_) => Some("The file is on an SSD, which may keep old copies of overwritten data".to_string()),
+        Err(_) => Some("Could not tell whether the file is on an SSD".to_string()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn overwrite_caveat(_path: &Path) -> Option<String> {
+    Some("Secure overwrite can't be verified on this platform (e.g. APFS and SSDs keep old copies)".to_string())
+}
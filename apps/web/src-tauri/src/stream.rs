@@ -0,0 +1,377 @@
+//! Decrypted streaming for the `kryptvault://` protocol
+//!
+//! Files are a single XChaCha20-Poly1305 message, so any byte range can be
+//! decrypted by seeking the keystream. The tag is checked once over the
+//! whole cached ciphertext when a stream is opened; after that, ranges are
+//! decrypted on the fly and plaintext never reaches disk.
+//!
+//! With one tag per file there is nothing to check a range against on its
+//! own, so playback can only start once the whole ciphertext is downloaded
+//! and verified. That costs startup time on large files, but no byte is
+//! ever served before the file is known to be intact.
+
+use crate::profile::Profile;
+use anyhow::{Context, Result};
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use chacha20::XChaCha20;
+use poly1305::universal_hash::{KeyInit, UniversalHash};
+use poly1305::Poly1305;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tauri::http::{header, Request, Response, StatusCode};
+use zeroize::Zeroizing;
+
+/// URI scheme registered with the webview
+pub const SCHEME: &str = "kryptvault";
+
+// Most bytes served for a single range request
+const MAX_RANGE_SIZE: u64 = 4 * 1024 * 1024;
+// ChaCha20 block 0 makes the Poly1305 key; the payload starts at block 1
+const PAYLOAD_OFFSET: u64 = 64;
+const NONCE_SIZE: usize = 24;
+const TAG_SIZE: u64 = 16;
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A verified ciphertext that can be decrypted at any offset
+pub struct StreamSource {
+    path: PathBuf,
+    dek: Zeroizing<[u8; 32]>,
+    nonce: [u8; NONCE_SIZE],
+    pub size: u64, // Plaintext size
+    pub mime_type: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamInfo {
+    pub handle: String,
+    pub url: String,
+    pub size: u64,
+    pub mime_type: String,
+}
+
+impl StreamSource {
+    /// Check the whole ciphertext's tag, then allow range reads from it
+    pub fn open(path: &Path, dek: Zeroizing<[u8; 32]>, nonce_b64: &str, mime_type: &str) -> Result<Self> {
+        let nonce = decode_nonce(nonce_b64)?;
+        let size = verify_ciphertext(path, &dek, &nonce)?;
+        Ok(StreamSource {
+            path: path.to_path_buf(),
+            dek,
+            nonce,
+            size,
+            mime_type: mime_type.to_string(),
+        })
+    }
+
+    /// Decrypt plaintext bytes `start..end`
+    pub fn read_range(&self, start: u64, end: u64) -> Result<Vec<u8>> {
+        let end = end.min(self.size);
+        if start >= end {
+            return Ok(Vec::new());
+        }
+
+        let mut data = vec![0u8; (end - start) as usize];
+        let mut file = File::open(&self.path).context("Failed to open cached ciphertext")?;
+        file.seek(SeekFrom::Start(start)).context("Failed to seek cached ciphertext")?;
+        file.read_exact(&mut data).context("Failed to read cached ciphertext")?;
+
+        let mut cipher = XChaCha20::new(self.dek.as_ref().into(), &self.nonce.into());
+        cipher.seek(PAYLOAD_OFFSET + start);
+        cipher.apply_keystream(&mut data);
+        Ok(data)
+    }
+}
+
+fn decode_nonce(nonce_b64: &str) -> Result<[u8; NONCE_SIZE]> {
+    let nonce_bytes = base64::decode(nonce_b64).context("Failed to decode nonce")?;
+    nonce_bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Nonce must be {} bytes", NONCE_SIZE))
+}
+
+/// Check an encrypted file would decrypt, without writing any plaintext
+/// Returns the plaintext size
+pub fn verify_file(path: &Path, dek: &[u8; 32], nonce_b64: &str) -> Result<u64> {
+    verify_ciphertext(path, dek, &decode_nonce(nonce_b64)?)
+}
+
+/// Check an XChaCha20-Poly1305 tag over a file without loading it into
+/// memory and return the plaintext size
+fn verify_ciphertext(path: &Path, dek: &[u8; 32], nonce: &[u8; NONCE_SIZE]) -> Result<u64> {
+    let mut file = File::open(path).context("Failed to open cached ciphertext")?;
+    let total = file.metadata().context("Failed to read cached ciphertext")?.len();
+    if total < TAG_SIZE {
+        return Err(anyhow::anyhow!("Ciphertext is truncated"));
+    }
+    let ciphertext_len = total - TAG_SIZE;
+
+    let mut cipher = XChaCha20::new(dek.into(), nonce.into());
+    let mut mac_key = Zeroizing::new([0u8; 32]);
+    cipher.apply_keystream(mac_key.as_mut());
+    let mut mac = Poly1305::new(mac_key.as_ref().into());
+
+    // Buffer is a multiple of 16, so only the last read needs padding
+    let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+    let mut remaining = ciphertext_len;
+    while remaining > 0 {
+        let len = remaining.min(READ_BUFFER_SIZE as u64) as usize;
+        file.read_exact(&mut buffer[..len]).context("Failed to read cached ciphertext")?;
+        mac.update_padded(&buffer[..len]);
+        remaining -= len as u64;
+    }
+
+    // No associated data, then the ciphertext length
+    let mut lengths = [0u8; 16];
+    lengths[8..].copy_from_slice(&ciphertext_len.to_le_bytes());
+    mac.update_padded(&lengths);
+
+    let mut tag = [0u8; TAG_SIZE as usize];
+    file.read_exact(&mut tag).context("Failed to read ciphertext tag")?;
+    mac.verify(&tag.into())
+        .map_err(|_| anyhow::anyhow!("Ciphertext failed authentication"))?;
+
+    Ok(ciphertext_len)
+}
+
+/// Open streams, keyed by an opaque handle; cleared when the vault locks
+#[derive(Default)]
+pub struct StreamStore {
+    sources: Mutex<HashMap<String, Arc<StreamSource>>>,
+}
+
+impl StreamStore {
+    pub fn insert(&self, source: StreamSource) -> StreamInfo {
+        let handle = uuid::Uuid::new_v4().to_string();
+        let info = StreamInfo {
+            url: stream_url(&handle),
+            handle: handle.clone(),
+            size: source.size,
+            mime_type: source.mime_type.clone(),
+        };
+        self.sources.lock().unwrap().insert(handle, Arc::new(source));
+        info
+    }
+
+    pub fn get(&self, handle: &str) -> Option<Arc<StreamSource>> {
+        self.sources.lock().unwrap().get(handle).cloned()
+    }
+
+    pub fn remove(&self, handle: &str) -> bool {
+        self.sources.lock().unwrap().remove(handle).is_some()
+    }
+
+    pub fn clear(&self) {
+        self.sources.lock().unwrap().clear();
+    }
+}
+
+/// URL the webview uses for a handle; Windows and Android serve custom
+/// schemes over http
+fn stream_url(handle: &str) -> String {
+    if cfg!(any(windows, target_os = "android")) {
+        format!("http://{}.localhost/{}", SCHEME, handle)
+    } else {
+        format!("{}://localhost/{}", SCHEME, handle)
+    }
+}
+
+/// Status and plaintext range to answer a request with
+/// Without a `Range` header a file larger than `MAX_RANGE_SIZE` gets a 206
+/// for its first window, so no request decrypts a whole large file at once
+/// Returns `None` when the range can't be satisfied
+fn select_range(range: Option<&str>, size: u64) -> Option<(StatusCode, u64, u64)> {
+    match range {
+        Some(value) => parse_range(value, size).map(|(start, end)| (StatusCode::PARTIAL_CONTENT, start, end)),
+        None if size <= MAX_RANGE_SIZE => Some((StatusCode::OK, 0, size)),
+        None => Some((StatusCode::PARTIAL_CONTENT, 0, MAX_RANGE_SIZE)),
+    }
+}
+
+/// Parse a single `Range: bytes=...` header into a half-open range of at
+/// most `MAX_RANGE_SIZE` bytes; clients ask again for the rest
+/// Returns `None` when the range can't be satisfied
+fn parse_range(value: &str, size: u64) -> Option<(u64, u64)> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    // Only the first range of a multi-range request is served
+    let spec = spec.split(',').next()?.trim();
+    let (start, end) = spec.split_once('-')?;
+
+    let (start, end) = if start.is_empty() {
+        // Suffix range: the last N bytes
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 {
+            return None;
+        }
+        (size.saturating_sub(suffix), size)
+    } else {
+        let start: u64 = start.parse().ok()?;
+        let end = if end.is_empty() {
+            size
+        } else {
+            end.parse::<u64>().ok()?.saturating_add(1).min(size)
+        };
+        (start, end)
+    };
+
+    let end = end.min(start.saturating_add(MAX_RANGE_SIZE));
+    (start < end).then_some((start, end))
+}
+
+fn status_response(status: StatusCode) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Vec::new())
+        .unwrap()
+}
+
+/// Answer a `kryptvault://localhost/<handle>` request from the profile's
+/// open streams, honouring `Range` so media elements can seek
+pub fn respond(profile: &Profile, request: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+    if profile.vault.ensure_unlocked().is_err() {
+        return status_response(StatusCode::FORBIDDEN);
+    }
+    let handle = request.uri().path().trim_matches('/');
+    let Some(source) = profile.streams.get(handle) else {
+        return status_response(StatusCode::NOT_FOUND);
+    };
+
+    let range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok());
+
+    let Some((status, start, end)) = select_range(range, source.size) else {
+        return Response::builder()
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", source.size))
+            .body(Vec::new())
+            .unwrap();
+    };
+
+    let data = match source.read_range(start, end) {
+        Ok(data) => data,
+        Err(e) => {
+            log::warn!("Failed to read stream {}: {:#}", handle, e);
+            return status_response(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let mut response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, &source.mime_type)
+        .header(header::CONTENT_LENGTH, data.len())
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CACHE_CONTROL, "no-store");
+    if status == StatusCode::PARTIAL_CONTENT {
+        response = response.header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end - 1, source.size),
+        );
+    }
+    response.body(data).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chacha20poly1305::aead::{Aead, KeyInit};
+    use chacha20poly1305::XChaCha20Poly1305;
+    use tempfile::TempDir;
+
+    const DEK: [u8; 32] = [7u8; 32];
+    const NONCE: [u8; NONCE_SIZE] = [9u8; NONCE_SIZE];
+
+    fn plaintext(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn write_ciphertext(dir: &TempDir, plaintext: &[u8]) -> PathBuf {
+        let cipher = XChaCha20Poly1305::new(&DEK.into());
+        let ciphertext = cipher.encrypt(&NONCE.into(), plaintext).unwrap();
+        let path = dir.path().join("file.enc");
+        std::fs::write(&path, ciphertext).unwrap();
+        path
+    }
+
+    fn source(path: &Path) -> StreamSource {
+        StreamSource::open(path, Zeroizing::new(DEK), &base64::encode(NONCE), "video/mp4").unwrap()
+    }
+
+    #[test]
+    fn test_verify_matches_aead_encryption() {
+        let dir = TempDir::new().unwrap();
+        // Longer than one read buffer and not a multiple of 16
+        let data = plaintext(READ_BUFFER_SIZE * 2 + 37);
+        let path = write_ciphertext(&dir, &data);
+
+        assert_eq!(verify_ciphertext(&path, &DEK, &NONCE).unwrap(), data.len() as u64);
+        assert!(verify_ciphertext(&path, &[8u8; 32], &NONCE).is_err());
+    }
+
+    #[test]
+    fn test_tampered_byte_fails_verification() {
+        let dir = TempDir::new().unwrap();
+        let path = write_ciphertext(&dir, &plaintext(1000));
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[500] ^= 1;
+        std::fs::write(&path, bytes).unwrap();
+
+        assert!(verify_ciphertext(&path, &DEK, &NONCE).is_err());
+    }
+
+    #[test]
+    fn test_truncated_ciphertext_fails_verification() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("file.enc");
+        std::fs::write(&path, [0u8; 10]).unwrap();
+
+        assert!(verify_ciphertext(&path, &DEK, &NONCE).is_err());
+    }
+
+    #[test]
+    fn test_read_range_across_block_boundaries() {
+        let dir = TempDir::new().unwrap();
+        let data = plaintext(1000);
+        let source = source(&write_ciphertext(&dir, &data));
+
+        for (start, end) in [(0, 1000), (0, 64), (63, 65), (60, 200), (128, 129), (999, 1000), (500, 2000)] {
+            let expected = &data[start..end.min(data.len())];
+            assert_eq!(source.read_range(start as u64, end as u64).unwrap(), expected, "{}..{}", start, end);
+        }
+        assert!(source.read_range(1000, 1010).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_range_clamps_to_max_size() {
+        let size = 3 * MAX_RANGE_SIZE;
+        assert_eq!(parse_range("bytes=0-99", size), Some((0, 100)));
+        assert_eq!(parse_range("bytes=10-", size), Some((10, 10 + MAX_RANGE_SIZE)));
+        assert_eq!(parse_range(&format!("bytes=0-{}", size - 1), size), Some((0, MAX_RANGE_SIZE)));
+        assert_eq!(parse_range("bytes=-100", size), Some((size - 100, size)));
+        assert_eq!(
+            parse_range(&format!("bytes=-{}", size), size),
+            Some((0, MAX_RANGE_SIZE))
+        );
+        assert_eq!(parse_range("bytes=50-", 100), Some((50, 100)));
+        assert_eq!(parse_range("bytes=100-", 100), None);
+        assert_eq!(parse_range("bytes=-0", 100), None);
+        assert_eq!(parse_range("items=0-1", 100), None);
+    }
+
+    #[test]
+    fn test_request_without_range_is_capped() {
+        assert_eq!(select_range(None, 100), Some((StatusCode::OK, 0, 100)));
+        assert_eq!(select_range(None, MAX_RANGE_SIZE), Some((StatusCode::OK, 0, MAX_RANGE_SIZE)));
+        assert_eq!(
+            select_range(None, 3 * MAX_RANGE_SIZE),
+            Some((StatusCode::PARTIAL_CONTENT, 0, MAX_RANGE_SIZE))
+        );
+        assert_eq!(select_range(Some("bytes=10-19"), 100), Some((StatusCode::PARTIAL_CONTENT, 10, 20)));
+        assert_eq!(select_range(Some("bytes=100-"), 100), None);
+    }
+}
@@ -0,0 +1,318 @@
+//! Temporary files for ciphertext in flight
+//!
+//! Each OS user gets a temp root only they can enter, with one directory per
+//! profile inside it. Files are owned by `TempFile` guards that delete them
+//! on every exit path, and anything a crash left behind is swept when the
+//! profile opens. The sweep only runs while holding the directory's lock
+//! file, so a second instance never deletes files the first is using. On Linux, small files can be staged in anonymous memory
+//! instead of on disk.
+
+use anyhow::{Context, Result};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Held by the instance that owns a profile's temp directory
+const LOCK_FILE: &str = ".lock";
+// Free space to leave on the temp volume after a staged file is written
+const DISK_HEADROOM: u64 = 64 * 1024 * 1024;
+// Largest file staged in memory; anything bigger goes to disk
+#[cfg(target_os = "linux")]
+const RAM_STAGING_LIMIT: u64 = 256 * 1024 * 1024;
+
+/// Not enough free space on the temp volume for an operation
+#[derive(Debug)]
+pub struct InsufficientSpace {
+    pub needed: u64,
+    pub available: u64,
+}
+
+impl fmt::Display for InsufficientSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Need {} bytes of temporary space but only {} are free", self.needed, self.available)
+    }
+}
+
+impl std::error::Error for InsufficientSpace {}
+
+/// Temp root for the current OS user
+/// Windows' temp directory is already per-user; elsewhere it is shared
+pub fn user_temp_root() -> PathBuf {
+    #[cfg(unix)]
+    {
+        std::env::temp_dir().join(format!("krypt-vault-{}", unsafe { libc::geteuid() }))
+    }
+    #[cfg(not(unix))]
+    {
+        std::env::temp_dir().join("krypt-vault")
+    }
+}
+
+/// Create a directory with mode 0700, refusing one that is a symlink or
+/// belongs to another user (someone else may have created it first)
+fn create_private_dir(dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+        match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to create {}", dir.display())),
+        }
+
+        let metadata = std::fs::symlink_metadata(dir)
+            .with_context(|| format!("Failed to inspect {}", dir.display()))?;
+        if !metadata.is_dir() {
+            return Err(anyhow::anyhow!("{} is not a directory", dir.display()));
+        }
+        if metadata.uid() != unsafe { libc::geteuid() } {
+            return Err(anyhow::anyhow!("{} belongs to another user", dir.display()));
+        }
+        if metadata.mode() & 0o777 != 0o700 {
+            std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+                .with_context(|| format!("Failed to restrict {}", dir.display()))?;
+        }
+    }
+    #[cfg(not(unix))]
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    Ok(())
+}
+
+/// Take an exclusive lock on `path` without waiting
+/// Returns None if another process holds it; the lock lasts as long as the file stays open
+fn try_lock_file(path: &Path) -> Result<Option<std::fs::File>> {
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsRawFd;
+
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::WouldBlock {
+                return Ok(None);
+            }
+            return Err(err).with_context(|| format!("Failed to lock {}", path.display()));
+        }
+        Ok(Some(file))
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        // Without sharing, a second open fails until this handle closes
+        const ERROR_SHARING_VIOLATION: i32 = 32;
+        match std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .share_mode(0)
+            .open(path)
+        {
+            Ok(file) => Ok(Some(file)),
+            Err(e) if e.raw_os_error() == Some(ERROR_SHARING_VIOLATION) => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to lock {}", path.display())),
+        }
+    }
+}
+
+/// A profile's temp directory
+pub struct TempManager {
+    dir: PathBuf,
+    ram_staging: AtomicBool,
+    // Held for as long as the profile is open; None if another instance has it
+    lock: Option<std::fs::File>,
+}
+
+impl TempManager {
+    /// Create (or take over) the profile's temp directory under `root`
+    /// No transfer survives a restart, so anything already there is removed,
+    /// unless another running instance holds the directory's lock
+    pub fn open(root: &Path, profile_id: &str) -> Result<Self> {
+        create_private_dir(root)?;
+        let dir = root.join(profile_id);
+        create_private_dir(&dir)?;
+
+        let lock = try_lock_file(&dir.join(LOCK_FILE))?;
+        if lock.is_none() {
+            log::warn!("Temp directory {} is in use by another instance; not sweeping it", dir.display());
+        }
+
+        let manager = TempManager {
+            dir,
+            ram_staging: AtomicBool::new(false),
+            lock,
+        };
+        manager.wipe();
+        Ok(manager)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Stage small files in memory rather than on disk (Linux only)
+    /// Returns whether memory staging is now in effect
+    pub fn set_ram_staging(&self, enabled: bool) -> bool {
+        let enabled = enabled && cfg!(target_os = "linux");
+        self.ram_staging.store(enabled, Ordering::Relaxed);
+        enabled
+    }
+
+    /// A new temp file, deleted when the guard drops
+    /// With a `size_hint`, checks there is room for it before anything is written
+    pub fn stage(&self, extension: &str, size_hint: Option<u64>) -> Result<TempFile> {
+        #[cfg(target_os = "linux")]
+        {
+            let fits = size_hint.is_some_and(|size| size <= RAM_STAGING_LIMIT);
+            if self.ram_staging.load(Ordering::Relaxed) && fits {
+                match TempFile::in_memory() {
+                    Ok(file) => return Ok(file),
+                    Err(e) => log::warn!("Falling back to disk staging: {:#}", e),
+                }
+            }
+        }
+
+        if let Some(size) = size_hint {
+            ensure_space(&self.dir, size)?;
+        }
+
+        let path = self.dir.join(format!("{}.{}", uuid::Uuid::new_v4(), extension));
+        let path = path
+            .to_str()
+            .context("Temp directory path is not valid UTF-8")?
+            .to_string();
+        Ok(TempFile {
+            path,
+            #[cfg(target_os = "linux")]
+            memory: None,
+        })
+    }
+
+    /// Best-effort removal of every file in the temp directory
+    /// Does nothing unless this instance holds the directory's lock
+    pub fn wipe(&self) {
+        if self.lock.is_none() {
+            return;
+        }
+
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::warn!("Failed to read temp directory: {}", e);
+                return;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_file() && entry.file_name() != LOCK_FILE {
+                if let Err(e) = std::fs::remove_file(&path) {
+                    log::warn!("Failed to remove temp file {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+/// Fail with `InsufficientSpace` unless `needed` bytes (plus headroom) are free
+#[cfg(unix)]
+pub fn ensure_space(dir: &Path, needed: u64) -> Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes()).context("Invalid temp path")?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to check free disk space");
+    }
+
+    // Field widths differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    let available = (stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64);
+    let needed = needed.saturating_add(DISK_HEADROOM);
+    if available < needed {
+        return Err(InsufficientSpace { needed, available }.into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn ensure_space(_dir: &Path, _needed: u64) -> Result<()> {
+    Ok(())
+}
+
+/// A temp file that is removed when dropped, however the operation ends
+pub struct TempFile {
+    path: String,
+    // Memory-backed file, reachable through /proc/self/fd while it is open
+    #[cfg(target_os = "linux")]
+    memory: Option<std::fs::File>,
+}
+
+impl TempFile {
+    #[cfg(target_os = "linux")]
+    fn in_memory() -> Result<Self> {
+        use std::os::fd::FromRawFd;
+
+        let fd = unsafe { libc::memfd_create(c"kryptvault".as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to create memory file");
+        }
+        let file = unsafe { std::fs::File::from_raw_fd(fd) };
+
+        Ok(TempFile {
+            path: format!("/proc/self/fd/{}", fd),
+            memory: Some(file),
+        })
+    }
+
+    /// Path to pass to anything that opens the file by name
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // A memory file is freed when its descriptor closes
+        #[cfg(target_os = "linux")]
+        if self.memory.is_some() {
+            return;
+        }
+
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::warn!("Failed to remove temp file {}: {}", self.path, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_instance_does_not_sweep() {
+        let root = tempfile::tempdir().unwrap();
+        let first = TempManager::open(root.path(), "profile").unwrap();
+        let staged = first.stage("enc", None).unwrap();
+        std::fs::write(staged.path(), b"in flight").unwrap();
+
+        let second = TempManager::open(root.path(), "profile").unwrap();
+        second.wipe();
+        assert!(Path::new(staged.path()).exists());
+
+        drop(second);
+        drop(first);
+        let third = TempManager::open(root.path(), "profile").unwrap();
+        assert!(!Path::new(staged.path()).exists());
+        drop(third);
+    }
+}
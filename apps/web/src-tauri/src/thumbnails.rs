@@ -0,0 +1,136 @@
+//! Thumbnails generated at upload time
+//!
+//! The server never sees file contents, so thumbnails are made on the
+//! client, encrypted under a key derived from the file's DEK and stored as
+//! a companion object next to the file.
+
+use anyhow::{Context, Result};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use hkdf::Hkdf;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageReader};
+use rand::RngCore;
+use sha2::Sha256;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+/// Longest edge of a thumbnail in pixels
+pub const THUMBNAIL_SIZE: u32 = 256;
+
+// Don't decode huge source files just for a thumbnail
+const MAX_SOURCE_SIZE: u64 = 64 * 1024 * 1024;
+const JPEG_QUALITY: u8 = 80;
+const NONCE_SIZE: usize = 24;
+
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp"];
+
+/// Make a JPEG thumbnail for images (and PDFs, when built with the
+/// `pdf-thumbnails` feature and a PDFium library is installed)
+/// Returns `None` for anything else, or anything that fails to decode
+pub fn generate_thumbnail(path: &Path) -> Option<Vec<u8>> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    let size = std::fs::metadata(path).ok()?.len();
+    if size > MAX_SOURCE_SIZE {
+        return None;
+    }
+
+    let image = if IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+        decode_image(path)
+    } else if extension == "pdf" {
+        render_pdf_first_page(path)
+    } else {
+        return None;
+    };
+
+    match image.and_then(|image| encode_thumbnail(&image)) {
+        Ok(thumbnail) => Some(thumbnail),
+        Err(e) => {
+            log::debug!("No thumbnail for {}: {:#}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn decode_image(path: &Path) -> Result<DynamicImage> {
+    ImageReader::open(path)
+        .context("Failed to open image")?
+        .with_guessed_format()
+        .context("Failed to detect image format")?
+        .decode()
+        .context("Failed to decode image")
+}
+
+#[cfg(feature = "pdf-thumbnails")]
+fn render_pdf_first_page(path: &Path) -> Result<DynamicImage> {
+    use pdfium_render::prelude::*;
+
+    let bindings = Pdfium::bind_to_system_library().context("PDFium library not found")?;
+    let pdfium = Pdfium::new(bindings);
+    let document = pdfium.load_pdf_from_file(path, None).context("Failed to open PDF")?;
+    let page = document.pages().first().context("PDF has no pages")?;
+
+    let config = PdfRenderConfig::new()
+        .set_target_width(THUMBNAIL_SIZE as i32)
+        .set_maximum_height(THUMBNAIL_SIZE as i32);
+    let image = page.render_with_config(&config).context("Failed to render PDF page")?.as_image();
+    Ok(image)
+}
+
+#[cfg(not(feature = "pdf-thumbnails"))]
+fn render_pdf_first_page(_path: &Path) -> Result<DynamicImage> {
+    Err(anyhow::anyhow!("Built without PDF thumbnail support"))
+}
+
+fn encode_thumbnail(image: &DynamicImage) -> Result<Vec<u8>> {
+    // JPEG has no alpha channel
+    let thumbnail = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).into_rgb8();
+
+    let mut encoded = Vec::new();
+    JpegEncoder::new_with_quality(&mut encoded, JPEG_QUALITY)
+        .encode_image(&thumbnail)
+        .context("Failed to encode thumbnail")?;
+    Ok(encoded)
+}
+
+/// Thumbnail key derived from the file's DEK, so anyone who can open the
+/// file can see its thumbnail and nobody else can
+fn thumbnail_key(dek: &[u8; 32]) -> Result<Zeroizing<[u8; 32]>> {
+    let hkdf = Hkdf::<Sha256>::new(None, dek);
+    let mut key = Zeroizing::new([0u8; 32]);
+    hkdf.expand(b"kryptvault thumbnail", key.as_mut())
+        .map_err(|_| anyhow::anyhow!("Failed to derive thumbnail key"))?;
+    Ok(key)
+}
+
+/// Encrypt a thumbnail as `nonce || ciphertext`
+pub fn seal_thumbnail(thumbnail: &[u8], dek: &[u8; 32]) -> Result<Vec<u8>> {
+    let key = thumbnail_key(dek)?;
+    let cipher = XChaCha20Poly1305::new(key.as_ref().into());
+    let mut nonce_bytes = [0u8; NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce_bytes);
+
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce_bytes), thumbnail)
+        .map_err(|e| anyhow::anyhow!("Thumbnail encryption failed: {}", e))?;
+
+    let mut sealed = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+    sealed.extend_from_slice(&nonce_bytes);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+pub fn open_thumbnail(sealed: &[u8], dek: &[u8; 32]) -> Result<Vec<u8>> {
+    if sealed.len() < NONCE_SIZE {
+        return Err(anyhow::anyhow!("Thumbnail is truncated"));
+    }
+    let (nonce_bytes, ciphertext) = sealed.split_at(NONCE_SIZE);
+
+    let key = thumbnail_key(dek)?;
+    let cipher = XChaCha20Poly1305::new(key.as_ref().into());
+    cipher
+        .decrypt(XNonce::from_slice(nonce_bytes), ciphertext)
+        .map_err(|_| anyhow::anyhow!("Thumbnail failed to decrypt"))
+}
@@ -0,0 +1,107 @@
+use crate::error::{CommandResult, ErrorCode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferKind {
+    Upload,
+    Download,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferInfo {
+    pub id: String,
+    pub kind: TransferKind,
+    pub name: String,
+    pub started_at: u64, // Unix timestamp in seconds
+}
+
+struct ActiveTransfer {
+    info: TransferInfo,
+    cancel: watch::Sender<bool>,
+}
+
+/// Uploads and downloads in flight for one profile
+/// Every transfer can be cancelled individually or all at once
+#[derive(Default)]
+pub struct TransferQueue {
+    active: Mutex<HashMap<String, ActiveTransfer>>,
+}
+
+/// Removes a transfer from the queue however its future ends
+struct TransferGuard<'a> {
+    queue: &'a TransferQueue,
+    id: String,
+}
+
+impl Drop for TransferGuard<'_> {
+    fn drop(&mut self) {
+        self.queue.active.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl TransferQueue {
+    pub fn list(&self) -> Vec<TransferInfo> {
+        let mut transfers: Vec<TransferInfo> = self
+            .active
+            .lock()
+            .unwrap()
+            .values()
+            .map(|transfer| transfer.info.clone())
+            .collect();
+        transfers.sort_by_key(|info| info.started_at);
+        transfers
+    }
+
+    /// Cancel one transfer; returns false if it is not in the queue
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.active.lock().unwrap().get(id) {
+            Some(transfer) => {
+                let _ = transfer.cancel.send(true);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn cancel_all(&self) {
+        for transfer in self.active.lock().unwrap().values() {
+            let _ = transfer.cancel.send(true);
+        }
+    }
+
+    /// Run `transfer` as a tracked transfer
+    /// Resolves to `Cancelled` as soon as the transfer is cancelled
+    pub async fn run<T, F>(&self, kind: TransferKind, name: &str, transfer: F) -> CommandResult<T>
+    where
+        F: Future<Output = CommandResult<T>>,
+    {
+        let id = uuid::Uuid::new_v4().to_string();
+        let (cancel, mut cancelled) = watch::channel(false);
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        self.active.lock().unwrap().insert(id.clone(), ActiveTransfer {
+            info: TransferInfo {
+                id: id.clone(),
+                kind,
+                name: name.to_string(),
+                started_at,
+            },
+            cancel,
+        });
+        let _guard = TransferGuard { queue: self, id };
+
+        tokio::select! {
+            result = transfer => result,
+            _ = cancelled.wait_for(|cancelled| *cancelled) => Err(ErrorCode::Cancelled.into()),
+        }
+    }
+}
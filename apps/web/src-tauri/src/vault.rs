@@ -0,0 +1,176 @@
+use crate::commands::{lock_profile, AppState};
+use crate::crypto::UserKeypair;
+use crate::error::{CommandResult, ErrorCode};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use tauri::{AppHandle, Manager};
+use zeroize::Zeroizing;
+
+/// Event emitted to the frontend whenever the vault locks
+pub const VAULT_LOCKED_EVENT: &str = "vault-locked";
+
+const WATCHER_INTERVAL: Duration = Duration::from_secs(5);
+// Wall-clock time running ahead of monotonic time by more than this means
+// the machine was suspended (monotonic clocks stop during sleep)
+const SLEEP_DETECTION_THRESHOLD: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoLockSettings {
+    pub idle_timeout_secs: Option<u64>, // None disables the idle timer
+    pub lock_on_sleep: bool,
+}
+
+impl Default for AutoLockSettings {
+    fn default() -> Self {
+        AutoLockSettings {
+            idle_timeout_secs: Some(15 * 60),
+            lock_on_sleep: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockReason {
+    Manual,
+    Idle,
+    Sleep,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VaultLockedPayload {
+    pub profile_id: String,
+    pub reason: LockReason,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VaultStatus {
+    pub locked: bool,
+    pub settings: AutoLockSettings,
+}
+
+struct VaultInner {
+    keypair: Option<Zeroizing<UserKeypair>>,
+    last_activity: Instant,
+    settings: AutoLockSettings,
+}
+
+/// In-memory key material, available only while the vault is unlocked
+/// Private keys are zeroized when the vault locks
+pub struct Vault {
+    inner: Mutex<VaultInner>,
+}
+
+impl Vault {
+    /// A new vault starts locked
+    pub fn new(settings: AutoLockSettings) -> Self {
+        Vault {
+            inner: Mutex::new(VaultInner {
+                keypair: None,
+                last_activity: Instant::now(),
+                settings,
+            }),
+        }
+    }
+
+    pub fn unlock(&self, keypair: UserKeypair) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.keypair = Some(Zeroizing::new(keypair));
+        inner.last_activity = Instant::now();
+    }
+
+    /// Drop the keys; returns false if the vault was already locked
+    pub fn lock(&self) -> bool {
+        // Dropping the Zeroizing wrapper wipes the private keys
+        self.inner.lock().unwrap().keypair.take().is_some()
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.lock().unwrap().keypair.is_none()
+    }
+
+    /// Record user activity, resetting the idle timer
+    pub fn touch(&self) {
+        self.inner.lock().unwrap().last_activity = Instant::now();
+    }
+
+    /// Fail with `Locked` unless the vault is unlocked; counts as activity
+    pub fn ensure_unlocked(&self) -> CommandResult<()> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.keypair.is_none() {
+            return Err(ErrorCode::Locked.into());
+        }
+        inner.last_activity = Instant::now();
+        Ok(())
+    }
+
+    /// Copy of the unlocked keypair; counts as activity
+    pub fn keypair(&self) -> CommandResult<Zeroizing<UserKeypair>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_activity = Instant::now();
+        match &inner.keypair {
+            Some(keypair) => Ok(Zeroizing::new(UserKeypair::clone(&**keypair))),
+            None => Err(ErrorCode::Locked.into()),
+        }
+    }
+
+    pub fn status(&self) -> VaultStatus {
+        let inner = self.inner.lock().unwrap();
+        VaultStatus {
+            locked: inner.keypair.is_none(),
+            settings: inner.settings.clone(),
+        }
+    }
+
+    pub fn set_settings(&self, settings: AutoLockSettings) {
+        self.inner.lock().unwrap().settings = settings;
+    }
+
+    fn idle_expired(&self) -> bool {
+        let inner = self.inner.lock().unwrap();
+        match inner.settings.idle_timeout_secs {
+            Some(timeout) => {
+                inner.keypair.is_some()
+                    && inner.last_activity.elapsed() >= Duration::from_secs(timeout)
+            }
+            None => false,
+        }
+    }
+
+    fn lock_on_sleep(&self) -> bool {
+        self.inner.lock().unwrap().settings.lock_on_sleep
+    }
+}
+
+/// Background task that locks each profile's vault after inactivity or a suspend
+/// and sweeps expired previews
+pub fn spawn_auto_lock_watcher(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut last_wall = SystemTime::now();
+        let mut last_mono = Instant::now();
+
+        loop {
+            tokio::time::sleep(WATCHER_INTERVAL).await;
+
+            let wall_elapsed = last_wall.elapsed().unwrap_or_default();
+            let mono_elapsed = last_mono.elapsed();
+            last_wall = SystemTime::now();
+            last_mono = Instant::now();
+
+            let state = app.state::<AppState>();
+            let slept = wall_elapsed.saturating_sub(mono_elapsed) > SLEEP_DETECTION_THRESHOLD;
+
+            for profile in state.profiles.all() {
+                profile.previews.purge_expired();
+                if slept && profile.vault.lock_on_sleep() && !profile.vault.is_locked() {
+                    log::info!("System resumed from sleep, locking profile {}", profile.config.id);
+                    lock_profile(&app, &profile, LockReason::Sleep);
+                } else if profile.vault.idle_expired() {
+                    log::info!("Vault idle timeout reached, locking profile {}", profile.config.id);
+                    lock_profile(&app, &profile, LockReason::Idle);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,560 @@
+use crate::crypto::{decrypt_bytes_with_dek, TAG_SIZE};
+use crate::s3::download_from_s3;
+use anyhow::{Context, Result};
+use fuser::{
+    BackgroundSession, FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData,
+    ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen, Request,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const TTL: Duration = Duration::from_secs(1); // Attribute cache lifetime for the kernel
+const ROOT_INO: u64 = 1;
+const BLOCK_SIZE: u32 = 4096;
+
+/// A folder as listed by the server, with its name already decrypted
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VaultFolderEntry {
+    pub folder_id: String,
+    pub parent_folder_id: Option<String>,
+    pub name: String,
+}
+
+/// A file as listed by the server, with everything needed to decrypt it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VaultFileEntry {
+    pub file_id: String,
+    pub folder_id: Option<String>,
+    pub name: String,
+    pub encrypted_size: u64,     // Ciphertext size as stored in S3
+    pub download_url: String,    // Presigned GET URL
+    pub dek_b64: String,         // Already unwrapped DEK in base64
+    pub nonce: String,           // Base64
+    pub modified_at: Option<u64>, // Unix timestamp in seconds
+    #[serde(default)]
+    pub ciphertext_sha256: Option<String>, // Base64; identifies the cached version when given
+}
+
+/// Where the filesystem fetches ciphertext that is not cached yet
+pub trait CiphertextSource: Send {
+    /// Write the complete ciphertext of `file` to `dest`
+    fn fetch(&self, file: &VaultFileEntry, dest: &Path) -> Result<()>;
+}
+
+/// Fetches ciphertext through the presigned URL in the listing
+pub struct S3Source;
+
+impl CiphertextSource for S3Source {
+    fn fetch(&self, file: &VaultFileEntry, dest: &Path) -> Result<()> {
+        let dest = dest.to_str().context("Invalid cache path")?;
+        tauri::async_runtime::block_on(download_from_s3(&file.download_url, dest))?;
+        Ok(())
+    }
+}
+
+enum NodeKind {
+    Dir { children: Vec<u64> },
+    File(VaultFileEntry),
+}
+
+struct Node {
+    name: String,
+    parent: u64,
+    kind: NodeKind,
+    mtime: SystemTime,
+}
+
+/// Read-only FUSE filesystem presenting the decrypted vault
+/// Ciphertext is fetched on first open and cached in `cache_dir`;
+/// plaintext only ever lives in memory while a file is open
+pub struct VaultFs {
+    nodes: HashMap<u64, Node>,
+    cache_dir: PathBuf,
+    source: Box<dyn CiphertextSource>,
+    open_files: HashMap<u64, Vec<u8>>, // fh -> plaintext
+    next_fh: u64,
+    uid: u32,
+    gid: u32,
+}
+
+impl VaultFs {
+    /// Build the inode tree from a server listing
+    /// Files whose folder is unknown are placed at the root
+    pub fn new(
+        folders: Vec<VaultFolderEntry>,
+        files: Vec<VaultFileEntry>,
+        cache_dir: PathBuf,
+    ) -> Result<Self> {
+        Self::with_source(folders, files, cache_dir, Box::new(S3Source))
+    }
+
+    /// Like `new`, but fetching ciphertext from `source`
+    pub fn with_source(
+        folders: Vec<VaultFolderEntry>,
+        files: Vec<VaultFileEntry>,
+        cache_dir: PathBuf,
+        source: Box<dyn CiphertextSource>,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(&cache_dir)
+            .context("Failed to create ciphertext cache directory")?;
+
+        let now = SystemTime::now();
+        let mut nodes = HashMap::new();
+        nodes.insert(ROOT_INO, Node {
+            name: String::new(),
+            parent: ROOT_INO,
+            kind: NodeKind::Dir { children: Vec::new() },
+            mtime: now,
+        });
+
+        // Assign inodes to folders first so files can reference them
+        let mut next_ino = ROOT_INO + 1;
+        let mut folder_inos = HashMap::new();
+        for folder in &folders {
+            folder_inos.insert(folder.folder_id.clone(), next_ino);
+            next_ino += 1;
+        }
+
+        let mut fs = VaultFs {
+            nodes,
+            cache_dir,
+            source,
+            open_files: HashMap::new(),
+            next_fh: 1,
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+        };
+        let mut taken: HashMap<u64, HashSet<String>> = HashMap::new();
+
+        for folder in folders {
+            let ino = folder_inos[&folder.folder_id];
+            let parent = folder
+                .parent_folder_id
+                .as_ref()
+                .and_then(|id| folder_inos.get(id).copied())
+                .unwrap_or(ROOT_INO);
+            let name = unique_name(&mut taken, parent, &folder.name, &folder.folder_id);
+            fs.insert(ino, parent, name, NodeKind::Dir { children: Vec::new() }, now);
+        }
+
+        for file in files {
+            let ino = next_ino;
+            next_ino += 1;
+            let parent = file
+                .folder_id
+                .as_ref()
+                .and_then(|id| folder_inos.get(id).copied())
+                .unwrap_or(ROOT_INO);
+            let name = unique_name(&mut taken, parent, &file.name, &file.file_id);
+            let mtime = file
+                .modified_at
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap_or(now);
+            fs.insert(ino, parent, name, NodeKind::File(file), mtime);
+        }
+
+        Ok(fs)
+    }
+
+    fn insert(&mut self, ino: u64, parent: u64, name: String, kind: NodeKind, mtime: SystemTime) {
+        self.nodes.insert(ino, Node { name, parent, kind, mtime });
+        if let Some(Node { kind: NodeKind::Dir { children }, .. }) = self.nodes.get_mut(&parent) {
+            children.push(ino);
+        }
+    }
+
+    fn attr(&self, ino: u64) -> Option<FileAttr> {
+        let node = self.nodes.get(&ino)?;
+        let (kind, size, perm, nlink) = match &node.kind {
+            NodeKind::Dir { .. } => (FileType::Directory, 0, 0o500, 2),
+            NodeKind::File(file) => (
+                FileType::RegularFile,
+                file.encrypted_size.saturating_sub(TAG_SIZE as u64),
+                0o400,
+                1,
+            ),
+        };
+
+        Some(FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: node.mtime,
+            mtime: node.mtime,
+            ctime: node.mtime,
+            crtime: node.mtime,
+            kind,
+            perm,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: BLOCK_SIZE,
+            flags: 0,
+        })
+    }
+
+    /// Fetch the ciphertext (from cache or the source) and decrypt it into memory
+    fn load_plaintext(&self, file: &VaultFileEntry) -> Result<Vec<u8>> {
+        let cached_path = self.cache_dir.join(format!("{}.enc", file.file_id));
+
+        // A cache entry left by an older version of the file is dropped
+        // rather than served; size, checksum and the DEK identify the version
+        if let Ok(ciphertext) = std::fs::read(&cached_path) {
+            if matches_listing(&ciphertext, file) {
+                if let Ok(plaintext) = decrypt_bytes_with_dek(&ciphertext, &file.dek_b64, &file.nonce) {
+                    return Ok(plaintext);
+                }
+            }
+            std::fs::remove_file(&cached_path).context("Failed to drop stale cache entry")?;
+        }
+
+        // Download next to the cache entry, then rename so a partial
+        // download never looks like a valid cached ciphertext
+        let partial_path = self.cache_dir.join(format!("{}.part", file.file_id));
+        self.source.fetch(file, &partial_path)?;
+
+        let plaintext = std::fs::read(&partial_path)
+            .context("Failed to read downloaded encrypted file")
+            .and_then(|ciphertext| {
+                if !matches_listing(&ciphertext, file) {
+                    anyhow::bail!("Downloaded ciphertext does not match the listing");
+                }
+                decrypt_bytes_with_dek(&ciphertext, &file.dek_b64, &file.nonce)
+            });
+        if plaintext.is_err() {
+            let _ = std::fs::remove_file(&partial_path);
+            return plaintext;
+        }
+
+        std::fs::rename(&partial_path, &cached_path)
+            .context("Failed to move downloaded file into cache")?;
+        plaintext
+    }
+
+    /// Resolve `name` inside the directory `parent`
+    fn child_by_name(&self, parent: u64, name: &OsStr) -> Result<u64, i32> {
+        let Some(Node { kind: NodeKind::Dir { children }, .. }) = self.nodes.get(&parent) else {
+            return Err(libc::ENOENT);
+        };
+
+        children
+            .iter()
+            .copied()
+            .find(|ino| self.nodes[ino].name.as_str() == name)
+            .ok_or(libc::ENOENT)
+    }
+
+    /// Decrypt a file into memory and return its new file handle
+    fn open_file(&mut self, ino: u64, flags: i32) -> Result<u64, i32> {
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            return Err(libc::EROFS);
+        }
+
+        let Some(Node { kind: NodeKind::File(file), .. }) = self.nodes.get(&ino) else {
+            return Err(libc::EISDIR);
+        };
+
+        let plaintext = self.load_plaintext(file).map_err(|e| {
+            log::error!("Failed to open vault file {}: {}", file.file_id, e);
+            libc::EIO
+        })?;
+
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        self.open_files.insert(fh, plaintext);
+        Ok(fh)
+    }
+
+    /// Slice of an open file's plaintext, clamped to its end
+    fn read_open(&self, fh: u64, offset: i64, size: u32) -> Result<&[u8], i32> {
+        let plaintext = self.open_files.get(&fh).ok_or(libc::EBADF)?;
+
+        let start = (offset.max(0) as usize).min(plaintext.len());
+        let end = start.saturating_add(size as usize).min(plaintext.len());
+        Ok(&plaintext[start..end])
+    }
+}
+
+/// Whether a ciphertext has the size and (when known) checksum the listing expects
+fn matches_listing(ciphertext: &[u8], file: &VaultFileEntry) -> bool {
+    if ciphertext.len() as u64 != file.encrypted_size {
+        return false;
+    }
+    match &file.ciphertext_sha256 {
+        Some(expected) => base64::encode(Sha256::digest(ciphertext)) == *expected,
+        None => true,
+    }
+}
+
+/// Pick a directory entry name that is valid and unique within its parent
+fn unique_name(
+    taken: &mut HashMap<u64, HashSet<String>>,
+    parent: u64,
+    name: &str,
+    id: &str,
+) -> String {
+    let mut name: String = name
+        .chars()
+        .map(|c| if c == '/' || c == '\0' { '_' } else { c })
+        .collect();
+    if name.is_empty() || name == "." || name == ".." {
+        name = id.to_string();
+    }
+
+    let names = taken.entry(parent).or_default();
+    if names.contains(&name) {
+        let short_id: String = id.chars().take(8).collect();
+        name = format!("{} ({})", name, short_id);
+    }
+    names.insert(name.clone());
+    name
+}
+
+impl Filesystem for VaultFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.child_by_name(parent, name).map(|ino| self.attr(ino)) {
+            Ok(Some(attr)) => reply.entry(&TTL, &attr, 0),
+            Ok(None) => reply.error(libc::ENOENT),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let Some(node) = self.nodes.get(&ino) else {
+            reply.error(libc::ENOENT);
+            return;
+        };
+        let NodeKind::Dir { children } = &node.kind else {
+            reply.error(libc::ENOTDIR);
+            return;
+        };
+
+        let mut entries = vec![
+            (ino, FileType::Directory, ".".to_string()),
+            (node.parent, FileType::Directory, "..".to_string()),
+        ];
+        for child in children {
+            let child_node = &self.nodes[child];
+            let kind = match child_node.kind {
+                NodeKind::Dir { .. } => FileType::Directory,
+                NodeKind::File(_) => FileType::RegularFile,
+            };
+            entries.push((*child, kind, child_node.name.clone()));
+        }
+
+        for (i, (entry_ino, kind, name)) in entries.into_iter().enumerate().skip(offset as usize) {
+            // The offset passed back to us is that of the next entry
+            if reply.add(entry_ino, (i + 1) as i64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        match self.open_file(ino, flags) {
+            Ok(fh) => reply.opened(fh, 0),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        match self.read_open(fh, offset, size) {
+            Ok(data) => reply.data(data),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.open_files.remove(&fh);
+        reply.ok();
+    }
+}
+
+/// Mount the vault read-only at `mount_point`
+/// The filesystem is unmounted when the returned session is dropped
+pub fn mount(fs: VaultFs, mount_point: &Path) -> Result<BackgroundSession> {
+    let options = [
+        MountOption::RO,
+        MountOption::NoExec,
+        MountOption::NoSuid,
+        MountOption::NoDev,
+        MountOption::FSName("kryptvault".to_string()),
+    ];
+
+    fuser::spawn_mount2(fs, mount_point, &options).context("Failed to mount vault filesystem")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{encrypt_with_key, generate_dek};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// Serves ciphertext from memory and counts how often it is asked
+    struct MemorySource {
+        blobs: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+        fetches: Arc<AtomicUsize>,
+    }
+
+    impl CiphertextSource for MemorySource {
+        fn fetch(&self, file: &VaultFileEntry, dest: &Path) -> Result<()> {
+            self.fetches.fetch_add(1, Ordering::SeqCst);
+            let blob = self.blobs.lock().unwrap().get(&file.file_id).cloned()
+                .context("No such object")?;
+            std::fs::write(dest, blob)?;
+            Ok(())
+        }
+    }
+
+    fn encrypted_entry(file_id: &str, plaintext: &[u8]) -> (VaultFileEntry, Vec<u8>) {
+        let dek = generate_dek();
+        let (ciphertext, nonce) = encrypt_with_key(plaintext, &dek).unwrap();
+        let ciphertext = base64::decode(ciphertext).unwrap();
+        let entry = VaultFileEntry {
+            file_id: file_id.to_string(),
+            folder_id: Some("folder-1".to_string()),
+            name: "notes.txt".to_string(),
+            encrypted_size: ciphertext.len() as u64,
+            download_url: String::new(),
+            dek_b64: base64::encode(dek),
+            nonce,
+            modified_at: None,
+            ciphertext_sha256: Some(base64::encode(Sha256::digest(&ciphertext))),
+        };
+        (entry, ciphertext)
+    }
+
+    fn folder() -> VaultFolderEntry {
+        VaultFolderEntry {
+            folder_id: "folder-1".to_string(),
+            parent_folder_id: None,
+            name: "Docs".to_string(),
+        }
+    }
+
+    fn mount_in_memory(
+        entry: VaultFileEntry,
+        blobs: &Arc<Mutex<HashMap<String, Vec<u8>>>>,
+        fetches: &Arc<AtomicUsize>,
+        cache_dir: &Path,
+    ) -> VaultFs {
+        let source = MemorySource { blobs: blobs.clone(), fetches: fetches.clone() };
+        VaultFs::with_source(vec![folder()], vec![entry], cache_dir.to_path_buf(), Box::new(source))
+            .unwrap()
+    }
+
+    fn read_all(fs: &mut VaultFs) -> Vec<u8> {
+        let dir = fs.child_by_name(ROOT_INO, OsStr::new("Docs")).unwrap();
+        let ino = fs.child_by_name(dir, OsStr::new("notes.txt")).unwrap();
+        let fh = fs.open_file(ino, libc::O_RDONLY).unwrap();
+        fs.read_open(fh, 0, u32::MAX).unwrap().to_vec()
+    }
+
+    #[test]
+    fn test_lookup_open_and_read() {
+        let cache = tempfile::tempdir().unwrap();
+        let (entry, ciphertext) = encrypted_entry("file-1", b"hello vault");
+        let blobs = Arc::new(Mutex::new(HashMap::from([("file-1".to_string(), ciphertext)])));
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let mut fs = mount_in_memory(entry, &blobs, &fetches, cache.path());
+
+        let dir = fs.child_by_name(ROOT_INO, OsStr::new("Docs")).unwrap();
+        let ino = fs.child_by_name(dir, OsStr::new("notes.txt")).unwrap();
+        assert_eq!(fs.child_by_name(dir, OsStr::new("missing")), Err(libc::ENOENT));
+        assert_eq!(fs.attr(ino).unwrap().size, b"hello vault".len() as u64);
+
+        assert_eq!(fs.open_file(ino, libc::O_WRONLY), Err(libc::EROFS));
+        assert_eq!(fs.open_file(dir, libc::O_RDONLY), Err(libc::EISDIR));
+
+        let fh = fs.open_file(ino, libc::O_RDONLY).unwrap();
+        assert_eq!(fs.read_open(fh, 6, 100).unwrap(), b"vault");
+        assert_eq!(fs.read_open(fh, 100, 10).unwrap(), b"");
+        assert_eq!(fs.read_open(fh + 1, 0, 10), Err(libc::EBADF));
+
+        // A second open is served from the cache
+        assert_eq!(read_all(&mut fs), b"hello vault");
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_stale_cache_entry_is_refetched() {
+        let cache = tempfile::tempdir().unwrap();
+        let (old_entry, old_ciphertext) = encrypted_entry("file-1", b"first version");
+        let blobs = Arc::new(Mutex::new(HashMap::from([("file-1".to_string(), old_ciphertext)])));
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let mut fs = mount_in_memory(old_entry, &blobs, &fetches, cache.path());
+        assert_eq!(read_all(&mut fs), b"first version");
+
+        // The file is replaced under the same ID with the same length
+        let (new_entry, new_ciphertext) = encrypted_entry("file-1", b"second versio");
+        blobs.lock().unwrap().insert("file-1".to_string(), new_ciphertext);
+
+        let mut fs = mount_in_memory(new_entry.clone(), &blobs, &fetches, cache.path());
+        assert_eq!(read_all(&mut fs), b"second versio");
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        // Without a checksum a stale entry still fails to decrypt and is replaced
+        let (mut third_entry, third_ciphertext) = encrypted_entry("file-1", b"third version");
+        third_entry.ciphertext_sha256 = None;
+        blobs.lock().unwrap().insert("file-1".to_string(), third_ciphertext);
+
+        let mut fs = mount_in_memory(third_entry, &blobs, &fetches, cache.path());
+        assert_eq!(read_all(&mut fs), b"third version");
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_mismatched_download_is_not_cached() {
+        let cache = tempfile::tempdir().unwrap();
+        let (entry, mut ciphertext) = encrypted_entry("file-1", b"hello vault");
+        ciphertext[0] ^= 1;
+        let blobs = Arc::new(Mutex::new(HashMap::from([("file-1".to_string(), ciphertext)])));
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let mut fs = mount_in_memory(entry, &blobs, &fetches, cache.path());
+
+        let dir = fs.child_by_name(ROOT_INO, OsStr::new("Docs")).unwrap();
+        let ino = fs.child_by_name(dir, OsStr::new("notes.txt")).unwrap();
+        assert_eq!(fs.open_file(ino, libc::O_RDONLY), Err(libc::EIO));
+        assert_eq!(std::fs::read_dir(cache.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::crypto::{decrypt_with_key, encrypt_with_key};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sodiumoxide::crypto::sign;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One version of a file
+/// Every upload gets its own DEK and nonce, so each version is wrapped and
+/// stored independently; `prev_hash` links it to the version before
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileVersion {
+    pub version: u64,
+    pub file_key: String,    // Storage key of this version's ciphertext
+    pub wrapped_dek: String, // Base64 sealed box containing this version's DEK
+    pub nonce: String,       // Base64
+    pub file_size: u64,
+    pub original_filename: String,
+    pub ciphertext_sha256: String, // Base64
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_file_key: Option<String>, // This version's DEK sealed to the uploader's vault key; None in older histories
+    pub created_at: u64,           // Unix timestamp in seconds
+    pub restored_from: Option<u64>, // Set when this version restores an older one
+    pub prev_hash: String,          // Base64 SHA-256 of the previous version; empty for the first
+}
+
+/// All versions of a file, oldest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionChain {
+    pub file_id: String,
+    pub versions: Vec<FileVersion>,
+}
+
+/// Chain plus the owner's signature over its exact bytes
+#[derive(Debug, Serialize, Deserialize)]
+struct SignedVersionChain {
+    chain: String,             // Base64 JSON of VersionChain
+    signer_public_key: String, // Base64 Ed25519
+    signature: String,         // Base64 Ed25519
+}
+
+/// What the server stores: a signed chain encrypted under the folder key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedVersionChain {
+    pub ciphertext: String, // Base64
+    pub nonce: String,      // Base64
+}
+
+/// A new upload to append to a chain
+pub struct NewVersion {
+    pub file_key: String,
+    pub wrapped_dek: String,
+    pub nonce: String,
+    pub file_size: u64,
+    pub original_filename: String,
+    pub ciphertext_sha256: String,
+    pub user_file_key: String,
+}
+
+/// Metadata differences between two versions
+#[derive(Debug, Serialize)]
+pub struct VersionDiff {
+    pub from_version: u64,
+    pub to_version: u64,
+    pub content_changed: bool,
+    pub size_change: i64,
+    pub renamed: Option<(String, String)>,
+    pub seconds_between: i64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn hash_version(version: &FileVersion) -> Result<String> {
+    let encoded = serde_json::to_vec(version)?;
+    Ok(base64::encode(Sha256::digest(&encoded)))
+}
+
+impl VersionChain {
+    pub fn new(file_id: &str) -> Self {
+        VersionChain {
+            file_id: file_id.to_string(),
+            versions: Vec::new(),
+        }
+    }
+
+    pub fn latest(&self) -> Option<&FileVersion> {
+        self.versions.last()
+    }
+
+    pub fn get(&self, version: u64) -> Result<&FileVersion> {
+        self.versions
+            .iter()
+            .find(|v| v.version == version)
+            .with_context(|| format!("Version {} does not exist", version))
+    }
+
+    /// Append a freshly uploaded version and return its number
+    pub fn append(&mut self, new: NewVersion) -> Result<u64> {
+        self.push(FileVersion {
+            version: 0,
+            file_key: new.file_key,
+            wrapped_dek: new.wrapped_dek,
+            nonce: new.nonce,
+            file_size: new.file_size,
+            original_filename: new.original_filename,
+            ciphertext_sha256: new.ciphertext_sha256,
+            user_file_key: Some(new.user_file_key),
+            created_at: now(),
+            restored_from: None,
+            prev_hash: String::new(),
+        })
+    }
+
+    /// Make an older version current again by appending a copy of it
+    /// The ciphertext is reused as is, so nothing is re-encrypted
+    pub fn restore(&mut self, version: u64) -> Result<u64> {
+        let old = self.get(version)?.clone();
+        self.push(FileVersion {
+            created_at: now(),
+            restored_from: Some(version),
+            ..old
+        })
+    }
+
+    fn push(&mut self, mut version: FileVersion) -> Result<u64> {
+        let (number, prev_hash) = match self.latest() {
+            Some(latest) => (latest.version + 1, hash_version(latest)?),
+            None => (1, String::new()),
+        };
+        version.version = number;
+        version.prev_hash = prev_hash;
+        self.versions.push(version);
+        Ok(number)
+    }
+
+    /// Check version numbers count up from 1 and each version links to the one before
+    fn verify_links(&self) -> Result<()> {
+        let mut expected_prev = String::new();
+        for (index, version) in self.versions.iter().enumerate() {
+            if version.version != index as u64 + 1 {
+                return Err(anyhow::anyhow!("Version history is out of order at version {}", version.version));
+            }
+            if version.prev_hash != expected_prev {
+                return Err(anyhow::anyhow!("Version {} does not link to the previous version", version.version));
+            }
+            expected_prev = hash_version(version)?;
+        }
+        Ok(())
+    }
+
+    pub fn diff(&self, from: u64, to: u64) -> Result<VersionDiff> {
+        let a = self.get(from)?;
+        let b = self.get(to)?;
+
+        Ok(VersionDiff {
+            from_version: from,
+            to_version: to,
+            content_changed: a.ciphertext_sha256 != b.ciphertext_sha256,
+            size_change: b.file_size as i64 - a.file_size as i64,
+            renamed: (a.original_filename != b.original_filename)
+                .then(|| (a.original_filename.clone(), b.original_filename.clone())),
+            seconds_between: b.created_at as i64 - a.created_at as i64,
+        })
+    }
+}
+
+/// Sign a chain with the owner's Ed25519 key and encrypt it under the folder key
+pub fn seal_chain(
+    chain: &VersionChain,
+    ed25519_private_key: &str,
+    folder_key: &[u8; 32],
+) -> Result<EncryptedVersionChain> {
+    sodiumoxide::init().map_err(|_| anyhow::anyhow!("Failed to initialize sodiumoxide"))?;
+
+    let sk_bytes = base64::decode(ed25519_private_key).context("Failed to decode Ed25519 private key")?;
+    let secret_key = sign::SecretKey::from_slice(&sk_bytes).context("Invalid Ed25519 private key")?;
+
+    let chain_bytes = serde_json::to_vec(chain)?;
+    let signature = sign::sign_detached(&chain_bytes, &secret_key);
+    let signed = SignedVersionChain {
+        chain: base64::encode(&chain_bytes),
+        signer_public_key: base64::encode(secret_key.public_key().as_ref()),
+        signature: base64::encode(signature.to_bytes()),
+    };
+
+    let (ciphertext, nonce) = encrypt_with_key(&serde_json::to_vec(&signed)?, folder_key)?;
+    Ok(EncryptedVersionChain { ciphertext, nonce })
+}
+
+/// Decrypt a chain and check it was signed by `owner_public_key` for
+/// `file_id` and that its versions link up
+pub fn open_chain(
+    encrypted: &EncryptedVersionChain,
+    folder_key: &[u8; 32],
+    owner_public_key: &str,
+    file_id: &str,
+) -> Result<VersionChain> {
+    sodiumoxide::init().map_err(|_| anyhow::anyhow!("Failed to initialize sodiumoxide"))?;
+
+    let signed_bytes = decrypt_with_key(&encrypted.ciphertext, &encrypted.nonce, folder_key)
+        .context("Failed to decrypt version history")?;
+    let signed: SignedVersionChain = serde_json::from_slice(&signed_bytes)
+        .context("Failed to parse version history")?;
+
+    if signed.signer_public_key != owner_public_key {
+        return Err(anyhow::anyhow!("Version history was not signed by the file owner"));
+    }
+
+    let pk_bytes = base64::decode(owner_public_key).context("Failed to decode owner public key")?;
+    let public_key = sign::PublicKey::from_slice(&pk_bytes).context("Invalid owner public key")?;
+    let chain_bytes = base64::decode(&signed.chain).context("Failed to decode version history")?;
+    let signature_bytes = base64::decode(&signed.signature).context("Failed to decode signature")?;
+    let signature = sign::Signature::from_bytes(&signature_bytes)
+        .map_err(|_| anyhow::anyhow!("Invalid signature size"))?;
+
+    if !sign::verify_detached(&signature, &chain_bytes, &public_key) {
+        return Err(anyhow::anyhow!("Version history signature is invalid"));
+    }
+
+    let chain: VersionChain = serde_json::from_slice(&chain_bytes).context("Failed to parse version history")?;
+    if chain.file_id != file_id {
+        return Err(anyhow::anyhow!("Version history belongs to a different file"));
+    }
+    chain.verify_links()?;
+
+    Ok(chain)
+}
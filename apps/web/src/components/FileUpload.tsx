@@ -1,6 +1,6 @@
 import { useState } from "react";
 import { filesApi } from "@/lib/files-api";
-import { encryptAndUploadFile } from "@/lib/tauri-crypto";
+import { encryptAndUploadFile, getVaultPublicKeys, signOwnKeyGrant } from "@/lib/tauri-crypto";
 import { Button } from "@/components/ui/button";
 import { Card, CardContent, CardDescription, CardHeader, CardTitle } from "@/components/ui/card";
 import { Progress } from "@/components/ui/progress";
@@ -131,17 +131,7 @@ export function FileUpload() {
       setProgress(20);
 
       // Step 3.5: Get user's public key for wrapping DEK
-      const userKeysStr = localStorage.getItem("userKeypair");
-      if (!userKeysStr) {
-        throw new Error("You need to set up encryption keys first. Please generate your keypair.");
-      }
-
-      const userKeys = JSON.parse(userKeysStr);
-      const userPublicKey = userKeys.x25519PublicKey || userKeys.x25519_public_key;
-      
-      if (!userPublicKey) {
-        throw new Error("Invalid keypair data. Please regenerate your encryption keys.");
-      }
+      const { x25519_public_key: userPublicKey } = await getVaultPublicKeys();
 
       console.log("🔑 Using user's public key for DEK wrapping");
 
@@ -166,10 +156,14 @@ export function FileUpload() {
 
       // Step 5: Complete upload on server
       console.log("💾 Completing upload on server...");
+      const ownGrant = await signOwnKeyGrant(uploadResponse.user_file_key, initResponse.fileId);
       await filesApi.completeUpload({
         fileId: initResponse.fileId,
         s3Key: uploadResponse.file_key,
-        wrappedDek: uploadResponse.wrapped_dek,
+        wrappedDek: uploadResponse.user_file_key, // Always sealed to the vault's own key
+        escrowWrappedDek: uploadResponse.escrow_wrapped_dek,
+        grant: ownGrant.grant,
+        grantSignature: ownGrant.signature,
         nonce: uploadResponse.nonce,
         originalFilename: uploadResponse.original_filename,
         fileSize: uploadResponse.file_size,
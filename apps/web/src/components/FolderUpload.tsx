@@ -1,7 +1,7 @@
 import { useState } from "react";
 import { filesApi } from "@/lib/files-api";
-import { createFolder, addFileToFolder, type CreateFolderRequest } from "@/lib/folders-api";
-import { encryptAndUploadFile, generateFolderKey, wrapDekWithFolderKey, unwrapSharedDek } from "@/lib/tauri-crypto";
+import { createFolder, addFileToFolder, storeOwnerGrant, type CreateFolderRequest } from "@/lib/folders-api";
+import { encryptAndUploadFile, generateFolderKey, wrapDekWithFolderKey, unwrapSharedDek, getVaultPublicKeys, signOwnKeyGrant } from "@/lib/tauri-crypto";
 import { Button } from "@/components/ui/button";
 import { Card, CardContent, CardDescription, CardHeader, CardTitle } from "@/components/ui/card";
 import { Progress } from "@/components/ui/progress";
@@ -67,19 +67,8 @@ export function FolderUpload() {
 
       setProgress(10);
 
-      // Step 2: Check for user keypair
-      const userKeysStr = localStorage.getItem("userKeypair");
-      if (!userKeysStr) {
-        throw new Error("You need to set up encryption keys first");
-      }
-
-      const userKeys = JSON.parse(userKeysStr);
-      const userPublicKey = userKeys.x25519PublicKey || userKeys.x25519_public_key;
-      const userPrivateKey = userKeys.x25519PrivateKey || userKeys.x25519_private_key;
-
-      if (!userPublicKey || !userPrivateKey) {
-        throw new Error("Invalid keypair data. Please regenerate your encryption keys.");
-      }
+      // Step 2: Get user's public key from the unlocked vault
+      const { x25519_public_key: userPublicKey } = await getVaultPublicKeys();
 
       // Step 3: Generate folder key
       toast.loading(`Uploading folder: ${folderName}`, {
@@ -112,6 +101,7 @@ export function FolderUpload() {
       };
 
       const { folderId } = await createFolder(createFolderRequest);
+      await storeOwnerGrant(folderId, await signOwnKeyGrant(wrappedFolderKey, folderId));
       console.log("📁 Created folder:", folderId);
       setProgress(25);
 
@@ -142,11 +132,15 @@ export function FolderUpload() {
           file_key: s3Key,
         });
 
-        // Complete upload
+        // Complete upload, with a grant over the key so later unwraps can check it
+        const ownGrant = await signOwnKeyGrant(encryptResult.user_file_key, fileId);
         await filesApi.completeUpload({
           fileId,
           s3Key: encryptResult.file_key,
-          wrappedDek: encryptResult.wrapped_dek,
+          wrappedDek: encryptResult.user_file_key, // Always sealed to the vault's own key
+          escrowWrappedDek: encryptResult.escrow_wrapped_dek,
+          grant: ownGrant.grant,
+          grantSignature: ownGrant.signature,
           nonce: encryptResult.nonce,
           originalFilename: encryptResult.original_filename,
           fileSize: encryptResult.file_size,
@@ -154,11 +148,7 @@ export function FolderUpload() {
 
         // Now wrap the file's DEK with folder key
         // First unwrap the DEK (it's wrapped with user's key)
-        const unwrappedDek = await unwrapSharedDek(
-          encryptResult.wrapped_dek,
-          userPublicKey,
-          userPrivateKey
-        );
+        const unwrappedDek = await unwrapSharedDek(encryptResult.user_file_key, fileId, { grant: ownGrant });
 
         // Then wrap it with the folder key
         const wrappedDekForFolder = await wrapDekWithFolderKey({
@@ -8,7 +8,9 @@ import {
   DialogTitle,
 } from "@/components/ui/dialog";
 import { Button } from "@/components/ui/button";
-import { generateUserKeypair } from "@/lib/tauri-crypto";
+import { Input } from "@/components/ui/input";
+import { Label } from "@/components/ui/label";
+import { createVaultKeyring, hasVaultKeyring, importVaultKeyring } from "@/lib/tauri-crypto";
 import { registerKeypair, getMyKeypair } from "@/lib/sharing-api";
 import { toast } from "sonner";
 import { Loader2, Key, LogOut } from "lucide-react";
@@ -25,6 +27,8 @@ export function KeypairSetupDialog({ open, onComplete }: KeypairSetupDialogProps
   const navigate = useNavigate();
   const [generating, setGenerating] = React.useState(false);
   const [error, setError] = React.useState<string | null>(null);
+  const [passphrase, setPassphrase] = React.useState("");
+  const [confirmPassphrase, setConfirmPassphrase] = React.useState("");
 
   const handleLogout = async () => {
     try {
@@ -38,20 +42,34 @@ export function KeypairSetupDialog({ open, onComplete }: KeypairSetupDialogProps
   };
 
   const handleSetup = async () => {
+    if (passphrase.length < 8) {
+      setError("Passphrase must be at least 8 characters");
+      return;
+    }
+    if (passphrase !== confirmPassphrase) {
+      setError("Passphrases do not match");
+      return;
+    }
+
     try {
       setGenerating(true);
       setError(null);
 
-      // Generate keypairs
-      toast.info("Generating encryption keys...");
-      const keypair = await generateUserKeypair();
-
-      // Store private keys locally (in production, use OS keychain)
-      localStorage.setItem("userKeypair", JSON.stringify(keypair));
+      // Keys from older versions lived in localStorage; move them into the keyring
+      const legacyKeypair = localStorage.getItem("userKeypair");
+      let publicKeys;
+      if (legacyKeypair) {
+        toast.info("Moving existing encryption keys into the keyring...");
+        publicKeys = await importVaultKeyring(JSON.parse(legacyKeypair), passphrase);
+        localStorage.removeItem("userKeypair");
+      } else {
+        toast.info("Generating encryption keys...");
+        publicKeys = await createVaultKeyring(passphrase);
+      }
 
       // Register public keys with server
       toast.info("Registering public keys with server...");
-      await registerKeypair(keypair.x25519_public_key, keypair.ed25519_public_key);
+      await registerKeypair(publicKeys.x25519_public_key, publicKeys.ed25519_public_key);
 
       toast.success("Encryption setup complete!");
       onComplete();
@@ -97,12 +115,33 @@ export function KeypairSetupDialog({ open, onComplete }: KeypairSetupDialogProps
             <AlertDescription>
               <p className="font-medium mb-1">⚠️ Important:</p>
               <p className="text-sm">
-                Your private keys will be stored on this device only. If you lose them,
-                you won't be able to decrypt your files.
+                Your private keys will be stored on this device only, sealed with your
+                passphrase. If you lose either, you won't be able to decrypt your files.
               </p>
             </AlertDescription>
           </Alert>
 
+          <div className="space-y-2">
+            <Label htmlFor="vault-passphrase">Passphrase</Label>
+            <Input
+              id="vault-passphrase"
+              type="password"
+              value={passphrase}
+              onChange={(e) => setPassphrase(e.target.value)}
+              disabled={generating}
+            />
+          </div>
+          <div className="space-y-2">
+            <Label htmlFor="vault-passphrase-confirm">Confirm passphrase</Label>
+            <Input
+              id="vault-passphrase-confirm"
+              type="password"
+              value={confirmPassphrase}
+              onChange={(e) => setConfirmPassphrase(e.target.value)}
+              disabled={generating}
+            />
+          </div>
+
           {error && (
             <Alert variant="destructive">
               <AlertDescription>{error}</AlertDescription>
@@ -154,9 +193,8 @@ export function useKeypairCheck() {
     try {
       setChecking(true);
       
-      // Check local storage first
-      const localKeypair = localStorage.getItem("userKeypair");
-      if (!localKeypair) {
+      // Check the profile keyring first
+      if (!(await hasVaultKeyring())) {
         setHasKeypair(false);
         return;
       }
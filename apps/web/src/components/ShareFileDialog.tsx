@@ -18,7 +18,7 @@ import {
   getFileAccessList,
   revokeAccess,
 } from "@/lib/sharing-api";
-import { shareFileKey } from "@/lib/tauri-crypto";
+import { keyGrantOf, shareFileKey, withUnsignedKeyPrompt, type KeyGrant } from "@/lib/tauri-crypto";
 import { Loader2, Search, Share2, Trash2, Users } from "lucide-react";
 import { Separator } from "@/components/ui/separator";
 import { ScrollArea } from "@/components/ui/scroll-area";
@@ -28,7 +28,7 @@ interface ShareFileDialogProps {
   onOpenChange: (open: boolean) => void;
   fileId: string;
   fileName: string;
-  wrappedDek: string;
+  wrappedDek: string; // Unused; the key and its grant are fetched when sharing
   currentUserId?: string; // Current logged-in user's ID
   onShareComplete?: () => void;
 }
@@ -54,7 +54,6 @@ export function ShareFileDialog({
   onOpenChange,
   fileId,
   fileName,
-  wrappedDek,
   currentUserId,
   onShareComplete,
 }: ShareFileDialogProps) {
@@ -126,48 +125,22 @@ export function ShareFileDialog({
     try {
       setSharing(true);
 
-      // Get user's keys from localStorage (in production, use keychain)
-      const userKeysStr = localStorage.getItem("userKeypair");
-      if (!userKeysStr) {
-        toast.error("You need to set up encryption first");
-        return;
-      }
-
-      let userKeys;
+      // Always fetch the wrappedDek with its grant; the key is only re-wrapped once the grant checks out
+      let fileWrappedDek: string;
+      let keyGrant: KeyGrant | null;
+      console.log("📥 Fetching wrappedDek from server...");
       try {
-        userKeys = JSON.parse(userKeysStr);
-        console.log("🔑 User keys structure:", Object.keys(userKeys));
-      } catch (e) {
-        toast.error("Invalid keypair data in localStorage");
+        const { filesApi } = await import("@/lib/files-api");
+        const downloadInfo = await filesApi.getDownloadInfo(fileId);
+        fileWrappedDek = downloadInfo.wrappedDek;
+        keyGrant = keyGrantOf(downloadInfo);
+        console.log("✅ Got wrappedDek from server");
+      } catch (err) {
+        console.error("Failed to get wrappedDek:", err);
+        toast.error("Failed to get file encryption key. This file may be in a folder.");
         return;
       }
 
-      // Get the keys with fallback for both naming conventions
-      const userPublicKey = userKeys.x25519PublicKey || userKeys.x25519_public_key;
-      const userPrivateKey = userKeys.x25519PrivateKey || userKeys.x25519_private_key;
-
-      if (!userPublicKey || !userPrivateKey) {
-        console.error("Missing keys:", { userPublicKey: !!userPublicKey, userPrivateKey: !!userPrivateKey });
-        toast.error("Incomplete keypair data. Please regenerate your encryption keys.");
-        return;
-      }
-
-      // Get the wrappedDek - if not provided, fetch it from the API
-      let fileWrappedDek = wrappedDek;
-      if (!fileWrappedDek || fileWrappedDek === "") {
-        console.log("📥 Fetching wrappedDek from server...");
-        try {
-          const { filesApi } = await import("@/lib/files-api");
-          const downloadInfo = await filesApi.getDownloadInfo(fileId);
-          fileWrappedDek = downloadInfo.wrappedDek;
-          console.log("✅ Got wrappedDek from server");
-        } catch (err) {
-          console.error("Failed to get wrappedDek:", err);
-          toast.error("Failed to get file encryption key. This file may be in a folder.");
-          return;
-        }
-      }
-
       // Get recipient's public key
       console.log("🔍 Getting recipient public key for:", recipientUserId);
       const recipient = await getUserPublicKey(recipientUserId);
@@ -176,15 +149,19 @@ export function ShareFileDialog({
       // Re-wrap DEK for recipient
       console.log("🔄 Re-wrapping DEK...");
       console.log("  - Wrapped DEK (first 50 chars):", fileWrappedDek.substring(0, 50) + "...");
-      console.log("  - User public key (first 20 chars):", userPublicKey.substring(0, 20) + "...");
-      console.log("  - User private key exists:", !!userPrivateKey);
       console.log("  - Recipient public key (first 20 chars):", recipient.x25519PublicKey.substring(0, 20) + "...");
 
-      const wrappedForRecipient = await shareFileKey(
-        fileWrappedDek,
-        userPublicKey,
-        userPrivateKey,
-        recipient.x25519PublicKey
+      const {
+        wrapped_dek: wrappedForRecipient,
+        grant,
+        escrow_wrapped_dek: escrowWrappedDek,
+      } = await withUnsignedKeyPrompt(fileId, () =>
+        shareFileKey(
+          fileWrappedDek,
+          keyGrant,
+          recipient.x25519PublicKey,
+          fileId
+        )
       );
 
       console.log("✅ DEK re-wrapped for recipient");
@@ -195,6 +172,9 @@ export function ShareFileDialog({
         fileId,
         recipientUserId,
         wrappedDek: wrappedForRecipient,
+        grant: grant.grant,
+        grantSignature: grant.signature,
+        escrowWrappedDek,
       });
 
       toast.success(`File shared with ${recipientEmail}`);
@@ -18,7 +18,8 @@ import {
   getFolderAccessList,
   revokeFolderAccess,
 } from "@/lib/sharing-api";
-import { shareFolderKey } from "@/lib/tauri-crypto";
+import { getFolderDetails } from "@/lib/folders-api";
+import { keyGrantOf, shareFolderKey, withUnsignedKeyPrompt } from "@/lib/tauri-crypto";
 import { Loader2, Search, Share2, Trash2, Users, FolderOpen } from "lucide-react";
 import { Separator } from "@/components/ui/separator";
 import { ScrollArea } from "@/components/ui/scroll-area";
@@ -28,7 +29,7 @@ interface ShareFolderDialogProps {
   onOpenChange: (open: boolean) => void;
   folderId: string;
   folderName: string;
-  wrappedFolderKey: string;
+  wrappedFolderKey: string; // Unused; the key and its grant are fetched when sharing
   onShareComplete?: () => void;
 }
 
@@ -53,7 +54,6 @@ export function ShareFolderDialog({
   onOpenChange,
   folderId,
   folderName,
-  wrappedFolderKey,
   onShareComplete,
 }: ShareFolderDialogProps) {
   const [email, setEmail] = React.useState("");
@@ -124,49 +124,30 @@ export function ShareFolderDialog({
     try {
       setSharing(true);
 
-      // Get user's keys from localStorage
-      const userKeysStr = localStorage.getItem("userKeypair");
-      if (!userKeysStr) {
-        toast.error("You need to set up encryption first");
-        return;
-      }
-
-      let userKeys;
-      try {
-        userKeys = JSON.parse(userKeysStr);
-        console.log("🔑 User keys structure:", Object.keys(userKeys));
-      } catch (e) {
-        toast.error("Invalid keypair data in localStorage");
-        return;
-      }
-
-      // Get the keys with fallback for both naming conventions
-      const userPublicKey = userKeys.x25519PublicKey || userKeys.x25519_public_key;
-      const userPrivateKey = userKeys.x25519PrivateKey || userKeys.x25519_private_key;
-
-      if (!userPublicKey || !userPrivateKey) {
-        console.error("Missing keys:", { userPublicKey: !!userPublicKey, userPrivateKey: !!userPrivateKey });
-        toast.error("Incomplete keypair data. Please regenerate your encryption keys.");
-        return;
-      }
-
       // Get recipient's public key
       console.log("🔍 Getting recipient public key for:", recipientUserId);
       const recipient = await getUserPublicKey(recipientUserId);
       console.log("✅ Recipient public key:", recipient.x25519PublicKey);
 
+      // Our own key is only re-wrapped once its grant checks out
+      const { folder } = await getFolderDetails(folderId);
+
       // Re-wrap folder key for recipient
       console.log("🔄 Re-wrapping folder key...");
-      console.log("  - Wrapped folder key (first 50 chars):", wrappedFolderKey.substring(0, 50) + "...");
-      console.log("  - User public key (first 20 chars):", userPublicKey.substring(0, 20) + "...");
-      console.log("  - User private key exists:", !!userPrivateKey);
+      console.log("  - Wrapped folder key (first 50 chars):", folder.wrappedFolderKey.substring(0, 50) + "...");
       console.log("  - Recipient public key (first 20 chars):", recipient.x25519PublicKey.substring(0, 20) + "...");
 
-      const wrappedForRecipient = await shareFolderKey(
-        wrappedFolderKey,
-        userPublicKey,
-        userPrivateKey,
-        recipient.x25519PublicKey
+      const {
+        wrapped_dek: wrappedForRecipient,
+        grant,
+        escrow_wrapped_dek: escrowWrappedKey,
+      } = await withUnsignedKeyPrompt(folderId, () =>
+        shareFolderKey(
+          folder.wrappedFolderKey,
+          keyGrantOf(folder),
+          recipient.x25519PublicKey,
+          folderId
+        )
       );
 
       console.log("✅ Folder key re-wrapped for recipient");
@@ -176,6 +157,9 @@ export function ShareFolderDialog({
       await shareFolder(folderId, {
         recipientUserId,
         wrappedFolderKey: wrappedForRecipient,
+        grant: grant.grant,
+        grantSignature: grant.signature,
+        escrowWrappedKey,
       });
 
       toast.success(`Folder shared with ${recipientEmail}`);
@@ -0,0 +1,125 @@
+import React from "react";
+import {
+  Dialog,
+  DialogContent,
+  DialogDescription,
+  DialogFooter,
+  DialogHeader,
+  DialogTitle,
+} from "@/components/ui/dialog";
+import { Button } from "@/components/ui/button";
+import { Input } from "@/components/ui/input";
+import { Alert, AlertDescription } from "@/components/ui/alert";
+import { Loader2, Lock } from "lucide-react";
+import { listen } from "@tauri-apps/api/event";
+import {
+  getVaultStatus,
+  hasVaultKeyring,
+  isCommandError,
+  unlockVault,
+  VAULT_LOCKED_EVENT,
+} from "@/lib/tauri-crypto";
+
+// Older versions kept plaintext keys in localStorage; the keyring replaces them
+function clearLegacyKeys() {
+  localStorage.removeItem("userKeypair");
+  for (const key of Object.keys(localStorage)) {
+    if (key.startsWith("folderKey_")) localStorage.removeItem(key);
+  }
+}
+
+/**
+ * Asks for the keyring passphrase whenever the vault is locked
+ */
+export function VaultUnlockDialog() {
+  const [locked, setLocked] = React.useState(false);
+  const [passphrase, setPassphrase] = React.useState("");
+  const [unlocking, setUnlocking] = React.useState(false);
+  const [error, setError] = React.useState<string | null>(null);
+
+  React.useEffect(() => {
+    const checkLocked = async () => {
+      try {
+        // Without a keyring there is nothing to unlock; key setup handles that case
+        if (!(await hasVaultKeyring())) return;
+        const status = await getVaultStatus();
+        setLocked(status.locked);
+      } catch (err) {
+        console.error("❌ Failed to read vault status:", err);
+      }
+    };
+    checkLocked();
+
+    const unlisten = listen(VAULT_LOCKED_EVENT, () => {
+      setPassphrase("");
+      setLocked(true);
+    });
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  const handleUnlock = async (e: React.FormEvent) => {
+    e.preventDefault();
+    try {
+      setUnlocking(true);
+      setError(null);
+      await unlockVault(passphrase);
+      clearLegacyKeys();
+      setPassphrase("");
+      setLocked(false);
+    } catch (err) {
+      console.error("Unlock error:", err);
+      setError(isCommandError(err) ? err.message : "Failed to unlock vault");
+    } finally {
+      setUnlocking(false);
+    }
+  };
+
+  return (
+    <Dialog open={locked} onOpenChange={() => {}}>
+      <DialogContent className="sm:max-w-[420px]" showCloseButton={false}>
+        <form onSubmit={handleUnlock}>
+          <DialogHeader>
+            <DialogTitle className="flex items-center gap-2">
+              <Lock className="h-5 w-5" />
+              Vault Locked
+            </DialogTitle>
+            <DialogDescription>
+              Enter your passphrase to unlock your encryption keys
+            </DialogDescription>
+          </DialogHeader>
+
+          <div className="space-y-4 mt-4">
+            <Input
+              type="password"
+              autoFocus
+              value={passphrase}
+              onChange={(e) => setPassphrase(e.target.value)}
+              placeholder="Passphrase"
+              disabled={unlocking}
+            />
+            {error && (
+              <Alert variant="destructive">
+                <AlertDescription>{error}</AlertDescription>
+              </Alert>
+            )}
+          </div>
+
+          <DialogFooter className="mt-4">
+            <Button type="submit" disabled={unlocking || !passphrase}>
+              {unlocking ? (
+                <>
+                  <Loader2 className="h-4 w-4 mr-2 animate-spin" />
+                  Unlocking...
+                </>
+              ) : (
+                "Unlock"
+              )}
+            </Button>
+          </DialogFooter>
+        </form>
+      </DialogContent>
+    </Dialog>
+  );
+}
@@ -13,6 +13,10 @@ import { filesApi } from "@/lib/files-api";
 import {
   encryptAndUploadFile,
   downloadAndDecryptFile,
+  downloadAndDecryptFileWithKey,
+  keyGrantOf,
+  signOwnKeyGrant,
+  withUnsignedKeyPrompt,
 } from "@/lib/tauri-crypto";
 import { open } from "@tauri-apps/plugin-dialog";
 import { save } from "@tauri-apps/plugin-dialog";
@@ -79,10 +83,14 @@ export function useFileEncryption() {
       });
 
       // Step 3: Complete upload on server
+      const ownGrant = await signOwnKeyGrant(uploadResponse.user_file_key, initResponse.fileId);
       await filesApi.completeUpload({
         fileId: initResponse.fileId,
         s3Key: uploadResponse.file_key,
-        wrappedDek: uploadResponse.wrapped_dek,
+        wrappedDek: uploadResponse.user_file_key, // Always sealed to the vault's own key
+        escrowWrappedDek: uploadResponse.escrow_wrapped_dek,
+        grant: ownGrant.grant,
+        grantSignature: ownGrant.signature,
         nonce: uploadResponse.nonce,
         originalFilename: uploadResponse.original_filename,
         fileSize: uploadResponse.file_size,
@@ -123,14 +131,31 @@ export function useFileEncryption() {
       const downloadInfo = await filesApi.getDownloadInfo(fileId);
 
       // Step 2: Download and decrypt via Tauri
-      await downloadAndDecryptFile({
-        download_url: downloadInfo.downloadUrl,
-        wrapped_dek: downloadInfo.wrappedDek,
-        nonce: downloadInfo.nonce,
-        server_public_key: downloadInfo.serverPublicKey,
-        server_private_key: downloadInfo.serverPrivateKey,
-        output_path: savePath,
-      });
+      if (downloadInfo.shareGrant !== undefined) {
+        // A per-user file key; no server key needed, and its grant is checked before use
+        await withUnsignedKeyPrompt(fileId, () =>
+          downloadAndDecryptFileWithKey({
+            download_url: downloadInfo.downloadUrl,
+            user_file_key: downloadInfo.wrappedDek,
+            file_id: fileId,
+            grant: keyGrantOf(downloadInfo),
+            nonce: downloadInfo.nonce,
+            output_path: savePath,
+            file_size: downloadInfo.fileSize,
+          })
+        );
+      } else {
+        // Files uploaded before per-user file keys; refused once legacy server keys are disabled
+        await downloadAndDecryptFile({
+          download_url: downloadInfo.downloadUrl,
+          wrapped_dek: downloadInfo.wrappedDek,
+          nonce: downloadInfo.nonce,
+          server_public_key: downloadInfo.serverPublicKey,
+          server_private_key: downloadInfo.serverPrivateKey,
+          output_path: savePath,
+          file_size: downloadInfo.fileSize,
+        });
+      }
 
       return savePath;
     } catch (err) {
@@ -1,12 +1,12 @@
 import type { auth } from "@krypt-vault/auth";
 import { createAuthClient } from "better-auth/react";
 import { inferAdditionalFields } from "better-auth/client/plugins";
+import { SERVER_URL } from "./server-url";
 
-const baseURL = import.meta.env.VITE_SERVER_URL || "http://localhost:3000";
+const baseURL = SERVER_URL;
 
 console.log("Auth Client Configuration:", {
   baseURL,
-  env: import.meta.env.VITE_SERVER_URL,
   mode: import.meta.env.MODE,
 });
 
@@ -1,4 +1,7 @@
-const API_BASE_URL = import.meta.env.VITE_SERVER_URL || "http://localhost:3000";
+import { SERVER_URL } from "./server-url";
+import type { KeyGrantInfo } from "./tauri-crypto";
+
+const API_BASE_URL = SERVER_URL;
 
 export interface FileMetadata {
   id: string;
@@ -41,16 +44,25 @@ export interface UploadCompleteRequest {
   mimeType?: string;
   description?: string;
   tags?: string[];
+  escrowWrappedDek?: string | null; // DEK sealed to the profile's escrow key, if one is set
+  grant?: string; // Grant the uploader signed over wrappedDek (see signOwnKeyGrant)
+  grantSignature?: string;
 }
 
-export interface DownloadResponse {
+export interface DownloadResponse extends KeyGrantInfo {
   downloadUrl: string;
   wrappedDek: string;
   nonce: string;
   originalFilename: string;
+  fileSize?: number; // Ciphertext size
   mimeType?: string;
 }
 
+export interface LegacyKeyFile {
+  fileId: string;
+  wrappedDek: string; // Sealed to the server keypair
+}
+
 export interface StorageUsage {
   usedBytes: number;
   quotaBytes: number;
@@ -151,6 +163,26 @@ class FilesApiClient {
     );
   }
 
+  /**
+   * Owned files whose DEK is only sealed to the server keypair
+   */
+  async listLegacyKeys(): Promise<LegacyKeyFile[]> {
+    const response = await this.request<{ files: LegacyKeyFile[] }>("/legacy-keys");
+    return response.files;
+  }
+
+  /**
+   * Store DEKs re-wrapped for the owner by migrateLegacyWraps
+   */
+  async storeMigratedKeys(
+    keys: { fileId: string; fileKey: string; grant?: string; grantSignature?: string }[]
+  ): Promise<{ success: boolean; stored: number }> {
+    return this.request<{ success: boolean; stored: number }>("/legacy-keys", {
+      method: "POST",
+      body: JSON.stringify({ keys }),
+    });
+  }
+
   /**
    * Get file metadata
    */
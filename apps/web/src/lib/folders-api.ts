@@ -1,4 +1,7 @@
-const API_URL = import.meta.env.VITE_SERVER_URL || "http://localhost:3000";
+import { SERVER_URL } from "./server-url";
+import type { KeyGrantInfo } from "./tauri-crypto";
+
+const API_URL = SERVER_URL;
 
 /**
  * Get headers with authorization token
@@ -28,6 +31,8 @@ export interface ShareFolderRequest {
   folderId: string;
   recipientUserId: string;
   wrappedFolderKey: string; // Folder key wrapped with recipient's public key
+  grant: string; // Share grant signed by the sharer over the folder ID
+  grantSignature: string;
 }
 
 export interface AddFileToFolderRequest {
@@ -77,7 +82,7 @@ export interface FolderFile {
 }
 
 export interface FolderDetails {
-  folder: {
+  folder: KeyGrantInfo & {
     folderId: string;
     name: string;
     description: string | null;
@@ -109,6 +114,27 @@ export async function createFolder(request: CreateFolderRequest): Promise<{ fold
   return response.json();
 }
 
+/**
+ * Store the grant the creator signed over their own folder key
+ * Sent right after createFolder, once the folder ID is known
+ */
+export async function storeOwnerGrant(
+  folderId: string,
+  grant: { grant: string; signature: string }
+): Promise<void> {
+  const response = await fetch(`${API_URL}/api/folders/${folderId}/owner-grant`, {
+    method: "PUT",
+    headers: getAuthHeaders(),
+    credentials: "include",
+    body: JSON.stringify({ grant: grant.grant, grantSignature: grant.signature }),
+  });
+
+  if (!response.ok) {
+    const error = await response.json();
+    throw new Error(error.error || "Failed to store folder grant");
+  }
+}
+
 /**
  * List user's folders (owned and shared)
  */
@@ -166,6 +192,8 @@ export async function shareFolder(request: ShareFolderRequest): Promise<void> {
     body: JSON.stringify({
       recipientUserId: request.recipientUserId,
       wrappedFolderKey: request.wrappedFolderKey,
+      grant: request.grant,
+      grantSignature: request.grantSignature,
     }),
   });
 
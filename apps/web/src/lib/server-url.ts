@@ -0,0 +1,8 @@
+import { getActiveProfile } from "./tauri-crypto";
+
+/**
+ * Base URL of the active profile's server, read from Rust when the app loads
+ * Modules importing this wait for it; switching profiles reloads the window
+ * so it (and the CSP allowing it) follow the new profile
+ */
+export const SERVER_URL = (await getActiveProfile()).server_url;
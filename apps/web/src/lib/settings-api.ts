@@ -1,4 +1,6 @@
-const API_BASE_URL = import.meta.env.VITE_SERVER_URL || "http://localhost:3000";
+import { SERVER_URL } from "./server-url";
+
+const API_BASE_URL = SERVER_URL;
 
 export interface UserSettings {
   id: string;
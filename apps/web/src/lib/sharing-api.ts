@@ -1,4 +1,6 @@
-const API_URL = import.meta.env.VITE_SERVER_URL || "http://localhost:3000";
+import { SERVER_URL } from "./server-url";
+
+const API_URL = SERVER_URL;
 
 /**
  * Get headers with authorization token
@@ -21,6 +23,9 @@ export interface ShareFileRequest {
   fileId: string;
   recipientUserId: string;
   wrappedDek: string;
+  grant: string; // Share grant signed by the sharer (base64 JSON)
+  grantSignature: string;
+  escrowWrappedDek?: string | null; // DEK sealed to the sharer's escrow key, if one is set
 }
 
 export interface ShareBulkRequest {
@@ -28,6 +33,9 @@ export interface ShareBulkRequest {
   recipients: {
     userId: string;
     wrappedDek: string;
+    grant: string;
+    grantSignature: string;
+    escrowWrappedDek?: string | null;
   }[];
 }
 
@@ -309,6 +317,9 @@ export async function getMyKeypair(): Promise<{ x25519PublicKey: string; ed25519
 export interface ShareFolderRequest {
   recipientUserId: string;
   wrappedFolderKey: string;
+  grant: string; // Share grant signed by the sharer over the folder ID
+  grantSignature: string;
+  escrowWrappedKey?: string | null; // Folder key sealed to the sharer's escrow key, if one is set
 }
 
 /**
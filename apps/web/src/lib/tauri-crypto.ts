@@ -1,4 +1,42 @@
 import { invoke } from "@tauri-apps/api/core";
+import { ask } from "@tauri-apps/plugin-dialog";
+
+/**
+ * Error codes returned by Tauri commands (mirrors `ErrorCode` in error.rs)
+ */
+export type CommandErrorCode =
+  | "AuthenticationFailed"
+  | "InvalidKey"
+  | "InvalidInput"
+  | "DecryptionFailed"
+  | "NetworkError"
+  | "PresignedUrlExpired"
+  | "Cancelled"
+  | "NotFound"
+  | "Io"
+  | "StorageFull"
+  | "Unsupported"
+  | "Locked"
+  | "UnsignedKey"
+  | "Internal";
+
+/**
+ * Error rejected by every Tauri command
+ */
+export interface CommandError {
+  code: CommandErrorCode;
+  message: string;
+  details?: string;
+}
+
+export function isCommandError(error: unknown): error is CommandError {
+  return (
+    typeof error === "object" &&
+    error !== null &&
+    "code" in error &&
+    "message" in error
+  );
+}
 
 export interface EncryptionResult {
   encrypted_file_path: string;
@@ -6,6 +44,7 @@ export interface EncryptionResult {
   nonce: string;
   file_size: number;
   original_filename: string;
+  ciphertext_sha256: string;
 }
 
 export interface FileUploadParams {
@@ -13,6 +52,9 @@ export interface FileUploadParams {
   server_public_key: string;
   presigned_url: string;
   file_key: string;
+  thumbnail_presigned_url?: string | null; // Upload an encrypted thumbnail here
+  verify_head_url?: string | null; // Presigned HEAD URL, for when S3 doesn't echo the checksum
+  checksum_signed?: boolean; // presigned_url was signed with the ciphertext's SHA-256
 }
 
 export interface FileUploadResponse {
@@ -22,6 +64,13 @@ export interface FileUploadResponse {
   nonce: string;
   file_size: number;
   original_filename: string;
+  ciphertext_sha256: string;
+  has_thumbnail: boolean;
+  bucket: string;
+  etag: string | null;
+  checksum_verified: boolean; // S3 confirmed it stored exactly this ciphertext
+  user_file_key: string; // DEK sealed to the vault's own key; the uploader's per-user file key
+  escrow_wrapped_dek: string | null; // DEK sealed to the profile's escrow key, if one is set
 }
 
 export interface FileDownloadParams {
@@ -31,6 +80,8 @@ export interface FileDownloadParams {
   server_public_key: string;
   server_private_key: string;
   output_path: string;
+  ciphertext_sha256?: string | null; // Checked before decrypting when given
+  file_size?: number | null; // Ciphertext size; temp space is checked before downloading
 }
 
 export interface DecryptionParams {
@@ -81,6 +132,27 @@ export async function downloadAndDecryptFile(
   return await invoke<string>("download_and_decrypt_file", { params });
 }
 
+export interface FileKeyDownloadParams {
+  download_url: string;
+  user_file_key: string; // The current user's per-user file key
+  file_id: string;
+  grant?: KeyGrant | null; // Checked before the key is unwrapped (see keyGrantOf)
+  nonce: string;
+  output_path: string;
+  ciphertext_sha256?: string | null; // Checked before decrypting when given
+  file_size?: number | null; // Ciphertext size; temp space is checked before downloading
+}
+
+/**
+ * Download and decrypt a file with the current user's per-user file key
+ * No server private key is involved
+ */
+export async function downloadAndDecryptFileWithKey(
+  params: FileKeyDownloadParams
+): Promise<string> {
+  return await invoke<string>("download_and_decrypt_file_with_key", { params });
+}
+
 /**
  * Generate a new server keypair (for testing/development)
  */
@@ -122,43 +194,214 @@ export async function decryptFileOnly(
 // USER KEYPAIR MANAGEMENT
 // ============================================================================
 
+export interface VaultPublicKeys {
+  x25519_public_key: string;
+  ed25519_public_key: string;
+}
+
+/**
+ * Generate new user keypairs and save them to the profile keyring, sealed with a passphrase
+ * Only the public keys come back; the vault is left unlocked
+ */
+export async function createVaultKeyring(passphrase: string): Promise<VaultPublicKeys> {
+  return await invoke<VaultPublicKeys>("create_vault_keyring", { passphrase });
+}
+
+/**
+ * Move an existing keypair into the profile keyring, sealed with a passphrase
+ * Replacing an existing keyring needs the vault unlocked and its current passphrase
+ */
+export async function importVaultKeyring(
+  keypair: UserKeypair,
+  passphrase: string,
+  currentPassphrase?: string
+): Promise<VaultPublicKeys> {
+  return await invoke<VaultPublicKeys>("import_vault_keyring", {
+    keypair,
+    passphrase,
+    currentPassphrase: currentPassphrase ?? null,
+  });
+}
+
 /**
- * Generate new user keypairs (X25519 for encryption + Ed25519 for signing)
+ * Whether the active profile has a keyring to unlock
  */
-export async function generateUserKeypair(): Promise<UserKeypair> {
-  return await invoke<UserKeypair>("generate_user_keypair_command");
+export async function hasVaultKeyring(): Promise<boolean> {
+  return await invoke<boolean>("has_vault_keyring");
+}
+
+/**
+ * Public keys of the unlocked vault
+ */
+export async function getVaultPublicKeys(): Promise<VaultPublicKeys> {
+  return await invoke<VaultPublicKeys>("get_vault_public_keys");
+}
+
+export interface SignedShareGrant {
+  grant: string;
+  signature: string;
+}
+
+export interface SharedFileKey {
+  wrapped_dek: string;
+  grant: SignedShareGrant;
+  escrow_wrapped_dek: string | null; // DEK sealed to the profile's escrow key, if one is set
+}
+
+/**
+ * Signed grant stored with a wrapped key, and who the server says signed it
+ * The server's word is only used to pick a sharer key to pin the first time
+ */
+export interface KeyGrant {
+  grant: SignedShareGrant;
+  sharer_id?: string | null;
+  sharer_public_key?: string | null; // Ed25519 key the server publishes for the sharer
+}
+
+/**
+ * Key metadata the server returns next to a wrapped key
+ */
+export interface KeyGrantInfo {
+  shareGrant?: {
+    grant: string;
+    signature: string;
+    sharerId: string;
+    sharerPublicKey: string | null;
+  } | null;
+}
+
+/**
+ * The grant to check a key against, from the server's key metadata
+ * Null for keys stored before grants existed
+ */
+export function keyGrantOf(info: KeyGrantInfo): KeyGrant | null {
+  if (!info.shareGrant) {
+    return null;
+  }
+  return {
+    grant: { grant: info.shareGrant.grant, signature: info.shareGrant.signature },
+    sharer_id: info.shareGrant.sharerId,
+    sharer_public_key: info.shareGrant.sharerPublicKey,
+  };
+}
+
+export interface ShareGrant {
+  file_id: string;
+  wrapped_dek: string;
+  recipient_public_key: string;
+  sharer_x25519_public_key: string;
+  sharer_ed25519_public_key: string;
+  issued_at: number;
 }
 
 /**
  * Share a file key with another user
- * Unwraps the DEK with current user's private key, then wraps it with recipient's public key
+ * Unwraps the DEK with the vault's private key, then wraps it with recipient's public key
+ * Also returns a grant over the new wrapped key, signed with the vault's Ed25519 key
  */
 export async function shareFileKey(
   wrappedDek: string,
-  userPublicKey: string,
-  userPrivateKey: string,
-  recipientPublicKey: string
-): Promise<string> {
-  return await invoke<string>("share_file_key", {
+  grant: KeyGrant | null,
+  recipientPublicKey: string,
+  fileId: string
+): Promise<SharedFileKey> {
+  return await invoke<SharedFileKey>("share_file_key", {
     wrappedDek,
-    userPublicKey,
-    userPrivateKey,
+    grant,
     recipientPublicKey,
+    fileId,
   });
 }
 
 /**
- * Unwrap a DEK that was shared with the current user
+ * Unwrap a DEK sealed to the current user
+ * The key is only unwrapped if its grant verifies against the vault's own
+ * key or the sharer's pinned key, or if the user allowed it unsigned
+ * `fileId` is the file's ID, or the folder's ID for a folder key
  */
 export async function unwrapSharedDek(
   wrappedDek: string,
-  userPublicKey: string,
-  userPrivateKey: string
+  fileId: string,
+  grant: KeyGrant | null
 ): Promise<string> {
   return await invoke<string>("unwrap_shared_dek", {
     wrappedDek,
-    userPublicKey,
-    userPrivateKey,
+    fileId,
+    grant,
+  });
+}
+
+/**
+ * Run `action`, and if it was refused for a key without a grant, ask the user
+ * whether to trust that key for this file; if they agree, allow it and retry
+ */
+export async function withUnsignedKeyPrompt<T>(
+  fileId: string,
+  action: () => Promise<T>
+): Promise<T> {
+  try {
+    return await action();
+  } catch (error) {
+    if (!isCommandError(error) || error.code !== "UnsignedKey") {
+      throw error;
+    }
+    const allowed = await ask(
+      "This key was stored without a signature, so nothing proves who shared it. Open it anyway?",
+      { title: "Unsigned key", kind: "warning" }
+    );
+    if (!allowed) {
+      throw error;
+    }
+    await allowUnsignedKey(fileId);
+    return await action();
+  }
+}
+
+/**
+ * Unwrap a key with the grant the server returned next to it
+ */
+export async function unwrapFileKey(
+  wrappedDek: string,
+  fileId: string,
+  info: KeyGrantInfo
+): Promise<string> {
+  const grant = keyGrantOf(info);
+  return await withUnsignedKeyPrompt(fileId, () => unwrapSharedDek(wrappedDek, fileId, grant));
+}
+
+/**
+ * Sign a grant over a key this device just wrapped for the current user
+ * Store it with the key so later unwraps can check it like any share
+ */
+export async function signOwnKeyGrant(
+  wrappedKey: string,
+  fileId: string
+): Promise<SignedShareGrant> {
+  return await invoke<SignedShareGrant>("sign_own_key_grant", {
+    wrappedKey,
+    fileId,
+  });
+}
+
+/**
+ * Allow a key without a grant to be unwrapped for this file or folder
+ */
+export async function allowUnsignedKey(fileId: string): Promise<void> {
+  await invoke("allow_unsigned_key", { fileId });
+}
+
+/**
+ * Check that a share grant was signed by the sharer and covers this key
+ */
+export async function verifyShareGrant(
+  wrappedDek: string,
+  fileId: string,
+  grant: KeyGrant
+): Promise<ShareGrant> {
+  return await invoke<ShareGrant>("verify_share_grant_command", {
+    wrappedDek,
+    fileId,
+    grant,
   });
 }
 
@@ -169,13 +412,17 @@ export async function downloadAndDecryptSharedFile(
   downloadUrl: string,
   dekBase64: string,
   nonce: string,
-  outputPath: string
+  outputPath: string,
+  ciphertextSha256: string | null = null,
+  fileSize: number | null = null // Ciphertext size, when known
 ): Promise<string> {
   return await invoke<string>("download_and_decrypt_shared_file", {
     downloadUrl,
     dekBase64,
     nonce,
     outputPath,
+    ciphertextSha256,
+    fileSize,
   });
 }
 
@@ -212,23 +459,1026 @@ export async function unwrapDekWithFolderKey(
   });
 }
 
+export interface PasswordWrappedKey {
+  ciphertext: string;
+  nonce: string;
+  kdf: {
+    algorithm: string;
+    memory_kib: number;
+    iterations: number;
+    parallelism: number;
+    salt: string;
+  };
+}
+
+/**
+ * Wrap a key with a password (Argon2id)
+ */
+export async function wrapKeyWithPassword(
+  keyB64: string,
+  password: string
+): Promise<PasswordWrappedKey> {
+  return await invoke<PasswordWrappedKey>("wrap_key_with_password", {
+    keyB64,
+    password,
+  });
+}
+
+/**
+ * Unwrap a password-wrapped key; fails with InvalidKey on a wrong password
+ */
+export async function unwrapKeyWithPassword(
+  wrapped: PasswordWrappedKey,
+  password: string
+): Promise<string> {
+  return await invoke<string>("unwrap_key_with_password", {
+    wrapped,
+    password,
+  });
+}
+
 /**
  * Share a folder key with another user
- * Unwraps the folder key with current user's private key, then wraps it with recipient's public key
+ * Unwraps the folder key with the vault's private key, then wraps it with recipient's public key
  * This is similar to shareFileKey but for folder keys
  */
 export async function shareFolderKey(
   wrappedFolderKey: string,
-  userPublicKey: string,
-  userPrivateKey: string,
-  recipientPublicKey: string
-): Promise<string> {
+  grant: KeyGrant | null,
+  recipientPublicKey: string,
+  folderId: string
+): Promise<SharedFileKey> {
   // Use the same share_file_key command since both keys are 32 bytes and use the same wrapping mechanism
-  return await invoke<string>("share_file_key", {
+  // The grant is signed over the folder ID
+  return await invoke<SharedFileKey>("share_file_key", {
     wrappedDek: wrappedFolderKey,
-    userPublicKey,
-    userPrivateKey,
+    grant,
     recipientPublicKey,
+    fileId: folderId,
   });
 }
 
+
+// ============================================================================
+// VAULT LOCK
+// ============================================================================
+
+export interface AutoLockSettings {
+  idle_timeout_secs: number | null;
+  lock_on_sleep: boolean;
+}
+
+export interface VaultStatus {
+  locked: boolean;
+  settings: AutoLockSettings;
+}
+
+/**
+ * Event emitted by the backend whenever the vault locks
+ */
+export const VAULT_LOCKED_EVENT = "vault-locked";
+
+export interface VaultLockedPayload {
+  profile_id: string;
+  reason: "manual" | "idle" | "sleep";
+}
+
+/**
+ * Unlock the active profile's vault
+ * Without a keypair, the keyring saved for the profile is used
+ */
+export async function unlockVault(passphrase: string): Promise<VaultPublicKeys> {
+  return await invoke<VaultPublicKeys>("unlock_vault", { passphrase });
+}
+
+/**
+ * Lock the vault immediately, wiping in-memory keys
+ */
+export async function lockVault(): Promise<void> {
+  return await invoke<void>("lock_vault");
+}
+
+export async function getVaultStatus(): Promise<VaultStatus> {
+  return await invoke<VaultStatus>("get_vault_status");
+}
+
+export async function setAutoLockSettings(
+  settings: AutoLockSettings
+): Promise<void> {
+  return await invoke<void>("set_auto_lock_settings", { settings });
+}
+
+/**
+ * Reset the backend idle timer
+ */
+export async function recordActivity(): Promise<void> {
+  return await invoke<void>("record_activity");
+}
+
+/**
+ * Stage temp files in memory instead of on disk (Linux only)
+ * Resolves to whether memory staging is now in effect
+ */
+export async function setRamStaging(enabled: boolean): Promise<boolean> {
+  return await invoke<boolean>("set_ram_staging", { enabled });
+}
+
+// ============================================================================
+// PROFILES
+// ============================================================================
+
+export interface ProfileConfig {
+  id: string;
+  name: string;
+  server_url: string;
+  disable_legacy_server_keys: boolean; // Refuse DEKs sealed to the server keypair
+  escrow_key: EscrowKey | null; // Every uploaded or shared DEK is also sealed to this
+  org_signing_key: string | null; // Pinned by the first escrow key; later ones must match
+}
+
+export interface ProfilesList {
+  active: string;
+  profiles: ProfileConfig[];
+}
+
+export async function listProfiles(): Promise<ProfilesList> {
+  return await invoke<ProfilesList>("list_profiles");
+}
+
+export async function getActiveProfile(): Promise<ProfileConfig> {
+  return await invoke<ProfileConfig>("get_active_profile");
+}
+
+/**
+ * Add a profile for an account on another server
+ * Its server is reachable once the window reloads, which `switchProfile` does
+ */
+export async function createProfile(
+  name: string,
+  serverUrl: string
+): Promise<ProfileConfig> {
+  return await invoke<ProfileConfig>("create_profile", { name, serverUrl });
+}
+
+/**
+ * Make another profile active and reload the window, so the server URL and
+ * the CSP allowing it are picked up for the new profile
+ */
+export async function switchProfile(profileId: string): Promise<ProfileConfig> {
+  const profile = await invoke<ProfileConfig>("switch_profile", { profileId });
+  window.location.reload();
+  return profile;
+}
+
+/**
+ * Delete a profile and its local keyring and files
+ * The active profile cannot be deleted
+ */
+export async function deleteProfile(profileId: string): Promise<void> {
+  return await invoke<void>("delete_profile", { profileId });
+}
+
+// ============================================================================
+// TRANSFERS
+// ============================================================================
+
+export interface TransferInfo {
+  id: string;
+  kind: "upload" | "download";
+  name: string;
+  started_at: number;
+}
+
+export async function listTransfers(): Promise<TransferInfo[]> {
+  return await invoke<TransferInfo[]>("list_transfers");
+}
+
+export async function cancelTransfer(transferId: string): Promise<void> {
+  return await invoke<void>("cancel_transfer", { transferId });
+}
+
+// ============================================================================
+// DEVICE LINKING
+// ============================================================================
+
+export type LinkRole = "sender" | "receiver";
+
+export interface LinkHandshake {
+  role: LinkRole;
+  safety_code: string;
+}
+
+/**
+ * Generate the short code to type on the new device
+ */
+export async function createLinkCode(): Promise<string> {
+  return await invoke<string>("create_link_code");
+}
+
+/**
+ * Connect to the other device through the relay
+ * Resolves once both devices joined; compare the safety codes before continuing
+ */
+export async function connectDeviceLink(
+  code: string,
+  role: LinkRole,
+  relayAddr?: string
+): Promise<LinkHandshake> {
+  return await invoke<LinkHandshake>("connect_device_link", {
+    code,
+    role,
+    relayAddr: relayAddr ?? null,
+  });
+}
+
+/**
+ * Send this device's keyring to the linked device (existing device)
+ */
+export async function sendLinkedKeyring(): Promise<void> {
+  return await invoke<void>("send_linked_keyring");
+}
+
+/**
+ * Receive the keyring from the existing device (new device)
+ */
+export async function receiveLinkedKeyring(passphrase: string): Promise<VaultPublicKeys> {
+  return await invoke<VaultPublicKeys>("receive_linked_keyring", { passphrase });
+}
+
+export async function cancelDeviceLink(): Promise<void> {
+  return await invoke<void>("cancel_device_link");
+}
+
+// ============================================================================
+// AUDIT LOG
+// ============================================================================
+
+export interface AuditVerification {
+  valid: boolean;
+  entries: number;
+  first_invalid_seq: number | null;
+  error: string | null;
+  head_verified: boolean; // A signed head was found and the log reaches it
+}
+
+/**
+ * Verify the hash chain and signatures of the local audit log
+ */
+export async function verifyAuditLog(): Promise<AuditVerification> {
+  return await invoke<AuditVerification>("verify_audit_log");
+}
+
+/**
+ * Verify the audit log and export it as JSON to outputPath
+ */
+export async function exportAuditLog(
+  outputPath: string
+): Promise<AuditVerification> {
+  return await invoke<AuditVerification>("export_audit_log", { outputPath });
+}
+
+// ============================================================================
+// FOLDER MANIFESTS
+// ============================================================================
+
+export interface ManifestEntry {
+  file_id: string;
+  ciphertext_sha256: string;
+  version: number;
+}
+
+export interface EncryptedManifest {
+  ciphertext: string;
+  nonce: string;
+}
+
+export interface ListedFile {
+  file_id: string;
+  ciphertext_sha256: string | null;
+  version: number | null;
+}
+
+export type Discrepancy =
+  | { kind: "missing"; file_id: string }
+  | { kind: "unexpected"; file_id: string }
+  | { kind: "hash_mismatch"; file_id: string; expected: string; actual: string | null } // null: not listed by the server
+  | { kind: "version_mismatch"; file_id: string; expected: number; actual: number | null }
+  | { kind: "manifest_rolled_back"; known_version: number; manifest_version: number };
+
+export interface ManifestReport {
+  folder_id: string;
+  manifest_version: number;
+  consistent: boolean;
+  discrepancies: Discrepancy[];
+}
+
+/**
+ * Sign the folder's file list with your Ed25519 key and encrypt it under
+ * the folder key (folder owners only)
+ */
+export async function createFolderManifest(params: {
+  folder_id: string;
+  manifest_version: number;
+  entries: ManifestEntry[];
+  folder_key_b64: string;
+}): Promise<EncryptedManifest> {
+  return await invoke<EncryptedManifest>("create_folder_manifest", { params });
+}
+
+/**
+ * Compare the server's folder listing with the owner's signed manifest
+ */
+export async function verifyFolderManifest(params: {
+  folder_id: string;
+  encrypted_manifest: EncryptedManifest;
+  folder_key_b64: string;
+  owner_public_key: string;
+  files: ListedFile[];
+}): Promise<ManifestReport> {
+  return await invoke<ManifestReport>("verify_folder_manifest", { params });
+}
+
+// ============================================================================
+// SHARE LINKS
+// ============================================================================
+
+export interface ShareLink {
+  url: string; // The key is in the fragment; never send it to the server
+  link_id: string;
+  wrapped_payload: string;
+  payload_nonce: string;
+  expires_at: number | null;
+  max_downloads: number | null;
+  password_protected: boolean;
+}
+
+export interface ShareLinkDownload {
+  output_path: string;
+  file_name: string;
+}
+
+/**
+ * Create an anonymous share link for a file
+ * Store link_id, wrapped_payload and payload_nonce on the server
+ */
+export async function createShareLink(params: {
+  file_id: string;
+  file_name: string;
+  dek_b64: string;
+  nonce: string;
+  expires_in_secs: number | null;
+  max_downloads: number | null;
+  base_url: string | null;
+  password: string | null; // Recipients must also enter this password
+}): Promise<ShareLink> {
+  return await invoke<ShareLink>("create_share_link", { params });
+}
+
+/**
+ * Download and decrypt the file behind a share link
+ * Saves under a new name in output_dir instead of replacing an existing file
+ */
+export async function downloadShareLink(params: {
+  link_url: string;
+  wrapped_payload: string;
+  payload_nonce: string;
+  download_count: number; // As counted by the server; the limit is advisory
+  download_url: string;
+  output_dir: string;
+  password: string | null;
+  file_size?: number | null; // Ciphertext size, when the server reports it
+}): Promise<ShareLinkDownload> {
+  return await invoke<ShareLinkDownload>("download_share_link", { params });
+}
+
+// ============================================================================
+// VERSION HISTORY
+// ============================================================================
+
+export interface EncryptedVersionChain {
+  ciphertext: string;
+  nonce: string;
+}
+
+export interface FileVersion {
+  version: number;
+  file_key: string;
+  wrapped_dek: string;
+  nonce: string;
+  file_size: number;
+  original_filename: string;
+  ciphertext_sha256: string;
+  user_file_key?: string; // Missing in histories recorded before per-user file keys
+  created_at: number;
+  restored_from: number | null;
+  prev_hash: string;
+}
+
+export interface VersionHistoryRef {
+  file_id: string;
+  history: EncryptedVersionChain;
+  folder_key_b64: string;
+  owner_public_key: string; // File owner's Ed25519 public key
+}
+
+export interface FileVersionUpload {
+  upload: FileUploadResponse;
+  version: number;
+  history: EncryptedVersionChain; // Store this in place of the old history
+}
+
+export interface RestoredFileVersion {
+  version: number;
+  history: EncryptedVersionChain;
+}
+
+export interface VersionDiff {
+  from_version: number;
+  to_version: number;
+  content_changed: boolean;
+  size_change: number;
+  renamed: [string, string] | null;
+  seconds_between: number;
+}
+
+/**
+ * Upload a new version of a file and append it to its version history
+ * Pass history: null for the first version
+ */
+export async function uploadFileVersion(params: {
+  upload: FileUploadParams;
+  file_id: string;
+  history: EncryptedVersionChain | null;
+  folder_key_b64: string;
+}): Promise<FileVersionUpload> {
+  return await invoke<FileVersionUpload>("upload_file_version", { params });
+}
+
+/**
+ * List every version of a file, oldest first
+ */
+export async function listFileVersions(
+  history: VersionHistoryRef
+): Promise<FileVersion[]> {
+  return await invoke<FileVersion[]>("list_file_versions", { history });
+}
+
+/**
+ * Download and decrypt one version of a file
+ */
+export async function downloadFileVersion(params: {
+  history: VersionHistoryRef;
+  version: number;
+  download_url: string; // Presigned URL for the version's file_key
+  output_path: string;
+}): Promise<string> {
+  return await invoke<string>("download_file_version", { params });
+}
+
+/**
+ * Make an older version current again (owner only)
+ */
+export async function restoreFileVersion(
+  history: VersionHistoryRef,
+  version: number
+): Promise<RestoredFileVersion> {
+  return await invoke<RestoredFileVersion>("restore_file_version", {
+    history,
+    version,
+  });
+}
+
+/**
+ * Compare the metadata of two versions of a file
+ */
+export async function diffFileVersions(
+  history: VersionHistoryRef,
+  fromVersion: number,
+  toVersion: number
+): Promise<VersionDiff> {
+  return await invoke<VersionDiff>("diff_file_versions", {
+    history,
+    fromVersion,
+    toVersion,
+  });
+}
+
+// ============================================================================
+// CHUNKED STORAGE
+// ============================================================================
+
+export interface EncryptedChunkManifest {
+  ciphertext: string;
+  nonce: string;
+}
+
+export interface ChunkedUploadPlan {
+  manifest: EncryptedChunkManifest;
+  chunk_ids: string[]; // Unique IDs; ask the server which are missing
+  chunk_count: number;
+  file_size: number;
+  escrow_wrapped_key: string | null; // Chunk key sealed to the profile's escrow key, if one is set
+}
+
+export interface ChunkUploadReport {
+  chunks_uploaded: number;
+  bytes_uploaded: number;
+}
+
+/**
+ * Generate a random chunk key (per user to deduplicate across files, or per file)
+ */
+export async function generateChunkKey(): Promise<string> {
+  return await invoke<string>("generate_chunk_key");
+}
+
+/**
+ * Chunk a file and build its encrypted manifest without uploading anything
+ * The manifest is bound to `fileId` and `version`; pass the same ones to open it
+ */
+export async function planChunkedUpload(
+  filePath: string,
+  chunkKeyB64: string,
+  fileId: string,
+  version: number
+): Promise<ChunkedUploadPlan> {
+  return await invoke<ChunkedUploadPlan>("plan_chunked_upload", {
+    filePath,
+    chunkKeyB64,
+    fileId,
+    version,
+  });
+}
+
+/**
+ * Encrypt and upload only the chunks the server is missing
+ */
+export async function uploadChunks(params: {
+  file_path: string;
+  chunk_key_b64: string;
+  uploads: Record<string, string>; // Chunk ID -> presigned PUT URL
+}): Promise<ChunkUploadReport> {
+  return await invoke<ChunkUploadReport>("upload_chunks", { params });
+}
+
+/**
+ * Rebuild a chunked file from its manifest
+ */
+export async function downloadChunkedFile(params: {
+  manifest: EncryptedChunkManifest;
+  file_id: string;
+  version: number; // Version the manifest was sealed for
+  chunk_key_b64: string;
+  chunk_urls: Record<string, string>; // Chunk ID -> presigned GET URL
+  output_path: string;
+}): Promise<string> {
+  return await invoke<string>("download_chunked_file", { params });
+}
+
+/**
+ * Where decryptRange gets chunks it hasn't cached: separate objects, or
+ * sealed chunks stored back to back in one object (read with ranged GETs)
+ */
+export type ChunkSource =
+  | { chunk_urls: Record<string, string> } // Chunk ID -> presigned GET URL
+  | { packed_url: string };
+
+/**
+ * Decrypt bytes offset..offset+length of a chunked file, fetching only the
+ * chunks that cover the range
+ */
+export async function decryptRange(params: {
+  manifest: EncryptedChunkManifest;
+  file_id: string;
+  version: number; // Version the manifest was sealed for
+  chunk_key_b64: string;
+  offset: number;
+  length: number;
+  source: ChunkSource;
+}): Promise<ArrayBuffer> {
+  return await invoke<ArrayBuffer>("decrypt_range", { params });
+}
+
+// ============================================================================
+// SEARCH
+// ============================================================================
+
+export interface IndexEntry {
+  file_id: string;
+  name: string;
+  tags: string[];
+  folder_path: string; // e.g. "Projects/2024/Taxes"
+}
+
+export type MatchField = "name" | "tag" | "folder_path";
+export type MatchKind = "fuzzy" | "substring" | "prefix" | "exact";
+
+export interface SearchHit {
+  file_id: string;
+  name: string;
+  tags: string[];
+  folder_path: string;
+  score: number;
+  matched: [MatchField, MatchKind][]; // One per query term
+}
+
+export interface EncryptedSearchIndex {
+  ciphertext: string;
+  nonce: string;
+}
+
+/**
+ * Add or update decrypted file metadata in the local search index
+ */
+export async function updateSearchIndex(entries: IndexEntry[]): Promise<number> {
+  return await invoke<number>("update_search_index", { entries });
+}
+
+/**
+ * Remove files from the local search index
+ */
+export async function removeFromSearchIndex(fileIds: string[]): Promise<number> {
+  return await invoke<number>("remove_from_search_index", { fileIds });
+}
+
+/**
+ * Search names, tags and folder paths (prefix and fuzzy matching)
+ */
+export async function searchFiles(
+  query: string,
+  limit: number | null = null
+): Promise<SearchHit[]> {
+  return await invoke<SearchHit[]>("search_files", { query, limit });
+}
+
+/**
+ * Export the encrypted index for syncing to the server
+ */
+export async function exportSearchIndex(): Promise<EncryptedSearchIndex> {
+  return await invoke<EncryptedSearchIndex>("export_search_index");
+}
+
+/**
+ * Merge an index synced from another device into the local one
+ */
+export async function importSearchIndex(
+  blob: EncryptedSearchIndex
+): Promise<number> {
+  return await invoke<number>("import_search_index", { blob });
+}
+
+// ============================================================================
+// THUMBNAILS
+// ============================================================================
+
+/**
+ * Download and decrypt a file's thumbnail (JPEG bytes)
+ */
+export async function getFileThumbnail(
+  downloadUrl: string,
+  dekB64: string
+): Promise<ArrayBuffer> {
+  return await invoke<ArrayBuffer>("get_file_thumbnail", {
+    downloadUrl,
+    dekB64,
+  });
+}
+
+// ============================================================================
+// PREVIEWS
+// ============================================================================
+
+export type PreviewKey =
+  | { dek_b64: string }
+  | { user_file_key: string; file_id: string; grant?: KeyGrant | null }; // The current user's per-user file key, checked against its grant
+
+export interface PreviewParams {
+  download_url: string;
+  nonce: string;
+  key: PreviewKey;
+  file_name: string; // Used to pick the MIME type
+  max_bytes: number | null; // Defaults to 50 MiB
+}
+
+export interface PreviewInfo {
+  handle: string;
+  size: number;
+  mime_type: string;
+  expires_in_secs: number;
+}
+
+/**
+ * Decrypt a file in memory and return its bytes; nothing touches disk
+ */
+export async function previewFile(params: PreviewParams): Promise<ArrayBuffer> {
+  return await invoke<ArrayBuffer>("preview_file", { params });
+}
+
+/**
+ * Decrypt a file in memory and keep it behind a short-lived handle
+ */
+export async function openPreview(params: PreviewParams): Promise<PreviewInfo> {
+  return await invoke<PreviewInfo>("open_preview", { params });
+}
+
+/**
+ * Read part of an open preview (omit length to read to the end)
+ */
+export async function readPreview(
+  handle: string,
+  offset: number | null = null,
+  length: number | null = null
+): Promise<ArrayBuffer> {
+  return await invoke<ArrayBuffer>("read_preview", { handle, offset, length });
+}
+
+/**
+ * Drop an open preview and zero its contents
+ */
+export async function closePreview(handle: string): Promise<void> {
+  return await invoke("close_preview", { handle });
+}
+
+// ============================================================================
+// STREAMING
+// ============================================================================
+
+export interface StreamParams {
+  file_id: string; // Names the ciphertext cache entry
+  download_url: string;
+  nonce: string;
+  key: PreviewKey;
+  file_name: string; // Used to pick the MIME type
+}
+
+export interface StreamInfo {
+  handle: string;
+  url: string; // Use as the src of a <video>, <audio> or <img>
+  size: number;
+  mime_type: string;
+}
+
+/**
+ * Serve a file at a kryptvault:// URL, decrypted on the fly with Range support
+ */
+export async function openStream(params: StreamParams): Promise<StreamInfo> {
+  return await invoke<StreamInfo>("open_stream", { params });
+}
+
+/**
+ * Stop serving a stream handle
+ */
+export async function closeStream(handle: string): Promise<void> {
+  return await invoke("close_stream", { handle });
+}
+
+// ============================================================================
+// MOVE INTO VAULT
+// ============================================================================
+
+/**
+ * How to confirm the upload before the original is deleted
+ */
+export type UploadVerification =
+  | { method: "head"; url: string } // Presigned HEAD URL; compares size
+  | { method: "round_trip"; url: string }; // Presigned GET URL; compares SHA-256
+
+export interface DeletionReport {
+  path: string;
+  overwrite_reliable: boolean; // False on SSDs and copy-on-write filesystems
+  caveat: string | null;
+}
+
+export interface MoveIntoVaultResponse extends FileUploadResponse {
+  deletion_id: string | null; // Pass to deleteMovedOriginal once the upload is saved; null when the original is kept
+  kept_reason: string | null;
+}
+
+/**
+ * Encrypt and upload a file and verify the upload
+ * The original stays until deleteMovedOriginal is called
+ */
+export async function moveIntoVault(
+  params: FileUploadParams & { verification: UploadVerification }
+): Promise<MoveIntoVaultResponse> {
+  return await invoke<MoveIntoVaultResponse>("move_into_vault", { params });
+}
+
+/**
+ * Securely delete a moved file's original
+ * Call only after filesApi.completeUpload has saved the file's record
+ */
+export async function deleteMovedOriginal(deletionId: string): Promise<DeletionReport> {
+  return await invoke<DeletionReport>("delete_moved_original", { deletionId });
+}
+
+// ============================================================================
+// INTEGRITY SCAN
+// ============================================================================
+
+export type ScanDepth = "head" | "checksum" | "decrypt";
+
+export type FileHealth = "healthy" | "corrupted" | "missing" | "undecryptable" | "failed";
+
+export interface ScanRecord {
+  file_id: string;
+  download_url?: string | null; // Presigned GET URL, for checksum and decrypt scans
+  head_url?: string | null; // Presigned HEAD URL, for head scans
+  file_size?: number | null; // Ciphertext size
+  ciphertext_sha256?: string | null;
+  nonce?: string | null;
+  key?: PreviewKey | null; // For decrypt scans
+}
+
+export interface FileCheck {
+  file_id: string;
+  health: FileHealth;
+  detail: string | null;
+  checked_at: number;
+}
+
+export interface ScanReport {
+  scan_id: string;
+  total: number;
+  healthy: number;
+  corrupted: number;
+  missing: number;
+  undecryptable: number;
+  failed: number; // Couldn't be checked; retried when the scan resumes
+  skipped: number; // Already checked by an earlier run of this scan
+  problems: FileCheck[];
+}
+
+/**
+ * Check stored files for missing, corrupted or undecryptable ciphertexts
+ * Pass a previous report's scan_id to resume it
+ */
+export async function scanVault(params: {
+  scan_id?: string | null;
+  depth: ScanDepth;
+  records: ScanRecord[];
+  concurrency?: number | null;
+}): Promise<ScanReport> {
+  return await invoke<ScanReport>("scan_vault", { params });
+}
+
+// ============================================================================
+// KEY CONSISTENCY
+// ============================================================================
+
+export interface FolderWrap {
+  wrapped_dek: string;
+  wrapping_nonce: string;
+  folder_key_b64: string;
+}
+
+export interface KeyCheckRecord {
+  file_id: string;
+  file_key?: string | null; // Sealed to the current user
+  folder_wraps?: FolderWrap[];
+  download_url?: string | null; // With nonce, decides which DEK is right when wraps disagree
+  nonce?: string | null;
+  file_size?: number | null; // Ciphertext size, when known
+}
+
+export type KeyConsistency = "consistent" | "unreadable" | "mismatch" | "broken";
+
+export interface KeyPathResult {
+  path: string; // "personal" or "folder:<index>"
+  unwrapped: boolean;
+  matches: boolean;
+  detail: string | null;
+}
+
+export interface KeyRepair {
+  file_key: string | null;
+  folder_wraps: { index: number; wrapped_dek: string; wrapping_nonce: string }[];
+}
+
+export interface KeyCheckResult {
+  file_id: string;
+  status: KeyConsistency;
+  verified_against_ciphertext: boolean;
+  paths: KeyPathResult[];
+  repair: KeyRepair | null;
+  error: string | null; // Why the ciphertext couldn't be checked, if it wasn't
+}
+
+export interface KeyCheckReport {
+  checked: number;
+  consistent: number;
+  problems: number;
+  repaired: number;
+  results: KeyCheckResult[];
+}
+
+/**
+ * Check that a file's personal and folder DEK wraps all unwrap to the same DEK
+ * With repair, returns replacement wraps for the caller to store
+ */
+export async function checkKeyConsistency(params: {
+  records: KeyCheckRecord[];
+  repair?: boolean;
+}): Promise<KeyCheckReport> {
+  return await invoke<KeyCheckReport>("check_key_consistency", { params });
+}
+
+// ============================================================================
+// LEGACY KEY MIGRATION
+// ============================================================================
+
+export interface LegacyWrapRecord {
+  file_id: string;
+  wrapped_dek: string; // Sealed to the server keypair
+}
+
+export interface MigratedWrap {
+  file_id: string;
+  file_key: string | null; // DEK sealed to the current user; upload as the file's file_key
+  grant: SignedShareGrant | null; // Self-signed grant over file_key, uploaded with it
+  error: string | null;
+}
+
+export interface LegacyMigrationReport {
+  migrated: number;
+  failed: number;
+  results: MigratedWrap[];
+}
+
+/**
+ * Re-wrap DEKs sealed to the server keypair for the current user
+ * Nothing is uploaded; store each returned file_key
+ */
+export async function migrateLegacyWraps(params: {
+  server_public_key: string;
+  server_private_key: string;
+  records: LegacyWrapRecord[];
+}): Promise<LegacyMigrationReport> {
+  return await invoke<LegacyMigrationReport>("migrate_legacy_wraps", { params });
+}
+
+/**
+ * Turn off (or back on) every command that takes a server private key
+ * for the active profile
+ * Not checked against the server: files that still need migrating (see
+ * filesApi.listLegacyKeys) can't be opened until the switch is turned back on
+ */
+export async function setLegacyServerKeysDisabled(disabled: boolean): Promise<void> {
+  await invoke("set_legacy_server_keys_disabled", { disabled });
+}
+
+// ============================================================================
+// ESCROW
+// ============================================================================
+
+export interface EscrowKey {
+  public_key: string; // Escrow X25519 public key
+  org_signing_key: string; // Organization's Ed25519 public key
+  signature: string; // Ed25519 over "kryptvault-escrow-key-v1" then the raw escrow public key
+}
+
+/**
+ * Fingerprint of an organization signing key (base64 Ed25519)
+ * Show it to the user to compare with the one the organization publishes
+ */
+export async function orgKeyFingerprint(orgSigningKey: string): Promise<string> {
+  return await invoke<string>("org_key_fingerprint", { orgSigningKey });
+}
+
+/**
+ * Pin the organization signing key once the user confirmed its fingerprint
+ * Escrow keys are refused until one is pinned (or built into the app)
+ */
+export async function pinOrgSigningKey(
+  orgSigningKey: string,
+  fingerprint: string
+): Promise<string> {
+  return await invoke<string>("pin_org_signing_key", { orgSigningKey, fingerprint });
+}
+
+/**
+ * Set (or with null, clear) the active profile's organization escrow key
+ * The key must be signed by the profile's pinned organization key; clearing
+ * needs the organization's Ed25519 signature over "kryptvault-escrow-clear-v1"
+ * then the raw escrow public key
+ */
+export async function setEscrowKey(
+  escrowKey: EscrowKey | null,
+  clearSignature: string | null = null
+): Promise<void> {
+  await invoke("set_escrow_key", { escrowKey, clearSignature });
+}
+
+/**
+ * Recover a file's DEK with the organization's escrow private key
+ * Every attempt is recorded in the audit log
+ */
+export async function recoverEscrowedDek(params: {
+  file_id: string;
+  escrow_wrapped_dek: string;
+  escrow_public_key: string;
+  escrow_private_key: string;
+  reason?: string | null;
+}): Promise<string> {
+  return await invoke<string>("recover_escrowed_dek", { params });
+}
@@ -1,4 +1,5 @@
 import { authClient } from "@/lib/auth-client";
+import { SERVER_URL } from "@/lib/server-url";
 import { createFileRoute, redirect, useNavigate } from "@tanstack/react-router";
 import React from "react";
 import { Button } from "@/components/ui/button";
@@ -22,7 +23,7 @@ import {
 } from "lucide-react";
 import {
   downloadAndDecryptSharedFile,
-  unwrapSharedDek,
+  unwrapFileKey,
   unwrapDekWithFolderKey,
 } from "@/lib/tauri-crypto";
 import { FileSidebar } from "@/components/FileSidebar";
@@ -112,39 +113,8 @@ function RouteComponent() {
       console.log("Folder details loaded:", details.folder.name);
       console.log("Wrapped folder key present:", !!details.folder.wrappedFolderKey);
       
-      // Unwrap and store folder key in localStorage for file downloads
-      const userKeysStr = localStorage.getItem("userKeypair");
-      if (userKeysStr && details.folder.wrappedFolderKey) {
-        try {
-          const userKeys = JSON.parse(userKeysStr);
-          const userPublicKey = userKeys.x25519PublicKey || userKeys.x25519_public_key;
-          const userPrivateKey = userKeys.x25519PrivateKey || userKeys.x25519_private_key;
-          
-          if (userPublicKey && userPrivateKey) {
-            console.log("Unwrapping folder key...");
-            // Unwrap the folder key
-            const folderKey = await unwrapSharedDek(
-              details.folder.wrappedFolderKey,
-              userPublicKey,
-              userPrivateKey
-            );
-            
-            // Store the unwrapped folder key
-            localStorage.setItem(`folderKey_${folderId}`, folderKey);
-            console.log("Folder key stored successfully");
-          } else {
-            console.error("User keypair incomplete");
-            setError("Encryption keys not properly set up. Please check your keypair.");
-          }
-        } catch (keyErr) {
-          console.error("Failed to unwrap folder key:", keyErr);
-          setError("Failed to decrypt folder key. You may not have access to this folder.");
-        }
-      } else {
-        console.warn("No wrapped folder key or user keypair found");
-        if (!details.folder.wrappedFolderKey) {
-          setError("Folder encryption key not found. This folder may not be properly configured.");
-        }
+      if (!details.folder.wrappedFolderKey) {
+        setError("Folder encryption key not found. This folder may not be properly configured.");
       }
     } catch (err) {
       console.error("Failed to load folder details:", err);
@@ -178,7 +148,7 @@ function RouteComponent() {
       });
 
       // Get download URL for the file from S3
-      const API_URL = import.meta.env.VITE_SERVER_URL || "http://localhost:3000";
+      const API_URL = SERVER_URL;
       const token = localStorage.getItem("bearer_token");
       
       const response = await fetch(`${API_URL}/api/files/${file.fileId}/download`, {
@@ -205,66 +175,27 @@ function RouteComponent() {
           description: "Unwrapping encryption key...",
         });
 
-        // Get folder key from localStorage
-        const folderKeyStr = localStorage.getItem(`folderKey_${folderId}`);
-        if (!folderKeyStr) {
-          // Try to unwrap the folder key again
-          const userKeysStr = localStorage.getItem("userKeypair");
-          if (!userKeysStr || !folderDetails) {
-            throw new Error("Folder key not found. Please reload the page and try again.");
-          }
-
-          try {
-            const userKeys = JSON.parse(userKeysStr);
-            const userPublicKey = userKeys.x25519PublicKey || userKeys.x25519_public_key;
-            const userPrivateKey = userKeys.x25519PrivateKey || userKeys.x25519_private_key;
-
-            if (!userPublicKey || !userPrivateKey) {
-              throw new Error("Encryption keys not properly configured.");
-            }
-
-            const folderKeyUnwrapped = await unwrapSharedDek(
-              folderDetails.folder.wrappedFolderKey,
-              userPublicKey,
-              userPrivateKey
-            );
-
-            localStorage.setItem(`folderKey_${folderId}`, folderKeyUnwrapped);
-            
-            const dekBase64 = await unwrapDekWithFolderKey({
-              wrapped_dek: downloadData.wrappedDek,
-              wrapping_nonce: downloadData.wrappingNonce,
-              folder_key_b64: folderKeyUnwrapped,
-            });
-
-            // Download and decrypt using the unwrapped DEK
-            toast.loading(`Downloading ${file.originalFilename}...`, {
-              id: toastId,
-              description: "Downloading and decrypting file...",
-            });
-
-            await downloadAndDecryptSharedFile(
-              downloadData.downloadUrl,
-              dekBase64,
-              downloadData.nonce,
-              savePath
-            );
-            
-            toast.success(`Download complete!`, {
-              id: toastId,
-              description: `Saved to: ${savePath}`,
-            });
-            return;
-          } catch (unwrapError) {
-            console.error("Failed to unwrap folder key during download:", unwrapError);
-            throw new Error("Cannot access folder encryption key. This folder may have been shared with a different keypair. Please try regenerating your encryption keys.");
-          }
+        if (!folderDetails) {
+          throw new Error("Folder key not found. Please reload the page and try again.");
+        }
+
+        // Unwrap the folder key with the vault keypair; it is never kept outside the vault
+        let folderKey: string;
+        try {
+          folderKey = await unwrapFileKey(
+            folderDetails.folder.wrappedFolderKey,
+            folderId,
+            folderDetails.folder
+          );
+        } catch (unwrapError) {
+          console.error("Failed to unwrap folder key during download:", unwrapError);
+          throw new Error("Cannot access folder encryption key. This folder may have been shared with a different keypair.");
         }
 
         const dekBase64 = await unwrapDekWithFolderKey({
           wrapped_dek: downloadData.wrappedDek,
           wrapping_nonce: downloadData.wrappingNonce,
-          folder_key_b64: folderKeyStr,
+          folder_key_b64: folderKey,
         });
 
         // Download and decrypt using the unwrapped DEK
@@ -277,7 +208,9 @@ function RouteComponent() {
           downloadData.downloadUrl,
           dekBase64,
           downloadData.nonce,
-          savePath
+          savePath,
+          null,
+          downloadData.fileSize
         );
       } else {
         // This is a regular shared file or owner's file
@@ -286,24 +219,11 @@ function RouteComponent() {
           description: "Unwrapping encryption key...",
         });
 
-        const userKeysStr = localStorage.getItem("userKeypair");
-        if (!userKeysStr) {
-          throw new Error("Encryption keys not found. Please set up your keypair first.");
-        }
-
-        const userKeys = JSON.parse(userKeysStr);
-        const userPublicKey = userKeys.x25519PublicKey || userKeys.x25519_public_key;
-        const userPrivateKey = userKeys.x25519PrivateKey || userKeys.x25519_private_key;
-
-        if (!userPublicKey || !userPrivateKey) {
-          throw new Error("Invalid keypair data. Please regenerate your encryption keys.");
-        }
-
-        // Unwrap the DEK using user's keypair
-        const dekBase64 = await unwrapSharedDek(
+        // Unwrap the DEK using the vault keypair
+        const dekBase64 = await unwrapFileKey(
           downloadData.wrappedDek,
-          userPublicKey,
-          userPrivateKey
+          file.fileId,
+          downloadData
         );
 
         // Download and decrypt
@@ -316,7 +236,9 @@ function RouteComponent() {
           downloadData.downloadUrl,
           dekBase64,
           downloadData.nonce,
-          savePath
+          savePath,
+          null,
+          downloadData.fileSize
         );
       }
 
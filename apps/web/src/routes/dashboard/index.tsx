@@ -88,20 +88,6 @@ const handleDownload = async (file: FileMetadata) => {
     try {
       setError(null);
       
-      // Get user's keypair from localStorage
-      const userKeysStr = localStorage.getItem("userKeypair");
-      if (!userKeysStr) {
-        throw new Error("Encryption keys not found. Please set up your keypair first.");
-      }
-
-      const userKeys = JSON.parse(userKeysStr);
-      const userPublicKey = userKeys.x25519PublicKey || userKeys.x25519_public_key;
-      const userPrivateKey = userKeys.x25519PrivateKey || userKeys.x25519_private_key;
-      
-      if (!userPublicKey || !userPrivateKey) {
-        throw new Error("Invalid keypair data. Please regenerate your encryption keys.");
-      }
-
       // Open save dialog
       const savePath = await save({
         title: "Save decrypted file",
@@ -129,15 +115,11 @@ const handleDownload = async (file: FileMetadata) => {
         description: "Unwrapping encryption key...",
       });
 
-      const { unwrapSharedDek, downloadAndDecryptSharedFile } = await import("@/lib/tauri-crypto");
+      const { unwrapFileKey, downloadAndDecryptSharedFile } = await import("@/lib/tauri-crypto");
       
       let dekBase64: string;
       try {
-        dekBase64 = await unwrapSharedDek(
-          downloadInfo.wrappedDek,
-          userPublicKey,
-          userPrivateKey
-        );
+        dekBase64 = await unwrapFileKey(downloadInfo.wrappedDek, file.id, downloadInfo);
       } catch (unwrapError) {
         console.error("Failed to unwrap DEK with user key:", unwrapError);
         throw new Error(
@@ -157,7 +139,9 @@ const handleDownload = async (file: FileMetadata) => {
         downloadInfo.downloadUrl,
         dekBase64,
         downloadInfo.nonce,
-        savePath
+        savePath,
+        null,
+        downloadInfo.fileSize
       );
 
       // Success
@@ -189,20 +173,6 @@ const handlePreview = async (file: FileMetadata) => {
     try {
       setError(null);
       
-      // Get user's keypair from localStorage
-      const userKeysStr = localStorage.getItem("userKeypair");
-      if (!userKeysStr) {
-        throw new Error("Encryption keys not found. Please set up your keypair first.");
-      }
-
-      const userKeys = JSON.parse(userKeysStr);
-      const userPublicKey = userKeys.x25519PublicKey || userKeys.x25519_public_key;
-      const userPrivateKey = userKeys.x25519PrivateKey || userKeys.x25519_private_key;
-      
-      if (!userPublicKey || !userPrivateKey) {
-        throw new Error("Invalid keypair data. Please regenerate your encryption keys.");
-      }
-
       // Show initial toast
       toastId = toast.loading(`Preparing preview for ${file.originalFilename}...`, {
         description: "Fetching file...",
@@ -225,15 +195,11 @@ const handlePreview = async (file: FileMetadata) => {
         description: "Unwrapping encryption key...",
       });
 
-      const { unwrapSharedDek, downloadAndDecryptSharedFile } = await import("@/lib/tauri-crypto");
+      const { unwrapFileKey, downloadAndDecryptSharedFile } = await import("@/lib/tauri-crypto");
       
       let dekBase64: string;
       try {
-        dekBase64 = await unwrapSharedDek(
-          downloadInfo.wrappedDek,
-          userPublicKey,
-          userPrivateKey
-        );
+        dekBase64 = await unwrapFileKey(downloadInfo.wrappedDek, file.id, downloadInfo);
       } catch (unwrapError) {
         console.error("Failed to unwrap DEK with user key:", unwrapError);
         throw new Error(
@@ -253,7 +219,9 @@ const handlePreview = async (file: FileMetadata) => {
         downloadInfo.downloadUrl,
         dekBase64,
         downloadInfo.nonce,
-        tempPath
+        tempPath,
+        null,
+        downloadInfo.fileSize
       );
 
       toast.success("Preview ready!", {
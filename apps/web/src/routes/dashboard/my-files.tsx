@@ -8,7 +8,7 @@ import { save } from "@tauri-apps/plugin-dialog";
 import { toast } from "sonner";
 import { FolderOpen, Filter, Grid3x3, List, MoreVertical, Plus, Download } from "lucide-react";
 import { Card } from "@/components/ui/card";
-import { downloadAndDecryptSharedFile, unwrapSharedDek } from "@/lib/tauri-crypto";
+import { downloadAndDecryptSharedFile, unwrapFileKey } from "@/lib/tauri-crypto";
 import { FileSidebar } from "@/components/FileSidebar";
 import { FolderSidebar } from "@/components/FolderSidebar";
 import { ShareFolderDialog } from "@/components/ShareFolderDialog";
@@ -128,20 +128,6 @@ function RouteComponent() {
     try {
       setError(null);
 
-      // Get user's keypair from localStorage
-      const userKeysStr = localStorage.getItem("userKeypair");
-      if (!userKeysStr) {
-        throw new Error("Encryption keys not found. Please set up your keypair first.");
-      }
-
-      const userKeys = JSON.parse(userKeysStr);
-      const userPublicKey = userKeys.x25519PublicKey || userKeys.x25519_public_key;
-      const userPrivateKey = userKeys.x25519PrivateKey || userKeys.x25519_private_key;
-
-      if (!userPublicKey || !userPrivateKey) {
-        throw new Error("Invalid keypair data. Please regenerate your encryption keys.");
-      }
-
       // Open save dialog
       const savePath = await save({
         title: "Save decrypted file",
@@ -171,11 +157,7 @@ function RouteComponent() {
 
       let dekBase64: string;
       try {
-        dekBase64 = await unwrapSharedDek(
-          downloadInfo.wrappedDek,
-          userPublicKey,
-          userPrivateKey
-        );
+        dekBase64 = await unwrapFileKey(downloadInfo.wrappedDek, file.id, downloadInfo);
       } catch (unwrapError) {
         console.error("Failed to unwrap DEK with user key:", unwrapError);
         throw new Error(
@@ -195,7 +177,9 @@ function RouteComponent() {
         downloadInfo.downloadUrl,
         dekBase64,
         downloadInfo.nonce,
-        savePath
+        savePath,
+        null,
+        downloadInfo.fileSize
       );
 
       // Success
@@ -292,20 +276,6 @@ function RouteComponent() {
     try {
       setError(null);
 
-      // Get user's keypair from localStorage
-      const userKeysStr = localStorage.getItem("userKeypair");
-      if (!userKeysStr) {
-        throw new Error("Encryption keys not found. Please set up your keypair first.");
-      }
-
-      const userKeys = JSON.parse(userKeysStr);
-      const userPublicKey = userKeys.x25519PublicKey || userKeys.x25519_public_key;
-      const userPrivateKey = userKeys.x25519PrivateKey || userKeys.x25519_private_key;
-
-      if (!userPublicKey || !userPrivateKey) {
-        throw new Error("Invalid keypair data. Please regenerate your encryption keys.");
-      }
-
       // Get folder details to fetch all files
       const toastId = toast.loading(`Preparing to download folder "${folder.name}"...`);
       
@@ -351,11 +321,7 @@ function RouteComponent() {
           // Unwrap the DEK using user's private key
           let dekBase64: string;
           try {
-            dekBase64 = await unwrapSharedDek(
-              downloadInfo.wrappedDek,
-              userPublicKey,
-              userPrivateKey
-            );
+            dekBase64 = await unwrapFileKey(downloadInfo.wrappedDek, file.fileId, downloadInfo);
           } catch (unwrapError) {
             console.error("Failed to unwrap DEK with user key:", unwrapError);
             throw new Error(
@@ -371,7 +337,9 @@ function RouteComponent() {
             downloadInfo.downloadUrl,
             dekBase64,
             downloadInfo.nonce,
-            outputPath
+            outputPath,
+            null,
+            downloadInfo.fileSize
           );
 
           successCount++;
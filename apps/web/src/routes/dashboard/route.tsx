@@ -19,6 +19,7 @@ import { filesApi } from "@/lib/files-api";
 import {
   createFolder,
   addFileToFolder,
+  storeOwnerGrant,
   type CreateFolderRequest,
 } from "@/lib/folders-api";
 import {
@@ -26,7 +27,11 @@ import {
   generateFolderKey,
   wrapDekWithFolderKey,
   unwrapSharedDek,
+  getVaultPublicKeys,
+  recordActivity,
+  signOwnKeyGrant,
 } from "@/lib/tauri-crypto";
+import { VaultUnlockDialog } from "@/components/VaultUnlockDialog";
 import { open } from "@tauri-apps/plugin-dialog";
 import { invoke } from "@tauri-apps/api/core";
 import { toast } from "sonner";
@@ -104,6 +109,25 @@ function RouteComponent() {
   const [searchQuery, setSearchQuery] = React.useState("");
   const navigate = useNavigate();
 
+  // Keep the vault's idle timer fresh; VaultUnlockDialog handles unlocking
+  React.useEffect(() => {
+    let lastPing = 0;
+    const onActivity = () => {
+      const now = Date.now();
+      if (now - lastPing > 30_000) {
+        lastPing = now;
+        recordActivity().catch(() => {});
+      }
+    };
+
+    window.addEventListener("keydown", onActivity);
+    window.addEventListener("pointerdown", onActivity);
+    return () => {
+      window.removeEventListener("keydown", onActivity);
+      window.removeEventListener("pointerdown", onActivity);
+    };
+  }, []);
+
   // FILE UPLOAD (single/multi) – functionality same
   const handleFileUpload = async () => {
     try {
@@ -124,22 +148,7 @@ function RouteComponent() {
       const toastId = toast.loading(`Uploading ${filePaths.length} file(s)...`);
 
       try {
-        const userKeysStr = localStorage.getItem("userKeypair");
-        if (!userKeysStr) {
-          throw new Error(
-            "You need to set up encryption keys first. Please generate your keypair."
-          );
-        }
-
-        const userKeys = JSON.parse(userKeysStr);
-        const userPublicKey =
-          userKeys.x25519PublicKey || userKeys.x25519_public_key;
-
-        if (!userPublicKey) {
-          throw new Error(
-            "Invalid keypair data. Please regenerate your encryption keys."
-          );
-        }
+        const { x25519_public_key: userPublicKey } = await getVaultPublicKeys();
 
         for (let i = 0; i < filePaths.length; i++) {
           const filePath = filePaths[i];
@@ -171,10 +180,14 @@ function RouteComponent() {
             file_key: initResponse.s3Key,
           });
 
+          const ownGrant = await signOwnKeyGrant(uploadResponse.user_file_key, initResponse.fileId);
           await filesApi.completeUpload({
             fileId: initResponse.fileId,
             s3Key: uploadResponse.file_key,
-            wrappedDek: uploadResponse.wrapped_dek,
+            wrappedDek: uploadResponse.user_file_key, // Always sealed to the vault's own key
+            escrowWrappedDek: uploadResponse.escrow_wrapped_dek,
+            grant: ownGrant.grant,
+            grantSignature: ownGrant.signature,
             nonce: uploadResponse.nonce,
             originalFilename: uploadResponse.original_filename,
             fileSize: uploadResponse.file_size,
@@ -235,16 +248,7 @@ function RouteComponent() {
     const toastId = toast.loading(`Creating folder "${folderName}"...`);
 
     try {
-      const userKeysStr = localStorage.getItem("userKeypair");
-      if (!userKeysStr) {
-        throw new Error("You need to set up encryption keys first.");
-      }
-
-      const userKeys = JSON.parse(userKeysStr);
-      const userPublicKey =
-        userKeys.x25519PublicKey || userKeys.x25519_public_key;
-      const userPrivateKey =
-        userKeys.x25519PrivateKey || userKeys.x25519_private_key;
+      const { x25519_public_key: userPublicKey } = await getVaultPublicKeys();
 
       const folderKeyBase64 = await generateFolderKey();
 
@@ -260,6 +264,7 @@ function RouteComponent() {
       };
 
       const { folderId } = await createFolder(createFolderRequest);
+      await storeOwnerGrant(folderId, await signOwnKeyGrant(wrappedFolderKey, folderId));
 
       toast.loading(`Uploading ${selectedFiles.length} files...`, {
         id: toastId,
@@ -288,20 +293,20 @@ function RouteComponent() {
           file_key: initResponse.s3Key,
         });
 
+        const ownGrant = await signOwnKeyGrant(encryptResult.user_file_key, initResponse.fileId);
         await filesApi.completeUpload({
           fileId: initResponse.fileId,
           s3Key: encryptResult.file_key,
-          wrappedDek: encryptResult.wrapped_dek,
+          wrappedDek: encryptResult.user_file_key, // Always sealed to the vault's own key
+          escrowWrappedDek: encryptResult.escrow_wrapped_dek,
+          grant: ownGrant.grant,
+          grantSignature: ownGrant.signature,
           nonce: encryptResult.nonce,
           originalFilename: encryptResult.original_filename,
           fileSize: encryptResult.file_size,
         });
 
-        const unwrappedDek = await unwrapSharedDek(
-          encryptResult.wrapped_dek,
-          userPublicKey,
-          userPrivateKey
-        );
+        const unwrappedDek = await unwrapSharedDek(encryptResult.user_file_key, initResponse.fileId, { grant: ownGrant });
 
         const wrappedDekForFolder = await wrapDekWithFolderKey({
           dek_b64: unwrappedDek,
@@ -628,6 +633,8 @@ function RouteComponent() {
           </DialogFooter>
         </DialogContent>
       </Dialog>
+
+      <VaultUnlockDialog />
     </div>
   );
 }
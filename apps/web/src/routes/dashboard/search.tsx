@@ -29,7 +29,7 @@ import {
   FolderOpen,
 } from "lucide-react";
 import { save } from "@tauri-apps/plugin-dialog";
-import { downloadAndDecryptSharedFile, unwrapSharedDek } from "@/lib/tauri-crypto";
+import { downloadAndDecryptSharedFile, unwrapFileKey } from "@/lib/tauri-crypto";
 
 function getFileIcon(ext?: string) {
   if (!ext) return FileIcon;
@@ -147,19 +147,6 @@ function RouteComponent() {
     try {
       setError(null);
 
-      const userKeysStr = localStorage.getItem("userKeypair");
-      if (!userKeysStr) {
-        throw new Error("Encryption keys not found. Please set up your keypair first.");
-      }
-
-      const userKeys = JSON.parse(userKeysStr);
-      const userPublicKey = userKeys.x25519PublicKey || userKeys.x25519_public_key;
-      const userPrivateKey = userKeys.x25519PrivateKey || userKeys.x25519_private_key;
-
-      if (!userPublicKey || !userPrivateKey) {
-        throw new Error("Invalid keypair data. Please regenerate your encryption keys.");
-      }
-
       const savePath = await save({
         title: "Save decrypted file",
         defaultPath: file.originalFilename,
@@ -178,11 +165,7 @@ function RouteComponent() {
         description: "Unwrapping encryption key...",
       });
 
-      const dekBase64 = await unwrapSharedDek(
-        downloadInfo.wrappedDek,
-        userPublicKey,
-        userPrivateKey
-      );
+      const dekBase64 = await unwrapFileKey(downloadInfo.wrappedDek, file.id, downloadInfo);
 
       toast.loading(`Downloading ${file.originalFilename}...`, {
         id: toastId,
@@ -193,7 +176,9 @@ function RouteComponent() {
         downloadInfo.downloadUrl,
         dekBase64,
         downloadInfo.nonce,
-        savePath
+        savePath,
+        null,
+        downloadInfo.fileSize
       );
 
       toast.success(`Download complete!`, {
@@ -20,7 +20,7 @@ import {
 } from "@/lib/folders-api";
 import {
   downloadAndDecryptSharedFile,
-  unwrapSharedDek,
+  unwrapFileKey,
 } from "@/lib/tauri-crypto";
 import { save } from "@tauri-apps/plugin-dialog";
 import { FileSidebar } from "@/components/FileSidebar";
@@ -147,26 +147,6 @@ function RouteComponent() {
     try {
       setError(null);
 
-      // Check for user keys
-      const userKeysStr = localStorage.getItem("userKeypair");
-      if (!userKeysStr) {
-        toast.error("You need to set up encryption keys first");
-        return;
-      }
-
-      const userKeys = JSON.parse(userKeysStr);
-      const userPublicKey =
-        userKeys.x25519PublicKey || userKeys.x25519_public_key;
-      const userPrivateKey =
-        userKeys.x25519PrivateKey || userKeys.x25519_private_key;
-
-      if (!userPublicKey || !userPrivateKey) {
-        toast.error(
-          "Invalid keypair data. Please regenerate your encryption keys."
-        );
-        return;
-      }
-
       // Open save dialog
       const savePath = await save({
         title: "Save decrypted file",
@@ -179,21 +159,16 @@ function RouteComponent() {
         description: "Unwrapping encryption key...",
       });
 
-      // Unwrap the DEK that was shared with us
-      const dekBase64 = await unwrapSharedDek(
-        file.wrappedDek,
-        userPublicKey,
-        userPrivateKey
-      );
-
-      // Get presigned download URL from server
-      toast.loading(`Downloading ${file.originalFilename}...`, {
-        id: toastId,
-        description: "Getting download URL...",
-      });
-
+      // Get presigned download URL and the share grant from server
       const downloadInfo = await filesApi.getDownloadInfo(file.fileId);
 
+      // Unwrap the DEK that was shared with us once its grant checks out
+      const dekBase64 = await unwrapFileKey(
+        downloadInfo.wrappedDek,
+        file.fileId,
+        downloadInfo
+      );
+
       toast.loading(`Downloading ${file.originalFilename}...`, {
         id: toastId,
         description: "Downloading and decrypting file...",
@@ -204,7 +179,9 @@ function RouteComponent() {
         downloadInfo.downloadUrl,
         dekBase64,
         file.nonce,
-        savePath
+        savePath,
+        null,
+        downloadInfo.fileSize
       );
 
       toast.success(`Download complete!`, {
@@ -236,20 +213,6 @@ function RouteComponent() {
 
   const handleDownloadFolder = async (folder: SharedFolder) => {
     try {
-      // Get user's keypair from localStorage
-      const userKeysStr = localStorage.getItem("userKeypair");
-      if (!userKeysStr) {
-        throw new Error("Encryption keys not found. Please set up your keypair first.");
-      }
-
-      const userKeys = JSON.parse(userKeysStr);
-      const userPublicKey = userKeys.x25519PublicKey || userKeys.x25519_public_key;
-      const userPrivateKey = userKeys.x25519PrivateKey || userKeys.x25519_private_key;
-
-      if (!userPublicKey || !userPrivateKey) {
-        throw new Error("Invalid keypair data. Please regenerate your encryption keys.");
-      }
-
       // Get folder details to fetch all files
       const toastId = toast.loading(`Preparing to download folder "${folder.name}"...`);
       
@@ -293,10 +256,10 @@ function RouteComponent() {
           const downloadInfo = await filesApi.getDownloadInfo(file.fileId);
 
           // Unwrap the DEK using user's private key
-          const dekBase64 = await unwrapSharedDek(
+          const dekBase64 = await unwrapFileKey(
             downloadInfo.wrappedDek,
-            userPublicKey,
-            userPrivateKey
+            file.fileId,
+            downloadInfo
           );
 
           // Create the output path
@@ -307,7 +270,9 @@ function RouteComponent() {
             downloadInfo.downloadUrl,
             dekBase64,
             downloadInfo.nonce,
-            outputPath
+            outputPath,
+            null,
+            downloadInfo.fileSize
           );
 
           successCount++;
@@ -760,21 +725,6 @@ function RouteComponent() {
           onDownload={async () => {
             // Get the actual file to download
             try {
-              const userKeysStr = localStorage.getItem("userKeypair");
-              if (!userKeysStr) {
-                toast.error("Encryption keys not found");
-                return;
-              }
-
-              const userKeys = JSON.parse(userKeysStr);
-              const userPublicKey = userKeys.x25519PublicKey || userKeys.x25519_public_key;
-              const userPrivateKey = userKeys.x25519PrivateKey || userKeys.x25519_private_key;
-
-              if (!userPublicKey || !userPrivateKey) {
-                toast.error("Invalid keypair data");
-                return;
-              }
-
               const savePath = await save({
                 title: "Save decrypted file",
                 defaultPath: selectedSharedByMeFile.originalFilename,
@@ -786,17 +736,19 @@ function RouteComponent() {
 
               const downloadInfo = await filesApi.getDownloadInfo(selectedSharedByMeFile.fileId);
 
-              const dekBase64 = await unwrapSharedDek(
+              const dekBase64 = await unwrapFileKey(
                 downloadInfo.wrappedDek,
-                userPublicKey,
-                userPrivateKey
+                selectedSharedByMeFile.fileId,
+                downloadInfo
               );
 
               await downloadAndDecryptSharedFile(
                 downloadInfo.downloadUrl,
                 dekBase64,
                 downloadInfo.nonce,
-                savePath
+                savePath,
+                null,
+                downloadInfo.fileSize
               );
 
               toast.success("Download complete!", { id: toastId, description: `Saved to: ${savePath}` });
@@ -73,20 +73,6 @@ function RouteComponent() {
     try {
       setError(null);
       
-      // Get user's keypair from localStorage
-      const userKeysStr = localStorage.getItem("userKeypair");
-      if (!userKeysStr) {
-        throw new Error("Encryption keys not found. Please set up your keypair first.");
-      }
-
-      const userKeys = JSON.parse(userKeysStr);
-      const userPublicKey = userKeys.x25519PublicKey || userKeys.x25519_public_key;
-      const userPrivateKey = userKeys.x25519PrivateKey || userKeys.x25519_private_key;
-      
-      if (!userPublicKey || !userPrivateKey) {
-        throw new Error("Invalid keypair data. Please regenerate your encryption keys.");
-      }
-
       // Open save dialog
       const savePath = await save({
         title: "Save decrypted file",
@@ -114,12 +100,8 @@ function RouteComponent() {
         description: "Unwrapping encryption key...",
       });
 
-      const { unwrapSharedDek, downloadAndDecryptSharedFile } = await import("@/lib/tauri-crypto");
-      const dekBase64 = await unwrapSharedDek(
-        downloadInfo.wrappedDek,
-        userPublicKey,
-        userPrivateKey
-      );
+      const { unwrapFileKey, downloadAndDecryptSharedFile } = await import("@/lib/tauri-crypto");
+      const dekBase64 = await unwrapFileKey(downloadInfo.wrappedDek, file.id, downloadInfo);
 
       // Download and decrypt using the unwrapped DEK
       toast.loading(`Downloading ${file.originalFilename}...`, {
@@ -131,7 +113,9 @@ function RouteComponent() {
         downloadInfo.downloadUrl,
         dekBase64,
         downloadInfo.nonce,
-        savePath
+        savePath,
+        null,
+        downloadInfo.fileSize
       );
 
       // Success
@@ -163,20 +147,6 @@ function RouteComponent() {
     try {
       setError(null);
       
-      // Get user's keypair from localStorage
-      const userKeysStr = localStorage.getItem("userKeypair");
-      if (!userKeysStr) {
-        throw new Error("Encryption keys not found. Please set up your keypair first.");
-      }
-
-      const userKeys = JSON.parse(userKeysStr);
-      const userPublicKey = userKeys.x25519PublicKey || userKeys.x25519_public_key;
-      const userPrivateKey = userKeys.x25519PrivateKey || userKeys.x25519_private_key;
-      
-      if (!userPublicKey || !userPrivateKey) {
-        throw new Error("Invalid keypair data. Please regenerate your encryption keys.");
-      }
-
       // Show initial toast
       toastId = toast.loading(`Preparing preview for ${file.originalFilename}...`, {
         description: "Fetching file...",
@@ -199,12 +169,8 @@ function RouteComponent() {
         description: "Unwrapping encryption key...",
       });
 
-      const { unwrapSharedDek, downloadAndDecryptSharedFile } = await import("@/lib/tauri-crypto");
-      const dekBase64 = await unwrapSharedDek(
-        downloadInfo.wrappedDek,
-        userPublicKey,
-        userPrivateKey
-      );
+      const { unwrapFileKey, downloadAndDecryptSharedFile } = await import("@/lib/tauri-crypto");
+      const dekBase64 = await unwrapFileKey(downloadInfo.wrappedDek, file.id, downloadInfo);
 
       // Download and decrypt using the unwrapped DEK
       toast.loading(`Preparing preview for ${file.originalFilename}...`, {
@@ -216,7 +182,9 @@ function RouteComponent() {
         downloadInfo.downloadUrl,
         dekBase64,
         downloadInfo.nonce,
-        tempPath
+        tempPath,
+        null,
+        downloadInfo.fileSize
       );
 
       toast.success("Preview ready!", {
@@ -6,6 +6,10 @@ import { defineConfig } from "vite";
 
 export default defineConfig({
 	plugins: [tailwindcss(), tanstackRouter({}), react()],
+	// lib/server-url.ts reads the active profile with a top-level await
+	build: {
+		target: "es2022",
+	},
 	resolve: {
 		alias: {
 			"@": path.resolve(__dirname, "./src"),
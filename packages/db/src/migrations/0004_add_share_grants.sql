@@ -0,0 +1,5 @@
+-- Signed share grants for file and folder keys shared with other users
+ALTER TABLE "file_key" ADD COLUMN IF NOT EXISTS "grant" text;
+ALTER TABLE "file_key" ADD COLUMN IF NOT EXISTS "grant_signature" text;
+ALTER TABLE "folder_key" ADD COLUMN IF NOT EXISTS "grant" text;
+ALTER TABLE "folder_key" ADD COLUMN IF NOT EXISTS "grant_signature" text;
@@ -0,0 +1,3 @@
+-- Keys sealed to the organization escrow key, for admin recovery
+ALTER TABLE "file_key" ADD COLUMN IF NOT EXISTS "escrow_wrapped_dek" text;
+ALTER TABLE "folder_key" ADD COLUMN IF NOT EXISTS "escrow_wrapped_key" text;
@@ -58,6 +58,13 @@ export const fileKey = pgTable("file_key", {
 		.notNull()
 		.references(() => user.id, { onDelete: "cascade" }), // Who shared it
 	
+	// Share grant signed with the sharer's Ed25519 key (null for uploads and legacy shares)
+	grant: text("grant"), // Base64 JSON
+	grantSignature: text("grant_signature"), // Base64 Ed25519 signature
+	
+	// DEK sealed to the uploader's or sharer's organization escrow key, if they had one
+	escrowWrappedDek: text("escrow_wrapped_dek"), // Base64 encoded
+	
 	createdAt: timestamp("created_at").notNull().defaultNow(),
 });
 
@@ -100,6 +107,13 @@ export const folderKey = pgTable("folder_key", {
 		.notNull()
 		.references(() => user.id, { onDelete: "cascade" }),
 	
+	// Share grant signed with the sharer's Ed25519 key (null for owners and legacy shares)
+	grant: text("grant"), // Base64 JSON
+	grantSignature: text("grant_signature"), // Base64 Ed25519 signature
+	
+	// Folder key sealed to the sharer's organization escrow key, if they had one
+	escrowWrappedKey: text("escrow_wrapped_key"), // Base64 encoded
+	
 	createdAt: timestamp("created_at").notNull().defaultNow(),
 });
 